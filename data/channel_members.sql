@@ -0,0 +1,16 @@
+CREATE TABLE channel_members
+(
+    channel VARCHAR(25) NOT NULL
+        CONSTRAINT channel_members_channels_name_fk
+            REFERENCES channels (name)
+            ON DELETE CASCADE,
+    username VARCHAR(25) NOT NULL
+        CONSTRAINT channel_members_users_name_fk
+            REFERENCES users (name),
+    role VARCHAR(16) NOT NULL
+        CONSTRAINT channel_members_role_check
+            CHECK (role IN ('owner', 'moderator', 'member')),
+    added_at TIMESTAMPTZ DEFAULT NOW() NOT NULL,
+    CONSTRAINT channel_members_pk
+        PRIMARY KEY (channel, username)
+);
@@ -0,0 +1,82 @@
+CREATE TABLE channel_sequences
+(
+    channel VARCHAR(25) NOT NULL
+        CONSTRAINT channel_sequences_pk
+            PRIMARY KEY,
+    last_seq BIGINT NOT NULL,
+    -- How many messages the channel holds, and when the latest was sent.
+    message_count BIGINT DEFAULT 0 NOT NULL,
+    last_activity TIMESTAMPTZ,
+    -- The earliest timestamp stored since stats rollups were last refreshed.
+    rollups_stale_from TIMESTAMPTZ
+);
+
+-- Numbers each message in its channel as it is stored. Imported messages
+-- are inserted with 0 and keep it, so that they come before everything
+-- posted live. Either way the message's hour is marked stale.
+CREATE OR REPLACE FUNCTION chat_messages_assign_seq() RETURNS TRIGGER AS
+$$
+BEGIN
+    IF NEW.seq = 0 THEN
+        INSERT INTO channel_sequences (channel, last_seq, message_count, last_activity, rollups_stale_from)
+        VALUES (NEW.channel, 0, 1, NEW.timestamp, NEW.timestamp)
+        ON CONFLICT (channel) DO UPDATE SET message_count = channel_sequences.message_count + 1,
+                                            last_activity = GREATEST(channel_sequences.last_activity, NEW.timestamp),
+                                            rollups_stale_from = LEAST(channel_sequences.rollups_stale_from, NEW.timestamp);
+
+        RETURN NEW;
+    END IF;
+
+    INSERT INTO channel_sequences (channel, last_seq, message_count, last_activity, rollups_stale_from)
+    VALUES (NEW.channel, 1, 1, NEW.timestamp, NEW.timestamp)
+    ON CONFLICT (channel) DO UPDATE SET last_seq = channel_sequences.last_seq + 1,
+                                        message_count = channel_sequences.message_count + 1,
+                                        last_activity = GREATEST(channel_sequences.last_activity, NEW.timestamp),
+                                        rollups_stale_from = LEAST(channel_sequences.rollups_stale_from, NEW.timestamp)
+    RETURNING last_seq INTO NEW.seq;
+
+    RETURN NEW;
+END
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER chat_messages_assign_seq
+    BEFORE INSERT
+    ON chat_messages
+    FOR EACH ROW
+EXECUTE FUNCTION chat_messages_assign_seq();
+
+-- Takes the given per channel counts of removed messages off the channels,
+-- whose latest activity is looked up again if it may have been removed.
+CREATE OR REPLACE FUNCTION channel_sequences_forget(channels TEXT[], counts BIGINT[], latest TIMESTAMPTZ[]) RETURNS VOID AS
+$$
+UPDATE channel_sequences s
+SET message_count = GREATEST(s.message_count - removed.count, 0),
+    last_activity = CASE
+                        WHEN removed.latest < s.last_activity THEN s.last_activity
+                        ELSE (SELECT MAX(m.timestamp) FROM chat_messages m WHERE m.channel = s.channel)
+        END
+FROM unnest(channels, counts, latest) AS removed(channel, count, latest)
+WHERE s.channel = removed.channel
+$$ LANGUAGE SQL;
+
+CREATE OR REPLACE FUNCTION chat_messages_forget_deleted() RETURNS TRIGGER AS
+$$
+BEGIN
+    PERFORM channel_sequences_forget(array_agg(channel), array_agg(count), array_agg(latest))
+    FROM (SELECT channel, COUNT(*) AS count, MAX(timestamp) AS latest FROM deleted GROUP BY channel) removed;
+
+    PERFORM chat_message_rollups_forget(array_agg(channel), array_agg(hour), array_agg(username), array_agg(count))
+    FROM (SELECT channel, chat_messages_hour(timestamp) AS hour, username, COUNT(*) AS count
+          FROM deleted
+          GROUP BY 1, 2, 3) removed;
+
+    RETURN NULL;
+END
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER chat_messages_forget_deleted
+    AFTER DELETE
+    ON chat_messages
+    REFERENCING OLD TABLE AS deleted
+    FOR EACH STATEMENT
+EXECUTE FUNCTION chat_messages_forget_deleted();
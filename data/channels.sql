@@ -0,0 +1,13 @@
+CREATE TABLE channels
+(
+    name VARCHAR(25) NOT NULL
+        CONSTRAINT channels_pk
+            PRIMARY KEY
+        CONSTRAINT channels_users_name_fk
+            REFERENCES users (name),
+    display_name VARCHAR(64) NOT NULL,
+    description TEXT,
+    archived BOOLEAN DEFAULT FALSE NOT NULL,
+    private BOOLEAN DEFAULT FALSE NOT NULL,
+    created_at TIMESTAMPTZ DEFAULT NOW() NOT NULL
+);
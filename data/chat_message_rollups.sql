@@ -0,0 +1,32 @@
+CREATE TABLE chat_message_rollups
+(
+    channel VARCHAR(25) NOT NULL,
+    bucket_start TIMESTAMPTZ NOT NULL,
+    username VARCHAR(25) NOT NULL,
+    message_count BIGINT NOT NULL,
+    CONSTRAINT chat_message_rollups_pk
+        PRIMARY KEY (channel, bucket_start, username)
+);
+
+-- The start of the UTC hour `at` falls in, which rollups are counted by.
+CREATE OR REPLACE FUNCTION chat_messages_hour(at TIMESTAMPTZ) RETURNS TIMESTAMPTZ AS
+$$
+SELECT date_trunc('hour', at AT TIME ZONE 'UTC') AT TIME ZONE 'UTC'
+$$ LANGUAGE SQL IMMUTABLE;
+
+-- Takes the given per chatter counts of removed messages off the rollups of
+-- their hours.
+CREATE OR REPLACE FUNCTION chat_message_rollups_forget(channels TEXT[], hours TIMESTAMPTZ[], usernames TEXT[], counts BIGINT[]) RETURNS VOID AS
+$$
+UPDATE chat_message_rollups r
+SET message_count = r.message_count - removed.count
+FROM unnest(channels, hours, usernames, counts) AS removed(channel, bucket_start, username, count)
+WHERE r.channel = removed.channel
+  AND r.bucket_start = removed.bucket_start
+  AND r.username = removed.username;
+
+DELETE
+FROM chat_message_rollups
+WHERE message_count <= 0
+  AND (channel, bucket_start, username) IN (SELECT * FROM unnest(channels, hours, usernames));
+$$ LANGUAGE SQL;
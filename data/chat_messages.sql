@@ -1,15 +1,28 @@
 CREATE TABLE chat_messages
 (
-    id INTEGER GENERATED ALWAYS AS IDENTITY
-        CONSTRAINT chat_messages_pk
-            PRIMARY KEY,
+    id INTEGER GENERATED ALWAYS AS IDENTITY,
     username VARCHAR(25) NOT NULL
         CONSTRAINT chat_messages_users_name_fk
             REFERENCES users (name),
     channel VARCHAR(25) NOT NULL
         CONSTRAINT chat_messages_users_name_fk2
             REFERENCES users (name),
-    timestamp TIMESTAMP NOT NULL,
-    text VARCHAR(500) NOT NULL
-);
+    timestamp TIMESTAMPTZ NOT NULL,
+    text VARCHAR(500) NOT NULL,
+    source_id VARCHAR(64),
+    -- Assigned on insert, or 0 if imported, see channel_sequences.sql.
+    seq BIGINT NOT NULL,
+    -- The text split into emotes, mentions, links and plain text.
+    fragments JSONB,
+    CONSTRAINT chat_messages_pk
+        PRIMARY KEY (id, timestamp)
+) PARTITION BY RANGE (timestamp);
+
+CREATE TABLE chat_messages_default PARTITION OF chat_messages DEFAULT;
+
+CREATE INDEX chat_messages_channel_timestamp_index
+    ON chat_messages (channel, timestamp);
+
+CREATE INDEX chat_messages_channel_seq_index
+    ON chat_messages (channel, seq);
 
@@ -0,0 +1,18 @@
+CREATE TABLE direct_messages
+(
+    id INTEGER GENERATED ALWAYS AS IDENTITY
+        CONSTRAINT direct_messages_pk
+            PRIMARY KEY,
+    sender VARCHAR(25) NOT NULL
+        CONSTRAINT direct_messages_users_name_fk
+            REFERENCES users (name),
+    recipient VARCHAR(25) NOT NULL
+        CONSTRAINT direct_messages_users_name_fk2
+            REFERENCES users (name),
+    timestamp TIMESTAMPTZ DEFAULT NOW() NOT NULL,
+    text VARCHAR(500) NOT NULL
+);
+
+-- Either participant can look a conversation up the same way.
+CREATE INDEX direct_messages_conversation_index
+    ON direct_messages (LEAST(sender, recipient), GREATEST(sender, recipient), id);
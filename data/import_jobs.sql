@@ -0,0 +1,11 @@
+CREATE TABLE import_jobs
+(
+    source VARCHAR(255) NOT NULL
+        CONSTRAINT import_jobs_pk
+            PRIMARY KEY,
+    lines_done BIGINT DEFAULT 0 NOT NULL,
+    imported BIGINT DEFAULT 0 NOT NULL,
+    started_at TIMESTAMPTZ DEFAULT NOW() NOT NULL,
+    updated_at TIMESTAMPTZ DEFAULT NOW() NOT NULL,
+    finished_at TIMESTAMPTZ
+);
@@ -0,0 +1,54 @@
+CREATE TABLE mentions
+(
+    id BIGINT GENERATED ALWAYS AS IDENTITY
+        CONSTRAINT mentions_pk
+            PRIMARY KEY,
+    username VARCHAR(25) NOT NULL
+        CONSTRAINT mentions_users_name_fk
+            REFERENCES users (name)
+            ON DELETE CASCADE,
+    message_id INTEGER NOT NULL,
+    message_timestamp TIMESTAMPTZ NOT NULL,
+    created_at TIMESTAMPTZ DEFAULT NOW() NOT NULL,
+    -- Unread until the user marks it read.
+    read_at TIMESTAMPTZ,
+    CONSTRAINT mentions_message_username_unique
+        UNIQUE (message_id, username)
+);
+
+CREATE INDEX mentions_username_id_index
+    ON mentions (username, id);
+
+CREATE INDEX users_lower_name_index
+    ON users (LOWER(name));
+
+-- Records the mentions of known users that can read the channel. Whether
+-- they still can is checked again whenever their mentions are read.
+CREATE OR REPLACE FUNCTION chat_messages_record_mentions() RETURNS TRIGGER AS
+$$
+BEGIN
+    INSERT INTO mentions (username, message_id, message_timestamp)
+    SELECT DISTINCT u.name, NEW.id, NEW.timestamp
+    FROM jsonb_array_elements(COALESCE(NEW.fragments, '[]'::JSONB)) AS f
+             JOIN users u ON LOWER(u.name) = LOWER(f ->> 'username')
+    WHERE f ->> 'type' = 'mention'
+      AND u.name <> NEW.username
+      AND NOT EXISTS (SELECT 1
+                      FROM channels c
+                      WHERE c.name = NEW.channel
+                        AND c.private
+                        AND NOT EXISTS (SELECT 1
+                                        FROM channel_members cm
+                                        WHERE cm.channel = c.name
+                                          AND cm.username = u.name))
+    ON CONFLICT DO NOTHING;
+
+    RETURN NULL;
+END
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER chat_messages_record_mentions
+    AFTER INSERT
+    ON chat_messages
+    FOR EACH ROW
+EXECUTE FUNCTION chat_messages_record_mentions();
@@ -0,0 +1,13 @@
+CREATE TABLE message_keys
+(
+    channel VARCHAR(25) NOT NULL,
+    key VARCHAR(300) NOT NULL,
+    message_id INTEGER NOT NULL,
+    message_timestamp TIMESTAMPTZ NOT NULL,
+    created_at TIMESTAMPTZ DEFAULT NOW() NOT NULL,
+    CONSTRAINT message_keys_pk
+        PRIMARY KEY (channel, key)
+);
+
+CREATE INDEX message_keys_message_id_index
+    ON message_keys (message_id);
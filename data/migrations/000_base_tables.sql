@@ -0,0 +1,85 @@
+-- Creates the baseline tables, and the tables that were added as schema
+-- files alongside them, which later migrations alter, so that migrating an
+-- empty database works too. Databases created from those files already have
+-- them, so this leaves existing tables alone. Reactions do not reference
+-- chat_messages, whose ids stop being unique once it is partitioned.
+
+CREATE TABLE IF NOT EXISTS users
+(
+    name VARCHAR(25) NOT NULL
+        CONSTRAINT "Users_pk"
+            PRIMARY KEY
+);
+
+CREATE TABLE IF NOT EXISTS chat_messages
+(
+    id INTEGER GENERATED ALWAYS AS IDENTITY
+        CONSTRAINT chat_messages_pk
+            PRIMARY KEY,
+    username VARCHAR(25) NOT NULL
+        CONSTRAINT chat_messages_users_name_fk
+            REFERENCES users (name),
+    channel VARCHAR(25) NOT NULL
+        CONSTRAINT chat_messages_users_name_fk2
+            REFERENCES users (name),
+    timestamp TIMESTAMP NOT NULL,
+    text VARCHAR(500) NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS logs
+(
+    id INTEGER GENERATED ALWAYS AS IDENTITY,
+    timestamp TIMESTAMP DEFAULT NOW() NOT NULL,
+    message TEXT,
+    channel VARCHAR(25),
+    chat_message_id INTEGER
+        CONSTRAINT logs_chat_messages_id_fk
+            REFERENCES chat_messages
+);
+
+CREATE TABLE IF NOT EXISTS reactions
+(
+    message_id INTEGER NOT NULL,
+    username VARCHAR(25) NOT NULL
+        CONSTRAINT reactions_users_name_fk
+            REFERENCES users (name),
+    emote VARCHAR(64) NOT NULL,
+    timestamp TIMESTAMP DEFAULT NOW() NOT NULL,
+    CONSTRAINT reactions_pk
+        PRIMARY KEY (message_id, username, emote)
+);
+
+CREATE TABLE IF NOT EXISTS channels
+(
+    name VARCHAR(25) NOT NULL
+        CONSTRAINT channels_pk
+            PRIMARY KEY
+        CONSTRAINT channels_users_name_fk
+            REFERENCES users (name),
+    display_name VARCHAR(64) NOT NULL,
+    description TEXT,
+    archived BOOLEAN DEFAULT FALSE NOT NULL,
+    created_at TIMESTAMPTZ DEFAULT NOW() NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS chat_message_rollups
+(
+    channel VARCHAR(25) NOT NULL,
+    bucket_start TIMESTAMPTZ NOT NULL,
+    username VARCHAR(25) NOT NULL,
+    message_count BIGINT NOT NULL,
+    CONSTRAINT chat_message_rollups_pk
+        PRIMARY KEY (channel, bucket_start, username)
+);
+
+CREATE TABLE IF NOT EXISTS import_jobs
+(
+    source VARCHAR(255) NOT NULL
+        CONSTRAINT import_jobs_pk
+            PRIMARY KEY,
+    lines_done BIGINT DEFAULT 0 NOT NULL,
+    imported BIGINT DEFAULT 0 NOT NULL,
+    started_at TIMESTAMPTZ DEFAULT NOW() NOT NULL,
+    updated_at TIMESTAMPTZ DEFAULT NOW() NOT NULL,
+    finished_at TIMESTAMPTZ
+);
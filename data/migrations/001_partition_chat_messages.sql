@@ -0,0 +1,118 @@
+-- Partitions chat_messages by month on timestamp. Rows outside every
+-- monthly partition land in chat_messages_default until maintenance moves
+-- them into a partition of their own.
+
+CREATE OR REPLACE FUNCTION chat_messages_partition_name(month DATE) RETURNS TEXT AS
+$$
+SELECT 'chat_messages_' || to_char(month, 'YYYY_MM')
+$$ LANGUAGE SQL IMMUTABLE;
+
+-- Creates the partition for the month containing `month`, moving that
+-- month's rows out of the default partition first. Returns its name.
+CREATE OR REPLACE FUNCTION chat_messages_ensure_partition(month DATE) RETURNS TEXT AS
+$$
+DECLARE
+    month_start TEXT := to_char(date_trunc('month', month), 'YYYY-MM-DD') || ' 00:00:00+00';
+    month_end TEXT := to_char(date_trunc('month', month) + INTERVAL '1 month', 'YYYY-MM-DD') || ' 00:00:00+00';
+    name TEXT := chat_messages_partition_name(month);
+BEGIN
+    -- Servers maintaining partitions at the same time take turns.
+    PERFORM pg_advisory_xact_lock(hashtext('chat_messages_ensure_partition'));
+
+    IF to_regclass(name) IS NOT NULL THEN
+        RETURN name;
+    END IF;
+
+    EXECUTE format('CREATE TABLE %I (LIKE chat_messages INCLUDING DEFAULTS)', name);
+    EXECUTE format(
+        'WITH moved AS (DELETE FROM chat_messages_default WHERE timestamp >= %L AND timestamp < %L RETURNING *) '
+            'INSERT INTO %I SELECT * FROM moved',
+        month_start, month_end, name);
+    EXECUTE format(
+        'ALTER TABLE chat_messages ATTACH PARTITION %I FOR VALUES FROM (%L) TO (%L)',
+        name, month_start, month_end);
+
+    RETURN name;
+END
+$$ LANGUAGE plpgsql;
+
+-- Detaches a monthly partition, dropping it unless `keep` is set, and
+-- clears what referenced its messages. Returns how many messages it held.
+CREATE OR REPLACE FUNCTION chat_messages_remove_partition(name TEXT, keep BOOLEAN) RETURNS BIGINT AS
+$$
+DECLARE
+    removed BIGINT;
+BEGIN
+    EXECUTE format('SELECT COUNT(*) FROM %I', name) INTO removed;
+    EXECUTE format('DELETE FROM reactions WHERE message_id IN (SELECT id FROM %I)', name);
+    EXECUTE format('UPDATE logs SET chat_message_id = NULL WHERE chat_message_id IN (SELECT id FROM %I)', name);
+    EXECUTE format('ALTER TABLE chat_messages DETACH PARTITION %I', name);
+
+    IF NOT keep THEN
+        EXECUTE format('DROP TABLE %I', name);
+    END IF;
+
+    RETURN removed;
+END
+$$ LANGUAGE plpgsql;
+
+DO
+$$
+DECLARE
+    month DATE;
+BEGIN
+    IF (SELECT relkind FROM pg_class WHERE oid = to_regclass('chat_messages')) IS DISTINCT FROM 'r' THEN
+        RETURN;
+    END IF;
+
+    -- Message ids are no longer unique on their own, so nothing can
+    -- reference them; deleting messages now cleans up after them instead.
+    ALTER TABLE reactions DROP CONSTRAINT IF EXISTS reactions_chat_messages_id_fk;
+    ALTER TABLE logs DROP CONSTRAINT IF EXISTS logs_chat_messages_id_fk;
+
+    ALTER TABLE chat_messages RENAME TO chat_messages_unpartitioned;
+
+    CREATE TABLE chat_messages
+    (
+        LIKE chat_messages_unpartitioned INCLUDING DEFAULTS INCLUDING IDENTITY
+    ) PARTITION BY RANGE (timestamp);
+
+    CREATE TABLE chat_messages_default PARTITION OF chat_messages DEFAULT;
+
+    FOR month IN SELECT DISTINCT date_trunc('month', timestamp)::DATE FROM chat_messages_unpartitioned
+        LOOP
+            PERFORM chat_messages_ensure_partition(month);
+        END LOOP;
+
+    INSERT INTO chat_messages (id, username, channel, timestamp, text)
+    OVERRIDING SYSTEM VALUE
+    SELECT id, username, channel, timestamp, text
+    FROM chat_messages_unpartitioned;
+
+    PERFORM setval(pg_get_serial_sequence('chat_messages', 'id'), MAX(id))
+    FROM chat_messages_unpartitioned
+    HAVING MAX(id) IS NOT NULL;
+
+    DROP TABLE chat_messages_unpartitioned;
+
+    ALTER TABLE chat_messages
+        ADD CONSTRAINT chat_messages_pk
+            PRIMARY KEY (id, timestamp),
+        ADD CONSTRAINT chat_messages_users_name_fk
+            FOREIGN KEY (username) REFERENCES users (name),
+        ADD CONSTRAINT chat_messages_users_name_fk2
+            FOREIGN KEY (channel) REFERENCES users (name);
+END
+$$;
+
+CREATE INDEX IF NOT EXISTS chat_messages_channel_timestamp_index
+    ON chat_messages (channel, timestamp);
+
+CREATE OR REPLACE VIEW chat_message_partitions AS
+SELECT child.relname::TEXT AS name,
+       to_date(right(child.relname, 7), 'YYYY_MM') AS month_start
+FROM pg_inherits
+         JOIN pg_class parent ON parent.oid = pg_inherits.inhparent
+         JOIN pg_class child ON child.oid = pg_inherits.inhrelid
+WHERE parent.relname = 'chat_messages'
+  AND child.relname ~ '^chat_messages_\d{4}_\d{2}$';
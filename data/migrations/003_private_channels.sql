@@ -0,0 +1,21 @@
+-- Lets channels be made private, readable only by their members.
+
+ALTER TABLE channels
+    ADD COLUMN IF NOT EXISTS private BOOLEAN DEFAULT FALSE NOT NULL;
+
+CREATE TABLE IF NOT EXISTS channel_members
+(
+    channel VARCHAR(25) NOT NULL
+        CONSTRAINT channel_members_channels_name_fk
+            REFERENCES channels (name)
+            ON DELETE CASCADE,
+    username VARCHAR(25) NOT NULL
+        CONSTRAINT channel_members_users_name_fk
+            REFERENCES users (name),
+    role VARCHAR(16) NOT NULL
+        CONSTRAINT channel_members_role_check
+            CHECK (role IN ('owner', 'moderator', 'member')),
+    added_at TIMESTAMPTZ DEFAULT NOW() NOT NULL,
+    CONSTRAINT channel_members_pk
+        PRIMARY KEY (channel, username)
+);
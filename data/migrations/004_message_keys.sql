@@ -0,0 +1,41 @@
+-- Remembers what makes each message unique within its channel, such as its
+-- source id or the idempotency key it was posted with. Partitioned tables
+-- can only enforce uniqueness together with the partition key, so this
+-- lives in a table of its own.
+
+ALTER TABLE chat_messages
+    ADD COLUMN IF NOT EXISTS source_id VARCHAR(64);
+
+CREATE TABLE IF NOT EXISTS message_keys
+(
+    channel VARCHAR(25) NOT NULL,
+    key VARCHAR(300) NOT NULL,
+    message_id INTEGER NOT NULL,
+    message_timestamp TIMESTAMPTZ NOT NULL,
+    created_at TIMESTAMPTZ DEFAULT NOW() NOT NULL,
+    CONSTRAINT message_keys_pk
+        PRIMARY KEY (channel, key)
+);
+
+CREATE INDEX IF NOT EXISTS message_keys_message_id_index
+    ON message_keys (message_id);
+
+-- As before, but also forgets the keys of the removed messages.
+CREATE OR REPLACE FUNCTION chat_messages_remove_partition(name TEXT, keep BOOLEAN) RETURNS BIGINT AS
+$$
+DECLARE
+    removed BIGINT;
+BEGIN
+    EXECUTE format('SELECT COUNT(*) FROM %I', name) INTO removed;
+    EXECUTE format('DELETE FROM reactions WHERE message_id IN (SELECT id FROM %I)', name);
+    EXECUTE format('UPDATE logs SET chat_message_id = NULL WHERE chat_message_id IN (SELECT id FROM %I)', name);
+    EXECUTE format('DELETE FROM message_keys WHERE message_id IN (SELECT id FROM %I)', name);
+    EXECUTE format('ALTER TABLE chat_messages DETACH PARTITION %I', name);
+
+    IF NOT keep THEN
+        EXECUTE format('DROP TABLE %I', name);
+    END IF;
+
+    RETURN removed;
+END
+$$ LANGUAGE plpgsql;
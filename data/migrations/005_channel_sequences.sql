@@ -0,0 +1,53 @@
+-- Numbers each channel's messages 1, 2, 3, ... in the order they are
+-- stored. The counter row is locked until the inserting transaction ends,
+-- so a channel's sequence numbers are committed in order and a rolled back
+-- insert leaves no gap behind.
+
+CREATE TABLE IF NOT EXISTS channel_sequences
+(
+    channel VARCHAR(25) NOT NULL
+        CONSTRAINT channel_sequences_pk
+            PRIMARY KEY,
+    last_seq BIGINT NOT NULL
+);
+
+ALTER TABLE chat_messages
+    ADD COLUMN IF NOT EXISTS seq BIGINT;
+
+UPDATE chat_messages m
+SET seq = numbered.seq
+FROM (SELECT id, timestamp, row_number() OVER (PARTITION BY channel ORDER BY timestamp, id) AS seq
+      FROM chat_messages) numbered
+WHERE m.id = numbered.id
+  AND m.timestamp = numbered.timestamp;
+
+INSERT INTO channel_sequences (channel, last_seq)
+SELECT channel, MAX(seq)
+FROM chat_messages
+GROUP BY channel
+ON CONFLICT (channel) DO UPDATE SET last_seq = EXCLUDED.last_seq;
+
+ALTER TABLE chat_messages
+    ALTER COLUMN seq SET NOT NULL;
+
+CREATE OR REPLACE FUNCTION chat_messages_assign_seq() RETURNS TRIGGER AS
+$$
+BEGIN
+    INSERT INTO channel_sequences (channel, last_seq)
+    VALUES (NEW.channel, 1)
+    ON CONFLICT (channel) DO UPDATE SET last_seq = channel_sequences.last_seq + 1
+    RETURNING last_seq INTO NEW.seq;
+
+    RETURN NEW;
+END
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS chat_messages_assign_seq ON chat_messages;
+CREATE TRIGGER chat_messages_assign_seq
+    BEFORE INSERT
+    ON chat_messages
+    FOR EACH ROW
+EXECUTE FUNCTION chat_messages_assign_seq();
+
+CREATE INDEX IF NOT EXISTS chat_messages_channel_seq_index
+    ON chat_messages (channel, seq);
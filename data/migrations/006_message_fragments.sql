@@ -0,0 +1,5 @@
+-- Each message's text split into typed fragments on ingest. Messages stored
+-- before this have none, and are parsed again when read.
+
+ALTER TABLE chat_messages
+    ADD COLUMN IF NOT EXISTS fragments JSONB;
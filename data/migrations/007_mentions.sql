@@ -0,0 +1,76 @@
+-- Every mention of a known user, recorded as messages are stored. Users are
+-- only recorded as mentioned in channels they can read.
+
+CREATE TABLE IF NOT EXISTS mentions
+(
+    id BIGINT GENERATED ALWAYS AS IDENTITY
+        CONSTRAINT mentions_pk
+            PRIMARY KEY,
+    username VARCHAR(25) NOT NULL
+        CONSTRAINT mentions_users_name_fk
+            REFERENCES users (name)
+            ON DELETE CASCADE,
+    message_id INTEGER NOT NULL,
+    message_timestamp TIMESTAMPTZ NOT NULL,
+    created_at TIMESTAMPTZ DEFAULT NOW() NOT NULL,
+    read_at TIMESTAMPTZ,
+    CONSTRAINT mentions_message_username_unique
+        UNIQUE (message_id, username)
+);
+
+CREATE INDEX IF NOT EXISTS mentions_username_id_index
+    ON mentions (username, id);
+
+CREATE INDEX IF NOT EXISTS users_lower_name_index
+    ON users (LOWER(name));
+
+CREATE OR REPLACE FUNCTION chat_messages_record_mentions() RETURNS TRIGGER AS
+$$
+BEGIN
+    INSERT INTO mentions (username, message_id, message_timestamp)
+    SELECT DISTINCT u.name, NEW.id, NEW.timestamp
+    FROM jsonb_array_elements(COALESCE(NEW.fragments, '[]'::JSONB)) AS f
+             JOIN users u ON LOWER(u.name) = LOWER(f ->> 'username')
+    WHERE f ->> 'type' = 'mention'
+      AND u.name <> NEW.username
+      AND NOT EXISTS (SELECT 1
+                      FROM channels c
+                      WHERE c.name = NEW.channel
+                        AND c.private
+                        AND NOT EXISTS (SELECT 1
+                                        FROM channel_members cm
+                                        WHERE cm.channel = c.name
+                                          AND cm.username = u.name))
+    ON CONFLICT DO NOTHING;
+
+    RETURN NULL;
+END
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS chat_messages_record_mentions ON chat_messages;
+CREATE TRIGGER chat_messages_record_mentions
+    AFTER INSERT
+    ON chat_messages
+    FOR EACH ROW
+EXECUTE FUNCTION chat_messages_record_mentions();
+
+-- As before, but also forgets the mentions in the removed messages.
+CREATE OR REPLACE FUNCTION chat_messages_remove_partition(name TEXT, keep BOOLEAN) RETURNS BIGINT AS
+$$
+DECLARE
+    removed BIGINT;
+BEGIN
+    EXECUTE format('SELECT COUNT(*) FROM %I', name) INTO removed;
+    EXECUTE format('DELETE FROM reactions WHERE message_id IN (SELECT id FROM %I)', name);
+    EXECUTE format('UPDATE logs SET chat_message_id = NULL WHERE chat_message_id IN (SELECT id FROM %I)', name);
+    EXECUTE format('DELETE FROM message_keys WHERE message_id IN (SELECT id FROM %I)', name);
+    EXECUTE format('DELETE FROM mentions WHERE message_id IN (SELECT id FROM %I)', name);
+    EXECUTE format('ALTER TABLE chat_messages DETACH PARTITION %I', name);
+
+    IF NOT keep THEN
+        EXECUTE format('DROP TABLE %I', name);
+    END IF;
+
+    RETURN removed;
+END
+$$ LANGUAGE plpgsql;
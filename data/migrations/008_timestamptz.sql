@@ -0,0 +1,97 @@
+-- Stores message, reaction and log timestamps with their time zone. The
+-- naive timestamps written so far were all in UTC. A partition key cannot
+-- change type, so chat_messages is rebuilt around its partitions, each of
+-- which is converted on its own.
+
+SET LOCAL TimeZone = 'UTC';
+
+DO
+$$
+DECLARE
+    partitions TEXT[];
+    bounds TEXT[];
+BEGIN
+    IF (SELECT atttypid::REGTYPE FROM pg_attribute
+        WHERE attrelid = 'chat_messages'::REGCLASS AND attname = 'timestamp') = 'timestamptz'::REGTYPE THEN
+        RETURN;
+    END IF;
+
+    SELECT array_agg(child.relname::TEXT), array_agg(pg_get_expr(child.relpartbound, child.oid))
+    INTO partitions, bounds
+    FROM pg_inherits
+             JOIN pg_class child ON child.oid = pg_inherits.inhrelid
+    WHERE pg_inherits.inhparent = 'chat_messages'::REGCLASS;
+
+    FOR i IN 1 .. coalesce(array_length(partitions, 1), 0)
+        LOOP
+            EXECUTE format('ALTER TABLE chat_messages DETACH PARTITION %I', partitions[i]);
+        END LOOP;
+
+    DROP TABLE chat_messages;
+
+    CREATE TABLE chat_messages
+    (
+        id INTEGER GENERATED ALWAYS AS IDENTITY,
+        username VARCHAR(25) NOT NULL
+            CONSTRAINT chat_messages_users_name_fk
+                REFERENCES users (name),
+        channel VARCHAR(25) NOT NULL
+            CONSTRAINT chat_messages_users_name_fk2
+                REFERENCES users (name),
+        timestamp TIMESTAMPTZ NOT NULL,
+        text VARCHAR(500) NOT NULL,
+        source_id VARCHAR(64),
+        seq BIGINT NOT NULL,
+        fragments JSONB,
+        CONSTRAINT chat_messages_pk
+            PRIMARY KEY (id, timestamp)
+    ) PARTITION BY RANGE (timestamp);
+
+    CREATE INDEX chat_messages_channel_timestamp_index
+        ON chat_messages (channel, timestamp);
+    CREATE INDEX chat_messages_channel_seq_index
+        ON chat_messages (channel, seq);
+
+    -- Bounds are written with an explicit +00, so they keep their meaning.
+    FOR i IN 1 .. coalesce(array_length(partitions, 1), 0)
+        LOOP
+            EXECUTE format(
+                'ALTER TABLE %I ALTER COLUMN timestamp TYPE TIMESTAMPTZ USING timestamp AT TIME ZONE ''UTC''',
+                partitions[i]);
+            EXECUTE format('ALTER TABLE chat_messages ATTACH PARTITION %I %s', partitions[i], bounds[i]);
+        END LOOP;
+
+    PERFORM setval(pg_get_serial_sequence('chat_messages', 'id'), MAX(id))
+    FROM chat_messages
+    HAVING MAX(id) IS NOT NULL;
+
+    CREATE TRIGGER chat_messages_assign_seq
+        BEFORE INSERT
+        ON chat_messages
+        FOR EACH ROW
+    EXECUTE FUNCTION chat_messages_assign_seq();
+
+    CREATE TRIGGER chat_messages_record_mentions
+        AFTER INSERT
+        ON chat_messages
+        FOR EACH ROW
+    EXECUTE FUNCTION chat_messages_record_mentions();
+END
+$$;
+
+DO
+$$
+BEGIN
+    IF (SELECT atttypid::REGTYPE FROM pg_attribute
+        WHERE attrelid = 'reactions'::REGCLASS AND attname = 'timestamp') = 'timestamp'::REGTYPE THEN
+        ALTER TABLE reactions
+            ALTER COLUMN timestamp TYPE TIMESTAMPTZ USING timestamp AT TIME ZONE 'UTC';
+    END IF;
+
+    IF (SELECT atttypid::REGTYPE FROM pg_attribute
+        WHERE attrelid = 'logs'::REGCLASS AND attname = 'timestamp') = 'timestamp'::REGTYPE THEN
+        ALTER TABLE logs
+            ALTER COLUMN timestamp TYPE TIMESTAMPTZ USING timestamp AT TIME ZONE 'UTC';
+    END IF;
+END
+$$;
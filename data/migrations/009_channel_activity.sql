@@ -0,0 +1,92 @@
+-- Keeps how many messages each channel holds, and when the latest was sent,
+-- next to its sequence counter, so listing channels does not scan
+-- chat_messages. Inserts already lock the counter row, so keeping them up to
+-- date there adds no contention.
+
+ALTER TABLE channel_sequences
+    ADD COLUMN IF NOT EXISTS message_count BIGINT NOT NULL DEFAULT 0,
+    ADD COLUMN IF NOT EXISTS last_activity TIMESTAMPTZ;
+
+INSERT INTO channel_sequences (channel, last_seq, message_count, last_activity)
+SELECT channel, MAX(seq), COUNT(*), MAX(timestamp)
+FROM chat_messages
+GROUP BY channel
+ON CONFLICT (channel) DO UPDATE SET message_count = EXCLUDED.message_count,
+                                    last_activity = EXCLUDED.last_activity;
+
+-- As before, but also counts the message.
+CREATE OR REPLACE FUNCTION chat_messages_assign_seq() RETURNS TRIGGER AS
+$$
+BEGIN
+    INSERT INTO channel_sequences (channel, last_seq, message_count, last_activity)
+    VALUES (NEW.channel, 1, 1, NEW.timestamp)
+    ON CONFLICT (channel) DO UPDATE SET last_seq = channel_sequences.last_seq + 1,
+                                        message_count = channel_sequences.message_count + 1,
+                                        last_activity = GREATEST(channel_sequences.last_activity, NEW.timestamp)
+    RETURNING last_seq INTO NEW.seq;
+
+    RETURN NEW;
+END
+$$ LANGUAGE plpgsql;
+
+-- Takes the given per channel counts of removed messages off the channels,
+-- whose latest activity is looked up again if it may have been removed.
+CREATE OR REPLACE FUNCTION channel_sequences_forget(channels TEXT[], counts BIGINT[], latest TIMESTAMPTZ[]) RETURNS VOID AS
+$$
+UPDATE channel_sequences s
+SET message_count = GREATEST(s.message_count - removed.count, 0),
+    last_activity = CASE
+                        WHEN removed.latest < s.last_activity THEN s.last_activity
+                        ELSE (SELECT MAX(m.timestamp) FROM chat_messages m WHERE m.channel = s.channel)
+        END
+FROM unnest(channels, counts, latest) AS removed(channel, count, latest)
+WHERE s.channel = removed.channel
+$$ LANGUAGE SQL;
+
+CREATE OR REPLACE FUNCTION chat_messages_forget_deleted() RETURNS TRIGGER AS
+$$
+BEGIN
+    PERFORM channel_sequences_forget(array_agg(channel), array_agg(count), array_agg(latest))
+    FROM (SELECT channel, COUNT(*) AS count, MAX(timestamp) AS latest FROM deleted GROUP BY channel) removed;
+
+    RETURN NULL;
+END
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS chat_messages_forget_deleted ON chat_messages;
+CREATE TRIGGER chat_messages_forget_deleted
+    AFTER DELETE
+    ON chat_messages
+    REFERENCING OLD TABLE AS deleted
+    FOR EACH STATEMENT
+EXECUTE FUNCTION chat_messages_forget_deleted();
+
+-- As before, but also takes the removed messages off their channels.
+CREATE OR REPLACE FUNCTION chat_messages_remove_partition(name TEXT, keep BOOLEAN) RETURNS BIGINT AS
+$$
+DECLARE
+    removed BIGINT;
+    channels TEXT[];
+    counts BIGINT[];
+    latest TIMESTAMPTZ[];
+BEGIN
+    EXECUTE format('SELECT COUNT(*) FROM %I', name) INTO removed;
+    EXECUTE format(
+        'SELECT array_agg(channel), array_agg(count), array_agg(latest) '
+            'FROM (SELECT channel, COUNT(*) AS count, MAX(timestamp) AS latest FROM %I GROUP BY channel) removed',
+        name) INTO channels, counts, latest;
+    EXECUTE format('DELETE FROM reactions WHERE message_id IN (SELECT id FROM %I)', name);
+    EXECUTE format('UPDATE logs SET chat_message_id = NULL WHERE chat_message_id IN (SELECT id FROM %I)', name);
+    EXECUTE format('DELETE FROM message_keys WHERE message_id IN (SELECT id FROM %I)', name);
+    EXECUTE format('DELETE FROM mentions WHERE message_id IN (SELECT id FROM %I)', name);
+    EXECUTE format('ALTER TABLE chat_messages DETACH PARTITION %I', name);
+
+    IF NOT keep THEN
+        EXECUTE format('DROP TABLE %I', name);
+    END IF;
+
+    PERFORM channel_sequences_forget(channels, counts, latest);
+
+    RETURN removed;
+END
+$$ LANGUAGE plpgsql;
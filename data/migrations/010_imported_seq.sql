@@ -0,0 +1,29 @@
+-- Imported messages are numbered 0 rather than taking their channel's next
+-- sequence number, which would place years old logs after everything posted
+-- live. History puts them before every live message, and subscribers
+-- resuming after a sequence number never replay them.
+
+-- As before, but leaves messages inserted with a sequence number of 0, which
+-- are imported, numbered 0 while still counting them.
+CREATE OR REPLACE FUNCTION chat_messages_assign_seq() RETURNS TRIGGER AS
+$$
+BEGIN
+    IF NEW.seq = 0 THEN
+        INSERT INTO channel_sequences (channel, last_seq, message_count, last_activity)
+        VALUES (NEW.channel, 0, 1, NEW.timestamp)
+        ON CONFLICT (channel) DO UPDATE SET message_count = channel_sequences.message_count + 1,
+                                            last_activity = GREATEST(channel_sequences.last_activity, NEW.timestamp);
+
+        RETURN NEW;
+    END IF;
+
+    INSERT INTO channel_sequences (channel, last_seq, message_count, last_activity)
+    VALUES (NEW.channel, 1, 1, NEW.timestamp)
+    ON CONFLICT (channel) DO UPDATE SET last_seq = channel_sequences.last_seq + 1,
+                                        message_count = channel_sequences.message_count + 1,
+                                        last_activity = GREATEST(channel_sequences.last_activity, NEW.timestamp)
+    RETURNING last_seq INTO NEW.seq;
+
+    RETURN NEW;
+END
+$$ LANGUAGE plpgsql;
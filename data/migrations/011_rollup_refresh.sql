@@ -0,0 +1,120 @@
+-- Rollups used to be refreshed from their latest hour on, so messages stored
+-- with older timestamps, such as late or imported ones, never reached them,
+-- and removed messages stayed counted. Now every insert records the earliest
+-- hour it changed next to its channel's sequence counter, which a refresh
+-- recomputes from, and removed messages are taken off the rollups as they
+-- go. Hours start in UTC whatever the session's time zone.
+
+CREATE OR REPLACE FUNCTION chat_messages_hour(at TIMESTAMPTZ) RETURNS TIMESTAMPTZ AS
+$$
+SELECT date_trunc('hour', at AT TIME ZONE 'UTC') AT TIME ZONE 'UTC'
+$$ LANGUAGE SQL IMMUTABLE;
+
+ALTER TABLE channel_sequences
+    ADD COLUMN IF NOT EXISTS rollups_stale_from TIMESTAMPTZ;
+
+-- Hours started in another time zone are dropped, and the rest recomputed
+-- on the first refresh.
+DELETE FROM chat_message_rollups
+WHERE bucket_start <> chat_messages_hour(bucket_start);
+
+UPDATE channel_sequences
+SET rollups_stale_from = '-infinity';
+
+-- As before, but also marks the message's hour as stale.
+CREATE OR REPLACE FUNCTION chat_messages_assign_seq() RETURNS TRIGGER AS
+$$
+BEGIN
+    IF NEW.seq = 0 THEN
+        INSERT INTO channel_sequences (channel, last_seq, message_count, last_activity, rollups_stale_from)
+        VALUES (NEW.channel, 0, 1, NEW.timestamp, NEW.timestamp)
+        ON CONFLICT (channel) DO UPDATE SET message_count = channel_sequences.message_count + 1,
+                                            last_activity = GREATEST(channel_sequences.last_activity, NEW.timestamp),
+                                            rollups_stale_from = LEAST(channel_sequences.rollups_stale_from, NEW.timestamp);
+
+        RETURN NEW;
+    END IF;
+
+    INSERT INTO channel_sequences (channel, last_seq, message_count, last_activity, rollups_stale_from)
+    VALUES (NEW.channel, 1, 1, NEW.timestamp, NEW.timestamp)
+    ON CONFLICT (channel) DO UPDATE SET last_seq = channel_sequences.last_seq + 1,
+                                        message_count = channel_sequences.message_count + 1,
+                                        last_activity = GREATEST(channel_sequences.last_activity, NEW.timestamp),
+                                        rollups_stale_from = LEAST(channel_sequences.rollups_stale_from, NEW.timestamp)
+    RETURNING last_seq INTO NEW.seq;
+
+    RETURN NEW;
+END
+$$ LANGUAGE plpgsql;
+
+-- Takes the given per chatter counts of removed messages off the rollups of
+-- their hours.
+CREATE OR REPLACE FUNCTION chat_message_rollups_forget(channels TEXT[], hours TIMESTAMPTZ[], usernames TEXT[], counts BIGINT[]) RETURNS VOID AS
+$$
+UPDATE chat_message_rollups r
+SET message_count = r.message_count - removed.count
+FROM unnest(channels, hours, usernames, counts) AS removed(channel, bucket_start, username, count)
+WHERE r.channel = removed.channel
+  AND r.bucket_start = removed.bucket_start
+  AND r.username = removed.username;
+
+DELETE
+FROM chat_message_rollups
+WHERE message_count <= 0
+  AND (channel, bucket_start, username) IN (SELECT * FROM unnest(channels, hours, usernames));
+$$ LANGUAGE SQL;
+
+-- As before, but also takes the removed messages off the rollups.
+CREATE OR REPLACE FUNCTION chat_messages_forget_deleted() RETURNS TRIGGER AS
+$$
+BEGIN
+    PERFORM channel_sequences_forget(array_agg(channel), array_agg(count), array_agg(latest))
+    FROM (SELECT channel, COUNT(*) AS count, MAX(timestamp) AS latest FROM deleted GROUP BY channel) removed;
+
+    PERFORM chat_message_rollups_forget(array_agg(channel), array_agg(hour), array_agg(username), array_agg(count))
+    FROM (SELECT channel, chat_messages_hour(timestamp) AS hour, username, COUNT(*) AS count
+          FROM deleted
+          GROUP BY 1, 2, 3) removed;
+
+    RETURN NULL;
+END
+$$ LANGUAGE plpgsql;
+
+-- As before, but also takes the removed messages off the rollups.
+CREATE OR REPLACE FUNCTION chat_messages_remove_partition(name TEXT, keep BOOLEAN) RETURNS BIGINT AS
+$$
+DECLARE
+    removed BIGINT;
+    channels TEXT[];
+    counts BIGINT[];
+    latest TIMESTAMPTZ[];
+    hour_channels TEXT[];
+    hours TIMESTAMPTZ[];
+    usernames TEXT[];
+    hour_counts BIGINT[];
+BEGIN
+    EXECUTE format('SELECT COUNT(*) FROM %I', name) INTO removed;
+    EXECUTE format(
+        'SELECT array_agg(channel), array_agg(count), array_agg(latest) '
+            'FROM (SELECT channel, COUNT(*) AS count, MAX(timestamp) AS latest FROM %I GROUP BY channel) removed',
+        name) INTO channels, counts, latest;
+    EXECUTE format(
+        'SELECT array_agg(channel), array_agg(hour), array_agg(username), array_agg(count) '
+            'FROM (SELECT channel, chat_messages_hour(timestamp) AS hour, username, COUNT(*) AS count FROM %I GROUP BY 1, 2, 3) removed',
+        name) INTO hour_channels, hours, usernames, hour_counts;
+    EXECUTE format('DELETE FROM reactions WHERE message_id IN (SELECT id FROM %I)', name);
+    EXECUTE format('UPDATE logs SET chat_message_id = NULL WHERE chat_message_id IN (SELECT id FROM %I)', name);
+    EXECUTE format('DELETE FROM message_keys WHERE message_id IN (SELECT id FROM %I)', name);
+    EXECUTE format('DELETE FROM mentions WHERE message_id IN (SELECT id FROM %I)', name);
+    EXECUTE format('ALTER TABLE chat_messages DETACH PARTITION %I', name);
+
+    IF NOT keep THEN
+        EXECUTE format('DROP TABLE %I', name);
+    END IF;
+
+    PERFORM channel_sequences_forget(channels, counts, latest);
+    PERFORM chat_message_rollups_forget(hour_channels, hours, usernames, hour_counts);
+
+    RETURN removed;
+END
+$$ LANGUAGE plpgsql;
@@ -0,0 +1,11 @@
+CREATE TABLE reactions
+(
+    message_id INTEGER NOT NULL,
+    username VARCHAR(25) NOT NULL
+        CONSTRAINT reactions_users_name_fk
+            REFERENCES users (name),
+    emote VARCHAR(64) NOT NULL,
+    timestamp TIMESTAMPTZ DEFAULT NOW() NOT NULL,
+    CONSTRAINT reactions_pk
+        PRIMARY KEY (message_id, username, emote)
+);
@@ -0,0 +1,189 @@
+use std::sync::Arc;
+
+use actix_web::error::{ErrorBadRequest, ErrorConflict};
+use actix_web::{delete, get, post, web, HttpResponse};
+use serde::Deserialize;
+use serde_json::json;
+use time::OffsetDateTime;
+
+use crate::api::auth::AdminUser;
+use crate::models::retention::RetentionPolicy;
+use crate::server::replay::{self, HubSink, ReplaySource, ReplaySpeed};
+use crate::server::server_state::ServerState;
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct RetentionPreviewQuery {
+    keep_days: Option<u32>,
+    keep_messages: Option<u32>,
+}
+
+/// Reports what a retention policy would remove from a channel right now,
+/// without removing anything. Previews the channel's configured policy
+/// unless one is given in the query.
+#[get("/retention/{channel}")]
+pub(crate) async fn retention_preview(
+    data: web::Data<ServerState>,
+    _admin: AdminUser,
+    path: web::Path<String>,
+    query: web::Query<RetentionPreviewQuery>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let channel = path.into_inner();
+
+    let policy = match (query.keep_days, query.keep_messages) {
+        (None, None) => data.retention.config().policy_for(&channel),
+        (Some(days), None) => RetentionPolicy::KeepDays(days),
+        (None, Some(messages)) => RetentionPolicy::KeepMessages(messages),
+        (Some(_), Some(_)) => {
+            return Err(ErrorBadRequest(
+                "Specify at most one of keep_days and keep_messages",
+            ))
+        }
+    };
+
+    let now = data.clock.now();
+    let preview = data.retention.preview(&channel, policy, now).await?;
+
+    Ok(HttpResponse::Ok().json(preview))
+}
+
+/// The state of every supervised chat stream.
+#[get("/streams")]
+pub(crate) async fn streams_index(
+    data: web::Data<ServerState>,
+    _admin: AdminUser,
+) -> Result<HttpResponse, actix_web::Error> {
+    Ok(HttpResponse::Ok().json(data.supervisor.statuses()))
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct NewReplay {
+    channel: String,
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    from: Option<OffsetDateTime>,
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    to: Option<OffsetDateTime>,
+    /// A factor such as 10, or "max". Real time by default.
+    #[serde(default)]
+    speed: ReplaySpeed,
+}
+
+/// Starts replaying the `[from, to)` part of a channel's log to the
+/// websocket subscribers of its replay channel, as if it were live. The
+/// replay shows up among the supervised streams under that channel's name.
+#[post("/replay")]
+pub(crate) async fn replay_post(
+    data: web::Data<ServerState>,
+    _admin: AdminUser,
+    replay: web::Json<NewReplay>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let replay = replay.into_inner();
+    if let (Some(from), Some(to)) = (replay.from, replay.to) {
+        if from >= to {
+            return Err(ErrorBadRequest("from must be before to"));
+        }
+    }
+
+    let name = replay::replay_channel(&replay.channel);
+    let source = ReplaySource::new(
+        data.repo.clone(),
+        replay.channel,
+        replay.from,
+        replay.to,
+        replay.speed,
+    );
+    let sink = HubSink::new(data.hub.clone(), name.clone());
+
+    data.supervisor
+        .supervise(&name, Arc::new(source), Arc::new(sink))
+        .await
+        .map_err(|_| ErrorConflict("The channel is already being replayed"))?;
+
+    Ok(HttpResponse::Accepted().json(json!({ "channel": name })))
+}
+
+/// Stops replaying a channel.
+#[delete("/replay/{channel}")]
+pub(crate) async fn replay_delete(
+    data: web::Data<ServerState>,
+    _admin: AdminUser,
+    path: web::Path<String>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let name = replay::replay_channel(&path.into_inner());
+
+    match data.supervisor.stop(&name) {
+        true => Ok(HttpResponse::NoContent().finish()),
+        false => Ok(HttpResponse::NotFound().finish()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::{init_service, TestRequest};
+    use actix_web::{test, web};
+    use serde_json::json;
+    use test_context::test_context;
+
+    use crate::api::admin::{replay_delete, replay_post, retention_preview, streams_index};
+    use crate::api::tests::{setup_app, ServerTestContext};
+    use crate::error::Error;
+
+    #[test_context(ServerTestContext)]
+    #[test]
+    async fn api_test_retention_preview_requires_admin(
+        ctx: &ServerTestContext,
+    ) -> Result<(), Error> {
+        let app = setup_app(&ctx.config)
+            .await
+            .service(web::scope("/admin").service(retention_preview));
+        let service = init_service(app).await;
+
+        let req = TestRequest::get()
+            .uri("/admin/retention/chan?keep_days=30")
+            .to_request();
+        let resp = test::call_service(&service, req).await;
+
+        assert_eq!(resp.status(), 401);
+
+        Ok(())
+    }
+
+    #[test_context(ServerTestContext)]
+    #[test]
+    async fn api_test_streams_require_admin(ctx: &ServerTestContext) -> Result<(), Error> {
+        let app = setup_app(&ctx.config)
+            .await
+            .service(web::scope("/admin").service(streams_index));
+        let service = init_service(app).await;
+
+        let req = TestRequest::get().uri("/admin/streams").to_request();
+        let resp = test::call_service(&service, req).await;
+
+        assert_eq!(resp.status(), 401);
+
+        Ok(())
+    }
+
+    #[test_context(ServerTestContext)]
+    #[test]
+    async fn api_test_replay_requires_admin(ctx: &ServerTestContext) -> Result<(), Error> {
+        let app = setup_app(&ctx.config).await.service(
+            web::scope("/admin")
+                .service(replay_post)
+                .service(replay_delete),
+        );
+        let service = init_service(app).await;
+
+        let req = TestRequest::post()
+            .uri("/admin/replay")
+            .set_json(json!({ "channel": "chan", "speed": 10 }))
+            .to_request();
+        let resp = test::call_service(&service, req).await;
+        assert_eq!(resp.status(), 401);
+
+        let req = TestRequest::delete().uri("/admin/replay/chan").to_request();
+        let resp = test::call_service(&service, req).await;
+        assert_eq!(resp.status(), 401);
+
+        Ok(())
+    }
+}
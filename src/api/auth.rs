@@ -0,0 +1,146 @@
+use std::future::{ready, Ready};
+
+use actix_web::dev::Payload;
+use actix_web::error::{ErrorForbidden, ErrorInternalServerError, ErrorUnauthorized};
+use actix_web::http::header::AUTHORIZATION;
+use actix_web::{web, FromRequest, HttpRequest};
+
+use crate::models::channel_member::{ChannelAccess, ChannelRole};
+use crate::server::server_state::ServerState;
+
+/// The user a request was made on behalf of, as identified by the bearer
+/// token in its `Authorization` header.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuthenticatedUser(pub String);
+
+impl AuthenticatedUser {
+    pub fn name(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromRequest for AuthenticatedUser {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        ready(authenticate(req))
+    }
+}
+
+/// An authenticated user who is also listed in `api.admins`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AdminUser(pub AuthenticatedUser);
+
+impl FromRequest for AdminUser {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        ready(authenticate(req).and_then(|user| authorize_admin(req, user)))
+    }
+}
+
+fn authorize_admin(
+    req: &HttpRequest,
+    user: AuthenticatedUser,
+) -> Result<AdminUser, actix_web::Error> {
+    let state = req
+        .app_data::<web::Data<ServerState>>()
+        .ok_or_else(|| ErrorInternalServerError("Server state is not configured"))?;
+
+    if state.admins.contains(user.name()) {
+        Ok(AdminUser(user))
+    } else {
+        Err(ErrorForbidden("Admin access required"))
+    }
+}
+
+fn authenticate(req: &HttpRequest) -> Result<AuthenticatedUser, actix_web::Error> {
+    let state = req
+        .app_data::<web::Data<ServerState>>()
+        .ok_or_else(|| ErrorInternalServerError("Server state is not configured"))?;
+
+    let token = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .ok_or_else(|| ErrorUnauthorized("Missing bearer token"))?;
+
+    state
+        .tokens
+        .get(token.trim())
+        .map(|username| AuthenticatedUser(username.clone()))
+        .ok_or_else(|| ErrorUnauthorized("Invalid bearer token"))
+}
+
+/// Makes sure `user`, or an anonymous client if `None`, may read and post
+/// in the channel, returning what else they may do there.
+pub(crate) async fn authorize_channel(
+    state: &ServerState,
+    channel: &str,
+    user: Option<&AuthenticatedUser>,
+) -> Result<ChannelAccess, actix_web::Error> {
+    let access = state
+        .channels
+        .get_access(channel, user.map(|user| user.name()))
+        .await?;
+
+    match (access.can_read(), user) {
+        (true, _) => Ok(access),
+        (false, None) => Err(ErrorUnauthorized("Sign in to access this channel")),
+        (false, Some(_)) => Err(ErrorForbidden("Not a member of this channel")),
+    }
+}
+
+/// Makes sure `user` may give `member` the role `role`, or remove them if
+/// `None`. Owners can change anyone, moderators only plain members.
+pub(crate) async fn authorize_member_change(
+    state: &ServerState,
+    channel: &str,
+    user: Option<&AuthenticatedUser>,
+    member: &str,
+    role: Option<ChannelRole>,
+) -> Result<ChannelAccess, actix_web::Error> {
+    let access = authorize_channel(state, channel, user).await?;
+    let current = state.channels.get_access(channel, Some(member)).await?.role;
+
+    let allowed = access.can_manage(ChannelRole::Member)
+        && [current, role]
+            .into_iter()
+            .flatten()
+            .all(|role| access.can_manage(role));
+    match (allowed, user) {
+        (true, _) => Ok(access),
+        (false, None) => Err(ErrorUnauthorized("Sign in to manage this channel")),
+        (false, Some(_)) => Err(ErrorForbidden(
+            "Only owners can manage owners and moderators of this channel",
+        )),
+    }
+}
+
+/// Makes sure `user` may change or delete the channel: its owners can, and
+/// admins can if it has no owner.
+pub(crate) async fn authorize_channel_manager(
+    state: &ServerState,
+    channel: &str,
+    user: Option<&AuthenticatedUser>,
+) -> Result<ChannelAccess, actix_web::Error> {
+    let access = authorize_channel(state, channel, user).await?;
+    if access.is_owner() {
+        return Ok(access);
+    }
+
+    let Some(user) = user else {
+        return Err(ErrorUnauthorized("Sign in to manage this channel"));
+    };
+
+    let members = state.channels.list_members(channel).await?;
+    let owned = members.iter().any(|m| m.role == ChannelRole::Owner);
+    match (owned, state.admins.contains(user.name())) {
+        (false, true) => Ok(access),
+        (true, _) => Err(ErrorForbidden("Only owners can manage this channel")),
+        (false, false) => Err(ErrorForbidden("Admin access required")),
+    }
+}
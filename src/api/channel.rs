@@ -1,43 +1,688 @@
-use actix_web::{get, HttpResponse, Responder, web};
-use tokio::count;
+use actix_web::error::{ErrorForbidden, ErrorUnauthorized};
+use actix_web::http::header::{ContentDisposition, DispositionParam, DispositionType};
+use actix_web::{delete, get, patch, post, put, web, HttpResponse};
+use futures::TryStreamExt;
+use serde::Deserialize;
+use time::{Duration, OffsetDateTime};
 
+use crate::api::auth::{
+    authorize_channel, authorize_channel_manager, authorize_member_change, AuthenticatedUser,
+};
+use crate::error::Error;
+use crate::models::channel::{ChannelUpdate, NewChannel};
+use crate::models::channel_member::ChannelRole;
+use crate::models::channel_stats::StatsBucket;
+use crate::models::chat_message::ChatMessage;
+use crate::models::reaction::ReactedChatMessage;
 use crate::server::server_state::ServerState;
+use crate::utils::export::{self, ExportFormat};
+use crate::utils::tz::TimeZone;
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct ChannelIndexQuery {
+    #[serde(default)]
+    include_archived: bool,
+}
 
 #[get("/")]
-pub(crate) async fn channel_index() -> impl Responder {
-    HttpResponse::Ok().body("Channel index")
+pub(crate) async fn channel_index(
+    data: web::Data<ServerState>,
+    user: Option<AuthenticatedUser>,
+    query: web::Query<ChannelIndexQuery>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let username = user.as_ref().map(|user| user.name());
+    let channels = data
+        .channels
+        .list_channels(query.include_archived, username)
+        .await?;
+
+    Ok(HttpResponse::Ok().json(channels))
+}
+
+/// Registers a channel, owned by the signed-in user if there is one.
+/// Private channels need an owner. Only admins can register a channel that
+/// already has messages, as its owner could otherwise hide them from
+/// everyone else.
+#[post("/")]
+pub(crate) async fn channel_post(
+    data: web::Data<ServerState>,
+    user: Option<AuthenticatedUser>,
+    channel: web::Json<NewChannel>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let owner = user.as_ref().map(|user| user.name());
+    if channel.private && owner.is_none() {
+        return Err(ErrorUnauthorized("Sign in to create a private channel"));
+    }
+
+    let admin = owner.is_some_and(|owner| data.admins.contains(owner));
+    if !admin && data.channels.has_messages(&channel.name).await? {
+        return Err(ErrorForbidden(
+            "Only admins can register a channel that already has messages",
+        ));
+    }
+
+    match data.channels.add_channel(&channel, owner).await? {
+        Some(channel) => Ok(HttpResponse::Created().json(channel)),
+        None => Ok(HttpResponse::Conflict().finish()),
+    }
+}
+
+#[get("/{channel}")]
+pub(crate) async fn channel_info(
+    data: web::Data<ServerState>,
+    user: Option<AuthenticatedUser>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let channel = path.into_inner();
+    authorize_channel(&data, &channel, user.as_ref()).await?;
+
+    match data.channels.get_channel(&channel).await? {
+        Some(channel) => Ok(HttpResponse::Ok().json(channel)),
+        None => Ok(HttpResponse::NotFound().finish()),
+    }
+}
+
+/// Only owners can change a channel, or admins if it has no owner.
+#[patch("/{channel}")]
+pub(crate) async fn channel_patch(
+    data: web::Data<ServerState>,
+    user: Option<AuthenticatedUser>,
+    path: web::Path<String>,
+    update: web::Json<ChannelUpdate>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let channel = path.into_inner();
+
+    authorize_channel_manager(&data, &channel, user.as_ref()).await?;
+
+    match data.channels.update_channel(&channel, &update).await? {
+        Some(channel) => Ok(HttpResponse::Ok().json(channel)),
+        None => Ok(HttpResponse::NotFound().finish()),
+    }
+}
+
+/// Only owners can delete a channel, or admins if it has no owner.
+#[delete("/{channel}")]
+pub(crate) async fn channel_delete(
+    data: web::Data<ServerState>,
+    user: Option<AuthenticatedUser>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let channel = path.into_inner();
+
+    authorize_channel_manager(&data, &channel, user.as_ref()).await?;
+
+    match data.channels.delete_channel(&channel).await? {
+        true => Ok(HttpResponse::NoContent().finish()),
+        false => Ok(HttpResponse::NotFound().finish()),
+    }
+}
+
+const DEFAULT_TOP_CHATTERS: i64 = 10;
+const MAX_TOP_CHATTERS: i64 = 100;
+const MAX_STATS_BUCKETS: i64 = 10_000;
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct StatsQuery {
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    from: Option<OffsetDateTime>,
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    to: Option<OffsetDateTime>,
+    #[serde(default)]
+    bucket: StatsBucket,
+    top: Option<i64>,
+}
+
+/// Defaults to the last day of activity, bucketed by hour.
+#[get("/{channel}/stats")]
+pub(crate) async fn channel_stats(
+    data: web::Data<ServerState>,
+    user: Option<AuthenticatedUser>,
+    path: web::Path<String>,
+    query: web::Query<StatsQuery>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let channel = path.into_inner();
+    authorize_channel(&data, &channel, user.as_ref()).await?;
+    let to = query.to.unwrap_or_else(|| data.clock.now());
+    let from = query.from.unwrap_or(to - Duration::DAY);
+
+    if from >= to {
+        return Ok(HttpResponse::BadRequest().body("`from` must be before `to`"));
+    }
+
+    if query.bucket.count_in(from, to) > MAX_STATS_BUCKETS {
+        return Ok(HttpResponse::BadRequest().body("Too many buckets, use a larger bucket"));
+    }
+
+    let top = query.top.unwrap_or(DEFAULT_TOP_CHATTERS);
+    if !(1..=MAX_TOP_CHATTERS).contains(&top) {
+        let message = format!("`top` must be between 1 and {}", MAX_TOP_CHATTERS);
+        return Ok(HttpResponse::BadRequest().body(message));
+    }
+    let stats = data
+        .stats
+        .get_channel_stats(&channel, from, to, query.bucket, top)
+        .await?;
+
+    Ok(HttpResponse::Ok().json(stats))
+}
+
+/// Who is watching the channel over websockets on this instance.
+#[get("/{channel}/presence")]
+pub(crate) async fn channel_presence(
+    data: web::Data<ServerState>,
+    user: Option<AuthenticatedUser>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let channel = path.into_inner();
+    authorize_channel(&data, &channel, user.as_ref()).await?;
+
+    Ok(HttpResponse::Ok().json(data.presence.get(&channel)))
+}
+
+#[get("/{channel}/members")]
+pub(crate) async fn channel_members(
+    data: web::Data<ServerState>,
+    user: Option<AuthenticatedUser>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let channel = path.into_inner();
+    authorize_channel(&data, &channel, user.as_ref()).await?;
+
+    let members = data.channels.list_members(&channel).await?;
+
+    Ok(HttpResponse::Ok().json(members))
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct MemberRequest {
+    role: ChannelRole,
+}
+
+/// Invites a user to the channel, or changes their role. Owners only.
+#[put("/{channel}/members/{user}")]
+pub(crate) async fn channel_member_put(
+    data: web::Data<ServerState>,
+    user: Option<AuthenticatedUser>,
+    path: web::Path<(String, String)>,
+    request: web::Json<MemberRequest>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let (channel, member) = path.into_inner();
+    authorize_member_change(&data, &channel, user.as_ref(), &member, Some(request.role)).await?;
+
+    if request.role != ChannelRole::Owner && is_last_owner(&data, &channel, &member).await? {
+        return Ok(HttpResponse::Conflict().body("A channel needs at least one owner"));
+    }
+
+    let member = data
+        .channels
+        .set_member(&channel, &member, request.role)
+        .await?;
+
+    Ok(HttpResponse::Ok().json(member))
+}
+
+/// Removes a member from the channel. Owners can remove anyone,
+/// moderators plain members, and members can remove themselves.
+#[delete("/{channel}/members/{user}")]
+pub(crate) async fn channel_member_delete(
+    data: web::Data<ServerState>,
+    user: Option<AuthenticatedUser>,
+    path: web::Path<(String, String)>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let (channel, member) = path.into_inner();
+
+    let leaving = user.as_ref().map(|user| user.name()) == Some(member.as_str());
+    if !leaving {
+        authorize_member_change(&data, &channel, user.as_ref(), &member, None).await?;
+    }
+
+    if is_last_owner(&data, &channel, &member).await? {
+        return Ok(HttpResponse::Conflict().body("A channel needs at least one owner"));
+    }
+
+    match data.channels.remove_member(&channel, &member).await? {
+        true => Ok(HttpResponse::NoContent().finish()),
+        false => Ok(HttpResponse::NotFound().finish()),
+    }
+}
+
+async fn is_last_owner(data: &ServerState, channel: &str, username: &str) -> Result<bool, Error> {
+    let members = data.channels.list_members(channel).await?;
+    let mut owners = members.iter().filter(|m| m.role == ChannelRole::Owner);
+
+    Ok(owners.clone().count() == 1 && owners.any(|m| m.username == username))
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct ExportQuery {
+    #[serde(default)]
+    format: ExportFormat,
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    from: Option<OffsetDateTime>,
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    to: Option<OffsetDateTime>,
+    #[serde(default, deserialize_with = "crate::utils::tz::deserialize")]
+    tz: Option<TimeZone>,
+}
+
+/// Streams the channel's whole log, or the `[from, to)` part of it.
+#[get("/{channel}/export")]
+pub(crate) async fn channel_export(
+    data: web::Data<ServerState>,
+    user: Option<AuthenticatedUser>,
+    path: web::Path<String>,
+    query: web::Query<ExportQuery>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let channel = path.into_inner();
+    authorize_channel(&data, &channel, user.as_ref()).await?;
+    let format = query.format;
+    let tz = query.tz.unwrap_or_default();
+
+    let messages = data
+        .repo
+        .stream_messages_from_channel(&channel, query.from, query.to)
+        .await?
+        .map_ok(move |mut message| {
+            message.timestamp = tz.convert(message.timestamp);
+            message
+        });
+
+    let filename = format!("{}.{}", channel, format.extension());
+    let disposition = ContentDisposition {
+        disposition: DispositionType::Attachment,
+        parameters: vec![DispositionParam::Filename(filename)],
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type(format.content_type())
+        .insert_header(disposition)
+        .streaming(export::encode_stream(messages, format)))
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct HistoryQuery {
+    /// The time zone to render timestamps in, UTC by default.
+    #[serde(default, deserialize_with = "crate::utils::tz::deserialize")]
+    tz: Option<TimeZone>,
 }
 
 #[get("/{channel}/messages/{count}")]
 pub(crate) async fn channel_get_count(
     data: web::Data<ServerState>,
+    user: Option<AuthenticatedUser>,
     path: web::Path<(String, i64)>,
-) -> impl Responder {
+    query: web::Query<HistoryQuery>,
+) -> Result<HttpResponse, actix_web::Error> {
     let (channel, count) = path.into_inner();
+    authorize_channel(&data, &channel, user.as_ref()).await?;
 
     let repo = &data.repo;
-    let messages = repo
-        .get_messages_from_channel(&channel, count)
-        .await
-        .unwrap();
+    let messages = repo.get_messages_from_channel(&channel, count).await?;
+    let messages = with_reactions(&data, messages, query.tz).await?;
 
-    HttpResponse::Ok().json(messages)
+    Ok(HttpResponse::Ok().json(messages))
 }
 
 #[get("/{channel}/messages")]
 pub(crate) async fn channel_get(
     data: web::Data<ServerState>,
+    user: Option<AuthenticatedUser>,
     path: web::Path<String>,
-) -> impl Responder {
+    query: web::Query<HistoryQuery>,
+) -> Result<HttpResponse, actix_web::Error> {
     let count = 10;
 
     let channel = path.into_inner();
+    authorize_channel(&data, &channel, user.as_ref()).await?;
     let repo = &data.repo;
-    let messages = repo
-        .get_messages_from_channel(&channel, count)
-        .await
-        .unwrap();
+    let messages = repo.get_messages_from_channel(&channel, count).await?;
+    let messages = with_reactions(&data, messages, query.tz).await?;
+
+    Ok(HttpResponse::Ok().json(messages))
+}
 
-    HttpResponse::Ok().json(messages)
+async fn with_reactions(
+    data: &ServerState,
+    messages: Vec<ChatMessage>,
+    tz: Option<TimeZone>,
+) -> Result<Vec<ReactedChatMessage>, Error> {
+    let ids: Vec<i32> = messages.iter().filter_map(|m| m.id).collect();
+    let mut counts = data.reactions.get_reaction_counts(&ids).await?;
+
+    let messages = messages
+        .into_iter()
+        .map(|mut message| {
+            message.timestamp = tz.unwrap_or_default().convert(message.timestamp);
+            let reactions = message
+                .id
+                .and_then(|id| counts.remove(&id))
+                .unwrap_or_default();
+
+            ReactedChatMessage { message, reactions }
+        })
+        .collect();
+
+    Ok(messages)
 }
 
+#[cfg(test)]
+mod tests {
+    use actix_web::test::{init_service, TestRequest};
+    use actix_web::{test, web};
+    use fake::{Fake, Faker};
+    use serde_json::{json, Value as JsonValue};
+    use test_context::test_context;
+
+    use crate::api::channel::{
+        channel_delete, channel_export, channel_get, channel_index, channel_info,
+        channel_member_delete, channel_member_put, channel_members, channel_patch, channel_post,
+        channel_presence, channel_stats,
+    };
+    use crate::api::tests::{
+        setup_app, ServerTestContext, TEST_ADMIN, TEST_ADMIN_TOKEN, TEST_TOKEN, TEST_USER,
+    };
+    use crate::error::Error;
+    use crate::models::chat_message::ChatMessage;
+
+    #[test_context(ServerTestContext)]
+    #[test]
+    async fn api_test_channel_registry(ctx: &ServerTestContext) -> Result<(), Error> {
+        let app = setup_app(&ctx.config).await.service(
+            web::scope("/channel")
+                .service(channel_index)
+                .service(channel_post)
+                .service(channel_info)
+                .service(channel_patch)
+                .service(channel_delete),
+        );
+        let service = init_service(app).await;
+
+        let name: String = Faker.fake();
+        let req = TestRequest::post()
+            .uri("/channel/")
+            .set_json(json!({ "name": name, "display_name": "Test" }))
+            .to_request();
+        let resp = test::call_service(&service, req).await;
+        assert_eq!(resp.status(), 201);
+
+        let req = TestRequest::get().uri("/channel/").to_request();
+        let channels: Vec<JsonValue> = test::call_and_read_body_json(&service, req).await;
+        let listed = channels.iter().find(|c| c["name"] == name).unwrap();
+        assert_eq!(listed["display_name"], "Test");
+        assert_eq!(listed["message_count"], 0);
+
+        // Without an owner, only admins can change or delete it.
+        let uri = format!("/channel/{}", name);
+        let req = TestRequest::patch()
+            .uri(&uri)
+            .set_json(json!({ "display_name": "Renamed" }))
+            .to_request();
+        let resp = test::call_service(&service, req).await;
+        assert_eq!(resp.status(), 401);
+
+        let req = TestRequest::delete().uri(&uri).to_request();
+        let resp = test::call_service(&service, req).await;
+        assert_eq!(resp.status(), 401);
+
+        let req = TestRequest::get().uri(&uri).to_request();
+        let resp = test::call_service(&service, req).await;
+        assert_eq!(resp.status(), 200);
+
+        Ok(())
+    }
+
+    #[test_context(ServerTestContext)]
+    #[test]
+    async fn api_test_channel_stats(ctx: &ServerTestContext) -> Result<(), Error> {
+        let app = setup_app(&ctx.config)
+            .await
+            .service(web::scope("/channel").service(channel_stats));
+        let service = init_service(app).await;
+
+        let req = TestRequest::get()
+            .uri("/channel/nobody/stats?from=2023-01-01T00:00:00Z&to=2023-01-02T00:00:00Z&bucket=minute")
+            .to_request();
+        let stats: JsonValue = test::call_and_read_body_json(&service, req).await;
+        assert_eq!(stats["total_messages"], 0);
+        assert_eq!(stats["bucket"], "minute");
+
+        let req = TestRequest::get()
+            .uri("/channel/nobody/stats?from=2023-01-02T00:00:00Z&to=2023-01-01T00:00:00Z")
+            .to_request();
+        let resp = test::call_service(&service, req).await;
+        assert_eq!(resp.status(), 400);
+
+        for top in ["-1", "0", "101"] {
+            let req = TestRequest::get()
+                .uri(&format!("/channel/nobody/stats?top={}", top))
+                .to_request();
+            let resp = test::call_service(&service, req).await;
+            assert_eq!(resp.status(), 400);
+        }
+
+        Ok(())
+    }
+
+    #[test_context(ServerTestContext)]
+    #[test]
+    async fn api_test_channel_export(ctx: &ServerTestContext) -> Result<(), Error> {
+        let app = setup_app(&ctx.config)
+            .await
+            .service(web::scope("/channel").service(channel_export));
+        let service = init_service(app).await;
+
+        let req = TestRequest::get()
+            .uri("/channel/nobody/export?format=csv")
+            .to_request();
+        let resp = test::call_service(&service, req).await;
+        assert!(resp.status().is_success());
+        assert_eq!(
+            resp.headers().get("content-type").unwrap(),
+            "text/csv; charset=utf-8"
+        );
+
+        let body = test::read_body(resp).await;
+        assert_eq!(body, "id,timestamp,channel,username,text\n");
+
+        let name: String = Faker.fake();
+        ctx.repo
+            .client
+            .as_ref()
+            .unwrap()
+            .execute("INSERT INTO users (name) VALUES ($1)", &[&name])
+            .await?;
+        let message = ChatMessage::new(
+            "summer".to_string(),
+            TEST_USER.to_string(),
+            name.clone(),
+            time::OffsetDateTime::from_unix_timestamp(1689422400).unwrap(),
+        );
+        ctx.repo.add_message(&message).await?;
+
+        let req = TestRequest::get()
+            .uri(&format!(
+                "/channel/{}/export?format=csv&tz=Europe/Paris",
+                name
+            ))
+            .to_request();
+        let body = test::call_and_read_body(&service, req).await;
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains(",2023-07-15T14:00:00+02:00,"), "{}", body);
+
+        let req = TestRequest::get()
+            .uri("/channel/nobody/export?format=csv&tz=Europe/Nowhere")
+            .to_request();
+        let resp = test::call_service(&service, req).await;
+        assert_eq!(resp.status(), 400);
+
+        Ok(())
+    }
+
+    #[test_context(ServerTestContext)]
+    #[test]
+    async fn api_test_channel_presence(ctx: &ServerTestContext) -> Result<(), Error> {
+        let app = setup_app(&ctx.config)
+            .await
+            .service(web::scope("/channel").service(channel_presence));
+        let service = init_service(app).await;
+
+        let req = TestRequest::get()
+            .uri("/channel/nobody/presence")
+            .to_request();
+        let presence: JsonValue = test::call_and_read_body_json(&service, req).await;
+        assert_eq!(presence["channel"], "nobody");
+        assert_eq!(presence["total"], 0);
+        assert_eq!(presence["viewers"], json!([]));
+
+        Ok(())
+    }
+
+    #[test_context(ServerTestContext)]
+    #[test]
+    async fn api_test_private_channel_is_hidden(ctx: &ServerTestContext) -> Result<(), Error> {
+        let app = setup_app(&ctx.config).await.service(
+            web::scope("/channel")
+                .service(channel_index)
+                .service(channel_post)
+                .service(channel_members)
+                .service(channel_info)
+                .service(channel_get),
+        );
+        let service = init_service(app).await;
+
+        let name: String = Faker.fake();
+        let req = TestRequest::post()
+            .uri("/channel/")
+            .set_json(json!({ "name": name, "private": true }))
+            .to_request();
+        let resp = test::call_service(&service, req).await;
+        assert_eq!(resp.status(), 401);
+
+        let client = ctx.repo.client.as_ref().unwrap();
+        client
+            .execute(
+                "INSERT INTO users (name) VALUES ($1) ON CONFLICT DO NOTHING",
+                &[&name],
+            )
+            .await?;
+        client
+            .execute(
+                "INSERT INTO channels (name, display_name, private) VALUES ($1, $1, TRUE)",
+                &[&name],
+            )
+            .await?;
+
+        for uri in [
+            format!("/channel/{}", name),
+            format!("/channel/{}/messages", name),
+            format!("/channel/{}/members", name),
+        ] {
+            let req = TestRequest::get().uri(&uri).to_request();
+            let resp = test::call_service(&service, req).await;
+            assert_eq!(resp.status(), 401, "{}", uri);
+        }
+
+        let req = TestRequest::get().uri("/channel/").to_request();
+        let channels: Vec<JsonValue> = test::call_and_read_body_json(&service, req).await;
+        assert!(channels.iter().all(|c| c["name"] != name));
+
+        Ok(())
+    }
+
+    #[test_context(ServerTestContext)]
+    #[test]
+    async fn api_test_register_channel_with_messages(ctx: &ServerTestContext) -> Result<(), Error> {
+        let app = setup_app(&ctx.config)
+            .await
+            .service(web::scope("/channel").service(channel_post));
+        let service = init_service(app).await;
+
+        let name: String = Faker.fake();
+        ctx.repo
+            .client
+            .as_ref()
+            .unwrap()
+            .execute("INSERT INTO users (name) VALUES ($1)", &[&name])
+            .await?;
+        let message = ChatMessage::new(
+            Faker.fake(),
+            TEST_USER.to_string(),
+            name.clone(),
+            time::OffsetDateTime::now_utc(),
+        );
+        ctx.repo.add_message(&message).await?;
+
+        let req = TestRequest::post()
+            .uri("/channel/")
+            .insert_header(("Authorization", format!("Bearer {}", TEST_TOKEN)))
+            .set_json(json!({ "name": name, "private": true }))
+            .to_request();
+        let resp = test::call_service(&service, req).await;
+        assert_eq!(resp.status(), 403);
+
+        let req = TestRequest::post()
+            .uri("/channel/")
+            .insert_header(("Authorization", format!("Bearer {}", TEST_ADMIN_TOKEN)))
+            .set_json(json!({ "name": name }))
+            .to_request();
+        let resp = test::call_service(&service, req).await;
+        assert_eq!(resp.status(), 201);
+
+        Ok(())
+    }
+
+    #[test_context(ServerTestContext)]
+    #[test]
+    async fn api_test_moderators_manage_members(ctx: &ServerTestContext) -> Result<(), Error> {
+        let app = setup_app(&ctx.config).await.service(
+            web::scope("/channel")
+                .service(channel_post)
+                .service(channel_member_put)
+                .service(channel_member_delete),
+        );
+        let service = init_service(app).await;
+
+        let name: String = Faker.fake();
+        let req = TestRequest::post()
+            .uri("/channel/")
+            .insert_header(("Authorization", format!("Bearer {}", TEST_ADMIN_TOKEN)))
+            .set_json(json!({ "name": name, "private": true }))
+            .to_request();
+        let resp = test::call_service(&service, req).await;
+        assert_eq!(resp.status(), 201);
+
+        let client = ctx.repo.client.as_ref().unwrap();
+        client
+            .execute(
+                "INSERT INTO channel_members (channel, username, role) VALUES ($1, $2, 'moderator')",
+                &[&name, &TEST_USER],
+            )
+            .await?;
+
+        let put = |member: &str, role: &str| {
+            TestRequest::put()
+                .uri(&format!("/channel/{}/members/{}", name, member))
+                .insert_header(("Authorization", format!("Bearer {}", TEST_TOKEN)))
+                .set_json(json!({ "role": role }))
+                .to_request()
+        };
+        let member: String = Faker.fake();
+        let resp = test::call_service(&service, put(&member, "member")).await;
+        assert_eq!(resp.status(), 200);
+        let resp = test::call_service(&service, put(&member, "moderator")).await;
+        assert_eq!(resp.status(), 403);
+
+        let delete = |member: &str| {
+            TestRequest::delete()
+                .uri(&format!("/channel/{}/members/{}", name, member))
+                .insert_header(("Authorization", format!("Bearer {}", TEST_TOKEN)))
+                .to_request()
+        };
+        let resp = test::call_service(&service, delete(TEST_ADMIN)).await;
+        assert_eq!(resp.status(), 403);
+        let resp = test::call_service(&service, delete(&member)).await;
+        assert_eq!(resp.status(), 204);
+
+        Ok(())
+    }
+}
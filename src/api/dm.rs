@@ -0,0 +1,119 @@
+use actix_web::error::ErrorBadRequest;
+use actix_web::{get, post, web, HttpResponse};
+use serde::Deserialize;
+
+use crate::api::auth::AuthenticatedUser;
+use crate::server::server_state::ServerState;
+use crate::utils::tz::TimeZone;
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 200;
+const MAX_TEXT_LENGTH: usize = 500;
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct NewDirectMessage {
+    text: String,
+}
+
+/// Sends a direct message from the signed-in user to `{user}`.
+#[post("/{user}")]
+pub(crate) async fn dm_post(
+    data: web::Data<ServerState>,
+    user: AuthenticatedUser,
+    path: web::Path<String>,
+    message: web::Json<NewDirectMessage>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let recipient = path.into_inner();
+    let text = message.into_inner().text;
+
+    if recipient == user.name() {
+        return Err(ErrorBadRequest("Cannot send a direct message to yourself"));
+    }
+    if text.trim().is_empty() || text.chars().count() > MAX_TEXT_LENGTH {
+        return Err(ErrorBadRequest(format!(
+            "Text must be between 1 and {} characters",
+            MAX_TEXT_LENGTH
+        )));
+    }
+
+    match data
+        .direct_messages
+        .send(user.name(), &recipient, &text)
+        .await?
+    {
+        Some(message) => Ok(HttpResponse::Created().json(message)),
+        None => Ok(HttpResponse::NotFound().body("No such user")),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct ConversationQuery {
+    /// Only messages older than this message id.
+    before: Option<i32>,
+    limit: Option<i64>,
+    /// The time zone to render timestamps in, UTC by default.
+    #[serde(default, deserialize_with = "crate::utils::tz::deserialize")]
+    tz: Option<TimeZone>,
+}
+
+/// The signed-in user's conversation with `{user}`, newest first. Older
+/// pages are read by passing the oldest id seen as `before`.
+#[get("/{user}")]
+pub(crate) async fn dm_get(
+    data: web::Data<ServerState>,
+    user: AuthenticatedUser,
+    path: web::Path<String>,
+    query: web::Query<ConversationQuery>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let other = path.into_inner();
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+
+    let tz = query.tz.unwrap_or_default();
+
+    let mut messages = data
+        .direct_messages
+        .get_conversation(user.name(), &other, query.before, limit)
+        .await?;
+    for message in &mut messages {
+        message.timestamp = tz.convert(message.timestamp);
+    }
+
+    Ok(HttpResponse::Ok().json(messages))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::{init_service, TestRequest};
+    use actix_web::{test, web};
+    use serde_json::json;
+    use test_context::test_context;
+
+    use crate::api::dm::{dm_get, dm_post};
+    use crate::api::tests::{setup_app, ServerTestContext};
+    use crate::error::Error;
+
+    #[test_context(ServerTestContext)]
+    #[test]
+    async fn api_test_dm_requires_sign_in(ctx: &ServerTestContext) -> Result<(), Error> {
+        let app = setup_app(&ctx.config)
+            .await
+            .service(web::scope("/dm").service(dm_post).service(dm_get));
+        let service = init_service(app).await;
+
+        let req = TestRequest::get().uri("/dm/somebody").to_request();
+        let resp = test::call_service(&service, req).await;
+        assert_eq!(resp.status(), 401);
+
+        let req = TestRequest::post()
+            .uri("/dm/somebody")
+            .set_json(json!({ "text": "hi" }))
+            .to_request();
+        let resp = test::call_service(&service, req).await;
+        assert_eq!(resp.status(), 401);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,295 @@
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::Duration;
+
+use actix_web::error::ErrorBadRequest;
+use actix_web::web::Bytes;
+use actix_web::{get, web, HttpRequest, HttpResponse};
+use futures::stream::{self, Stream};
+use log::warn;
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::api::auth::{authorize_channel, AuthenticatedUser};
+use crate::dal::channel_repository::ChannelRepository;
+use crate::dal::chat_message_repository::ChatMessageRepository;
+use crate::error::Error;
+use crate::models::chat_message::ChatMessage;
+use crate::server::hub::ChannelEvent;
+use crate::server::server_state::ServerState;
+
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+/// How many missed messages are read back from the database at a time.
+const REPLAY_PAGE_SIZE: i64 = 500;
+const LAST_EVENT_ID: &str = "Last-Event-ID";
+
+/// Streams a channel's new messages as server-sent events, each with the
+/// message's sequence number as its event id. A `Last-Event-ID` header
+/// replays what was missed since that message before going live. The stream
+/// ends if the client may no longer read the channel.
+#[get("/{channel}/events")]
+pub(crate) async fn channel_events(
+    req: HttpRequest,
+    data: web::Data<ServerState>,
+    user: Option<AuthenticatedUser>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let channel = path.into_inner();
+    authorize_channel(&data, &channel, user.as_ref()).await?;
+
+    let last_event_id = match req.headers().get(LAST_EVENT_ID) {
+        Some(value) => Some(
+            value
+                .to_str()
+                .ok()
+                .and_then(|seq| seq.trim().parse::<i64>().ok())
+                .ok_or_else(|| ErrorBadRequest("Last-Event-ID must be a sequence number"))?,
+        ),
+        None => None,
+    };
+
+    // Subscribing before replaying means nothing written in between is lost.
+    let receiver = data.hub.subscribe(&channel);
+    let events = event_stream(
+        data.repo.clone(),
+        data.channels.clone(),
+        user.map(|user| user.0),
+        channel,
+        last_event_id,
+        receiver,
+        KEEP_ALIVE_INTERVAL,
+    );
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        .streaming(events))
+}
+
+struct EventStreamState {
+    repo: Arc<ChatMessageRepository>,
+    channels: Arc<ChannelRepository>,
+    user: Option<String>,
+    channel: String,
+    /// The sequence number of the last message sent, if any.
+    last_seq: Option<i64>,
+    replaying: bool,
+    pending: VecDeque<ChatMessage>,
+    receiver: broadcast::Receiver<ChannelEvent>,
+    keep_alive: Duration,
+}
+
+impl EventStreamState {
+    /// The next chunk to send, or `None` once the stream should end.
+    async fn next_chunk(&mut self) -> Option<Result<Bytes, Error>> {
+        loop {
+            if let Some(message) = self.pending.pop_front() {
+                self.last_seq = message.seq;
+                return Some(message_event(&message));
+            }
+
+            if self.replaying {
+                let Some(after) = self.last_seq else {
+                    self.replaying = false;
+                    continue;
+                };
+
+                match self
+                    .repo
+                    .get_messages_after(&self.channel, after, REPLAY_PAGE_SIZE)
+                    .await
+                {
+                    Ok(messages) => {
+                        self.replaying = messages.len() as i64 == REPLAY_PAGE_SIZE;
+                        self.pending.extend(messages);
+                    }
+                    Err(err) => return Some(Err(err)),
+                }
+                continue;
+            }
+
+            match tokio::time::timeout(self.keep_alive, self.receiver.recv()).await {
+                Ok(Ok(ChannelEvent::Message { message, .. })) => match self.last_seq {
+                    // Anything up to the last message was already sent.
+                    Some(last) if message.seq <= Some(last) => {}
+                    // Whatever was skipped is read back before going on.
+                    Some(last) if message.seq > Some(last + 1) => self.replaying = true,
+                    _ => self.pending.push_back(message),
+                },
+                Ok(Ok(ChannelEvent::AccessChanged { .. })) => {
+                    let access = self
+                        .channels
+                        .get_access(&self.channel, self.user.as_deref())
+                        .await;
+                    if !access.is_ok_and(|access| access.can_read()) {
+                        return None;
+                    }
+                }
+                Ok(Ok(_)) => {}
+                Ok(Err(RecvError::Lagged(skipped))) => {
+                    // Ending the stream lets the client reconnect with its
+                    // Last-Event-ID and catch up from the database.
+                    warn!("Event stream lagged behind, skipped {} events", skipped);
+                    return None;
+                }
+                Ok(Err(RecvError::Closed)) => return None,
+                Err(_) => return Some(Ok(Bytes::from_static(b": keep-alive\n\n"))),
+            }
+        }
+    }
+}
+
+fn event_stream(
+    repo: Arc<ChatMessageRepository>,
+    channels: Arc<ChannelRepository>,
+    user: Option<String>,
+    channel: String,
+    last_event_id: Option<i64>,
+    receiver: broadcast::Receiver<ChannelEvent>,
+    keep_alive: Duration,
+) -> impl Stream<Item = Result<Bytes, Error>> {
+    let state = EventStreamState {
+        repo,
+        channels,
+        user,
+        channel,
+        last_seq: last_event_id,
+        replaying: last_event_id.is_some(),
+        pending: VecDeque::new(),
+        receiver,
+        keep_alive,
+    };
+
+    stream::unfold(state, |mut state| async move {
+        let chunk = state.next_chunk().await?;
+        Some((chunk, state))
+    })
+}
+
+fn message_event(message: &ChatMessage) -> Result<Bytes, Error> {
+    let data = serde_json::to_string(message)?;
+    let id = message.seq.map(|seq| seq.to_string()).unwrap_or_default();
+
+    Ok(Bytes::from(format!(
+        "id: {}\nevent: message\ndata: {}\n\n",
+        id, data
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::body::MessageBody;
+    use actix_web::test::{init_service, TestRequest};
+    use actix_web::{test, web};
+    use fake::{Fake, Faker};
+    use futures::future::poll_fn;
+    use futures::StreamExt;
+    use std::pin::Pin;
+    use test_context::test_context;
+
+    use super::*;
+    use crate::api::tests::{setup_app, ServerTestContext};
+    use crate::config::Config;
+    use crate::server::hub::Hub;
+
+    fn message(seq: i64, channel: &str) -> ChatMessage {
+        let mut message = Faker.fake::<ChatMessage>();
+        message.seq = Some(seq);
+        message.channel = channel.to_string();
+        message
+    }
+
+    fn published(hub: &Hub, message: ChatMessage) {
+        let channel = message.channel.clone();
+        let event = ChannelEvent::Message {
+            channel: channel.clone(),
+            message,
+        };
+        hub.publish(&channel, event);
+    }
+
+    #[test]
+    async fn test_message_event_format() {
+        let message = message(42, "chan");
+        let event = message_event(&message).unwrap();
+        let event = std::str::from_utf8(&event).unwrap();
+
+        assert!(event.starts_with("id: 42\nevent: message\ndata: {"));
+        assert!(event.ends_with("}\n\n"));
+        assert_eq!(event.trim_end().lines().count(), 3);
+    }
+
+    #[test]
+    async fn test_live_events_and_keep_alive() {
+        // Nothing is replayed, so the repositories are never connected.
+        let config = Config::load("config.json.default").await.unwrap();
+        let repo = Arc::new(ChatMessageRepository::new(&config).unwrap());
+        let channels = Arc::new(ChannelRepository::new(&config).unwrap());
+        let hub = Hub::new();
+
+        let receiver = hub.subscribe("chan");
+        let mut events = Box::pin(event_stream(
+            repo,
+            channels,
+            None,
+            "chan".to_string(),
+            None,
+            receiver,
+            Duration::from_millis(10),
+        ));
+
+        published(&hub, message(7, "chan"));
+        let event = events.next().await.unwrap().unwrap();
+        assert!(event.starts_with(b"id: 7\n"));
+
+        let event = events.next().await.unwrap().unwrap();
+        assert_eq!(&event[..], b": keep-alive\n\n");
+    }
+
+    #[test_context(ServerTestContext)]
+    #[test]
+    async fn api_test_channel_events_replays_missed(ctx: &ServerTestContext) -> Result<(), Error> {
+        let app = setup_app(&ctx.config)
+            .await
+            .service(web::scope("/channel").service(channel_events));
+        let service = init_service(app).await;
+
+        let message = Faker.fake::<ChatMessage>();
+        let client = ctx.repo.client.as_ref().unwrap();
+        client
+            .execute(
+                "INSERT INTO users (name) VALUES ($1), ($2) ON CONFLICT DO NOTHING",
+                &[&message.username, &message.channel],
+            )
+            .await?;
+        let first = ctx.repo.add_message(&message).await?.seq.unwrap();
+        let second = ctx.repo.add_message(&message).await?.seq.unwrap();
+
+        let req = TestRequest::get()
+            .uri(&format!("/channel/{}/events", message.channel))
+            .insert_header((LAST_EVENT_ID, first.to_string()))
+            .to_request();
+        let resp = test::call_service(&service, req).await;
+        assert_eq!(
+            resp.headers().get("content-type").unwrap(),
+            "text/event-stream"
+        );
+
+        let mut body = resp.into_body();
+        let chunk = poll_fn(|cx| Pin::new(&mut body).poll_next(cx))
+            .await
+            .unwrap()
+            .ok()
+            .unwrap();
+        assert!(chunk.starts_with(format!("id: {}\n", second).as_bytes()));
+
+        let req = TestRequest::get()
+            .uri(&format!("/channel/{}/events", message.channel))
+            .insert_header((LAST_EVENT_ID, "yesterday"))
+            .to_request();
+        let resp = test::call_service(&service, req).await;
+        assert_eq!(resp.status(), 400);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,111 @@
+use actix_web::{get, post, web, HttpResponse};
+use log::info;
+use serde::Deserialize;
+
+use crate::api::auth::AdminUser;
+use crate::server::server_state::ServerState;
+use crate::utils::import::{self, ImportFormat, ImportOptions};
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct ImportQuery {
+    source: String,
+    #[serde(default)]
+    format: ImportFormat,
+    channel: Option<String>,
+}
+
+/// Imports the log in the request body. Admins only, since it can add
+/// messages from anyone to any channel. Posting the same `source` again
+/// resumes an interrupted import instead of duplicating what was committed,
+/// and messages with a source id already stored are left out. Each source
+/// is imported by one request at a time.
+#[post("")]
+pub(crate) async fn import_post(
+    data: web::Data<ServerState>,
+    admin: AdminUser,
+    query: web::Query<ImportQuery>,
+    payload: web::Payload,
+) -> Result<HttpResponse, actix_web::Error> {
+    let query = query.into_inner();
+    let options = ImportOptions {
+        source: query.source,
+        format: query.format,
+        channel: query.channel,
+    };
+
+    let mut importer = data.importer.session().await?;
+    if !importer.lock_source(&options.source).await? {
+        let message = format!("{} is already being imported", options.source);
+        return Ok(HttpResponse::Conflict().body(message));
+    }
+
+    info!("{} is importing {}", admin.0.name(), options.source);
+
+    let lines = import::lines_from_chunks(payload);
+    let report = import::import_lines(&mut importer, lines, &options).await?;
+
+    Ok(HttpResponse::Ok().json(report))
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct ImportStatusQuery {
+    source: String,
+}
+
+/// How far the import of `source` has gotten, updated as each batch is
+/// committed.
+#[get("")]
+pub(crate) async fn import_get(
+    data: web::Data<ServerState>,
+    _admin: AdminUser,
+    query: web::Query<ImportStatusQuery>,
+) -> Result<HttpResponse, actix_web::Error> {
+    match data.importer.get_job(&query.source).await? {
+        Some(job) => Ok(HttpResponse::Ok().json(job)),
+        None => Ok(HttpResponse::NotFound().finish()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::{init_service, TestRequest};
+    use actix_web::{test, web};
+    use test_context::test_context;
+
+    use crate::api::import::{import_get, import_post};
+    use crate::api::tests::{setup_app, ServerTestContext, TEST_TOKEN};
+    use crate::error::Error;
+
+    #[test_context(ServerTestContext)]
+    #[test]
+    async fn api_test_import_requires_admin(ctx: &ServerTestContext) -> Result<(), Error> {
+        let app = setup_app(&ctx.config).await.service(
+            web::scope("/import")
+                .service(import_post)
+                .service(import_get),
+        );
+        let service = init_service(app).await;
+
+        let req = TestRequest::post()
+            .uri("/import?source=test")
+            .insert_header(("Authorization", "Bearer not-a-token"))
+            .set_payload("")
+            .to_request();
+        let resp = test::call_service(&service, req).await;
+        assert_eq!(resp.status(), 401);
+
+        let req = TestRequest::post()
+            .uri("/import?source=test")
+            .insert_header(("Authorization", format!("Bearer {}", TEST_TOKEN)))
+            .set_payload("")
+            .to_request();
+        let resp = test::call_service(&service, req).await;
+        assert_eq!(resp.status(), 403);
+
+        let req = TestRequest::get().uri("/import?source=test").to_request();
+        let resp = test::call_service(&service, req).await;
+        assert_eq!(resp.status(), 401);
+
+        Ok(())
+    }
+}
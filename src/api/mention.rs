@@ -0,0 +1,135 @@
+use actix_web::error::{ErrorBadRequest, ErrorForbidden};
+use actix_web::{get, patch, web, HttpResponse};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::api::auth::AuthenticatedUser;
+use crate::server::server_state::ServerState;
+use crate::utils::tz::TimeZone;
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 200;
+const MAX_IDS: usize = 500;
+
+/// Only the user themselves can see their mentions.
+fn authorize_inbox(user: &AuthenticatedUser, name: &str) -> Result<(), actix_web::Error> {
+    if user.name() != name {
+        return Err(ErrorForbidden("Not your mentions"));
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct MentionsQuery {
+    /// Only mentions older than this mention id.
+    before: Option<i64>,
+    limit: Option<i64>,
+    /// Only unread mentions.
+    #[serde(default)]
+    unread: bool,
+    /// The time zone to render timestamps in, UTC by default.
+    #[serde(default, deserialize_with = "crate::utils::tz::deserialize")]
+    tz: Option<TimeZone>,
+}
+
+/// Where `{name}` was mentioned, newest first, with how many mentions are
+/// unread. Older pages are read by passing the oldest id seen as `before`.
+#[get("/{name}/mentions")]
+pub(crate) async fn mentions_get(
+    data: web::Data<ServerState>,
+    user: AuthenticatedUser,
+    path: web::Path<String>,
+    query: web::Query<MentionsQuery>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let name = path.into_inner();
+    authorize_inbox(&user, &name)?;
+
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+
+    let tz = query.tz.unwrap_or_default();
+
+    let mut page = data
+        .mentions
+        .get_mentions(&name, query.before, query.unread, limit)
+        .await?;
+    for mention in &mut page.mentions {
+        mention.message.timestamp = tz.convert(mention.message.timestamp);
+    }
+
+    Ok(HttpResponse::Ok().json(page))
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct MentionsUpdate {
+    ids: Vec<i64>,
+    read: bool,
+}
+
+/// Marks some of `{name}`'s mentions read or unread, returning how many are
+/// left unread.
+#[patch("/{name}/mentions")]
+pub(crate) async fn mentions_patch(
+    data: web::Data<ServerState>,
+    user: AuthenticatedUser,
+    path: web::Path<String>,
+    update: web::Json<MentionsUpdate>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let name = path.into_inner();
+    authorize_inbox(&user, &name)?;
+
+    if update.ids.len() > MAX_IDS {
+        return Err(ErrorBadRequest(format!(
+            "At most {} mentions can be updated at once",
+            MAX_IDS
+        )));
+    }
+
+    data.mentions
+        .set_read(&name, &update.ids, update.read)
+        .await?;
+    let unread = data.mentions.count_unread(&name).await?;
+
+    Ok(HttpResponse::Ok().json(json!({ "unread": unread })))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::{init_service, TestRequest};
+    use actix_web::{test, web};
+    use serde_json::json;
+    use test_context::test_context;
+
+    use crate::api::mention::{mentions_get, mentions_patch};
+    use crate::api::tests::{setup_app, ServerTestContext};
+    use crate::error::Error;
+
+    #[test_context(ServerTestContext)]
+    #[test]
+    async fn api_test_mentions_require_sign_in(ctx: &ServerTestContext) -> Result<(), Error> {
+        let app = setup_app(&ctx.config).await.service(
+            web::scope("/user")
+                .service(mentions_get)
+                .service(mentions_patch),
+        );
+        let service = init_service(app).await;
+
+        let req = TestRequest::get()
+            .uri("/user/somebody/mentions")
+            .to_request();
+        let resp = test::call_service(&service, req).await;
+        assert_eq!(resp.status(), 401);
+
+        let req = TestRequest::patch()
+            .uri("/user/somebody/mentions")
+            .set_json(json!({ "ids": [1], "read": true }))
+            .to_request();
+        let resp = test::call_service(&service, req).await;
+        assert_eq!(resp.status(), 401);
+
+        Ok(())
+    }
+}
@@ -1,42 +1,113 @@
-use actix_web::{get, HttpResponse, post, Responder, web};
-use log::error;
+use actix_web::error::{ErrorBadRequest, ErrorForbidden};
+use actix_web::{get, post, web, HttpRequest, HttpResponse, Responder};
+use serde::Deserialize;
 
+use crate::api::auth::{authorize_channel, AuthenticatedUser};
 use crate::models::chat_message::ChatMessage;
 use crate::server::server_state::ServerState;
 
+const IDEMPOTENCY_KEY: &str = "Idempotency-Key";
+/// Set on responses that return a message stored by an earlier request.
+const IDEMPOTENT_REPLAYED: &str = "Idempotent-Replayed";
+const MAX_IDEMPOTENCY_KEY_LENGTH: usize = 255;
+const MAX_SOURCE_ID_LENGTH: usize = 64;
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct MessagePostQuery {
+    /// Respond only once the message has been written.
+    #[serde(default)]
+    wait: bool,
+}
+
 #[get("")]
 pub(crate) async fn message_index() -> impl Responder {
     HttpResponse::Ok().body("Messages index")
 }
 
+/// Messages to a private channel must come from a member, posting as
+/// themselves. Retrying with the same `Idempotency-Key` header as the same
+/// user, or posting a message with a `source_id` already stored in the
+/// channel, returns the stored message with `Idempotent-Replayed: true`
+/// rather than adding another. New messages are returned as queued, or as
+/// stored if `wait` is set.
 #[post("")]
 pub async fn message_post(
+    req: HttpRequest,
     data: web::Data<ServerState>,
+    user: Option<AuthenticatedUser>,
+    query: web::Query<MessagePostQuery>,
     message: web::Json<ChatMessage>,
-) -> impl Responder {
-    let repo = &data.repo;
-    let message = message.into_inner();
-    repo.add_message(&message).await.unwrap_or_else(|err| {
-        error!("Could not add message: {}", err);
-    });
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut message = message.into_inner();
+    // Fragments are always parsed here, from the text and any emotes tag.
+    message.fragments = None;
+
+    if let Some(key) = req.headers().get(IDEMPOTENCY_KEY) {
+        let key = key
+            .to_str()
+            .ok()
+            .filter(|key| !key.is_empty() && key.len() <= MAX_IDEMPOTENCY_KEY_LENGTH)
+            .ok_or_else(|| {
+                ErrorBadRequest(format!(
+                    "Idempotency-Key must be 1 to {} visible ASCII characters",
+                    MAX_IDEMPOTENCY_KEY_LENGTH
+                ))
+            })?;
+        // Anonymous clients can only be told apart by who they post as.
+        let poster = match &user {
+            Some(user) => user.name().to_string(),
+            None => message.username.clone(),
+        };
+        message.set_idempotency_key(&poster, key);
+    }
 
-    let response = format!("Successfully added message: {}", message);
+    if let Some(source_id) = &message.source_id {
+        if source_id.is_empty() || source_id.len() > MAX_SOURCE_ID_LENGTH {
+            return Err(ErrorBadRequest(format!(
+                "source_id must be 1 to {} bytes",
+                MAX_SOURCE_ID_LENGTH
+            )));
+        }
+    }
+
+    let access = authorize_channel(&data, &message.channel, user.as_ref()).await?;
+    if access.private && user.as_ref().map(|user| user.name()) != Some(&message.username) {
+        return Err(ErrorForbidden("Members can only post as themselves"));
+    }
+
+    if let Some(stored) = data.repo.get_duplicate(&message).await? {
+        return Ok(HttpResponse::Ok()
+            .insert_header((IDEMPOTENT_REPLAYED, "true"))
+            .json(stored));
+    }
 
-    HttpResponse::Ok().body(response)
+    if query.wait {
+        let message = data.writer.write(message).await?;
+        return Ok(HttpResponse::Created().json(message));
+    }
+
+    data.writer.submit(message.clone()).await?;
+
+    Ok(HttpResponse::Accepted().json(message))
 }
 
+#[get("/ingest")]
+pub(crate) async fn ingest_stats(data: web::Data<ServerState>) -> impl Responder {
+    HttpResponse::Ok().json(data.writer.stats())
+}
 
 #[cfg(test)]
 mod tests {
-    use actix_web::{test, web};
     use actix_web::test::{init_service, TestRequest};
+    use actix_web::{test, web};
     use fake::{Fake, Faker};
-    use test_context::{AsyncTestContext, test_context};
+    use test_context::test_context;
 
-    use crate::api::message::message_post;
-    use crate::api::tests::{ServerTestContext, setup_app};
+    use crate::api::message::{ingest_stats, message_post};
+    use crate::api::tests::{setup_app, ServerTestContext, TEST_TOKEN};
     use crate::error::Error;
     use crate::models::chat_message::ChatMessage;
+    use serde_json::Value;
 
     #[test_context(ServerTestContext)]
     #[test]
@@ -59,4 +130,144 @@ mod tests {
 
         Ok(())
     }
-}
\ No newline at end of file
+
+    #[test_context(ServerTestContext)]
+    #[test]
+    async fn api_test_message_post_wait(ctx: &ServerTestContext) -> Result<(), Error> {
+        let app = setup_app(&ctx.config).await.service(
+            web::scope("/message")
+                .service(message_post)
+                .service(ingest_stats),
+        );
+        let service = init_service(app).await;
+
+        let message = Faker.fake::<ChatMessage>();
+        let client = ctx.repo.client.as_ref().unwrap();
+        client
+            .execute(
+                "INSERT INTO users (name) VALUES ($1), ($2) ON CONFLICT DO NOTHING",
+                &[&message.username, &message.channel],
+            )
+            .await?;
+
+        let req = TestRequest::post()
+            .uri("/message?wait=true")
+            .set_json(&message)
+            .to_request();
+        let resp = test::call_service(&service, req).await;
+        assert_eq!(resp.status(), 201);
+
+        let stored = ctx.repo.get_messages_by_user(&message.username).await?;
+        assert_eq!(stored.len(), 1);
+
+        let req = TestRequest::get().uri("/message/ingest").to_request();
+        let stats: Value = test::call_and_read_body_json(&service, req).await;
+        assert_eq!(stats["messages"], 1);
+
+        Ok(())
+    }
+
+    #[test_context(ServerTestContext)]
+    #[test]
+    async fn api_test_message_post_idempotent(ctx: &ServerTestContext) -> Result<(), Error> {
+        let app = setup_app(&ctx.config)
+            .await
+            .service(web::scope("/message").service(message_post));
+        let service = init_service(app).await;
+
+        let message = Faker.fake::<ChatMessage>();
+        let client = ctx.repo.client.as_ref().unwrap();
+        client
+            .execute(
+                "INSERT INTO users (name) VALUES ($1), ($2) ON CONFLICT DO NOTHING",
+                &[&message.username, &message.channel],
+            )
+            .await?;
+
+        let key: String = Faker.fake();
+        let req = TestRequest::post()
+            .uri("/message?wait=true")
+            .insert_header(("Idempotency-Key", key.as_str()))
+            .set_json(&message)
+            .to_request();
+        let resp = test::call_service(&service, req).await;
+        assert_eq!(resp.status(), 201);
+        let original: ChatMessage = test::read_body_json(resp).await;
+
+        // Retries return the stored message, whether they wait or not.
+        for uri in ["/message?wait=true", "/message"] {
+            let mut retried = message.clone();
+            retried.text = "retried".to_string();
+            let req = TestRequest::post()
+                .uri(uri)
+                .insert_header(("Idempotency-Key", key.as_str()))
+                .set_json(&retried)
+                .to_request();
+            let resp = test::call_service(&service, req).await;
+            assert_eq!(resp.status(), 200);
+            assert_eq!(resp.headers().get("Idempotent-Replayed").unwrap(), "true");
+            let replayed: ChatMessage = test::read_body_json(resp).await;
+            assert_eq!(replayed, original);
+        }
+
+        let stored = ctx.repo.get_messages_by_user(&message.username).await?;
+        assert_eq!(stored.len(), 1);
+
+        let req = TestRequest::post()
+            .uri("/message")
+            .insert_header(("Idempotency-Key", ""))
+            .set_json(&message)
+            .to_request();
+        let resp = test::call_service(&service, req).await;
+        assert_eq!(resp.status(), 400);
+
+        Ok(())
+    }
+
+    #[test_context(ServerTestContext)]
+    #[test]
+    async fn api_test_idempotency_key_is_scoped_to_the_signed_in_user(
+        ctx: &ServerTestContext,
+    ) -> Result<(), Error> {
+        let app = setup_app(&ctx.config)
+            .await
+            .service(web::scope("/message").service(message_post));
+        let service = init_service(app).await;
+
+        let message = Faker.fake::<ChatMessage>();
+        let mut impostor = message.clone();
+        impostor.username = Faker.fake();
+        let client = ctx.repo.client.as_ref().unwrap();
+        client
+            .execute(
+                "INSERT INTO users (name) VALUES ($1), ($2), ($3) ON CONFLICT DO NOTHING",
+                &[&message.username, &impostor.username, &message.channel],
+            )
+            .await?;
+
+        // Posting as someone else does not escape the signed-in user's key.
+        let key: String = Faker.fake();
+        let mut statuses = vec![];
+        for message in [&message, &impostor] {
+            let req = TestRequest::post()
+                .uri("/message?wait=true")
+                .insert_header(("Authorization", format!("Bearer {}", TEST_TOKEN)))
+                .insert_header(("Idempotency-Key", key.as_str()))
+                .set_json(message)
+                .to_request();
+            statuses.push(test::call_service(&service, req).await.status());
+        }
+        assert_eq!(statuses, [201, 200]);
+
+        // Anonymous clients are told apart by who they post as.
+        let req = TestRequest::post()
+            .uri("/message?wait=true")
+            .insert_header(("Idempotency-Key", key.as_str()))
+            .set_json(&impostor)
+            .to_request();
+        let resp = test::call_service(&service, req).await;
+        assert_eq!(resp.status(), 201);
+
+        Ok(())
+    }
+}
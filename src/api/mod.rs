@@ -1,15 +1,31 @@
+use std::collections::{HashMap, HashSet};
+
 use crate::config::Config;
 use crate::error::Error;
 use crate::error::Error::Configuration;
 
+pub(crate) mod admin;
+pub mod auth;
 pub(crate) mod channel;
+pub(crate) mod dm;
+pub(crate) mod events;
+pub(crate) mod import;
+pub(crate) mod mention;
 pub mod message;
+pub(crate) mod reaction;
+#[cfg(test)]
 mod tests;
 
 const ERR_CONFIG_NO_ADDRESS: &str = "No address specified in api config";
+const ERR_CONFIG_INVALID_TOKENS: &str = "api.tokens must map tokens to usernames";
+const ERR_CONFIG_INVALID_ADMINS: &str = "api.admins must be a list of usernames";
 
 pub struct ApiConfig {
     pub address: String,
+    /// Bearer tokens and the usernames they authenticate as.
+    pub tokens: HashMap<String, String>,
+    /// Users allowed to use the admin endpoints.
+    pub admins: HashSet<String>,
 }
 
 impl Config {
@@ -21,8 +37,22 @@ impl Config {
             None => Err(Configuration(ERR_CONFIG_NO_ADDRESS.to_string()))?,
         };
 
+        let tokens = match json.get("tokens") {
+            Some(x) => serde_json::from_value(x.clone())
+                .map_err(|_| Configuration(ERR_CONFIG_INVALID_TOKENS.to_string()))?,
+            None => HashMap::new(),
+        };
+
+        let admins = match json.get("admins") {
+            Some(x) => serde_json::from_value(x.clone())
+                .map_err(|_| Configuration(ERR_CONFIG_INVALID_ADMINS.to_string()))?,
+            None => HashSet::new(),
+        };
+
         Ok(ApiConfig {
             address: maybe_address.unwrap(),
+            tokens,
+            admins,
         })
     }
 }
@@ -0,0 +1,152 @@
+use actix_web::{delete, post, web, HttpResponse};
+
+use crate::api::auth::{authorize_channel, AuthenticatedUser};
+use crate::models::reaction::Reaction;
+use crate::server::hub::ChannelEvent;
+use crate::server::server_state::ServerState;
+
+/// Reacts to the message as the signed-in user.
+#[post("/{id}/reactions/{emote}")]
+pub(crate) async fn reaction_post(
+    data: web::Data<ServerState>,
+    user: AuthenticatedUser,
+    path: web::Path<(i32, String)>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let (message_id, emote) = path.into_inner();
+
+    let Some(message) = data.repo.get_message(message_id).await? else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    authorize_channel(&data, &message.channel, Some(&user)).await?;
+
+    let reaction = Reaction::new(message_id, user.name().to_string(), emote);
+    if data.reactions.add_reaction(&reaction).await? {
+        let channel = message.channel.clone();
+        let event = ChannelEvent::ReactionAdded { channel, reaction };
+        data.hub.publish(&message.channel, event);
+    }
+
+    reaction_counts(&data, message_id).await
+}
+
+#[delete("/{id}/reactions/{emote}")]
+pub(crate) async fn reaction_delete(
+    data: web::Data<ServerState>,
+    user: AuthenticatedUser,
+    path: web::Path<(i32, String)>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let (message_id, emote) = path.into_inner();
+
+    let Some(message) = data.repo.get_message(message_id).await? else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    authorize_channel(&data, &message.channel, Some(&user)).await?;
+
+    let reaction = Reaction::new(message_id, user.name().to_string(), emote);
+    if data.reactions.remove_reaction(&reaction).await? {
+        let channel = message.channel.clone();
+        let event = ChannelEvent::ReactionRemoved { channel, reaction };
+        data.hub.publish(&message.channel, event);
+    }
+
+    reaction_counts(&data, message_id).await
+}
+
+async fn reaction_counts(
+    data: &ServerState,
+    message_id: i32,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut counts = data.reactions.get_reaction_counts(&[message_id]).await?;
+    let counts = counts.remove(&message_id).unwrap_or_default();
+
+    Ok(HttpResponse::Ok().json(counts))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::{init_service, TestRequest};
+    use actix_web::{test, web};
+    use fake::{Fake, Faker};
+    use test_context::test_context;
+
+    use crate::api::reaction::{reaction_delete, reaction_post};
+    use crate::api::tests::{setup_app, ServerTestContext, TEST_TOKEN, TEST_USER};
+    use crate::error::Error;
+    use crate::models::chat_message::ChatMessage;
+    use crate::models::reaction::ReactionCount;
+
+    #[test_context(ServerTestContext)]
+    #[test]
+    async fn api_test_reaction_post_and_delete(ctx: &ServerTestContext) -> Result<(), Error> {
+        let app = setup_app(&ctx.config).await.service(
+            web::scope("/message")
+                .service(reaction_post)
+                .service(reaction_delete),
+        );
+        let service = init_service(app).await;
+
+        let message = Faker.fake::<ChatMessage>();
+        let client = ctx.repo.client.as_ref().unwrap();
+        client
+            .execute(
+                "INSERT INTO users (name) VALUES ($1), ($2) ON CONFLICT DO NOTHING",
+                &[&message.username, &message.channel],
+            )
+            .await?;
+        let message_id = ctx.repo.add_message(&message).await?.id.unwrap();
+
+        let uri = format!("/message/{}/reactions/Kappa", message_id);
+        let auth = ("Authorization", format!("Bearer {}", TEST_TOKEN));
+
+        let req = TestRequest::post().uri(&uri).to_request();
+        let resp = test::call_service(&service, req).await;
+        assert_eq!(resp.status(), 401);
+
+        let req = TestRequest::post()
+            .uri(&uri)
+            .insert_header(auth.clone())
+            .to_request();
+        let counts: Vec<ReactionCount> = test::call_and_read_body_json(&service, req).await;
+        assert_eq!(counts[0].count, 1);
+
+        let reactions = ctx
+            .repo
+            .client
+            .as_ref()
+            .unwrap()
+            .query_one(
+                "SELECT username FROM reactions WHERE message_id = $1",
+                &[&message_id],
+            )
+            .await?;
+        assert_eq!(reactions.get::<_, &str>("username"), TEST_USER);
+
+        let req = TestRequest::delete()
+            .uri(&uri)
+            .insert_header(auth)
+            .to_request();
+        let counts: Vec<ReactionCount> = test::call_and_read_body_json(&service, req).await;
+        assert!(counts.is_empty());
+
+        Ok(())
+    }
+
+    #[test_context(ServerTestContext)]
+    #[test]
+    async fn api_test_reaction_post_unknown_message(ctx: &ServerTestContext) -> Result<(), Error> {
+        let app = setup_app(&ctx.config)
+            .await
+            .service(web::scope("/message").service(reaction_post));
+        let service = init_service(app).await;
+
+        let req = TestRequest::post()
+            .uri("/message/-1/reactions/Kappa")
+            .insert_header(("Authorization", format!("Bearer {}", TEST_TOKEN)))
+            .to_request();
+        let resp = test::call_service(&service, req).await;
+
+        assert_eq!(resp.status(), 404);
+
+        Ok(())
+    }
+}
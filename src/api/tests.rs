@@ -1,27 +1,59 @@
 use std::sync::Arc;
 
-use actix_web::App;
 use actix_web::body::MessageBody;
 use actix_web::dev::{ServiceFactory, ServiceRequest, ServiceResponse};
 use actix_web::web::Data;
+use actix_web::App;
 use test_context::AsyncTestContext;
 
 use crate::config::Config;
+use crate::dal;
+use crate::dal::batch_writer::BatchWriter;
+use crate::dal::channel_repository::ChannelRepository;
 use crate::dal::chat_message_repository::ChatMessageRepository;
+use crate::dal::direct_message_repository::DirectMessageRepository;
+use crate::dal::import_repository::ImportRepository;
+use crate::dal::mention_repository::MentionRepository;
+use crate::dal::reaction_repository::ReactionRepository;
+use crate::dal::retention_repository::RetentionRepository;
+use crate::dal::stats_repository::StatsRepository;
+use crate::server::ephemeral::Typing;
+use crate::server::hub::Hub;
+use crate::server::presence::{Presence, LEAVE_DEBOUNCE};
+use crate::server::resume::RecentMessages;
 use crate::server::server_state::ServerState;
+use crate::server::supervisor::Supervisor;
+use crate::utils::clock::{Clock, SystemClock};
+
+/// Bearer tokens that every test app accepts, for a user and an admin.
+pub(crate) const TEST_TOKEN: &str = "test-token";
+pub(crate) const TEST_USER: &str = "test_user";
+pub(crate) const TEST_ADMIN_TOKEN: &str = "test-admin-token";
+pub(crate) const TEST_ADMIN: &str = "test_admin";
 
 #[derive(Debug)]
 pub(crate) struct ServerTestContext {
     pub(crate) config: Config,
-    repo: Arc<ChatMessageRepository>,
+    pub(crate) repo: Arc<ChatMessageRepository>,
 }
 
 #[async_trait::async_trait]
 impl AsyncTestContext for ServerTestContext {
     async fn setup() -> ServerTestContext {
         let config = Config::load("config.json").await.unwrap();
+        dal::migrations::run(&config).await.unwrap();
+
         let mut repo = ChatMessageRepository::new(&config).unwrap();
         repo.connect().await.unwrap();
+        repo.client
+            .as_ref()
+            .unwrap()
+            .execute(
+                "INSERT INTO users (name) VALUES ($1), ($2) ON CONFLICT DO NOTHING",
+                &[&TEST_USER, &TEST_ADMIN],
+            )
+            .await
+            .unwrap();
 
         ServerTestContext {
             config,
@@ -37,16 +69,69 @@ pub(crate) async fn setup_app(
 ) -> App<
     impl ServiceFactory<
         ServiceRequest,
-        Response=ServiceResponse<impl MessageBody>,
-        Config=(),
-        InitError=(),
-        Error=actix_web::Error,
+        Response = ServiceResponse<impl MessageBody>,
+        Config = (),
+        InitError = (),
+        Error = actix_web::Error,
     >,
 > {
     let mut repo = ChatMessageRepository::new(config).unwrap();
     let _ = &repo.connect().await;
 
+    let mut channels = ChannelRepository::new(config).unwrap();
+    let _ = &channels.connect().await;
+
+    let mut reactions = ReactionRepository::new(config).unwrap();
+    let _ = &reactions.connect().await;
+
+    let mut direct_messages = DirectMessageRepository::new(config).unwrap();
+    let _ = &direct_messages.connect().await;
+
+    let mut mentions = MentionRepository::new(config).unwrap();
+    let _ = &mentions.connect().await;
+
+    let mut stats = StatsRepository::new(config).unwrap();
+    let _ = &stats.connect().await;
+
+    let mut retention = RetentionRepository::new(config).unwrap();
+    let _ = &retention.connect().await;
+
+    let mut importer = ImportRepository::new(config).unwrap();
+    let _ = &importer.connect().await;
+
+    let mut api_config = config.api().unwrap();
+    api_config
+        .tokens
+        .insert(TEST_TOKEN.to_string(), TEST_USER.to_string());
+    api_config
+        .tokens
+        .insert(TEST_ADMIN_TOKEN.to_string(), TEST_ADMIN.to_string());
+    api_config.admins.insert(TEST_ADMIN.to_string());
+
+    let repo = Arc::new(repo);
+    let writer = BatchWriter::spawn(repo.clone(), config.ingest().unwrap());
+
+    let hub = Arc::new(Hub::new());
+    let ephemeral = config.ephemeral().unwrap();
+    let clock: Arc<dyn Clock> = Arc::new(SystemClock);
     App::new().app_data(Data::new(ServerState {
-        repo: Arc::new(repo),
+        repo,
+        writer,
+        channels: Arc::new(channels),
+        reactions: Arc::new(reactions),
+        direct_messages: Arc::new(direct_messages),
+        mentions: Arc::new(mentions),
+        stats: Arc::new(stats),
+        retention: Arc::new(retention),
+        importer: Arc::new(importer),
+        presence: Arc::new(Presence::new(hub.clone(), LEAVE_DEBOUNCE)),
+        typing: Arc::new(Typing::new(hub.clone(), ephemeral.typing_ttl)),
+        ephemeral: Arc::new(ephemeral),
+        supervisor: Arc::new(Supervisor::new(config.supervisor().unwrap(), clock.clone())),
+        clock,
+        recent: Arc::new(RecentMessages::new(config.resume().unwrap())),
+        hub,
+        tokens: Arc::new(api_config.tokens),
+        admins: Arc::new(api_config.admins),
     }))
-}
\ No newline at end of file
+}
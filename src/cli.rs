@@ -0,0 +1,245 @@
+use actix_web::web::Bytes;
+use futures::stream;
+use futures::StreamExt;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+use tokio::fs::File;
+use tokio::io::{self, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::config::Config;
+use crate::dal::chat_message_repository::ChatMessageRepository;
+use crate::dal::import_repository::ImportRepository;
+use crate::error::Error;
+use crate::error::Error::Configuration;
+use crate::utils::export::{self, ExportFormat};
+use crate::utils::import::{self, ImportFormat, ImportOptions};
+
+const USAGE: &str = "Usage: chatserver [serve]
+       chatserver export <channel> [--format ndjson|csv|txt] [--from <rfc3339>] [--to <rfc3339>] [--output <path>]
+       chatserver import <path> [--format ndjson|txt] [--channel <channel>] [--source <name>]";
+const READ_CHUNK_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum Command {
+    Serve,
+    Export(ExportArgs),
+    Import(ImportArgs),
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct ExportArgs {
+    pub(crate) channel: String,
+    pub(crate) format: ExportFormat,
+    pub(crate) from: Option<OffsetDateTime>,
+    pub(crate) to: Option<OffsetDateTime>,
+    /// Defaults to stdout.
+    pub(crate) output: Option<String>,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct ImportArgs {
+    pub(crate) path: String,
+    /// Inferred from the file extension if not given.
+    pub(crate) format: Option<ImportFormat>,
+    pub(crate) channel: Option<String>,
+    /// Defaults to the path.
+    pub(crate) source: Option<String>,
+}
+
+/// Parses the command line arguments, without the program name.
+pub(crate) fn parse<I>(args: I) -> Result<Command, Error>
+where
+    I: IntoIterator<Item = String>,
+{
+    let mut args = args.into_iter();
+
+    match args.next().as_deref() {
+        None | Some("serve") => Ok(Command::Serve),
+        Some("export") => parse_export(args).map(Command::Export),
+        Some("import") => parse_import(args).map(Command::Import),
+        Some(other) => Err(usage_error(&format!("Unknown command: {}", other))),
+    }
+}
+
+fn parse_export(mut args: impl Iterator<Item = String>) -> Result<ExportArgs, Error> {
+    let mut export = ExportArgs::default();
+
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| usage_error(&format!("Missing value for {}", arg)))
+        };
+
+        match arg.as_str() {
+            "--format" => export.format = value()?.parse()?,
+            "--from" => export.from = Some(parse_timestamp(&value()?)?),
+            "--to" => export.to = Some(parse_timestamp(&value()?)?),
+            "--output" => export.output = Some(value()?),
+            _ if arg.starts_with("--") => {
+                return Err(usage_error(&format!("Unknown option: {}", arg)));
+            }
+            _ if export.channel.is_empty() => export.channel = arg,
+            _ => return Err(usage_error(&format!("Unexpected argument: {}", arg))),
+        }
+    }
+
+    if export.channel.is_empty() {
+        return Err(usage_error("No channel specified"));
+    }
+
+    Ok(export)
+}
+
+fn parse_import(mut args: impl Iterator<Item = String>) -> Result<ImportArgs, Error> {
+    let mut import = ImportArgs::default();
+
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| usage_error(&format!("Missing value for {}", arg)))
+        };
+
+        match arg.as_str() {
+            "--format" => import.format = Some(value()?.parse()?),
+            "--channel" => import.channel = Some(value()?),
+            "--source" => import.source = Some(value()?),
+            _ if arg.starts_with("--") => {
+                return Err(usage_error(&format!("Unknown option: {}", arg)));
+            }
+            _ if import.path.is_empty() => import.path = arg,
+            _ => return Err(usage_error(&format!("Unexpected argument: {}", arg))),
+        }
+    }
+
+    if import.path.is_empty() {
+        return Err(usage_error("No file specified"));
+    }
+
+    Ok(import)
+}
+
+fn parse_timestamp(value: &str) -> Result<OffsetDateTime, Error> {
+    OffsetDateTime::parse(value, &Rfc3339)
+        .map_err(|e| usage_error(&format!("Invalid timestamp {}: {}", value, e)))
+}
+
+fn usage_error(message: &str) -> Error {
+    Configuration(format!("{}\n{}", message, USAGE))
+}
+
+/// Writes a channel's log to the requested output, chunk by chunk.
+pub(crate) async fn export(config: &Config, args: ExportArgs) -> Result<(), Error> {
+    let repo = ChatMessageRepository::new(config)?;
+    let messages = repo
+        .stream_messages_from_channel(&args.channel, args.from, args.to)
+        .await?;
+
+    let mut output: Box<dyn AsyncWrite + Unpin> = match &args.output {
+        Some(path) => Box::new(File::create(path).await?),
+        None => Box::new(io::stdout()),
+    };
+
+    let mut chunks = Box::pin(export::encode_stream(messages, args.format));
+    while let Some(chunk) = chunks.next().await {
+        output.write_all(&chunk?).await?;
+    }
+
+    output.flush().await?;
+    Ok(())
+}
+
+/// Imports a log file, resuming an earlier run of the same source.
+pub(crate) async fn import(config: &Config, args: ImportArgs) -> Result<(), Error> {
+    let mut repo = ImportRepository::new(config)?;
+    repo.connect().await?;
+
+    let options = ImportOptions {
+        source: args.source.unwrap_or_else(|| args.path.clone()),
+        format: args
+            .format
+            .unwrap_or_else(|| ImportFormat::from_path(&args.path)),
+        channel: args.channel,
+    };
+
+    let file = File::open(&args.path).await?;
+    let chunks = stream::unfold(file, |mut file| async move {
+        let mut buffer = vec![0; READ_CHUNK_SIZE];
+        match file.read(&mut buffer).await {
+            Ok(0) => None,
+            Ok(read) => {
+                buffer.truncate(read);
+                Some((Ok(Bytes::from(buffer)), file))
+            }
+            Err(err) => Some((Err(err), file)),
+        }
+    });
+
+    let lines = import::lines_from_chunks(chunks);
+    let report = import::import_lines(&mut repo, lines, &options).await?;
+
+    for error in report.errors.iter() {
+        eprintln!("line {}: {}", error.line, error.error);
+    }
+    eprintln!(
+        "Imported {} messages from {} lines of {} ({} errors)",
+        report.imported, report.lines_read, report.source, report.error_count
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_serve() {
+        assert_eq!(parse(args(&[])).unwrap(), Command::Serve);
+        assert_eq!(parse(args(&["serve"])).unwrap(), Command::Serve);
+        assert!(parse(args(&["dance"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_export() {
+        let command = parse(args(&[
+            "export",
+            "chan",
+            "--format",
+            "txt",
+            "--from",
+            "2023-01-01T00:00:00Z",
+        ]))
+        .unwrap();
+
+        let Command::Export(export) = command else {
+            panic!("Expected an export command");
+        };
+        assert_eq!(export.channel, "chan");
+        assert_eq!(export.format, ExportFormat::Txt);
+        assert_eq!(export.from.unwrap().unix_timestamp(), 1672531200);
+        assert_eq!(export.to, None);
+
+        assert!(parse(args(&["export"])).is_err());
+        assert!(parse(args(&["export", "chan", "--format"])).is_err());
+        assert!(parse(args(&["export", "chan", "--from", "yesterday"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_import() {
+        let command = parse(args(&["import", "chan.log", "--channel", "chan"])).unwrap();
+
+        let Command::Import(import) = command else {
+            panic!("Expected an import command");
+        };
+        assert_eq!(import.path, "chan.log");
+        assert_eq!(import.channel.as_deref(), Some("chan"));
+        assert_eq!(import.format, None);
+
+        assert!(parse(args(&["import"])).is_err());
+        assert!(parse(args(&["import", "a", "b"])).is_err());
+    }
+}
@@ -13,19 +13,6 @@ pub struct Config {
 }
 
 impl Config {
-    fn new(json: JsonValue) -> Self {
-        Self { json }
-    }
-
-    fn from_key(json: JsonValue, key: &str) -> Result<Self, Error> {
-        let json = json[key].clone();
-        Ok(Self::new(json))
-    }
-
-    pub(crate) fn get(self, key: &str) -> Result<Self, Error> {
-        Self::from_key(self.json, key)
-    }
-
     pub async fn load(path: &str) -> Result<Self, Error> {
         let mut file = File::open(path).await?;
         let mut contents = String::new();
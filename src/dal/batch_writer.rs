@@ -0,0 +1,324 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::{debug, error, warn};
+use serde::Serialize;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::Instant;
+
+use crate::config::Config;
+use crate::dal::chat_message_repository::ChatMessageRepository;
+use crate::error::Error;
+use crate::error::Error::Configuration;
+use crate::models::chat_message::ChatMessage;
+
+const DEFAULT_BATCH_SIZE: u64 = 500;
+const DEFAULT_FLUSH_INTERVAL_MS: u64 = 50;
+const DEFAULT_QUEUE_CAPACITY: u64 = 10_000;
+const ERR_INVALID_INGEST: &str = "ingest.batch_size and ingest.queue_capacity must be positive";
+const ERR_WRITER_STOPPED: &str = "The message writer has stopped";
+
+#[derive(Debug, Clone)]
+pub struct IngestConfig {
+    /// A batch is written as soon as it holds this many messages...
+    pub batch_size: usize,
+    /// ...or once its oldest message has waited this long.
+    pub flush_interval: Duration,
+    /// Submitting waits while this many messages are queued.
+    pub queue_capacity: usize,
+}
+
+impl Config {
+    pub(crate) fn ingest(&self) -> Result<IngestConfig, Error> {
+        let json = self["ingest"].clone();
+        let get =
+            |key: &str, default: u64| json.get(key).and_then(|x| x.as_u64()).unwrap_or(default);
+
+        let batch_size = get("batch_size", DEFAULT_BATCH_SIZE) as usize;
+        let queue_capacity = get("queue_capacity", DEFAULT_QUEUE_CAPACITY) as usize;
+        if batch_size == 0 || queue_capacity == 0 {
+            return Err(Configuration(ERR_INVALID_INGEST.to_string()));
+        }
+
+        Ok(IngestConfig {
+            batch_size,
+            flush_interval: Duration::from_millis(get(
+                "flush_interval_ms",
+                DEFAULT_FLUSH_INTERVAL_MS,
+            )),
+            queue_capacity,
+        })
+    }
+}
+
+/// The outcome of a single message, sent once its batch has been written.
+pub type WriteAck = oneshot::Receiver<Result<ChatMessage, Error>>;
+
+#[derive(Debug)]
+struct PendingMessage {
+    message: ChatMessage,
+    queued_at: Instant,
+    ack: oneshot::Sender<Result<ChatMessage, Error>>,
+}
+
+#[derive(Debug, Default)]
+struct IngestMetrics {
+    batches: AtomicU64,
+    messages: AtomicU64,
+    failed: AtomicU64,
+    last_batch_size: AtomicU64,
+    last_write_micros: AtomicU64,
+    max_write_micros: AtomicU64,
+    last_wait_micros: AtomicU64,
+}
+
+impl IngestMetrics {
+    fn record(&self, size: usize, failed: usize, write: Duration, wait: Duration) {
+        let write = write.as_micros() as u64;
+
+        self.batches.fetch_add(1, Ordering::Relaxed);
+        self.messages.fetch_add(size as u64, Ordering::Relaxed);
+        self.failed.fetch_add(failed as u64, Ordering::Relaxed);
+        self.last_batch_size.store(size as u64, Ordering::Relaxed);
+        self.last_write_micros.store(write, Ordering::Relaxed);
+        self.max_write_micros.fetch_max(write, Ordering::Relaxed);
+        self.last_wait_micros
+            .store(wait.as_micros() as u64, Ordering::Relaxed);
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct IngestStats {
+    pub batches: u64,
+    pub messages: u64,
+    pub failed: u64,
+    pub queued: usize,
+    pub last_batch_size: u64,
+    /// How long the last batch took to write.
+    pub last_write_ms: f64,
+    pub max_write_ms: f64,
+    /// How long the oldest message of the last batch waited to be written.
+    pub last_wait_ms: f64,
+}
+
+/// Collects submitted messages into batches and writes each with a single
+/// insert. Batches are written one at a time, in submission order.
+#[derive(Debug, Clone)]
+pub struct BatchWriter {
+    sender: mpsc::Sender<PendingMessage>,
+    queue_capacity: usize,
+    metrics: Arc<IngestMetrics>,
+}
+
+impl BatchWriter {
+    /// Starts the writer task, which runs until every handle is dropped and
+    /// the queue has drained.
+    pub fn spawn(repo: Arc<ChatMessageRepository>, config: IngestConfig) -> Self {
+        let (sender, receiver) = mpsc::channel(config.queue_capacity);
+        let metrics = Arc::new(IngestMetrics::default());
+
+        tokio::spawn(run(repo, receiver, config.clone(), metrics.clone()));
+
+        Self {
+            sender,
+            queue_capacity: config.queue_capacity,
+            metrics,
+        }
+    }
+
+    /// Queues a message, waiting for room if the queue is full. The returned
+    /// receiver resolves once the message has been written.
+    pub async fn submit(&self, message: ChatMessage) -> Result<WriteAck, Error> {
+        let (ack, receiver) = oneshot::channel();
+        let pending = PendingMessage {
+            message,
+            queued_at: Instant::now(),
+            ack,
+        };
+
+        self.sender
+            .send(pending)
+            .await
+            .map_err(|_| Error::Server(ERR_WRITER_STOPPED.to_string()))?;
+
+        Ok(receiver)
+    }
+
+    /// Queues a message and waits until it has been written.
+    pub async fn write(&self, message: ChatMessage) -> Result<ChatMessage, Error> {
+        self.submit(message)
+            .await?
+            .await
+            .map_err(|_| Error::Server(ERR_WRITER_STOPPED.to_string()))?
+    }
+
+    pub fn stats(&self) -> IngestStats {
+        let metrics = &self.metrics;
+        let millis = |x: &AtomicU64| x.load(Ordering::Relaxed) as f64 / 1000.0;
+
+        IngestStats {
+            batches: metrics.batches.load(Ordering::Relaxed),
+            messages: metrics.messages.load(Ordering::Relaxed),
+            failed: metrics.failed.load(Ordering::Relaxed),
+            queued: self.queue_capacity - self.sender.capacity(),
+            last_batch_size: metrics.last_batch_size.load(Ordering::Relaxed),
+            last_write_ms: millis(&metrics.last_write_micros),
+            max_write_ms: millis(&metrics.max_write_micros),
+            last_wait_ms: millis(&metrics.last_wait_micros),
+        }
+    }
+}
+
+async fn run(
+    repo: Arc<ChatMessageRepository>,
+    mut receiver: mpsc::Receiver<PendingMessage>,
+    config: IngestConfig,
+    metrics: Arc<IngestMetrics>,
+) {
+    while let Some(first) = receiver.recv().await {
+        let deadline = first.queued_at + config.flush_interval;
+        let mut batch = vec![first];
+
+        while batch.len() < config.batch_size {
+            match tokio::time::timeout_at(deadline, receiver.recv()).await {
+                Ok(Some(pending)) => batch.push(pending),
+                Ok(None) | Err(_) => break,
+            }
+        }
+
+        write_batch(&repo, batch, &metrics).await;
+    }
+
+    debug!("Message writer stopped");
+}
+
+async fn write_batch(
+    repo: &ChatMessageRepository,
+    batch: Vec<PendingMessage>,
+    metrics: &IngestMetrics,
+) {
+    let started = Instant::now();
+    let wait = started - batch[0].queued_at;
+    let size = batch.len();
+
+    let (messages, acks): (Vec<ChatMessage>, Vec<_>) = batch
+        .into_iter()
+        .map(|pending| (pending.message, pending.ack))
+        .unzip();
+
+    let results = match repo.add_messages(&messages).await {
+        Ok(stored) => stored.into_iter().map(Ok).collect(),
+        Err(err) => {
+            // One bad message fails the whole insert, so retry one at a time
+            // to find out which ones were at fault.
+            warn!("Could not write batch of {} messages: {}", size, err);
+            let mut results = Vec::with_capacity(size);
+            for message in messages.iter() {
+                results.push(repo.add_message(message).await);
+            }
+            results
+        }
+    };
+
+    let failed = results.iter().filter(|r| r.is_err()).count();
+    let write = started.elapsed();
+    metrics.record(size, failed, write, wait);
+    debug!(
+        "Wrote batch of {} messages in {:?} after waiting {:?}",
+        size, write, wait
+    );
+
+    for (result, ack) in results.into_iter().zip(acks) {
+        if let Err(err) = &result {
+            error!("Could not add message: {}", err);
+        }
+
+        // Nobody waiting on the ack is fine.
+        let _ = ack.send(result);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use fake::{Fake, Faker};
+    use test_context::{test_context, AsyncTestContext};
+    use time::OffsetDateTime;
+    use tokio::test;
+
+    use super::*;
+
+    struct BatchWriterTestContext {
+        repo: Arc<ChatMessageRepository>,
+    }
+
+    #[async_trait::async_trait]
+    impl AsyncTestContext for BatchWriterTestContext {
+        async fn setup() -> BatchWriterTestContext {
+            let config = Config::load("config.json").await.unwrap();
+            let mut repo = ChatMessageRepository::new(&config).unwrap();
+            repo.connect().await.unwrap();
+
+            BatchWriterTestContext {
+                repo: Arc::new(repo),
+            }
+        }
+
+        async fn teardown(self) {}
+    }
+
+    #[test_context(BatchWriterTestContext)]
+    #[test]
+    async fn repo_batch_writer_keeps_order(ctx: &BatchWriterTestContext) -> Result<(), Error> {
+        let channel: String = Faker.fake();
+        let client = ctx.repo.client.as_ref().unwrap();
+        client
+            .execute(
+                "INSERT INTO users (name) VALUES ($1) ON CONFLICT DO NOTHING",
+                &[&channel],
+            )
+            .await?;
+
+        let config = IngestConfig {
+            batch_size: 4,
+            flush_interval: Duration::from_millis(10),
+            queue_capacity: 16,
+        };
+        let writer = BatchWriter::spawn(ctx.repo.clone(), config);
+
+        let mut acks = Vec::new();
+        for i in 0..10 {
+            let message = ChatMessage::new(
+                i.to_string(),
+                channel.clone(),
+                channel.clone(),
+                OffsetDateTime::now_utc(),
+            );
+            acks.push(writer.submit(message).await?);
+        }
+
+        // An unknown user fails on its own without taking the batch with it.
+        let unknown = ChatMessage::new(
+            "lost".to_string(),
+            Faker.fake(),
+            channel.clone(),
+            OffsetDateTime::now_utc(),
+        );
+        assert!(writer.write(unknown).await.is_err());
+
+        let mut ids = Vec::new();
+        for (i, ack) in acks.into_iter().enumerate() {
+            let stored = ack.await.unwrap()?;
+            assert_eq!(stored.text, i.to_string());
+            ids.push(stored.id.unwrap());
+        }
+        assert!(ids.windows(2).all(|w| w[0] < w[1]));
+
+        let stats = writer.stats();
+        assert_eq!(stats.messages, 11);
+        assert_eq!(stats.failed, 1);
+        assert!(stats.batches >= 3);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,599 @@
+use enum_iterator::Sequence;
+use futures::stream::{self, Stream};
+use log::warn;
+use tokio_postgres::types::Type;
+use tokio_postgres::Client;
+
+use crate::config::Config;
+use crate::dal;
+use crate::error::Error;
+use crate::models::channel::{Channel, ChannelSummary, ChannelUpdate, NewChannel};
+use crate::models::channel_member::{ChannelAccess, ChannelMember, ChannelRole};
+use crate::utils::connection_string::ConnectionString;
+use crate::utils::repo_statement::ToRepoStatement;
+
+/// The name of every channel whose readers may have changed is announced
+/// here to every instance.
+const ACCESS_NOTIFY_CHANNEL: &str = "channel_access";
+
+#[derive(Debug, PartialEq, Sequence)]
+enum ChannelRepoStatement {
+    InsertUser,
+    Insert,
+    GetByName,
+    Update,
+    Delete,
+    ListWithActivity,
+    HasMessages,
+    GetAccess,
+    ListMembers,
+    SetMember,
+    RemoveMember,
+    Notify,
+    Listen,
+}
+
+impl ToRepoStatement for ChannelRepoStatement {
+    fn as_string(&self) -> String {
+        match self {
+            ChannelRepoStatement::InsertUser => "INSERT INTO users (name) VALUES ($1) ON CONFLICT DO NOTHING".to_string(),
+            ChannelRepoStatement::Insert => "INSERT INTO channels (name, display_name, description, private) VALUES ($1, $2, $3, $4) ON CONFLICT DO NOTHING RETURNING *".to_string(),
+            ChannelRepoStatement::GetByName => "SELECT * FROM channels WHERE name = $1".to_string(),
+            ChannelRepoStatement::Update => "UPDATE channels SET display_name = COALESCE($2, display_name), description = CASE WHEN $6 THEN $3 ELSE description END, archived = COALESCE($4, archived), private = COALESCE($5, private) WHERE name = $1 RETURNING *".to_string(),
+            // Once unregistered the channel is public, so its history goes with it.
+            ChannelRepoStatement::Delete => "WITH deleted_channel AS (DELETE FROM channels WHERE name = $1 RETURNING name), deleted AS (DELETE FROM chat_messages WHERE channel IN (SELECT name FROM deleted_channel) RETURNING id), deleted_reactions AS (DELETE FROM reactions WHERE message_id IN (SELECT id FROM deleted)), cleared_logs AS (UPDATE logs SET chat_message_id = NULL WHERE chat_message_id IN (SELECT id FROM deleted)), deleted_keys AS (DELETE FROM message_keys WHERE message_id IN (SELECT id FROM deleted)), deleted_mentions AS (DELETE FROM mentions WHERE message_id IN (SELECT id FROM deleted)), deleted_rollups AS (DELETE FROM chat_message_rollups WHERE channel IN (SELECT name FROM deleted_channel)) SELECT COUNT(*) AS count FROM deleted_channel".to_string(),
+            // Activity is counted as messages are stored, see channel_sequences.
+            ChannelRepoStatement::ListWithActivity => "SELECT c.*, COALESCE(s.message_count, 0) AS message_count, s.last_activity FROM channels c LEFT JOIN channel_sequences s ON s.channel = c.name WHERE ($1 OR NOT c.archived) AND (NOT c.private OR EXISTS (SELECT 1 FROM channel_members cm WHERE cm.channel = c.name AND cm.username = $2)) ORDER BY c.name".to_string(),
+            ChannelRepoStatement::HasMessages => "SELECT EXISTS (SELECT 1 FROM channel_sequences WHERE channel = $1 AND message_count > 0) AS has_messages".to_string(),
+            // Channels missing from the registry are public.
+            ChannelRepoStatement::GetAccess => "SELECT c.private, m.role FROM channels c LEFT JOIN channel_members m ON m.channel = c.name AND m.username = $2 WHERE c.name = $1".to_string(),
+            ChannelRepoStatement::ListMembers => "SELECT * FROM channel_members WHERE channel = $1 ORDER BY added_at, username".to_string(),
+            ChannelRepoStatement::SetMember => "INSERT INTO channel_members (channel, username, role) VALUES ($1, $2, $3) ON CONFLICT (channel, username) DO UPDATE SET role = EXCLUDED.role RETURNING *".to_string(),
+            ChannelRepoStatement::RemoveMember => "DELETE FROM channel_members WHERE channel = $1 AND username = $2".to_string(),
+            ChannelRepoStatement::Notify => "SELECT pg_notify($1, $2)".to_string(),
+            ChannelRepoStatement::Listen => format!("LISTEN {}", ACCESS_NOTIFY_CHANNEL),
+        }
+    }
+
+    fn get_types(&self) -> Vec<Type> {
+        match self {
+            ChannelRepoStatement::InsertUser => vec![Type::TEXT],
+            ChannelRepoStatement::Insert => vec![Type::TEXT, Type::TEXT, Type::TEXT, Type::BOOL],
+            ChannelRepoStatement::GetByName => vec![Type::TEXT],
+            ChannelRepoStatement::Update => {
+                vec![
+                    Type::TEXT,
+                    Type::TEXT,
+                    Type::TEXT,
+                    Type::BOOL,
+                    Type::BOOL,
+                    Type::BOOL,
+                ]
+            }
+            ChannelRepoStatement::Delete => vec![Type::TEXT],
+            ChannelRepoStatement::ListWithActivity => vec![Type::BOOL, Type::TEXT],
+            ChannelRepoStatement::HasMessages => vec![Type::TEXT],
+            ChannelRepoStatement::GetAccess => vec![Type::TEXT, Type::TEXT],
+            ChannelRepoStatement::ListMembers => vec![Type::TEXT],
+            ChannelRepoStatement::SetMember => vec![Type::TEXT, Type::TEXT, Type::TEXT],
+            ChannelRepoStatement::RemoveMember => vec![Type::TEXT, Type::TEXT],
+            ChannelRepoStatement::Notify => vec![Type::TEXT, Type::TEXT],
+            ChannelRepoStatement::Listen => vec![],
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ChannelRepository {
+    connection_string: ConnectionString,
+    pub client: Option<Client>,
+}
+
+impl ChannelRepository {
+    pub fn new(config: &Config) -> Result<Self, Error> {
+        let connection_string = config.db()?;
+
+        Ok(Self {
+            connection_string,
+            client: None,
+        })
+    }
+
+    pub async fn connect(&mut self) -> Result<(), Error> {
+        let client = dal::connect(&self.connection_string).await?;
+        self.client = Some(client);
+
+        Ok(())
+    }
+
+    /// Registers a channel, creating the matching user if needed, with
+    /// `owner` as its first owner if given. Returns `None` if a channel with
+    /// this name already exists.
+    pub async fn add_channel(
+        &self,
+        channel: &NewChannel,
+        owner: Option<&str>,
+    ) -> Result<Option<Channel>, Error> {
+        let client = self.client.as_ref().unwrap();
+
+        client
+            .execute(
+                &ChannelRepoStatement::InsertUser.as_string(),
+                &[&channel.name],
+            )
+            .await?;
+
+        let display_name = channel.display_name.as_ref().unwrap_or(&channel.name);
+        let row = client
+            .query_opt(
+                &ChannelRepoStatement::Insert.as_string(),
+                &[
+                    &channel.name,
+                    display_name,
+                    &channel.description,
+                    &channel.private,
+                ],
+            )
+            .await?;
+
+        let Some(row) = row else {
+            return Ok(None);
+        };
+
+        if let Some(owner) = owner {
+            self.set_member(&channel.name, owner, ChannelRole::Owner)
+                .await?;
+        }
+
+        Ok(Some(Channel::from(&row)))
+    }
+
+    pub async fn get_channel(&self, name: &str) -> Result<Option<Channel>, Error> {
+        let client = self.client.as_ref().unwrap();
+
+        let row = client
+            .query_opt(&ChannelRepoStatement::GetByName.as_string(), &[&name])
+            .await?;
+
+        Ok(row.as_ref().map(Channel::from))
+    }
+
+    /// Updates the channel, announcing to every instance that its readers
+    /// may have changed.
+    pub async fn update_channel(
+        &self,
+        name: &str,
+        update: &ChannelUpdate,
+    ) -> Result<Option<Channel>, Error> {
+        let client = self.client.as_ref().unwrap();
+
+        let row = client
+            .query_opt(
+                &ChannelRepoStatement::Update.as_string(),
+                &[
+                    &name,
+                    &update.display_name,
+                    &update.description.clone().flatten(),
+                    &update.archived,
+                    &update.private,
+                    &update.description.is_some(),
+                ],
+            )
+            .await?;
+
+        if row.is_some() && update.private.is_some() {
+            self.notify_access_changed(name).await;
+        }
+
+        Ok(row.as_ref().map(Channel::from))
+    }
+
+    /// Removes the channel from the registry along with every message posted
+    /// to it, as anyone could read them once the channel is unregistered.
+    pub async fn delete_channel(&self, name: &str) -> Result<bool, Error> {
+        let client = self.client.as_ref().unwrap();
+
+        let row = client
+            .query_one(&ChannelRepoStatement::Delete.as_string(), &[&name])
+            .await?;
+        let deleted: i64 = row.get("count");
+
+        Ok(deleted > 0)
+    }
+
+    /// Lists the public channels, and the private ones `username` is a
+    /// member of.
+    pub async fn list_channels(
+        &self,
+        include_archived: bool,
+        username: Option<&str>,
+    ) -> Result<Vec<ChannelSummary>, Error> {
+        let client = self.client.as_ref().unwrap();
+
+        let rows = client
+            .query(
+                &ChannelRepoStatement::ListWithActivity.as_string(),
+                &[&include_archived, &username],
+            )
+            .await?;
+
+        Ok(rows.iter().map(ChannelSummary::from).collect())
+    }
+
+    /// Whether anything was posted to the channel that is still stored,
+    /// registered or not.
+    pub async fn has_messages(&self, name: &str) -> Result<bool, Error> {
+        let client = self.client.as_ref().unwrap();
+
+        let row = client
+            .query_one(&ChannelRepoStatement::HasMessages.as_string(), &[&name])
+            .await?;
+
+        Ok(row.get("has_messages"))
+    }
+
+    /// What `username`, or an anonymous client if `None`, may do in the
+    /// channel.
+    pub async fn get_access(
+        &self,
+        channel: &str,
+        username: Option<&str>,
+    ) -> Result<ChannelAccess, Error> {
+        let client = self.client.as_ref().unwrap();
+
+        let row = client
+            .query_opt(
+                &ChannelRepoStatement::GetAccess.as_string(),
+                &[&channel, &username],
+            )
+            .await?;
+
+        Ok(row.as_ref().map(ChannelAccess::from).unwrap_or_default())
+    }
+
+    pub async fn list_members(&self, channel: &str) -> Result<Vec<ChannelMember>, Error> {
+        let client = self.client.as_ref().unwrap();
+
+        let members = client
+            .query(&ChannelRepoStatement::ListMembers.as_string(), &[&channel])
+            .await?
+            .iter()
+            .map(ChannelMember::from)
+            .collect();
+
+        Ok(members)
+    }
+
+    /// Adds a member to the channel, or changes the role of an existing one.
+    pub async fn set_member(
+        &self,
+        channel: &str,
+        username: &str,
+        role: ChannelRole,
+    ) -> Result<ChannelMember, Error> {
+        let client = self.client.as_ref().unwrap();
+
+        client
+            .execute(&ChannelRepoStatement::InsertUser.as_string(), &[&username])
+            .await?;
+
+        let row = client
+            .query_one(
+                &ChannelRepoStatement::SetMember.as_string(),
+                &[&channel, &username, &role.as_str()],
+            )
+            .await?;
+
+        Ok(ChannelMember::from(&row))
+    }
+
+    /// Removes a member from the channel, announcing to every instance that
+    /// they may no longer read it.
+    pub async fn remove_member(&self, channel: &str, username: &str) -> Result<bool, Error> {
+        let client = self.client.as_ref().unwrap();
+
+        let removed = client
+            .execute(
+                &ChannelRepoStatement::RemoveMember.as_string(),
+                &[&channel, &username],
+            )
+            .await?;
+
+        if removed > 0 {
+            self.notify_access_changed(channel).await;
+        }
+
+        Ok(removed > 0)
+    }
+
+    /// The change is already made, so failing to announce it is logged
+    /// rather than returned.
+    async fn notify_access_changed(&self, channel: &str) {
+        let client = self.client.as_ref().unwrap();
+
+        if let Err(err) = client
+            .execute(
+                &ChannelRepoStatement::Notify.as_string(),
+                &[&ACCESS_NOTIFY_CHANNEL, &channel],
+            )
+            .await
+        {
+            warn!("Could not announce access change to {}: {}", channel, err);
+        }
+    }
+
+    /// Streams the names of the channels whose readers may have changed on
+    /// any instance from now on, over a dedicated connection. The stream
+    /// ends if that connection is lost.
+    pub async fn listen(&self) -> Result<impl Stream<Item = String> + 'static, Error> {
+        let (client, notifications) =
+            dal::connect_with_notifications(&self.connection_string).await?;
+        client
+            .batch_execute(&ChannelRepoStatement::Listen.as_string())
+            .await?;
+
+        // The client is carried along so the connection stays open.
+        let channels = stream::unfold(
+            (client, notifications),
+            |(client, mut notifications)| async move {
+                let notification = notifications.recv().await?;
+                let channel = notification.payload().to_string();
+                Some((channel, (client, notifications)))
+            },
+        );
+
+        Ok(channels)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use fake::{Fake, Faker};
+    use futures::StreamExt;
+    use test_context::{test_context, AsyncTestContext};
+    use time::OffsetDateTime;
+    use tokio::test;
+
+    use super::*;
+    use crate::dal::chat_message_repository::ChatMessageRepository;
+    use crate::models::chat_message::ChatMessage;
+
+    struct ChannelRepoTestContext {
+        repo: ChannelRepository,
+    }
+
+    #[async_trait::async_trait]
+    impl AsyncTestContext for ChannelRepoTestContext {
+        async fn setup() -> ChannelRepoTestContext {
+            let config = Config::load("config.json").await.unwrap();
+            dal::migrations::run(&config).await.unwrap();
+
+            let mut repo = ChannelRepository::new(&config).unwrap();
+            repo.connect().await.unwrap();
+
+            ChannelRepoTestContext { repo }
+        }
+
+        async fn teardown(self) {}
+    }
+
+    #[test_context(ChannelRepoTestContext)]
+    #[test]
+    async fn repo_channel_crud(ctx: &ChannelRepoTestContext) -> Result<(), Error> {
+        let new_channel = NewChannel {
+            name: Faker.fake(),
+            display_name: None,
+            description: Some("A test channel".to_string()),
+            private: false,
+        };
+
+        let channel = ctx.repo.add_channel(&new_channel, None).await?.unwrap();
+        assert_eq!(channel.display_name, new_channel.name);
+        assert!(!channel.archived);
+        assert!(ctx.repo.add_channel(&new_channel, None).await?.is_none());
+
+        let update = ChannelUpdate {
+            archived: Some(true),
+            ..Default::default()
+        };
+        let channel = ctx
+            .repo
+            .update_channel(&channel.name, &update)
+            .await?
+            .unwrap();
+        assert!(channel.archived);
+        assert_eq!(channel.description, new_channel.description);
+
+        let update: ChannelUpdate = serde_json::from_str(r#"{"description": null}"#)?;
+        let channel = ctx
+            .repo
+            .update_channel(&channel.name, &update)
+            .await?
+            .unwrap();
+        assert_eq!(channel.description, None);
+        assert!(channel.archived);
+
+        let listed = ctx.repo.list_channels(false, None).await?;
+        assert!(listed.iter().all(|s| s.channel.name != channel.name));
+        let listed = ctx.repo.list_channels(true, None).await?;
+        let summary = listed
+            .iter()
+            .find(|s| s.channel.name == channel.name)
+            .unwrap();
+        assert_eq!(summary.message_count, 0);
+        assert_eq!(summary.last_activity, None);
+
+        assert!(ctx.repo.delete_channel(&channel.name).await?);
+        assert!(ctx.repo.get_channel(&channel.name).await?.is_none());
+
+        Ok(())
+    }
+
+    #[test_context(ChannelRepoTestContext)]
+    #[test]
+    async fn repo_private_channel_membership(ctx: &ChannelRepoTestContext) -> Result<(), Error> {
+        let (owner, member): (String, String) = (Faker.fake(), Faker.fake());
+        let new_channel = NewChannel {
+            name: Faker.fake(),
+            display_name: None,
+            description: None,
+            private: true,
+        };
+        let name = new_channel.name.clone();
+
+        let channel = ctx
+            .repo
+            .add_channel(&new_channel, Some(&owner))
+            .await?
+            .unwrap();
+        assert!(channel.private);
+
+        let access = ctx.repo.get_access(&name, Some(&owner)).await?;
+        assert!(access.is_owner());
+        assert!(!ctx.repo.get_access(&name, None).await?.can_read());
+        assert!(!ctx.repo.get_access(&name, Some(&member)).await?.can_read());
+        assert!(ctx.repo.get_access("unregistered", None).await?.can_read());
+
+        ctx.repo
+            .set_member(&name, &member, ChannelRole::Member)
+            .await?;
+        let access = ctx.repo.get_access(&name, Some(&member)).await?;
+        assert_eq!(access.role, Some(ChannelRole::Member));
+
+        let listed = ctx.repo.list_channels(false, Some(&member)).await?;
+        assert!(listed.iter().any(|s| s.channel.name == name));
+        let listed = ctx.repo.list_channels(false, None).await?;
+        assert!(listed.iter().all(|s| s.channel.name != name));
+
+        let members = ctx.repo.list_members(&name).await?;
+        assert_eq!(members.len(), 2);
+
+        assert!(ctx.repo.remove_member(&name, &member).await?);
+        assert!(!ctx.repo.remove_member(&name, &member).await?);
+
+        // Members go along with the channel.
+        assert!(ctx.repo.delete_channel(&name).await?);
+        assert!(ctx.repo.list_members(&name).await?.is_empty());
+
+        Ok(())
+    }
+
+    #[test_context(ChannelRepoTestContext)]
+    #[test]
+    async fn repo_delete_private_channel_removes_history(
+        ctx: &ChannelRepoTestContext,
+    ) -> Result<(), Error> {
+        let owner: String = Faker.fake();
+        let new_channel = NewChannel {
+            name: Faker.fake(),
+            display_name: None,
+            description: None,
+            private: true,
+        };
+        let name = new_channel.name.clone();
+        ctx.repo.add_channel(&new_channel, Some(&owner)).await?;
+
+        let config = Config::load("config.json").await?;
+        let mut messages = ChatMessageRepository::new(&config)?;
+        messages.connect().await?;
+        let message = ChatMessage::new(
+            Faker.fake(),
+            owner.clone(),
+            name.clone(),
+            OffsetDateTime::now_utc(),
+        );
+        messages.add_message(&message).await?;
+
+        assert!(ctx.repo.delete_channel(&name).await?);
+
+        // Anyone may read the channel now, but there is nothing left to read.
+        assert!(ctx.repo.get_access(&name, None).await?.can_read());
+        assert!(messages
+            .get_messages_from_channel(&name, 10)
+            .await?
+            .is_empty());
+
+        Ok(())
+    }
+
+    #[test_context(ChannelRepoTestContext)]
+    #[test]
+    async fn repo_list_channels_counts_activity(ctx: &ChannelRepoTestContext) -> Result<(), Error> {
+        let new_channel = NewChannel {
+            name: Faker.fake(),
+            display_name: None,
+            description: None,
+            private: false,
+        };
+        let name = new_channel.name.clone();
+        ctx.repo.add_channel(&new_channel, None).await?;
+
+        let config = Config::load("config.json").await?;
+        let mut messages = ChatMessageRepository::new(&config)?;
+        messages.connect().await?;
+
+        let start = OffsetDateTime::from_unix_timestamp(1672531200).unwrap();
+        let mut stored = vec![];
+        for minutes in [0, 5] {
+            let timestamp = start + time::Duration::minutes(minutes);
+            let message = ChatMessage::new(Faker.fake(), name.clone(), name.clone(), timestamp);
+            stored.push(messages.add_message(&message).await?);
+        }
+
+        let summary = |listed: Vec<ChannelSummary>| {
+            listed.into_iter().find(|s| s.channel.name == name).unwrap()
+        };
+        let listed = summary(ctx.repo.list_channels(false, None).await?);
+        assert_eq!(listed.message_count, 2);
+        assert_eq!(listed.last_activity, Some(stored[1].timestamp));
+
+        // Removing the latest message brings the activity back to the one before.
+        let client = ctx.repo.client.as_ref().unwrap();
+        client
+            .execute("DELETE FROM chat_messages WHERE id = $1", &[&stored[1].id])
+            .await?;
+        let listed = summary(ctx.repo.list_channels(false, None).await?);
+        assert_eq!(listed.message_count, 1);
+        assert_eq!(listed.last_activity, Some(stored[0].timestamp));
+
+        ctx.repo.delete_channel(&name).await?;
+
+        Ok(())
+    }
+
+    #[test_context(ChannelRepoTestContext)]
+    #[test]
+    async fn repo_listen_receives_access_changes(
+        ctx: &ChannelRepoTestContext,
+    ) -> Result<(), Error> {
+        let (owner, member): (String, String) = (Faker.fake(), Faker.fake());
+        let new_channel = NewChannel {
+            name: Faker.fake(),
+            display_name: None,
+            description: None,
+            private: true,
+        };
+        let name = new_channel.name.clone();
+        ctx.repo.add_channel(&new_channel, Some(&owner)).await?;
+        ctx.repo
+            .set_member(&name, &member, ChannelRole::Member)
+            .await?;
+
+        let config = Config::load("config.json").await?;
+        let listener = ChannelRepository::new(&config)?;
+        let mut channels = Box::pin(listener.listen().await?);
+
+        ctx.repo.remove_member(&name, &member).await?;
+
+        // Other tests may be changing channels at the same time.
+        tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            while channels.next().await.unwrap() != name {}
+        })
+        .await
+        .expect("No notification for the removed member");
+
+        ctx.repo.delete_channel(&name).await?;
+
+        Ok(())
+    }
+}
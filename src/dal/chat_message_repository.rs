@@ -1,36 +1,87 @@
+use std::collections::HashMap;
+
 use enum_iterator::Sequence;
-use tokio_postgres::types::Type;
-use tokio_postgres::{Client, NoTls, Row};
+use futures::stream::{self, Stream};
+use futures::StreamExt;
+use log::warn;
+use time::OffsetDateTime;
+use tokio_postgres::types::{Json, ToSql, Type};
+use tokio_postgres::{Client, Row};
 
 use crate::config::Config;
+use crate::dal;
+use crate::dal::mention_repository::MENTION_NOTIFY_CHANNEL;
 use crate::error::Error;
 use crate::models::chat_message::ChatMessage;
+use crate::models::fragment::Fragment;
 use crate::utils::connection_string::ConnectionString;
 use crate::utils::repo_statement::{RepoStatement, ToRepoStatement};
 
+const ERR_DUPLICATE_NOT_FOUND: &str = "Duplicate of a message that is no longer stored";
+
+/// Every stored message is announced here, as JSON, to every instance.
+const MESSAGE_NOTIFY_CHANNEL: &str = "chat_messages";
+/// Postgres rejects notification payloads of 8000 bytes or more.
+const MAX_NOTIFY_PAYLOAD: usize = 7999;
+
 #[derive(Debug, PartialEq, Sequence)]
 enum ChatRepoStatement {
-    Insert,
+    InsertBatch,
+    GetByDedupeKeys,
+    GetById,
     GetByChannel,
+    GetByChannelInRange,
+    GetByChannelInRangeByTimestamp,
+    GetByChannelAfterSeq,
     GetByUser,
+    Notify,
+    NotifyMentions,
+    Listen,
 }
 
 impl ToRepoStatement for ChatRepoStatement {
     fn as_string(&self) -> String {
         match self {
-            ChatRepoStatement::Insert => "INSERT INTO chat_messages (text, channel, username, timestamp) VALUES ($1, $2, $3, $4) RETURNING *".to_string(),
-            ChatRepoStatement::GetByChannel => "SELECT * FROM chat_messages WHERE channel = $1 ORDER BY timestamp DESC LIMIT $2".to_string(),
+            // Ids are drawn in input order, so a batch keeps the order it was
+            // submitted in. Each id is drawn before its dedupe key is claimed,
+            // and messages whose key was already taken are left out.
+            ChatRepoStatement::InsertBatch => "WITH input AS (SELECT nextval(pg_get_serial_sequence('chat_messages', 'id'))::INTEGER AS id, m.* FROM (SELECT * FROM unnest($1::TEXT[], $2::TEXT[], $3::TEXT[], $4::TIMESTAMPTZ[], $5::TEXT[], $6::TEXT[], $7::JSONB[]) WITH ORDINALITY AS m(text, channel, username, timestamp, source_id, dedupe_key, fragments, n) ORDER BY n) AS m), claimed AS (INSERT INTO message_keys (channel, key, message_id, message_timestamp) SELECT channel, dedupe_key, id, timestamp FROM input WHERE dedupe_key IS NOT NULL ON CONFLICT DO NOTHING RETURNING message_id), inserted AS (INSERT INTO chat_messages (id, text, channel, username, timestamp, source_id, fragments) OVERRIDING SYSTEM VALUE SELECT id, text, channel, username, timestamp, source_id, fragments FROM input WHERE dedupe_key IS NULL OR id IN (SELECT message_id FROM claimed) ORDER BY n RETURNING *) SELECT input.n, inserted.* FROM inserted JOIN input ON input.id = inserted.id".to_string(),
+            ChatRepoStatement::GetByDedupeKeys => "SELECT k.key AS dedupe_key, m.* FROM message_keys k JOIN chat_messages m ON m.id = k.message_id AND m.timestamp = k.message_timestamp WHERE (k.channel, k.key) IN (SELECT * FROM unnest($1::TEXT[], $2::TEXT[]))".to_string(),
+            ChatRepoStatement::GetById => "SELECT * FROM chat_messages WHERE id = $1".to_string(),
+            ChatRepoStatement::GetByChannel => "SELECT * FROM chat_messages WHERE channel = $1 ORDER BY seq DESC, timestamp DESC, id DESC LIMIT $2".to_string(),
+            ChatRepoStatement::GetByChannelInRange => "SELECT * FROM chat_messages WHERE channel = $1 AND ($2::TIMESTAMPTZ IS NULL OR timestamp >= $2) AND ($3::TIMESTAMPTZ IS NULL OR timestamp < $3) ORDER BY seq, timestamp, id".to_string(),
+            ChatRepoStatement::GetByChannelInRangeByTimestamp => "SELECT * FROM chat_messages WHERE channel = $1 AND ($2::TIMESTAMPTZ IS NULL OR timestamp >= $2) AND ($3::TIMESTAMPTZ IS NULL OR timestamp < $3) ORDER BY timestamp, seq".to_string(),
+            ChatRepoStatement::GetByChannelAfterSeq => "SELECT * FROM chat_messages WHERE channel = $1 AND seq > $2 ORDER BY seq LIMIT $3".to_string(),
             ChatRepoStatement::GetByUser => "SELECT * FROM chat_messages WHERE username = $1".to_string(),
+            ChatRepoStatement::Notify => "SELECT pg_notify($1, payload) FROM unnest($2::TEXT[]) AS payload".to_string(),
+            ChatRepoStatement::NotifyMentions => "SELECT pg_notify($1, id::TEXT) FROM mentions WHERE message_id = ANY($2)".to_string(),
+            ChatRepoStatement::Listen => format!("LISTEN {}", MESSAGE_NOTIFY_CHANNEL),
         }
     }
 
     fn get_types(&self) -> Vec<Type> {
         match self {
-            ChatRepoStatement::Insert => {
-                vec![Type::TEXT, Type::TEXT, Type::TEXT, Type::TIMESTAMPTZ]
-            }
+            ChatRepoStatement::InsertBatch => vec![
+                Type::TEXT_ARRAY,
+                Type::TEXT_ARRAY,
+                Type::TEXT_ARRAY,
+                Type::TIMESTAMPTZ_ARRAY,
+                Type::TEXT_ARRAY,
+                Type::TEXT_ARRAY,
+                Type::JSONB_ARRAY,
+            ],
+            ChatRepoStatement::GetByDedupeKeys => vec![Type::TEXT_ARRAY, Type::TEXT_ARRAY],
+            ChatRepoStatement::GetById => vec![Type::INT4],
             ChatRepoStatement::GetByChannel => vec![Type::TEXT],
+            ChatRepoStatement::GetByChannelInRange
+            | ChatRepoStatement::GetByChannelInRangeByTimestamp => {
+                vec![Type::TEXT, Type::TIMESTAMPTZ, Type::TIMESTAMPTZ]
+            }
+            ChatRepoStatement::GetByChannelAfterSeq => vec![Type::TEXT, Type::INT8, Type::INT8],
             ChatRepoStatement::GetByUser => vec![Type::TEXT],
+            ChatRepoStatement::Notify => vec![Type::TEXT, Type::TEXT_ARRAY],
+            ChatRepoStatement::NotifyMentions => vec![Type::TEXT, Type::INT4_ARRAY],
+            ChatRepoStatement::Listen => vec![],
         }
     }
 }
@@ -54,18 +105,11 @@ impl ChatMessageRepository {
     }
 
     pub async fn connect(&mut self) -> Result<(), Error> {
-        let (client, connection) =
-            tokio_postgres::connect(&self.connection_string.as_string(), NoTls).await?;
+        let client = dal::connect(&self.connection_string).await?;
 
         self.client = Some(client);
         self.prepare_statements().await?;
 
-        tokio::spawn(async move {
-            if let Err(e) = connection.await {
-                eprintln!("connection error: {}", e);
-            }
-        });
-
         Ok(())
     }
 
@@ -74,21 +118,22 @@ impl ChatMessageRepository {
     async fn prepare_statements(&mut self) -> Result<(), Error> {
         let _client = self.client.as_ref().unwrap();
 
-        let statements: Vec<RepoStatement> = vec![
-            ChatRepoStatement::Insert,
+        let statements: Vec<RepoStatement> = [
+            ChatRepoStatement::InsertBatch,
+            ChatRepoStatement::GetByDedupeKeys,
+            ChatRepoStatement::GetById,
             ChatRepoStatement::GetByChannel,
+            ChatRepoStatement::GetByChannelInRange,
+            ChatRepoStatement::GetByChannelAfterSeq,
             ChatRepoStatement::GetByUser,
+            ChatRepoStatement::Notify,
+            ChatRepoStatement::NotifyMentions,
+            ChatRepoStatement::Listen,
         ]
         .iter()
         .map(|s| RepoStatement::from(s as &dyn ToRepoStatement))
         .collect();
 
-        // This is currently broken, causes indefinite hang.
-        // for statement in statements.iter_mut() {
-        // debug!("Preparing statement: {}", statement.statement);
-        // statement.prepare(client).await?;
-        // }
-
         self.statements = statements;
 
         Ok(())
@@ -98,7 +143,7 @@ impl ChatMessageRepository {
         &self,
         channel: &str,
         num_to_get: i64,
-    ) -> Result<Vec<ChatMessage>, tokio_postgres::Error> {
+    ) -> Result<Vec<ChatMessage>, Error> {
         let client = self.client.as_ref().unwrap();
 
         let rows = client
@@ -113,24 +158,290 @@ impl ChatMessageRepository {
         Ok(messages)
     }
 
-    pub async fn add_message(&self, message: &ChatMessage) -> Result<(), Error> {
+    pub async fn get_message(&self, id: i32) -> Result<Option<ChatMessage>, Error> {
         let client = self.client.as_ref().unwrap();
 
-        client
-            .execute(
-                &ChatRepoStatement::Insert.as_string(),
+        let row = client
+            .query_opt(&ChatRepoStatement::GetById.as_string(), &[&id])
+            .await?;
+
+        Ok(row.map(ChatMessage::from))
+    }
+
+    /// The stored message that `message` would be a duplicate of, going by
+    /// [`ChatMessage::dedupe_key`], if there is one.
+    pub async fn get_duplicate(&self, message: &ChatMessage) -> Result<Option<ChatMessage>, Error> {
+        let client = self.client.as_ref().unwrap();
+
+        let Some(key) = message.dedupe_key() else {
+            return Ok(None);
+        };
+
+        let row = client
+            .query_opt(
+                &ChatRepoStatement::GetByDedupeKeys.as_string(),
+                &[&[&message.channel], &[&key]],
+            )
+            .await?;
+
+        Ok(row.map(ChatMessage::from))
+    }
+
+    /// Returns up to `limit` of a channel's messages that come after the one
+    /// with sequence number `after_seq`, in order.
+    pub async fn get_messages_after(
+        &self,
+        channel: &str,
+        after_seq: i64,
+        limit: i64,
+    ) -> Result<Vec<ChatMessage>, Error> {
+        let client = self.client.as_ref().unwrap();
+
+        let rows = client
+            .query(
+                &ChatRepoStatement::GetByChannelAfterSeq.as_string(),
+                &[&channel, &after_seq, &limit],
+            )
+            .await?;
+
+        Ok(from_rows(rows))
+    }
+
+    /// Stores the message and returns it as it was written, including its id
+    /// and sequence number. Returns the message already stored instead if
+    /// this one is a duplicate.
+    pub async fn add_message(&self, message: &ChatMessage) -> Result<ChatMessage, Error> {
+        let mut stored = self.add_messages(std::slice::from_ref(message)).await?;
+
+        Ok(stored.remove(0))
+    }
+
+    /// Stores the messages with a single insert and returns them as written,
+    /// in the order they were given. Duplicates of stored messages, going by
+    /// [`ChatMessage::dedupe_key`], are not inserted again; the message
+    /// stored first is returned in their place.
+    pub async fn add_messages(&self, messages: &[ChatMessage]) -> Result<Vec<ChatMessage>, Error> {
+        let client = self.client.as_ref().unwrap();
+
+        let texts: Vec<&str> = messages.iter().map(|m| m.text.as_str()).collect();
+        let channels: Vec<&str> = messages.iter().map(|m| m.channel.as_str()).collect();
+        let usernames: Vec<&str> = messages.iter().map(|m| m.username.as_str()).collect();
+        let timestamps: Vec<OffsetDateTime> = messages.iter().map(|m| m.timestamp).collect();
+        let source_ids: Vec<Option<&str>> =
+            messages.iter().map(|m| m.source_id.as_deref()).collect();
+        let keys: Vec<Option<String>> = messages.iter().map(ChatMessage::dedupe_key).collect();
+        let fragments: Vec<Json<Vec<Fragment>>> =
+            messages.iter().map(|m| Json(m.fragments())).collect();
+
+        let rows = client
+            .query(
+                &ChatRepoStatement::InsertBatch.as_string(),
                 &[
-                    &message.text,
-                    &message.channel,
-                    &message.username,
-                    &message.timestamp,
+                    &texts,
+                    &channels,
+                    &usernames,
+                    &timestamps,
+                    &source_ids,
+                    &keys,
+                    &fragments,
                 ],
             )
             .await?;
 
+        let mut results: Vec<Option<ChatMessage>> = vec![None; messages.len()];
+        let mut inserted = Vec::with_capacity(rows.len());
+        for row in rows {
+            let n: i64 = row.get("n");
+            let message = ChatMessage::from(row);
+            results[n as usize - 1] = Some(message.clone());
+            inserted.push(message);
+        }
+
+        inserted.sort_by_key(|m| m.id);
+        self.notify(&inserted).await;
+
+        if inserted.len() < messages.len() {
+            self.fill_duplicates(&mut results, &channels, &keys).await?;
+        }
+
+        results
+            .into_iter()
+            .map(|m| m.ok_or_else(|| Error::Db(ERR_DUPLICATE_NOT_FOUND.to_string())))
+            .collect()
+    }
+
+    /// Fills in the messages left out as duplicates with the ones stored
+    /// under the same key.
+    async fn fill_duplicates(
+        &self,
+        results: &mut [Option<ChatMessage>],
+        channels: &[&str],
+        keys: &[Option<String>],
+    ) -> Result<(), Error> {
+        let client = self.client.as_ref().unwrap();
+
+        let missing: Vec<usize> = (0..results.len())
+            .filter(|&i| results[i].is_none())
+            .collect();
+        let missing_channels: Vec<&str> = missing.iter().map(|&i| channels[i]).collect();
+        let missing_keys: Vec<Option<&str>> = missing.iter().map(|&i| keys[i].as_deref()).collect();
+
+        let rows = client
+            .query(
+                &ChatRepoStatement::GetByDedupeKeys.as_string(),
+                &[&missing_channels, &missing_keys],
+            )
+            .await?;
+
+        let mut originals = HashMap::new();
+        for row in rows {
+            let key: String = row.get("dedupe_key");
+            let message = ChatMessage::from(row);
+            originals.insert((message.channel.clone(), key), message);
+        }
+
+        for i in missing {
+            let Some(key) = &keys[i] else { continue };
+            results[i] = originals
+                .get(&(channels[i].to_string(), key.clone()))
+                .cloned();
+        }
+
         Ok(())
     }
 
+    /// Announces stored messages, and the mentions in them, to every
+    /// instance's listeners. The messages are already committed by now, so a
+    /// failure here is only logged; live subscribers can catch up from the
+    /// database.
+    async fn notify(&self, messages: &[ChatMessage]) {
+        let client = self.client.as_ref().unwrap();
+
+        let payloads: Result<Vec<String>, _> = messages.iter().map(notify_payload).collect();
+        let result = match payloads {
+            Ok(payloads) => client
+                .execute(
+                    &ChatRepoStatement::Notify.as_string(),
+                    &[&MESSAGE_NOTIFY_CHANNEL, &payloads],
+                )
+                .await
+                .map_err(Error::from),
+            Err(err) => Err(Error::from(err)),
+        };
+
+        if let Err(err) = result {
+            warn!("Could not announce {} messages: {}", messages.len(), err);
+        }
+
+        // The database recorded the mentions while inserting the messages.
+        let ids: Vec<i32> = messages.iter().filter_map(|m| m.id).collect();
+        if let Err(err) = client
+            .execute(
+                &ChatRepoStatement::NotifyMentions.as_string(),
+                &[&MENTION_NOTIFY_CHANNEL, &ids],
+            )
+            .await
+        {
+            warn!("Could not announce mentions: {}", err);
+        }
+    }
+
+    /// Opens a dedicated connection that receives every message stored from
+    /// now on, by any instance. Bulk imports are not announced. The stream
+    /// ends when the connection is lost.
+    ///
+    /// Messages announced without their fragments are read back in full.
+    pub async fn listen(&self) -> Result<impl Stream<Item = ChatMessage> + 'static, Error> {
+        let (client, notifications) =
+            dal::connect_with_notifications(&self.connection_string).await?;
+        client
+            .batch_execute(&ChatRepoStatement::Listen.as_string())
+            .await?;
+
+        // The client is carried along so the connection stays open.
+        let messages = stream::unfold(
+            (client, notifications),
+            |(client, mut notifications)| async move {
+                loop {
+                    let notification = notifications.recv().await?;
+                    match serde_json::from_str::<ChatMessage>(notification.payload()) {
+                        Ok(message) if message.fragments.is_some() => {
+                            return Some((message, (client, notifications)))
+                        }
+                        Ok(message) => {
+                            let row = client
+                                .query_opt(&ChatRepoStatement::GetById.as_string(), &[&message.id])
+                                .await;
+                            match row {
+                                Ok(Some(row)) => {
+                                    return Some((ChatMessage::from(row), (client, notifications)))
+                                }
+                                Ok(None) => {}
+                                Err(err) => warn!("Could not read announced message: {}", err),
+                            }
+                        }
+                        Err(err) => warn!("Ignoring malformed message notification: {}", err),
+                    }
+                }
+            },
+        );
+
+        Ok(messages)
+    }
+
+    /// Streams a channel's messages in chronological order, optionally bounded
+    /// by `[from, to)`. Rows are read as they arrive over a dedicated
+    /// connection, so large ranges neither sit in memory nor hold up other
+    /// queries on the shared client.
+    pub async fn stream_messages_from_channel(
+        &self,
+        channel: &str,
+        from: Option<OffsetDateTime>,
+        to: Option<OffsetDateTime>,
+    ) -> Result<impl Stream<Item = Result<ChatMessage, Error>> + 'static, Error> {
+        self.stream_range(ChatRepoStatement::GetByChannelInRange, channel, from, to)
+            .await
+    }
+
+    /// Like [`Self::stream_messages_from_channel`], but in the order the
+    /// messages were sent rather than stored.
+    pub async fn stream_messages_by_timestamp(
+        &self,
+        channel: &str,
+        from: Option<OffsetDateTime>,
+        to: Option<OffsetDateTime>,
+    ) -> Result<impl Stream<Item = Result<ChatMessage, Error>> + 'static, Error> {
+        let statement = ChatRepoStatement::GetByChannelInRangeByTimestamp;
+        self.stream_range(statement, channel, from, to).await
+    }
+
+    async fn stream_range(
+        &self,
+        statement: ChatRepoStatement,
+        channel: &str,
+        from: Option<OffsetDateTime>,
+        to: Option<OffsetDateTime>,
+    ) -> Result<impl Stream<Item = Result<ChatMessage, Error>> + 'static, Error> {
+        let client = dal::connect(&self.connection_string).await?;
+
+        let params: [&(dyn ToSql + Sync); 3] = [&channel, &from, &to];
+        let rows = client
+            .query_raw(
+                &statement.as_string(),
+                params.iter().map(|p| *p as &dyn ToSql),
+            )
+            .await?;
+
+        // The client is carried along so the connection outlives the rows.
+        let messages = stream::unfold((client, Box::pin(rows)), |(client, mut rows)| async {
+            let row = rows.next().await?;
+            let message = row.map(ChatMessage::from).map_err(Error::from);
+            Some((message, (client, rows)))
+        });
+
+        Ok(messages)
+    }
+
     pub async fn get_messages_by_user(&self, username: &str) -> Result<Vec<ChatMessage>, Error> {
         let client = self.client.as_ref().unwrap();
 
@@ -144,6 +455,19 @@ impl ChatMessageRepository {
     }
 }
 
+/// The message as JSON, leaving out its fragments if it would otherwise be
+/// too long to announce.
+fn notify_payload(message: &ChatMessage) -> Result<String, serde_json::Error> {
+    let payload = serde_json::to_string(message)?;
+    if payload.len() <= MAX_NOTIFY_PAYLOAD {
+        return Ok(payload);
+    }
+
+    let mut message = message.clone();
+    message.fragments = None;
+    serde_json::to_string(&message)
+}
+
 fn from_rows(rows: Vec<Row>) -> Vec<ChatMessage> {
     let mut messages = Vec::new();
 
@@ -157,11 +481,13 @@ fn from_rows(rows: Vec<Row>) -> Vec<ChatMessage> {
 #[cfg(test)]
 mod test {
     use fake::{Fake, Faker};
+    use futures::TryStreamExt;
     use test_context::{test_context, AsyncTestContext};
     use tokio::test;
 
     use super::*;
     use crate::models::chat_message::ChatMessage;
+    use crate::models::fragment::FragmentKind;
 
     struct ChatMessageRepoTestContext {
         config: Config,
@@ -172,6 +498,8 @@ mod test {
     impl AsyncTestContext for ChatMessageRepoTestContext {
         async fn setup() -> ChatMessageRepoTestContext {
             let config = Config::load("config.json").await.unwrap();
+            dal::migrations::run(&config).await.unwrap();
+
             let mut repo = ChatMessageRepository::new(&config).unwrap();
             repo.connect().await.unwrap();
 
@@ -220,4 +548,182 @@ mod test {
 
         Ok(())
     }
+
+    #[test_context(ChatMessageRepoTestContext)]
+    #[test]
+    async fn repo_listen_receives_stored_messages(
+        ctx: &ChatMessageRepoTestContext,
+    ) -> Result<(), Error> {
+        let message = Faker.fake::<ChatMessage>();
+        let client = ctx.repo.client.as_ref().unwrap();
+        client
+            .execute(
+                "INSERT INTO users (name) VALUES ($1), ($2) ON CONFLICT DO NOTHING",
+                &[&message.username, &message.channel],
+            )
+            .await?;
+
+        let listener = ChatMessageRepository::new(&ctx.config)?;
+        let mut messages = Box::pin(listener.listen().await?);
+
+        let stored = ctx.repo.add_message(&message).await?;
+
+        // Other tests may be storing messages at the same time.
+        let received = tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            loop {
+                let received = messages.next().await.unwrap();
+                if received.id == stored.id {
+                    return received;
+                }
+            }
+        })
+        .await
+        .expect("No notification for the stored message");
+        assert_eq!(received, stored);
+
+        Ok(())
+    }
+
+    #[test_context(ChatMessageRepoTestContext)]
+    #[test]
+    async fn repo_duplicates_return_the_original(
+        ctx: &ChatMessageRepoTestContext,
+    ) -> Result<(), Error> {
+        let mut message = Faker.fake::<ChatMessage>();
+        let client = ctx.repo.client.as_ref().unwrap();
+        client
+            .execute(
+                "INSERT INTO users (name) VALUES ($1), ($2) ON CONFLICT DO NOTHING",
+                &[&message.username, &message.channel],
+            )
+            .await?;
+
+        message.source_id = Some(Faker.fake());
+        let original = ctx.repo.add_message(&message).await?;
+        assert_eq!(original.source_id, message.source_id);
+
+        let mut replayed = message.clone();
+        replayed.text = "replayed".to_string();
+        let mut posted = message.clone();
+        posted.source_id = None;
+        let key: String = Faker.fake();
+        posted.set_idempotency_key(&posted.username.clone(), &key);
+        let mut retried = posted.clone();
+        retried.text = "retried".to_string();
+        let mut plain = message.clone();
+        plain.source_id = None;
+        // Someone else's key is not a duplicate, even if it is the same.
+        let mut other = posted.clone();
+        other.username = Faker.fake();
+        other.set_idempotency_key(&other.username.clone(), &key);
+        other.text = "other".to_string();
+        client
+            .execute(
+                "INSERT INTO users (name) VALUES ($1) ON CONFLICT DO NOTHING",
+                &[&other.username],
+            )
+            .await?;
+
+        // Duplicates of a stored message and of another in the same batch.
+        let stored = ctx
+            .repo
+            .add_messages(&[
+                replayed,
+                posted.clone(),
+                retried,
+                plain.clone(),
+                plain,
+                other,
+            ])
+            .await?;
+        assert_eq!(stored[0], original);
+        assert_eq!(stored[1].text, posted.text);
+        assert_eq!(stored[2], stored[1]);
+        assert_ne!(stored[3].id, stored[4].id);
+        assert_eq!(stored[5].text, "other");
+
+        Ok(())
+    }
+
+    #[test_context(ChatMessageRepoTestContext)]
+    #[test]
+    async fn repo_seq_follows_insertion_order(
+        ctx: &ChatMessageRepoTestContext,
+    ) -> Result<(), Error> {
+        let mut message = Faker.fake::<ChatMessage>();
+        message.channel = Faker.fake();
+        let client = ctx.repo.client.as_ref().unwrap();
+        client
+            .execute(
+                "INSERT INTO users (name) VALUES ($1), ($2) ON CONFLICT DO NOTHING",
+                &[&message.username, &message.channel],
+            )
+            .await?;
+
+        // Later messages claim earlier timestamps, which must not reorder them.
+        let mut stored = Vec::new();
+        for offset in 0..3 {
+            let mut next = message.clone();
+            next.timestamp -= time::Duration::minutes(offset);
+            stored.push(ctx.repo.add_message(&next).await?);
+        }
+        let seqs: Vec<_> = stored.iter().map(|m| m.seq.unwrap()).collect();
+        assert_eq!(seqs, vec![1, 2, 3]);
+
+        let newest = ctx
+            .repo
+            .get_messages_from_channel(&message.channel, 10)
+            .await?;
+        assert_eq!(newest.first(), stored.last());
+
+        let after = ctx.repo.get_messages_after(&message.channel, 1, 10).await?;
+        assert_eq!(after, stored[1..]);
+
+        let by_timestamp: Vec<_> = ctx
+            .repo
+            .stream_messages_by_timestamp(&message.channel, None, None)
+            .await?
+            .try_collect()
+            .await?;
+        stored.reverse();
+        assert_eq!(by_timestamp, stored);
+
+        Ok(())
+    }
+
+    #[test_context(ChatMessageRepoTestContext)]
+    #[test]
+    async fn repo_fragments_are_stored(ctx: &ChatMessageRepoTestContext) -> Result<(), Error> {
+        let mut message = Faker.fake::<ChatMessage>();
+        let client = ctx.repo.client.as_ref().unwrap();
+        client
+            .execute(
+                "INSERT INTO users (name) VALUES ($1), ($2) ON CONFLICT DO NOTHING",
+                &[&message.username, &message.channel],
+            )
+            .await?;
+
+        message.text = "Kappa @you".to_string();
+        message.emotes = Some("25:0-4".to_string());
+        let stored = ctx.repo.add_message(&message).await?;
+
+        let kinds: Vec<_> = stored.fragments().into_iter().map(|f| f.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                FragmentKind::Emote {
+                    id: "25".to_string()
+                },
+                FragmentKind::Text,
+                FragmentKind::Mention {
+                    username: "you".to_string()
+                },
+            ]
+        );
+
+        let read = ctx.repo.get_message(stored.id.unwrap()).await?;
+        assert_eq!(read.unwrap().fragments, stored.fragments);
+
+        Ok(())
+    }
 }
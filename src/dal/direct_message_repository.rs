@@ -0,0 +1,254 @@
+use enum_iterator::Sequence;
+use futures::stream::{self, Stream};
+use log::warn;
+use tokio_postgres::types::Type;
+use tokio_postgres::Client;
+
+use crate::config::Config;
+use crate::dal;
+use crate::error::Error;
+use crate::models::direct_message::DirectMessage;
+use crate::utils::connection_string::ConnectionString;
+use crate::utils::repo_statement::ToRepoStatement;
+
+/// Every stored direct message is announced here, as JSON, to every
+/// instance.
+const DIRECT_MESSAGE_NOTIFY_CHANNEL: &str = "direct_messages";
+
+#[derive(Debug, PartialEq, Sequence)]
+enum DirectMessageRepoStatement {
+    InsertUser,
+    Insert,
+    GetConversation,
+    Notify,
+    Listen,
+}
+
+impl ToRepoStatement for DirectMessageRepoStatement {
+    fn as_string(&self) -> String {
+        match self {
+            DirectMessageRepoStatement::InsertUser => "INSERT INTO users (name) VALUES ($1) ON CONFLICT DO NOTHING".to_string(),
+            // Nothing is inserted when the recipient is not a known user.
+            DirectMessageRepoStatement::Insert => "INSERT INTO direct_messages (sender, recipient, text) SELECT $1, name, $3 FROM users WHERE name = $2 RETURNING *".to_string(),
+            DirectMessageRepoStatement::GetConversation => "SELECT * FROM direct_messages WHERE LEAST(sender, recipient) = LEAST($1, $2) AND GREATEST(sender, recipient) = GREATEST($1, $2) AND ($3::INTEGER IS NULL OR id < $3) ORDER BY id DESC LIMIT $4".to_string(),
+            DirectMessageRepoStatement::Notify => "SELECT pg_notify($1, $2)".to_string(),
+            DirectMessageRepoStatement::Listen => format!("LISTEN {}", DIRECT_MESSAGE_NOTIFY_CHANNEL),
+        }
+    }
+
+    fn get_types(&self) -> Vec<Type> {
+        match self {
+            DirectMessageRepoStatement::InsertUser => vec![Type::TEXT],
+            DirectMessageRepoStatement::Insert => vec![Type::TEXT, Type::TEXT, Type::TEXT],
+            DirectMessageRepoStatement::GetConversation => {
+                vec![Type::TEXT, Type::TEXT, Type::INT4, Type::INT8]
+            }
+            DirectMessageRepoStatement::Notify => vec![Type::TEXT, Type::TEXT],
+            DirectMessageRepoStatement::Listen => vec![],
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct DirectMessageRepository {
+    connection_string: ConnectionString,
+    pub client: Option<Client>,
+}
+
+impl DirectMessageRepository {
+    pub fn new(config: &Config) -> Result<Self, Error> {
+        let connection_string = config.db()?;
+
+        Ok(Self {
+            connection_string,
+            client: None,
+        })
+    }
+
+    pub async fn connect(&mut self) -> Result<(), Error> {
+        let client = dal::connect(&self.connection_string).await?;
+        self.client = Some(client);
+
+        Ok(())
+    }
+
+    /// Stores a message from `sender` to `recipient`, announcing it to every
+    /// instance. Returns `None` if the recipient is not a known user.
+    pub async fn send(
+        &self,
+        sender: &str,
+        recipient: &str,
+        text: &str,
+    ) -> Result<Option<DirectMessage>, Error> {
+        let client = self.client.as_ref().unwrap();
+
+        client
+            .execute(
+                &DirectMessageRepoStatement::InsertUser.as_string(),
+                &[&sender],
+            )
+            .await?;
+
+        let Some(row) = client
+            .query_opt(
+                &DirectMessageRepoStatement::Insert.as_string(),
+                &[&sender, &recipient, &text],
+            )
+            .await?
+        else {
+            return Ok(None);
+        };
+
+        let message = DirectMessage::from(&row);
+        self.notify(&message).await;
+
+        Ok(Some(message))
+    }
+
+    /// A page of the conversation between two users, newest first, starting
+    /// before the message with id `before` if given.
+    pub async fn get_conversation(
+        &self,
+        username: &str,
+        other: &str,
+        before: Option<i32>,
+        limit: i64,
+    ) -> Result<Vec<DirectMessage>, Error> {
+        let client = self.client.as_ref().unwrap();
+
+        let messages = client
+            .query(
+                &DirectMessageRepoStatement::GetConversation.as_string(),
+                &[&username, &other, &before, &limit],
+            )
+            .await?
+            .iter()
+            .map(DirectMessage::from)
+            .collect();
+
+        Ok(messages)
+    }
+
+    /// The message is already stored, so failing to announce it is logged
+    /// rather than returned.
+    async fn notify(&self, message: &DirectMessage) {
+        let client = self.client.as_ref().unwrap();
+
+        let payload = match serde_json::to_string(message) {
+            Ok(payload) => payload,
+            Err(err) => {
+                warn!("Could not announce direct message {}: {}", message.id, err);
+                return;
+            }
+        };
+
+        if let Err(err) = client
+            .execute(
+                &DirectMessageRepoStatement::Notify.as_string(),
+                &[&DIRECT_MESSAGE_NOTIFY_CHANNEL, &payload],
+            )
+            .await
+        {
+            warn!("Could not announce direct message {}: {}", message.id, err);
+        }
+    }
+
+    /// Streams the direct messages stored by any instance from now on, over
+    /// a dedicated connection. The stream ends if that connection is lost.
+    pub async fn listen(&self) -> Result<impl Stream<Item = DirectMessage> + 'static, Error> {
+        let (client, notifications) =
+            dal::connect_with_notifications(&self.connection_string).await?;
+        client
+            .batch_execute(&DirectMessageRepoStatement::Listen.as_string())
+            .await?;
+
+        // The client is carried along so the connection stays open.
+        let messages = stream::unfold(
+            (client, notifications),
+            |(client, mut notifications)| async move {
+                loop {
+                    let notification = notifications.recv().await?;
+                    match serde_json::from_str(notification.payload()) {
+                        Ok(message) => return Some((message, (client, notifications))),
+                        Err(err) => {
+                            warn!("Ignoring malformed direct message notification: {}", err)
+                        }
+                    }
+                }
+            },
+        );
+
+        Ok(messages)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use fake::{Fake, Faker};
+    use futures::StreamExt;
+    use test_context::{test_context, AsyncTestContext};
+    use tokio::test;
+
+    use super::*;
+
+    struct DirectMessageRepoTestContext {
+        repo: DirectMessageRepository,
+    }
+
+    #[async_trait::async_trait]
+    impl AsyncTestContext for DirectMessageRepoTestContext {
+        async fn setup() -> DirectMessageRepoTestContext {
+            let config = Config::load("config.json").await.unwrap();
+            dal::migrations::run(&config).await.unwrap();
+
+            let mut repo = DirectMessageRepository::new(&config).unwrap();
+            repo.connect().await.unwrap();
+
+            DirectMessageRepoTestContext { repo }
+        }
+
+        async fn teardown(self) {}
+    }
+
+    #[test_context(DirectMessageRepoTestContext)]
+    #[test]
+    async fn repo_conversation_is_shared_and_paged(
+        ctx: &DirectMessageRepoTestContext,
+    ) -> Result<(), Error> {
+        let (alice, bob, carol): (String, String, String) =
+            (Faker.fake(), Faker.fake(), Faker.fake());
+        assert!(ctx.repo.send(&alice, &bob, "hi").await?.is_none());
+
+        let client = ctx.repo.client.as_ref().unwrap();
+        for name in [&alice, &bob, &carol] {
+            client
+                .execute(
+                    "INSERT INTO users (name) VALUES ($1) ON CONFLICT DO NOTHING",
+                    &[name],
+                )
+                .await?;
+        }
+
+        let mut listener = Box::pin(ctx.repo.listen().await?);
+
+        let first = ctx.repo.send(&alice, &bob, "hi").await?.unwrap();
+        let second = ctx.repo.send(&bob, &alice, "hello").await?.unwrap();
+        ctx.repo.send(&alice, &carol, "psst").await?.unwrap();
+
+        let announced = tokio::time::timeout(Duration::from_secs(5), listener.next()).await;
+        assert_eq!(announced.unwrap(), Some(first.clone()));
+
+        let page = ctx.repo.get_conversation(&bob, &alice, None, 1).await?;
+        assert_eq!(page, vec![second.clone()]);
+
+        let page = ctx
+            .repo
+            .get_conversation(&alice, &bob, Some(second.id), 10)
+            .await?;
+        assert_eq!(page, vec![first]);
+
+        Ok(())
+    }
+}
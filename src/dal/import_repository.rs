@@ -0,0 +1,422 @@
+use std::collections::HashSet;
+
+use enum_iterator::Sequence;
+use futures::pin_mut;
+use tokio_postgres::binary_copy::BinaryCopyInWriter;
+use tokio_postgres::types::{Json, Type};
+use tokio_postgres::Client;
+
+use crate::config::Config;
+use crate::dal;
+use crate::error::Error;
+use crate::models::chat_message::ChatMessage;
+use crate::models::import::ImportJob;
+use crate::utils::connection_string::ConnectionString;
+use crate::utils::repo_statement::ToRepoStatement;
+
+#[derive(Debug, PartialEq, Sequence)]
+enum ImportRepoStatement {
+    LockSource,
+    StartJob,
+    GetJob,
+    InsertUsers,
+    InsertChannels,
+    CreateStaging,
+    CopyMessages,
+    InsertStaged,
+    UpdateJob,
+    FinishJob,
+}
+
+impl ToRepoStatement for ImportRepoStatement {
+    fn as_string(&self) -> String {
+        match self {
+            ImportRepoStatement::LockSource => "SELECT pg_try_advisory_lock(hashtext('import_jobs'), hashtext($1)) AS locked".to_string(),
+            ImportRepoStatement::StartJob => "INSERT INTO import_jobs (source) VALUES ($1) ON CONFLICT DO NOTHING".to_string(),
+            ImportRepoStatement::GetJob => "SELECT * FROM import_jobs WHERE source = $1".to_string(),
+            ImportRepoStatement::InsertUsers => "INSERT INTO users (name) SELECT DISTINCT unnest($1::VARCHAR[]) ON CONFLICT DO NOTHING".to_string(),
+            ImportRepoStatement::InsertChannels => "INSERT INTO channels (name, display_name) SELECT DISTINCT name, name FROM unnest($1::VARCHAR[]) AS name ON CONFLICT DO NOTHING".to_string(),
+            ImportRepoStatement::CreateStaging => "CREATE TEMP TABLE import_staging (n BIGINT, text TEXT, channel TEXT, username TEXT, timestamp TIMESTAMPTZ, source_id TEXT, dedupe_key TEXT, fragments JSONB) ON COMMIT DROP".to_string(),
+            ImportRepoStatement::CopyMessages => "COPY import_staging (n, text, channel, username, timestamp, source_id, dedupe_key, fragments) FROM STDIN (FORMAT binary)".to_string(),
+            ImportRepoStatement::InsertStaged => "WITH input AS (SELECT nextval(pg_get_serial_sequence('chat_messages', 'id'))::INTEGER AS id, s.* FROM (SELECT * FROM import_staging ORDER BY n) AS s), claimed AS (INSERT INTO message_keys (channel, key, message_id, message_timestamp) SELECT channel, dedupe_key, id, timestamp FROM input WHERE dedupe_key IS NOT NULL ON CONFLICT DO NOTHING RETURNING message_id) INSERT INTO chat_messages (id, text, channel, username, timestamp, source_id, fragments, seq) OVERRIDING SYSTEM VALUE SELECT id, text, channel, username, timestamp, source_id, fragments, 0 FROM input WHERE dedupe_key IS NULL OR id IN (SELECT message_id FROM claimed) ORDER BY n".to_string(),
+            ImportRepoStatement::UpdateJob => "UPDATE import_jobs SET lines_done = $2, imported = imported + $3, updated_at = NOW() WHERE source = $1".to_string(),
+            ImportRepoStatement::FinishJob => "UPDATE import_jobs SET finished_at = NOW(), updated_at = NOW() WHERE source = $1 RETURNING *".to_string(),
+        }
+    }
+
+    fn get_types(&self) -> Vec<Type> {
+        match self {
+            ImportRepoStatement::LockSource
+            | ImportRepoStatement::StartJob
+            | ImportRepoStatement::GetJob
+            | ImportRepoStatement::FinishJob => vec![Type::TEXT],
+            ImportRepoStatement::InsertUsers | ImportRepoStatement::InsertChannels => {
+                vec![Type::VARCHAR_ARRAY]
+            }
+            ImportRepoStatement::CreateStaging
+            | ImportRepoStatement::CopyMessages
+            | ImportRepoStatement::InsertStaged => vec![],
+            ImportRepoStatement::UpdateJob => vec![Type::TEXT, Type::INT8, Type::INT8],
+        }
+    }
+}
+
+/// Column types of the binary `COPY` into the staging table.
+const COPY_TYPES: [Type; 8] = [
+    Type::INT8,
+    Type::TEXT,
+    Type::TEXT,
+    Type::TEXT,
+    Type::TIMESTAMPTZ,
+    Type::TEXT,
+    Type::TEXT,
+    Type::JSONB,
+];
+
+/// Loads messages in bulk over a connection of its own, since each batch is
+/// written in a transaction. Each import opens a [`session`] of its own.
+///
+/// [`session`]: ImportRepository::session
+#[derive(Debug)]
+pub struct ImportRepository {
+    connection_string: ConnectionString,
+    pub client: Option<Client>,
+}
+
+impl ImportRepository {
+    pub fn new(config: &Config) -> Result<Self, Error> {
+        let connection_string = config.db()?;
+
+        Ok(Self {
+            connection_string,
+            client: None,
+        })
+    }
+
+    pub async fn connect(&mut self) -> Result<(), Error> {
+        let client = dal::connect(&self.connection_string).await?;
+        self.client = Some(client);
+
+        Ok(())
+    }
+
+    /// Opens a connection for one import, so that imports of different
+    /// sources run side by side.
+    pub async fn session(&self) -> Result<Self, Error> {
+        let mut session = Self {
+            connection_string: self.connection_string.clone(),
+            client: None,
+        };
+        session.connect().await?;
+
+        Ok(session)
+    }
+
+    /// Claims the source for this session until it disconnects, returning
+    /// whether no other session is importing it.
+    pub async fn lock_source(&self, source: &str) -> Result<bool, Error> {
+        let client = self.client.as_ref().unwrap();
+
+        let row = client
+            .query_one(&ImportRepoStatement::LockSource.as_string(), &[&source])
+            .await?;
+
+        Ok(row.get("locked"))
+    }
+
+    /// How far the import of this source has gotten, if it was ever started.
+    pub async fn get_job(&self, source: &str) -> Result<Option<ImportJob>, Error> {
+        let client = self.client.as_ref().unwrap();
+
+        let row = client
+            .query_opt(&ImportRepoStatement::GetJob.as_string(), &[&source])
+            .await?;
+
+        Ok(row.as_ref().map(ImportJob::from))
+    }
+
+    /// Returns the job for this source, picking up where an interrupted run
+    /// left off if there was one. Fails if another session is importing it.
+    pub async fn start_job(&self, source: &str) -> Result<ImportJob, Error> {
+        if !self.lock_source(source).await? {
+            return Err(Error::Server(format!(
+                "{} is already being imported",
+                source
+            )));
+        }
+
+        let client = self.client.as_ref().unwrap();
+
+        client
+            .execute(&ImportRepoStatement::StartJob.as_string(), &[&source])
+            .await?;
+        let row = client
+            .query_one(&ImportRepoStatement::GetJob.as_string(), &[&source])
+            .await?;
+
+        Ok(ImportJob::from(&row))
+    }
+
+    /// Writes a batch of messages with a single `COPY`, creating the users
+    /// and channels they reference, and records that the first `lines_done`
+    /// lines of the source are now imported. They are numbered 0, before
+    /// every message posted live, see `010_imported_seq.sql`. Either all of it is committed
+    /// or none of it is. Messages are staged first, so that duplicates of
+    /// stored messages, going by [`ChatMessage::dedupe_key`], are left out.
+    /// Returns how many were stored.
+    pub async fn load_batch(
+        &mut self,
+        source: &str,
+        messages: &[ChatMessage],
+        lines_done: i64,
+    ) -> Result<u64, Error> {
+        let client = self.client.as_mut().unwrap();
+        let transaction = client.transaction().await?;
+
+        let users: HashSet<&str> = messages
+            .iter()
+            .flat_map(|m| [m.username.as_str(), m.channel.as_str()])
+            .collect();
+        let users: Vec<&str> = users.into_iter().collect();
+        let channels: HashSet<&str> = messages.iter().map(|m| m.channel.as_str()).collect();
+        let channels: Vec<&str> = channels.into_iter().collect();
+
+        transaction
+            .execute(&ImportRepoStatement::InsertUsers.as_string(), &[&users])
+            .await?;
+        transaction
+            .execute(
+                &ImportRepoStatement::InsertChannels.as_string(),
+                &[&channels],
+            )
+            .await?;
+
+        transaction
+            .batch_execute(&ImportRepoStatement::CreateStaging.as_string())
+            .await?;
+        let sink = transaction
+            .copy_in(&ImportRepoStatement::CopyMessages.as_string())
+            .await?;
+        let writer = BinaryCopyInWriter::new(sink, &COPY_TYPES);
+        pin_mut!(writer);
+
+        for (n, message) in messages.iter().enumerate() {
+            writer
+                .as_mut()
+                .write(&[
+                    &(n as i64),
+                    &message.text,
+                    &message.channel,
+                    &message.username,
+                    &message.timestamp,
+                    &message.source_id,
+                    &message.dedupe_key(),
+                    &Json(message.fragments()),
+                ])
+                .await?;
+        }
+        writer.finish().await?;
+
+        let copied = transaction
+            .execute(&ImportRepoStatement::InsertStaged.as_string(), &[])
+            .await?;
+
+        transaction
+            .execute(
+                &ImportRepoStatement::UpdateJob.as_string(),
+                &[&source, &lines_done, &(copied as i64)],
+            )
+            .await?;
+        transaction.commit().await?;
+
+        Ok(copied)
+    }
+
+    pub async fn finish_job(&self, source: &str) -> Result<ImportJob, Error> {
+        let client = self.client.as_ref().unwrap();
+
+        let row = client
+            .query_one(&ImportRepoStatement::FinishJob.as_string(), &[&source])
+            .await?;
+
+        Ok(ImportJob::from(&row))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use fake::{Fake, Faker};
+    use futures::stream;
+    use test_context::{test_context, AsyncTestContext};
+    use time::OffsetDateTime;
+    use tokio::test;
+
+    use super::*;
+    use crate::dal::chat_message_repository::ChatMessageRepository;
+    use crate::utils::import::{self, ImportFormat, ImportOptions};
+
+    struct ImportRepoTestContext {
+        repo: ImportRepository,
+    }
+
+    #[async_trait::async_trait]
+    impl AsyncTestContext for ImportRepoTestContext {
+        async fn setup() -> ImportRepoTestContext {
+            let config = Config::load("config.json").await.unwrap();
+            let mut repo = ImportRepository::new(&config).unwrap();
+            repo.connect().await.unwrap();
+
+            ImportRepoTestContext { repo }
+        }
+
+        async fn teardown(self) {}
+    }
+
+    #[test_context(ImportRepoTestContext)]
+    #[test]
+    async fn repo_import_resumes_committed_lines(
+        ctx: &mut ImportRepoTestContext,
+    ) -> Result<(), Error> {
+        let channel: String = Faker.fake();
+        let options = ImportOptions {
+            source: Faker.fake(),
+            format: ImportFormat::Txt,
+            channel: Some(channel.clone()),
+        };
+
+        let lines = || {
+            stream::iter(
+                [
+                    "[2023-01-01 12:00:00] someone: hello",
+                    "garbage",
+                    "[2023-01-01 12:00:01] <other> hi",
+                ]
+                .map(|line| Ok(line.to_string())),
+            )
+        };
+
+        let report = import::import_lines(&mut ctx.repo, lines(), &options).await?;
+        assert_eq!(report.imported, 2);
+        assert_eq!(report.error_count, 1);
+        assert_eq!(report.errors[0].line, 2);
+
+        let report = import::import_lines(&mut ctx.repo, lines(), &options).await?;
+        assert_eq!(report.resumed_from, 3);
+        assert_eq!(report.lines_read, 0);
+        assert_eq!(report.imported, 0);
+
+        let client = ctx.repo.client.as_ref().unwrap();
+        let row = client
+            .query_one(
+                "SELECT COUNT(*) AS count FROM chat_messages WHERE channel = $1",
+                &[&channel],
+            )
+            .await?;
+        assert_eq!(row.get::<_, i64>("count"), 2);
+
+        Ok(())
+    }
+
+    #[test_context(ImportRepoTestContext)]
+    #[test]
+    async fn repo_import_skips_stored_source_ids(
+        ctx: &mut ImportRepoTestContext,
+    ) -> Result<(), Error> {
+        let channel: String = Faker.fake();
+        let source_id: String = Faker.fake();
+        let line = format!(
+            r#"{{"text": "hi", "username": "someone", "channel": "{}", "timestamp": "2023-01-01T12:00:00Z", "source_id": "{}"}}"#,
+            channel, source_id
+        );
+
+        // The same message, from two logs and twice in the second.
+        let mut imported = Vec::new();
+        for lines in [vec![line.clone()], vec![line.clone(), line]] {
+            let options = ImportOptions {
+                source: Faker.fake(),
+                format: ImportFormat::Ndjson,
+                channel: None,
+            };
+            let lines = stream::iter(lines.into_iter().map(Ok));
+            let report = import::import_lines(&mut ctx.repo, lines, &options).await?;
+            imported.push(report.imported);
+        }
+        assert_eq!(imported, vec![1, 0]);
+
+        let client = ctx.repo.client.as_ref().unwrap();
+        let row = client
+            .query_one(
+                "SELECT COUNT(*) AS count FROM chat_messages WHERE channel = $1 AND source_id = $2",
+                &[&channel, &source_id],
+            )
+            .await?;
+        assert_eq!(row.get::<_, i64>("count"), 1);
+
+        Ok(())
+    }
+
+    #[test_context(ImportRepoTestContext)]
+    #[test]
+    async fn repo_import_places_old_messages_before_live_ones(
+        ctx: &mut ImportRepoTestContext,
+    ) -> Result<(), Error> {
+        let channel: String = Faker.fake();
+        let client = ctx.repo.client.as_ref().unwrap();
+        client
+            .execute(
+                "INSERT INTO users (name) VALUES ($1), ('someone') ON CONFLICT DO NOTHING",
+                &[&channel],
+            )
+            .await?;
+
+        let config = Config::load("config.json").await?;
+        let mut messages = ChatMessageRepository::new(&config)?;
+        messages.connect().await?;
+        let live = ChatMessage::new(
+            "live".to_string(),
+            "someone".to_string(),
+            channel.clone(),
+            OffsetDateTime::now_utc(),
+        );
+        let live = messages.add_message(&live).await?;
+
+        let options = ImportOptions {
+            source: Faker.fake(),
+            format: ImportFormat::Txt,
+            channel: Some(channel.clone()),
+        };
+        let lines = stream::iter(
+            [
+                "[2020-01-01 12:00:00] someone: first",
+                "[2020-01-01 12:00:01] someone: second",
+            ]
+            .map(|line| Ok(line.to_string())),
+        );
+        import::import_lines(&mut ctx.repo, lines, &options).await?;
+
+        let history = messages.get_messages_from_channel(&channel, 10).await?;
+        let texts: Vec<_> = history.iter().map(|m| m.text.as_str()).collect();
+        assert_eq!(texts, ["live", "second", "first"]);
+        assert_eq!(history[1].seq, Some(0));
+
+        // Nothing new to replay to a subscriber resuming from before them.
+        let missed = messages.get_messages_after(&channel, 0, 10).await?;
+        assert_eq!(missed, vec![live]);
+
+        Ok(())
+    }
+
+    #[test_context(ImportRepoTestContext)]
+    #[test]
+    async fn repo_import_locks_source(ctx: &mut ImportRepoTestContext) -> Result<(), Error> {
+        let source: String = Faker.fake();
+        let other = ctx.repo.session().await?;
+
+        assert!(ctx.repo.lock_source(&source).await?);
+        assert!(!other.lock_source(&source).await?);
+        assert!(other.start_job(&source).await.is_err());
+        assert_eq!(ctx.repo.get_job(&source).await?, None);
+
+        Ok(())
+    }
+}
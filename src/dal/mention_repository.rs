@@ -0,0 +1,323 @@
+use enum_iterator::Sequence;
+use futures::stream::{self, Stream};
+use log::warn;
+use tokio_postgres::types::Type;
+use tokio_postgres::Client;
+
+use crate::config::Config;
+use crate::dal;
+use crate::error::Error;
+use crate::models::mention::{Mention, MentionPage};
+use crate::utils::connection_string::ConnectionString;
+use crate::utils::repo_statement::ToRepoStatement;
+
+/// The id of every mention in a message stored through the API is announced
+/// here, to every instance.
+pub(crate) const MENTION_NOTIFY_CHANNEL: &str = "mentions";
+
+/// A mention with its message, as read by [`Mention::from`].
+const SELECT_MENTIONS: &str = "SELECT mn.id AS mention_id, mn.username AS mentioned, mn.read_at IS NOT NULL AS read, m.* FROM mentions mn JOIN chat_messages m ON m.id = mn.message_id AND m.timestamp = mn.message_timestamp";
+
+/// Whether the mentioned user can still read the channel of the message `m`,
+/// which they could when the mention was recorded.
+const CAN_READ_CHANNEL: &str = "NOT EXISTS (SELECT 1 FROM channels c WHERE c.name = m.channel AND c.private AND NOT EXISTS (SELECT 1 FROM channel_members cm WHERE cm.channel = c.name AND cm.username = mn.username))";
+
+#[derive(Debug, PartialEq, Sequence)]
+enum MentionRepoStatement {
+    GetPage,
+    GetById,
+    CountUnread,
+    SetRead,
+    Listen,
+}
+
+impl ToRepoStatement for MentionRepoStatement {
+    fn as_string(&self) -> String {
+        match self {
+            MentionRepoStatement::GetPage => format!("{} WHERE mn.username = $1 AND ($2::BIGINT IS NULL OR mn.id < $2) AND (NOT $3 OR mn.read_at IS NULL) AND {} ORDER BY mn.id DESC LIMIT $4", SELECT_MENTIONS, CAN_READ_CHANNEL),
+            MentionRepoStatement::GetById => format!("{} WHERE mn.id = $1 AND {}", SELECT_MENTIONS, CAN_READ_CHANNEL),
+            MentionRepoStatement::CountUnread => format!("SELECT COUNT(*) AS count FROM mentions mn JOIN chat_messages m ON m.id = mn.message_id AND m.timestamp = mn.message_timestamp WHERE mn.username = $1 AND mn.read_at IS NULL AND {}", CAN_READ_CHANNEL),
+            MentionRepoStatement::SetRead => "UPDATE mentions SET read_at = CASE WHEN $3 THEN COALESCE(read_at, NOW()) END WHERE username = $1 AND id = ANY($2)".to_string(),
+            MentionRepoStatement::Listen => format!("LISTEN {}", MENTION_NOTIFY_CHANNEL),
+        }
+    }
+
+    fn get_types(&self) -> Vec<Type> {
+        match self {
+            MentionRepoStatement::GetPage => {
+                vec![Type::TEXT, Type::INT8, Type::BOOL, Type::INT8]
+            }
+            MentionRepoStatement::GetById => vec![Type::INT8],
+            MentionRepoStatement::CountUnread => vec![Type::TEXT],
+            MentionRepoStatement::SetRead => vec![Type::TEXT, Type::INT8_ARRAY, Type::BOOL],
+            MentionRepoStatement::Listen => vec![],
+        }
+    }
+}
+
+/// Reads users' mentions, which are recorded by the database as messages
+/// are stored.
+#[derive(Debug)]
+pub struct MentionRepository {
+    connection_string: ConnectionString,
+    pub client: Option<Client>,
+}
+
+impl MentionRepository {
+    pub fn new(config: &Config) -> Result<Self, Error> {
+        let connection_string = config.db()?;
+
+        Ok(Self {
+            connection_string,
+            client: None,
+        })
+    }
+
+    pub async fn connect(&mut self) -> Result<(), Error> {
+        let client = dal::connect(&self.connection_string).await?;
+        self.client = Some(client);
+
+        Ok(())
+    }
+
+    /// A page of the user's mentions, newest first, starting before the
+    /// mention with id `before` if given.
+    pub async fn get_mentions(
+        &self,
+        username: &str,
+        before: Option<i64>,
+        unread_only: bool,
+        limit: i64,
+    ) -> Result<MentionPage, Error> {
+        let client = self.client.as_ref().unwrap();
+
+        let mentions = client
+            .query(
+                &MentionRepoStatement::GetPage.as_string(),
+                &[&username, &before, &unread_only, &limit],
+            )
+            .await?
+            .into_iter()
+            .map(Mention::from)
+            .collect();
+
+        Ok(MentionPage {
+            mentions,
+            unread: self.count_unread(username).await?,
+        })
+    }
+
+    pub async fn count_unread(&self, username: &str) -> Result<i64, Error> {
+        let client = self.client.as_ref().unwrap();
+
+        let row = client
+            .query_one(&MentionRepoStatement::CountUnread.as_string(), &[&username])
+            .await?;
+
+        Ok(row.get("count"))
+    }
+
+    /// Marks the user's mentions with the given ids read or unread. Ids of
+    /// other users' mentions are ignored. Returns how many were updated.
+    pub async fn set_read(&self, username: &str, ids: &[i64], read: bool) -> Result<u64, Error> {
+        let client = self.client.as_ref().unwrap();
+
+        let updated = client
+            .execute(
+                &MentionRepoStatement::SetRead.as_string(),
+                &[&username, &ids, &read],
+            )
+            .await?;
+
+        Ok(updated)
+    }
+
+    /// Streams the mentions announced by any instance from now on, over a
+    /// dedicated connection. The stream ends if that connection is lost.
+    pub async fn listen(&self) -> Result<impl Stream<Item = Mention> + 'static, Error> {
+        let (client, notifications) =
+            dal::connect_with_notifications(&self.connection_string).await?;
+        client
+            .batch_execute(&MentionRepoStatement::Listen.as_string())
+            .await?;
+
+        // Only ids are announced, so each mention is read back in full.
+        let mentions = stream::unfold(
+            (client, notifications),
+            |(client, mut notifications)| async move {
+                loop {
+                    let notification = notifications.recv().await?;
+                    let Ok(id) = notification.payload().parse::<i64>() else {
+                        warn!("Ignoring malformed mention notification");
+                        continue;
+                    };
+
+                    match client
+                        .query_opt(&MentionRepoStatement::GetById.as_string(), &[&id])
+                        .await
+                    {
+                        Ok(Some(row)) => {
+                            return Some((Mention::from(row), (client, notifications)))
+                        }
+                        Ok(None) => {}
+                        Err(err) => warn!("Could not read mention {}: {}", id, err),
+                    }
+                }
+            },
+        );
+
+        Ok(mentions)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use fake::{Fake, Faker};
+    use futures::StreamExt;
+    use test_context::{test_context, AsyncTestContext};
+    use time::OffsetDateTime;
+    use tokio::test;
+
+    use super::*;
+    use crate::dal::chat_message_repository::ChatMessageRepository;
+    use crate::models::chat_message::ChatMessage;
+
+    struct MentionRepoTestContext {
+        repo: MentionRepository,
+        messages: ChatMessageRepository,
+    }
+
+    #[async_trait::async_trait]
+    impl AsyncTestContext for MentionRepoTestContext {
+        async fn setup() -> MentionRepoTestContext {
+            let config = Config::load("config.json").await.unwrap();
+            dal::migrations::run(&config).await.unwrap();
+
+            let mut repo = MentionRepository::new(&config).unwrap();
+            repo.connect().await.unwrap();
+            let mut messages = ChatMessageRepository::new(&config).unwrap();
+            messages.connect().await.unwrap();
+
+            MentionRepoTestContext { repo, messages }
+        }
+
+        async fn teardown(self) {}
+    }
+
+    #[test_context(MentionRepoTestContext)]
+    #[test]
+    async fn repo_mentions_of_known_users_are_recorded(
+        ctx: &MentionRepoTestContext,
+    ) -> Result<(), Error> {
+        let (alice, bob, channel): (String, String, String) =
+            (Faker.fake(), Faker.fake(), Faker.fake());
+        let client = ctx.repo.client.as_ref().unwrap();
+        for name in [&alice, &bob, &channel] {
+            client
+                .execute(
+                    "INSERT INTO users (name) VALUES ($1) ON CONFLICT DO NOTHING",
+                    &[name],
+                )
+                .await?;
+        }
+
+        let mut listener = Box::pin(ctx.repo.listen().await?);
+
+        // Unknown users and the sender themselves are not recorded.
+        let text = format!("hi @{} and @nobody_{} from @{}", bob, alice, alice);
+        let message = ChatMessage::new(
+            text,
+            alice.clone(),
+            channel.clone(),
+            OffsetDateTime::now_utc(),
+        );
+        let first = ctx.messages.add_message(&message).await?;
+        let message = ChatMessage::new(
+            format!("@{}?", bob),
+            alice,
+            channel,
+            OffsetDateTime::now_utc(),
+        );
+        let second = ctx.messages.add_message(&message).await?;
+
+        let announced = tokio::time::timeout(Duration::from_secs(5), listener.next()).await;
+        let announced = announced.unwrap().unwrap();
+        assert_eq!(announced.username, bob);
+        assert_eq!(announced.message, first);
+
+        let page = ctx.repo.get_mentions(&bob, None, false, 10).await?;
+        assert_eq!(page.unread, 2);
+        let messages: Vec<_> = page.mentions.iter().map(|m| &m.message).collect();
+        assert_eq!(messages, vec![&second, &first]);
+
+        let newest = page.mentions[0].id;
+        assert_eq!(ctx.repo.set_read(&bob, &[newest], true).await?, 1);
+        let page = ctx.repo.get_mentions(&bob, None, true, 10).await?;
+        assert_eq!(page.unread, 1);
+        assert_eq!(page.mentions.len(), 1);
+        assert_eq!(page.mentions[0].message, first);
+
+        let page = ctx.repo.get_mentions(&bob, Some(newest), false, 10).await?;
+        assert_eq!(page.mentions.len(), 1);
+        assert!(!page.mentions[0].read);
+
+        Ok(())
+    }
+
+    #[test_context(MentionRepoTestContext)]
+    #[test]
+    async fn repo_mentions_follow_channel_access(
+        ctx: &MentionRepoTestContext,
+    ) -> Result<(), Error> {
+        let (alice, bob, channel): (String, String, String) =
+            (Faker.fake(), Faker.fake(), Faker.fake());
+        let client = ctx.repo.client.as_ref().unwrap();
+        for name in [&alice, &bob, &channel] {
+            client
+                .execute(
+                    "INSERT INTO users (name) VALUES ($1) ON CONFLICT DO NOTHING",
+                    &[name],
+                )
+                .await?;
+        }
+        client
+            .execute(
+                "INSERT INTO channels (name, display_name, private) VALUES ($1, $1, TRUE)",
+                &[&channel],
+            )
+            .await?;
+        client
+            .execute(
+                "INSERT INTO channel_members (channel, username, role) VALUES ($1, $2, 'member')",
+                &[&channel, &bob],
+            )
+            .await?;
+
+        let message = ChatMessage::new(
+            format!("hi @{}", bob),
+            alice,
+            channel.clone(),
+            OffsetDateTime::now_utc(),
+        );
+        ctx.messages.add_message(&message).await?;
+
+        let page = ctx.repo.get_mentions(&bob, None, false, 10).await?;
+        assert_eq!(page.mentions.len(), 1);
+        assert_eq!(page.unread, 1);
+
+        // Once removed from the channel, its mentions are no longer shown.
+        client
+            .execute(
+                "DELETE FROM channel_members WHERE channel = $1 AND username = $2",
+                &[&channel, &bob],
+            )
+            .await?;
+        let page = ctx.repo.get_mentions(&bob, None, false, 10).await?;
+        assert!(page.mentions.is_empty());
+        assert_eq!(page.unread, 0);
+
+        Ok(())
+    }
+}
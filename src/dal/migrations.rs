@@ -0,0 +1,175 @@
+use enum_iterator::Sequence;
+use log::info;
+use tokio_postgres::types::Type;
+
+use crate::config::Config;
+use crate::dal;
+use crate::error::Error;
+use crate::utils::repo_statement::ToRepoStatement;
+
+/// Arbitrary, but shared by every server so that only one migrates at a time.
+const MIGRATION_LOCK_ID: i64 = 7314;
+
+#[derive(Debug)]
+struct Migration {
+    version: i32,
+    name: &'static str,
+    sql: &'static str,
+}
+
+/// Applied in order, each exactly once. Never edit one that has shipped;
+/// add another instead.
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 0,
+        name: "base_tables",
+        sql: include_str!("../../data/migrations/000_base_tables.sql"),
+    },
+    Migration {
+        version: 1,
+        name: "partition_chat_messages",
+        sql: include_str!("../../data/migrations/001_partition_chat_messages.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "direct_messages",
+        sql: include_str!("../../data/migrations/002_direct_messages.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "private_channels",
+        sql: include_str!("../../data/migrations/003_private_channels.sql"),
+    },
+    Migration {
+        version: 4,
+        name: "message_keys",
+        sql: include_str!("../../data/migrations/004_message_keys.sql"),
+    },
+    Migration {
+        version: 5,
+        name: "channel_sequences",
+        sql: include_str!("../../data/migrations/005_channel_sequences.sql"),
+    },
+    Migration {
+        version: 6,
+        name: "message_fragments",
+        sql: include_str!("../../data/migrations/006_message_fragments.sql"),
+    },
+    Migration {
+        version: 7,
+        name: "mentions",
+        sql: include_str!("../../data/migrations/007_mentions.sql"),
+    },
+    Migration {
+        version: 8,
+        name: "timestamptz",
+        sql: include_str!("../../data/migrations/008_timestamptz.sql"),
+    },
+    Migration {
+        version: 9,
+        name: "channel_activity",
+        sql: include_str!("../../data/migrations/009_channel_activity.sql"),
+    },
+    Migration {
+        version: 10,
+        name: "imported_seq",
+        sql: include_str!("../../data/migrations/010_imported_seq.sql"),
+    },
+    Migration {
+        version: 11,
+        name: "rollup_refresh",
+        sql: include_str!("../../data/migrations/011_rollup_refresh.sql"),
+    },
+];
+
+#[derive(Debug, PartialEq, Sequence)]
+enum MigrationStatement {
+    CreateTable,
+    Lock,
+    IsApplied,
+    Record,
+}
+
+impl ToRepoStatement for MigrationStatement {
+    fn as_string(&self) -> String {
+        match self {
+            MigrationStatement::CreateTable => "CREATE TABLE IF NOT EXISTS schema_migrations (version INTEGER NOT NULL CONSTRAINT schema_migrations_pk PRIMARY KEY, name VARCHAR(255) NOT NULL, applied_at TIMESTAMPTZ DEFAULT NOW() NOT NULL)".to_string(),
+            MigrationStatement::Lock => "SELECT pg_advisory_xact_lock($1)".to_string(),
+            MigrationStatement::IsApplied => "SELECT EXISTS (SELECT 1 FROM schema_migrations WHERE version = $1) AS applied".to_string(),
+            MigrationStatement::Record => "INSERT INTO schema_migrations (version, name) VALUES ($1, $2)".to_string(),
+        }
+    }
+
+    fn get_types(&self) -> Vec<Type> {
+        match self {
+            MigrationStatement::CreateTable => vec![],
+            MigrationStatement::Lock => vec![Type::INT8],
+            MigrationStatement::IsApplied => vec![Type::INT4],
+            MigrationStatement::Record => vec![Type::INT4, Type::VARCHAR],
+        }
+    }
+}
+
+/// Brings the schema up to date, returning the versions that were applied.
+/// Each migration runs in its own transaction.
+pub(crate) async fn run(config: &Config) -> Result<Vec<i32>, Error> {
+    let mut client = dal::connect(&config.db()?).await?;
+    client
+        .batch_execute(&MigrationStatement::CreateTable.as_string())
+        .await?;
+
+    let mut applied = Vec::new();
+    for migration in MIGRATIONS {
+        let transaction = client.transaction().await?;
+        transaction
+            .execute(&MigrationStatement::Lock.as_string(), &[&MIGRATION_LOCK_ID])
+            .await?;
+
+        let row = transaction
+            .query_one(
+                &MigrationStatement::IsApplied.as_string(),
+                &[&migration.version],
+            )
+            .await?;
+        if row.get("applied") {
+            continue;
+        }
+
+        info!(
+            "Applying migration {}: {}",
+            migration.version, migration.name
+        );
+        transaction.batch_execute(migration.sql).await?;
+        transaction
+            .execute(
+                &MigrationStatement::Record.as_string(),
+                &[&migration.version, &migration.name],
+            )
+            .await?;
+        transaction.commit().await?;
+
+        applied.push(migration.version);
+    }
+
+    Ok(applied)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn repo_run_migrations_is_idempotent() -> Result<(), Error> {
+        let config = Config::load("config.json").await?;
+
+        run(&config).await?;
+        assert!(run(&config).await?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_migration_versions_increase() {
+        assert!(MIGRATIONS.windows(2).all(|w| w[0].version < w[1].version));
+    }
+}
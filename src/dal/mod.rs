@@ -1 +1,63 @@
+use futures::stream;
+use futures::StreamExt;
+use tokio::sync::mpsc;
+use tokio_postgres::{AsyncMessage, Client, NoTls, Notification};
+
+use crate::error::Error;
+use crate::utils::connection_string::ConnectionString;
+
+pub mod batch_writer;
+pub mod channel_repository;
 pub mod chat_message_repository;
+pub mod direct_message_repository;
+pub mod import_repository;
+pub mod mention_repository;
+pub(crate) mod migrations;
+pub mod partition_repository;
+pub mod reaction_repository;
+pub mod retention_repository;
+pub mod stats_repository;
+
+/// Opens a new client and drives its connection on a background task.
+pub(crate) async fn connect(connection_string: &ConnectionString) -> Result<Client, Error> {
+    let (client, connection) =
+        tokio_postgres::connect(&connection_string.as_string(), NoTls).await?;
+
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            eprintln!("connection error: {}", e);
+        }
+    });
+
+    Ok(client)
+}
+
+/// Opens a new client like [`connect`], forwarding the notifications it
+/// receives. The receiver closes once the connection is lost.
+pub(crate) async fn connect_with_notifications(
+    connection_string: &ConnectionString,
+) -> Result<(Client, mpsc::UnboundedReceiver<Notification>), Error> {
+    let (client, mut connection) =
+        tokio_postgres::connect(&connection_string.as_string(), NoTls).await?;
+    let (sender, receiver) = mpsc::unbounded_channel();
+
+    tokio::spawn(async move {
+        let mut messages = stream::poll_fn(move |cx| connection.poll_message(cx));
+        while let Some(message) = messages.next().await {
+            match message {
+                Ok(AsyncMessage::Notification(notification)) => {
+                    if sender.send(notification).is_err() {
+                        break;
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    eprintln!("connection error: {}", e);
+                    break;
+                }
+            }
+        }
+    });
+
+    Ok((client, receiver))
+}
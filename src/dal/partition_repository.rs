@@ -0,0 +1,255 @@
+use std::time::Duration;
+
+use enum_iterator::Sequence;
+use log::info;
+use time::{Date, OffsetDateTime};
+use tokio_postgres::types::Type;
+use tokio_postgres::Client;
+
+use crate::config::Config;
+use crate::dal;
+use crate::error::Error;
+use crate::models::partition::{next_month, Partition};
+use crate::utils::connection_string::ConnectionString;
+use crate::utils::repo_statement::ToRepoStatement;
+
+const DEFAULT_MONTHS_AHEAD: u64 = 3;
+const DEFAULT_MAINTENANCE_INTERVAL_SECS: u64 = 6 * 3600;
+
+#[derive(Debug, Clone)]
+pub struct PartitionConfig {
+    /// How many months past the current one to keep partitions ready for.
+    pub months_ahead: u32,
+    pub interval: Duration,
+}
+
+impl Config {
+    pub(crate) fn partitions(&self) -> Result<PartitionConfig, Error> {
+        let json = self["partitions"].clone();
+
+        let months_ahead = json
+            .get("months_ahead")
+            .and_then(|x| x.as_u64())
+            .unwrap_or(DEFAULT_MONTHS_AHEAD);
+
+        let interval = json
+            .get("interval_secs")
+            .and_then(|x| x.as_u64())
+            .unwrap_or(DEFAULT_MAINTENANCE_INTERVAL_SECS);
+
+        Ok(PartitionConfig {
+            months_ahead: months_ahead as u32,
+            interval: Duration::from_secs(interval),
+        })
+    }
+}
+
+#[derive(Debug, PartialEq, Sequence)]
+enum PartitionRepoStatement {
+    List,
+    Ensure,
+    GetDefaultMonths,
+    Remove,
+}
+
+impl ToRepoStatement for PartitionRepoStatement {
+    fn as_string(&self) -> String {
+        match self {
+            PartitionRepoStatement::List => "SELECT name, month_start FROM chat_message_partitions ORDER BY month_start".to_string(),
+            PartitionRepoStatement::Ensure => "SELECT chat_messages_ensure_partition($1) AS name".to_string(),
+            PartitionRepoStatement::GetDefaultMonths => "SELECT DISTINCT date_trunc('month', timestamp)::DATE AS month FROM chat_messages_default".to_string(),
+            PartitionRepoStatement::Remove => "SELECT chat_messages_remove_partition($1, $2) AS removed".to_string(),
+        }
+    }
+
+    fn get_types(&self) -> Vec<Type> {
+        match self {
+            PartitionRepoStatement::List => vec![],
+            PartitionRepoStatement::Ensure => vec![Type::DATE],
+            PartitionRepoStatement::GetDefaultMonths => vec![],
+            PartitionRepoStatement::Remove => vec![Type::TEXT, Type::BOOL],
+        }
+    }
+}
+
+/// Manages the monthly partitions of `chat_messages`. The partitioning
+/// itself is set up by the migrations.
+#[derive(Debug)]
+pub struct PartitionRepository {
+    connection_string: ConnectionString,
+    config: PartitionConfig,
+    pub client: Option<Client>,
+}
+
+impl PartitionRepository {
+    pub fn new(config: &Config) -> Result<Self, Error> {
+        let connection_string = config.db()?;
+        let config = config.partitions()?;
+
+        Ok(Self {
+            connection_string,
+            config,
+            client: None,
+        })
+    }
+
+    pub async fn connect(&mut self) -> Result<(), Error> {
+        let client = dal::connect(&self.connection_string).await?;
+        self.client = Some(client);
+
+        Ok(())
+    }
+
+    pub fn config(&self) -> &PartitionConfig {
+        &self.config
+    }
+
+    pub async fn list_partitions(&self) -> Result<Vec<Partition>, Error> {
+        let client = self.client.as_ref().unwrap();
+
+        let partitions = client
+            .query(&PartitionRepoStatement::List.as_string(), &[])
+            .await?
+            .iter()
+            .map(Partition::from)
+            .collect();
+
+        Ok(partitions)
+    }
+
+    /// Creates the partition for the month containing `month` if it does not
+    /// exist yet.
+    pub async fn ensure_partition(&self, month: Date) -> Result<String, Error> {
+        let client = self.client.as_ref().unwrap();
+
+        let row = client
+            .query_one(&PartitionRepoStatement::Ensure.as_string(), &[&month])
+            .await?;
+
+        Ok(row.get("name"))
+    }
+
+    /// Makes sure partitions exist for the current and upcoming months, and
+    /// moves anything that landed in the default partition into a monthly
+    /// one, such as imported history.
+    pub async fn maintain(&self, now: OffsetDateTime) -> Result<(), Error> {
+        let client = self.client.as_ref().unwrap();
+
+        let mut month = now.date().replace_day(1).unwrap();
+        for _ in 0..=self.config.months_ahead {
+            self.ensure_partition(month).await?;
+            month = next_month(month);
+        }
+
+        let stray_months: Vec<Date> = client
+            .query(&PartitionRepoStatement::GetDefaultMonths.as_string(), &[])
+            .await?
+            .iter()
+            .map(|row| row.get("month"))
+            .collect();
+
+        for month in stray_months {
+            let name = self.ensure_partition(month).await?;
+            info!("Moved messages out of the default partition into {}", name);
+        }
+
+        Ok(())
+    }
+
+    /// Detaches a partition, dropping it unless `keep` is set. Returns the
+    /// number of messages it held.
+    pub async fn remove_partition(&self, name: &str, keep: bool) -> Result<i64, Error> {
+        let client = self.client.as_ref().unwrap();
+
+        let row = client
+            .query_one(&PartitionRepoStatement::Remove.as_string(), &[&name, &keep])
+            .await?;
+
+        Ok(row.get("removed"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use fake::{Fake, Faker};
+    use test_context::{test_context, AsyncTestContext};
+    use time::Month;
+    use tokio::test;
+
+    use super::*;
+    use crate::dal::chat_message_repository::ChatMessageRepository;
+    use crate::models::chat_message::ChatMessage;
+
+    struct PartitionRepoTestContext {
+        messages: ChatMessageRepository,
+        repo: PartitionRepository,
+    }
+
+    #[async_trait::async_trait]
+    impl AsyncTestContext for PartitionRepoTestContext {
+        async fn setup() -> PartitionRepoTestContext {
+            let config = Config::load("config.json").await.unwrap();
+            dal::migrations::run(&config).await.unwrap();
+
+            let mut messages = ChatMessageRepository::new(&config).unwrap();
+            messages.connect().await.unwrap();
+            let mut repo = PartitionRepository::new(&config).unwrap();
+            repo.connect().await.unwrap();
+
+            PartitionRepoTestContext { messages, repo }
+        }
+
+        async fn teardown(self) {}
+    }
+
+    #[test_context(PartitionRepoTestContext)]
+    #[test]
+    async fn repo_maintain_partitions(ctx: &PartitionRepoTestContext) -> Result<(), Error> {
+        let now = OffsetDateTime::now_utc();
+        ctx.repo.maintain(now).await?;
+
+        let partitions = ctx.repo.list_partitions().await?;
+        let this_month = now.date().replace_day(1).unwrap();
+        assert!(partitions.iter().any(|p| p.month_start == this_month));
+
+        // A message from far in the future lands in the default partition
+        // until maintenance gives it a month of its own.
+        let channel: String = Faker.fake();
+        let client = ctx.repo.client.as_ref().unwrap();
+        client
+            .execute(
+                "INSERT INTO users (name) VALUES ($1) ON CONFLICT DO NOTHING",
+                &[&channel],
+            )
+            .await?;
+
+        let month = Date::from_calendar_date(2999, Month::January, 1).unwrap();
+        let timestamp = Partition {
+            name: String::new(),
+            month_start: month,
+        }
+        .starts_at();
+        let message = ChatMessage::new(Faker.fake(), channel.clone(), channel, timestamp);
+        let stored = ctx.messages.add_message(&message).await?;
+
+        ctx.repo.maintain(now).await?;
+
+        let partitions = ctx.repo.list_partitions().await?;
+        let partition = partitions.iter().find(|p| p.month_start == month).unwrap();
+        assert_eq!(partition.name, "chat_messages_2999_01");
+        assert!(ctx
+            .messages
+            .get_message(stored.id.unwrap())
+            .await?
+            .is_some());
+
+        assert_eq!(ctx.repo.remove_partition(&partition.name, false).await?, 1);
+        assert!(ctx
+            .messages
+            .get_message(stored.id.unwrap())
+            .await?
+            .is_none());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,184 @@
+use std::collections::HashMap;
+
+use enum_iterator::Sequence;
+use tokio_postgres::types::Type;
+use tokio_postgres::Client;
+
+use crate::config::Config;
+use crate::dal;
+use crate::error::Error;
+use crate::models::reaction::{Reaction, ReactionCount};
+use crate::utils::connection_string::ConnectionString;
+use crate::utils::repo_statement::ToRepoStatement;
+
+#[derive(Debug, PartialEq, Sequence)]
+enum ReactionRepoStatement {
+    Insert,
+    Delete,
+    CountByMessages,
+}
+
+impl ToRepoStatement for ReactionRepoStatement {
+    fn as_string(&self) -> String {
+        match self {
+            ReactionRepoStatement::Insert => "INSERT INTO reactions (message_id, username, emote) VALUES ($1, $2, $3) ON CONFLICT DO NOTHING".to_string(),
+            ReactionRepoStatement::Delete => "DELETE FROM reactions WHERE message_id = $1 AND username = $2 AND emote = $3".to_string(),
+            ReactionRepoStatement::CountByMessages => "SELECT message_id, emote, COUNT(*) AS count FROM reactions WHERE message_id = ANY($1) GROUP BY message_id, emote ORDER BY message_id, count DESC, emote".to_string(),
+        }
+    }
+
+    fn get_types(&self) -> Vec<Type> {
+        match self {
+            ReactionRepoStatement::Insert | ReactionRepoStatement::Delete => {
+                vec![Type::INT4, Type::TEXT, Type::TEXT]
+            }
+            ReactionRepoStatement::CountByMessages => vec![Type::INT4_ARRAY],
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ReactionRepository {
+    connection_string: ConnectionString,
+    pub client: Option<Client>,
+}
+
+impl ReactionRepository {
+    pub fn new(config: &Config) -> Result<Self, Error> {
+        let connection_string = config.db()?;
+
+        Ok(Self {
+            connection_string,
+            client: None,
+        })
+    }
+
+    pub async fn connect(&mut self) -> Result<(), Error> {
+        let client = dal::connect(&self.connection_string).await?;
+        self.client = Some(client);
+
+        Ok(())
+    }
+
+    /// Returns `true` if the reaction was added, or `false` if the user had
+    /// already reacted to the message with this emote.
+    pub async fn add_reaction(&self, reaction: &Reaction) -> Result<bool, Error> {
+        let client = self.client.as_ref().unwrap();
+
+        let inserted = client
+            .execute(
+                &ReactionRepoStatement::Insert.as_string(),
+                &[&reaction.message_id, &reaction.username, &reaction.emote],
+            )
+            .await?;
+
+        Ok(inserted > 0)
+    }
+
+    /// Returns `true` if the reaction existed and was removed.
+    pub async fn remove_reaction(&self, reaction: &Reaction) -> Result<bool, Error> {
+        let client = self.client.as_ref().unwrap();
+
+        let deleted = client
+            .execute(
+                &ReactionRepoStatement::Delete.as_string(),
+                &[&reaction.message_id, &reaction.username, &reaction.emote],
+            )
+            .await?;
+
+        Ok(deleted > 0)
+    }
+
+    /// Aggregates reaction counts per emote for each of the given messages.
+    /// Messages without reactions are absent from the returned map.
+    pub async fn get_reaction_counts(
+        &self,
+        message_ids: &[i32],
+    ) -> Result<HashMap<i32, Vec<ReactionCount>>, Error> {
+        let client = self.client.as_ref().unwrap();
+
+        let rows = client
+            .query(
+                &ReactionRepoStatement::CountByMessages.as_string(),
+                &[&message_ids],
+            )
+            .await?;
+
+        let mut counts: HashMap<i32, Vec<ReactionCount>> = HashMap::new();
+        for row in rows.iter() {
+            counts
+                .entry(row.get("message_id"))
+                .or_default()
+                .push(ReactionCount::from(row));
+        }
+
+        Ok(counts)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use fake::{Fake, Faker};
+    use test_context::{test_context, AsyncTestContext};
+    use tokio::test;
+
+    use super::*;
+    use crate::dal::chat_message_repository::ChatMessageRepository;
+    use crate::models::chat_message::ChatMessage;
+
+    struct ReactionRepoTestContext {
+        messages: ChatMessageRepository,
+        repo: ReactionRepository,
+    }
+
+    #[async_trait::async_trait]
+    impl AsyncTestContext for ReactionRepoTestContext {
+        async fn setup() -> ReactionRepoTestContext {
+            let config = Config::load("config.json").await.unwrap();
+            let mut messages = ChatMessageRepository::new(&config).unwrap();
+            messages.connect().await.unwrap();
+            let mut repo = ReactionRepository::new(&config).unwrap();
+            repo.connect().await.unwrap();
+
+            ReactionRepoTestContext { messages, repo }
+        }
+
+        async fn teardown(self) {}
+    }
+
+    async fn add_message(ctx: &ReactionRepoTestContext) -> Result<i32, Error> {
+        let client = ctx.repo.client.as_ref().unwrap();
+        let message = Faker.fake::<ChatMessage>();
+        client
+            .execute(
+                "INSERT INTO users (name) VALUES ($1), ($2) ON CONFLICT DO NOTHING",
+                &[&message.username, &message.channel],
+            )
+            .await?;
+
+        let stored = ctx.messages.add_message(&message).await?;
+
+        Ok(stored.id.unwrap())
+    }
+
+    #[test_context(ReactionRepoTestContext)]
+    #[test]
+    async fn repo_add_reaction_is_unique_per_user(
+        ctx: &ReactionRepoTestContext,
+    ) -> Result<(), Error> {
+        let message_id = add_message(ctx).await?;
+        let message = ctx.messages.get_message(message_id).await?.unwrap();
+        let reaction = Reaction::new(message_id, message.username, "Kappa".to_string());
+
+        assert!(ctx.repo.add_reaction(&reaction).await?);
+        assert!(!ctx.repo.add_reaction(&reaction).await?);
+
+        let counts = ctx.repo.get_reaction_counts(&[message_id]).await?;
+        assert_eq!(counts[&message_id][0].count, 1);
+
+        assert!(ctx.repo.remove_reaction(&reaction).await?);
+        assert!(!ctx.repo.remove_reaction(&reaction).await?);
+
+        Ok(())
+    }
+}
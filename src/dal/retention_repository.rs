@@ -0,0 +1,546 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use enum_iterator::Sequence;
+use log::info;
+use time::OffsetDateTime;
+use tokio_postgres::types::Type;
+use tokio_postgres::Client;
+
+use crate::config::Config;
+use crate::dal;
+use crate::dal::partition_repository::PartitionRepository;
+use crate::error::Error;
+use crate::error::Error::Configuration;
+use crate::models::chat_message::ChatMessage;
+use crate::models::partition::Partition;
+use crate::models::retention::{RetentionPolicy, RetentionPreview, RetentionRun};
+use crate::utils::archive;
+use crate::utils::connection_string::ConnectionString;
+use crate::utils::repo_statement::ToRepoStatement;
+
+const DEFAULT_RETENTION_INTERVAL_SECS: u64 = 3600;
+const DEFAULT_RETENTION_BATCH_SIZE: i64 = 5000;
+const ERR_INVALID_RETENTION_POLICY: &str =
+    "Retention policies must be \"forever\", {\"keep_days\": n} or {\"keep_messages\": n}";
+
+#[derive(Debug, Clone, Default)]
+pub struct RetentionConfig {
+    /// Applies to every channel without a policy of its own.
+    pub default: RetentionPolicy,
+    pub channels: HashMap<String, RetentionPolicy>,
+    pub interval: Duration,
+    /// Expired messages are written here before being deleted, if set.
+    pub archive_dir: Option<PathBuf>,
+    /// The most messages archived and deleted at a time.
+    pub batch_size: i64,
+    /// Expired partitions are detached and left in place rather than
+    /// dropped, if set.
+    pub detach_partitions: bool,
+}
+
+impl RetentionConfig {
+    pub fn policy_for(&self, channel: &str) -> RetentionPolicy {
+        self.channels.get(channel).copied().unwrap_or(self.default)
+    }
+
+    /// Whole monthly partitions ending before this time hold only expired
+    /// messages. That is only known when every policy is age based.
+    pub fn partition_cutoff(&self, now: OffsetDateTime) -> Option<OffsetDateTime> {
+        let mut longest = self.default;
+        for policy in self.channels.values().copied().chain([self.default]) {
+            match (policy, longest) {
+                (RetentionPolicy::KeepDays(days), RetentionPolicy::KeepDays(most)) => {
+                    longest = RetentionPolicy::KeepDays(days.max(most))
+                }
+                _ => return None,
+            }
+        }
+
+        longest.cutoff(now)
+    }
+
+    fn enforces_anything(&self) -> bool {
+        self.default != RetentionPolicy::Forever
+            || self
+                .channels
+                .values()
+                .any(|p| *p != RetentionPolicy::Forever)
+    }
+}
+
+impl Config {
+    pub(crate) fn retention(&self) -> Result<RetentionConfig, Error> {
+        let json = self["retention"].clone();
+        let invalid = |_| Configuration(ERR_INVALID_RETENTION_POLICY.to_string());
+
+        let default = match json.get("default") {
+            Some(x) => serde_json::from_value(x.clone()).map_err(invalid)?,
+            None => RetentionPolicy::Forever,
+        };
+
+        let channels = match json.get("channels") {
+            Some(x) => serde_json::from_value(x.clone()).map_err(invalid)?,
+            None => HashMap::new(),
+        };
+
+        let interval = json
+            .get("interval_secs")
+            .and_then(|x| x.as_u64())
+            .unwrap_or(DEFAULT_RETENTION_INTERVAL_SECS);
+
+        let batch_size = json
+            .get("batch_size")
+            .and_then(|x| x.as_i64())
+            .filter(|x| *x > 0)
+            .unwrap_or(DEFAULT_RETENTION_BATCH_SIZE);
+
+        Ok(RetentionConfig {
+            default,
+            channels,
+            interval: Duration::from_secs(interval),
+            archive_dir: json
+                .get("archive_dir")
+                .and_then(|x| x.as_str())
+                .map(PathBuf::from),
+            batch_size,
+            detach_partitions: json
+                .get("detach_partitions")
+                .and_then(|x| x.as_bool())
+                .unwrap_or(false),
+        })
+    }
+}
+
+/// Expired messages are those at or before the newest expired one, ordered
+/// by `(timestamp, id)`. Pinning that boundary at the start of a run keeps
+/// messages that arrive during it from shifting what a count based policy
+/// removes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct ExpiryBoundary {
+    timestamp: OffsetDateTime,
+    id: i32,
+}
+
+#[derive(Debug, PartialEq, Sequence)]
+enum RetentionRepoStatement {
+    GetChannels,
+    GetNewestExpired,
+    GetExpiredSummary,
+    GetExpired,
+    GetInRange,
+    DeleteByIds,
+}
+
+impl ToRepoStatement for RetentionRepoStatement {
+    fn as_string(&self) -> String {
+        match self {
+            // Every channel with messages is counted there, without scanning
+            // every partition.
+            RetentionRepoStatement::GetChannels => "SELECT channel FROM channel_sequences WHERE message_count > 0".to_string(),
+            RetentionRepoStatement::GetNewestExpired => "SELECT timestamp, id FROM chat_messages WHERE channel = $1 AND ($2::TIMESTAMPTZ IS NULL OR timestamp < $2) ORDER BY timestamp DESC, id DESC OFFSET $3 LIMIT 1".to_string(),
+            RetentionRepoStatement::GetExpiredSummary => "SELECT COUNT(*) AS count, MIN(timestamp) AS oldest, MAX(timestamp) AS newest FROM chat_messages WHERE channel = $1 AND (timestamp, id) <= ($2::TIMESTAMPTZ, $3)".to_string(),
+            RetentionRepoStatement::GetExpired => "SELECT * FROM chat_messages WHERE channel = $1 AND (timestamp, id) <= ($2::TIMESTAMPTZ, $3) ORDER BY timestamp, id LIMIT $4".to_string(),
+            RetentionRepoStatement::GetInRange => "SELECT * FROM chat_messages WHERE timestamp >= $1 AND timestamp < $2 AND (timestamp, id) > ($3::TIMESTAMPTZ, $4) ORDER BY timestamp, id LIMIT $5".to_string(),
+            // Nothing references messages by foreign key since partitioning,
+            // so whatever pointed at them goes here too.
+            RetentionRepoStatement::DeleteByIds => "WITH deleted AS (DELETE FROM chat_messages WHERE id = ANY($1) RETURNING id), deleted_reactions AS (DELETE FROM reactions WHERE message_id IN (SELECT id FROM deleted)), cleared_logs AS (UPDATE logs SET chat_message_id = NULL WHERE chat_message_id IN (SELECT id FROM deleted)), deleted_keys AS (DELETE FROM message_keys WHERE message_id IN (SELECT id FROM deleted)), deleted_mentions AS (DELETE FROM mentions WHERE message_id IN (SELECT id FROM deleted)) SELECT COUNT(*) AS count FROM deleted".to_string(),
+        }
+    }
+
+    fn get_types(&self) -> Vec<Type> {
+        match self {
+            RetentionRepoStatement::GetChannels => vec![],
+            RetentionRepoStatement::GetNewestExpired => {
+                vec![Type::TEXT, Type::TIMESTAMPTZ, Type::INT8]
+            }
+            RetentionRepoStatement::GetExpiredSummary => {
+                vec![Type::TEXT, Type::TIMESTAMPTZ, Type::INT4]
+            }
+            RetentionRepoStatement::GetExpired => {
+                vec![Type::TEXT, Type::TIMESTAMPTZ, Type::INT4, Type::INT8]
+            }
+            RetentionRepoStatement::GetInRange => vec![
+                Type::TIMESTAMPTZ,
+                Type::TIMESTAMPTZ,
+                Type::TIMESTAMPTZ,
+                Type::INT4,
+                Type::INT8,
+            ],
+            RetentionRepoStatement::DeleteByIds => vec![Type::INT4_ARRAY],
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct RetentionRepository {
+    connection_string: ConnectionString,
+    config: RetentionConfig,
+    partitions: PartitionRepository,
+    pub client: Option<Client>,
+}
+
+impl RetentionRepository {
+    pub fn new(config: &Config) -> Result<Self, Error> {
+        let connection_string = config.db()?;
+        let partitions = PartitionRepository::new(config)?;
+        let config = config.retention()?;
+
+        Ok(Self {
+            connection_string,
+            config,
+            partitions,
+            client: None,
+        })
+    }
+
+    pub async fn connect(&mut self) -> Result<(), Error> {
+        let client = dal::connect(&self.connection_string).await?;
+        self.client = Some(client);
+        self.partitions.connect().await?;
+
+        Ok(())
+    }
+
+    pub fn config(&self) -> &RetentionConfig {
+        &self.config
+    }
+
+    pub fn enforces_anything(&self) -> bool {
+        self.config.enforces_anything()
+    }
+
+    async fn get_boundary(
+        &self,
+        channel: &str,
+        policy: RetentionPolicy,
+        now: OffsetDateTime,
+    ) -> Result<Option<ExpiryBoundary>, Error> {
+        let client = self.client.as_ref().unwrap();
+
+        let (cutoff, offset) = match policy {
+            RetentionPolicy::Forever => return Ok(None),
+            RetentionPolicy::KeepDays(_) => (policy.cutoff(now), 0),
+            RetentionPolicy::KeepMessages(keep) => (None, keep as i64),
+        };
+
+        let row = client
+            .query_opt(
+                &RetentionRepoStatement::GetNewestExpired.as_string(),
+                &[&channel, &cutoff, &offset],
+            )
+            .await?;
+
+        Ok(row.map(|row| ExpiryBoundary {
+            timestamp: row.get("timestamp"),
+            id: row.get("id"),
+        }))
+    }
+
+    /// Counts the messages that enforcing the policy at `now` would remove.
+    pub async fn preview(
+        &self,
+        channel: &str,
+        policy: RetentionPolicy,
+        now: OffsetDateTime,
+    ) -> Result<RetentionPreview, Error> {
+        let client = self.client.as_ref().unwrap();
+
+        let mut preview = RetentionPreview {
+            channel: channel.to_string(),
+            policy,
+            expired_messages: 0,
+            oldest_expired: None,
+            newest_expired: None,
+        };
+
+        let Some(boundary) = self.get_boundary(channel, policy, now).await? else {
+            return Ok(preview);
+        };
+
+        let row = client
+            .query_one(
+                &RetentionRepoStatement::GetExpiredSummary.as_string(),
+                &[&channel, &boundary.timestamp, &boundary.id],
+            )
+            .await?;
+
+        preview.expired_messages = row.get("count");
+        preview.oldest_expired = row.get("oldest");
+        preview.newest_expired = row.get("newest");
+
+        Ok(preview)
+    }
+
+    /// Removes a channel's expired messages in batches, archiving each batch
+    /// first if an archive directory is configured. A batch is only deleted
+    /// once it has been archived.
+    pub async fn enforce(&self, channel: &str, now: OffsetDateTime) -> Result<RetentionRun, Error> {
+        let client = self.client.as_ref().unwrap();
+        let policy = self.config.policy_for(channel);
+
+        let mut run = RetentionRun {
+            channel: channel.to_string(),
+            ..Default::default()
+        };
+
+        let Some(boundary) = self.get_boundary(channel, policy, now).await? else {
+            return Ok(run);
+        };
+
+        loop {
+            let messages: Vec<ChatMessage> = client
+                .query(
+                    &RetentionRepoStatement::GetExpired.as_string(),
+                    &[
+                        &channel,
+                        &boundary.timestamp,
+                        &boundary.id,
+                        &self.config.batch_size,
+                    ],
+                )
+                .await?
+                .into_iter()
+                .map(ChatMessage::from)
+                .collect();
+
+            if messages.is_empty() {
+                break;
+            }
+
+            if let Some(dir) = &self.config.archive_dir {
+                archive::append(dir, channel, &messages).await?;
+                run.archived += messages.len() as u64;
+            }
+
+            let ids: Vec<i32> = messages.iter().filter_map(|m| m.id).collect();
+            let row = client
+                .query_one(&RetentionRepoStatement::DeleteByIds.as_string(), &[&ids])
+                .await?;
+            run.deleted += row.get::<_, i64>("count") as u64;
+        }
+
+        if run.deleted > 0 {
+            info!(
+                "Retention removed {} messages from {} ({} archived)",
+                run.deleted, channel, run.archived
+            );
+        }
+
+        Ok(run)
+    }
+
+    /// Archives every message in a partition, a batch at a time, grouped
+    /// into each channel's archive.
+    async fn archive_partition(&self, dir: &Path, partition: &Partition) -> Result<u64, Error> {
+        let client = self.client.as_ref().unwrap();
+        let (from, to) = (partition.starts_at(), partition.ends_at());
+
+        let mut archived = 0;
+        let mut after = (from, i32::MIN);
+        loop {
+            let messages: Vec<ChatMessage> = client
+                .query(
+                    &RetentionRepoStatement::GetInRange.as_string(),
+                    &[&from, &to, &after.0, &after.1, &self.config.batch_size],
+                )
+                .await?
+                .into_iter()
+                .map(ChatMessage::from)
+                .collect();
+
+            let Some(last) = messages.last() else {
+                break;
+            };
+            after = (last.timestamp, last.id.unwrap());
+            archived += messages.len() as u64;
+
+            let mut by_channel: BTreeMap<String, Vec<ChatMessage>> = BTreeMap::new();
+            for message in messages {
+                by_channel
+                    .entry(message.channel.clone())
+                    .or_default()
+                    .push(message);
+            }
+            for (channel, messages) in by_channel {
+                archive::append(dir, &channel, &messages).await?;
+            }
+        }
+
+        Ok(archived)
+    }
+
+    /// Removes the monthly partitions that hold only expired messages,
+    /// archiving them first if an archive directory is configured. Returns
+    /// the number of messages removed.
+    async fn remove_expired_partitions(&self, now: OffsetDateTime) -> Result<i64, Error> {
+        let Some(cutoff) = self.config.partition_cutoff(now) else {
+            return Ok(0);
+        };
+
+        let mut removed = 0;
+        for partition in self.partitions.list_partitions().await? {
+            if partition.ends_at() > cutoff {
+                continue;
+            }
+
+            let archived = match &self.config.archive_dir {
+                Some(dir) => self.archive_partition(dir, &partition).await?,
+                None => 0,
+            };
+
+            let count = self
+                .partitions
+                .remove_partition(&partition.name, self.config.detach_partitions)
+                .await?;
+            removed += count;
+
+            info!(
+                "Retention removed partition {} with {} messages ({} archived)",
+                partition.name, count, archived
+            );
+        }
+
+        Ok(removed)
+    }
+
+    /// Enforces the configured policies on every channel with messages,
+    /// removing whole partitions where possible before deleting rows.
+    pub async fn enforce_all(&self, now: OffsetDateTime) -> Result<Vec<RetentionRun>, Error> {
+        let client = self.client.as_ref().unwrap();
+
+        self.remove_expired_partitions(now).await?;
+
+        let channels: Vec<String> = client
+            .query(&RetentionRepoStatement::GetChannels.as_string(), &[])
+            .await?
+            .iter()
+            .map(|row| row.get("channel"))
+            .collect();
+
+        let mut runs = Vec::new();
+        for channel in channels {
+            if self.config.policy_for(&channel) != RetentionPolicy::Forever {
+                runs.push(self.enforce(&channel, now).await?);
+            }
+        }
+
+        Ok(runs)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use fake::{Fake, Faker};
+    use test_context::{test_context, AsyncTestContext};
+    use time::Duration;
+    use tokio::test;
+
+    use super::*;
+    use crate::dal::chat_message_repository::ChatMessageRepository;
+
+    struct RetentionRepoTestContext {
+        messages: ChatMessageRepository,
+        repo: RetentionRepository,
+    }
+
+    #[async_trait::async_trait]
+    impl AsyncTestContext for RetentionRepoTestContext {
+        async fn setup() -> RetentionRepoTestContext {
+            let config = Config::load("config.json").await.unwrap();
+            let mut messages = ChatMessageRepository::new(&config).unwrap();
+            messages.connect().await.unwrap();
+            let mut repo = RetentionRepository::new(&config).unwrap();
+            repo.connect().await.unwrap();
+
+            RetentionRepoTestContext { messages, repo }
+        }
+
+        async fn teardown(self) {}
+    }
+
+    #[test]
+    async fn test_partition_cutoff() {
+        let now = OffsetDateTime::from_unix_timestamp(1672531200).unwrap();
+        let mut config = RetentionConfig {
+            default: RetentionPolicy::KeepDays(30),
+            ..Default::default()
+        };
+        config
+            .channels
+            .insert("big".to_string(), RetentionPolicy::KeepDays(90));
+        assert_eq!(config.partition_cutoff(now), Some(now - Duration::days(90)));
+
+        config
+            .channels
+            .insert("forever".to_string(), RetentionPolicy::Forever);
+        assert_eq!(config.partition_cutoff(now), None);
+    }
+
+    #[test_context(RetentionRepoTestContext)]
+    #[test]
+    async fn repo_enforce_retention(ctx: &mut RetentionRepoTestContext) -> Result<(), Error> {
+        let channel: String = Faker.fake();
+        let client = ctx.repo.client.as_ref().unwrap();
+        client
+            .execute(
+                "INSERT INTO users (name) VALUES ($1) ON CONFLICT DO NOTHING",
+                &[&channel],
+            )
+            .await?;
+
+        let now = OffsetDateTime::now_utc();
+        for days in [10, 5, 3, 1, 0] {
+            let timestamp = now - Duration::days(days);
+            let message = ChatMessage::new(
+                days.to_string(),
+                channel.clone(),
+                channel.clone(),
+                timestamp,
+            );
+            ctx.messages.add_message(&message).await?;
+        }
+
+        let preview = ctx
+            .repo
+            .preview(&channel, RetentionPolicy::KeepDays(4), now)
+            .await?;
+        assert_eq!(preview.expired_messages, 2);
+
+        let preview = ctx
+            .repo
+            .preview(&channel, RetentionPolicy::KeepMessages(1), now)
+            .await?;
+        assert_eq!(preview.expired_messages, 4);
+
+        let preview = ctx
+            .repo
+            .preview(&channel, RetentionPolicy::Forever, now)
+            .await?;
+        assert_eq!(preview.expired_messages, 0);
+
+        let archive_dir = std::env::temp_dir().join(format!("retention-{}", channel));
+        ctx.repo.config.archive_dir = Some(archive_dir.clone());
+        ctx.repo.config.batch_size = 1;
+        ctx.repo
+            .config
+            .channels
+            .insert(channel.clone(), RetentionPolicy::KeepMessages(2));
+
+        let run = ctx.repo.enforce(&channel, now).await?;
+        assert_eq!(run.deleted, 3);
+        assert_eq!(run.archived, 3);
+
+        let remaining = ctx.messages.get_messages_from_channel(&channel, 10).await?;
+        let texts: Vec<&str> = remaining.iter().map(|m| m.text.as_str()).collect();
+        assert_eq!(texts, ["0", "1"]);
+
+        let archived = archive::read_all(&archive_dir.join(&channel)).await?;
+        assert_eq!(archived.len(), 3);
+
+        let _ = tokio::fs::remove_dir_all(archive_dir).await;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,456 @@
+use std::collections::HashSet;
+use std::time::Duration;
+
+use enum_iterator::Sequence;
+use time::{OffsetDateTime, Time, UtcOffset};
+use tokio_postgres::types::{ToSql, Type};
+use tokio_postgres::Client;
+
+use crate::config::Config;
+use crate::dal;
+use crate::error::Error;
+use crate::error::Error::Configuration;
+use crate::models::channel_stats::{
+    BucketCount, ChannelStats, ChatterCount, PeakRate, StatsBucket, StatsSource,
+};
+use crate::utils::connection_string::ConnectionString;
+use crate::utils::repo_statement::ToRepoStatement;
+
+const DEFAULT_ROLLUP_INTERVAL_SECS: u64 = 300;
+const ERR_INVALID_ROLLUP_CHANNELS: &str = "stats.rollup_channels must be a list of channels";
+
+#[derive(Debug, Clone)]
+pub struct StatsConfig {
+    /// Channels large enough that hourly and daily stats are served from the
+    /// rollup table instead of scanning `chat_messages`.
+    pub rollup_channels: HashSet<String>,
+    pub rollup_interval: Duration,
+}
+
+impl Config {
+    pub(crate) fn stats(&self) -> Result<StatsConfig, Error> {
+        let json = self["stats"].clone();
+
+        let rollup_channels = match json.get("rollup_channels") {
+            Some(channels) => channels
+                .as_array()
+                .ok_or(Configuration(ERR_INVALID_ROLLUP_CHANNELS.to_string()))?
+                .iter()
+                .map(|c| c.as_str().map(|c| c.to_string()))
+                .collect::<Option<HashSet<String>>>()
+                .ok_or(Configuration(ERR_INVALID_ROLLUP_CHANNELS.to_string()))?,
+            None => HashSet::new(),
+        };
+
+        let rollup_interval = json
+            .get("rollup_interval_secs")
+            .and_then(|x| x.as_u64())
+            .unwrap_or(DEFAULT_ROLLUP_INTERVAL_SECS);
+
+        Ok(StatsConfig {
+            rollup_channels,
+            rollup_interval: Duration::from_secs(rollup_interval),
+        })
+    }
+}
+
+/// Per chatter counts of a channel over `[$2, $5)`: the whole hours
+/// `[$3, $4)` come from the rollups and the partial hours at either end
+/// from the raw messages.
+const ROLLUP_COUNTS: &str = "WITH counts AS (SELECT bucket_start AS at, username, message_count AS count FROM chat_message_rollups WHERE channel = $1 AND bucket_start >= $3 AND bucket_start < $4 UNION ALL SELECT timestamp, username, 1 FROM chat_messages WHERE channel = $1 AND ((timestamp >= $2 AND timestamp < $3) OR (timestamp >= $4 AND timestamp < $5)))";
+const ROLLUP_TYPES: [Type; 5] = [
+    Type::TEXT,
+    Type::TIMESTAMPTZ,
+    Type::TIMESTAMPTZ,
+    Type::TIMESTAMPTZ,
+    Type::TIMESTAMPTZ,
+];
+
+#[derive(Debug, PartialEq, Sequence)]
+enum StatsRepoStatement {
+    RawBuckets,
+    RawSummary,
+    RawTopChatters,
+    RollupBuckets,
+    RollupSummary,
+    RollupTopChatters,
+    RefreshRollups,
+}
+
+impl ToRepoStatement for StatsRepoStatement {
+    fn as_string(&self) -> String {
+        match self {
+            StatsRepoStatement::RawBuckets => "SELECT date_trunc($1, timestamp AT TIME ZONE 'UTC') AT TIME ZONE 'UTC' AS bucket, COUNT(*) AS count FROM chat_messages WHERE channel = $2 AND timestamp >= $3 AND timestamp < $4 GROUP BY bucket ORDER BY bucket".to_string(),
+            StatsRepoStatement::RawSummary => "SELECT COUNT(*) AS total, COUNT(DISTINCT username) AS unique_chatters FROM chat_messages WHERE channel = $1 AND timestamp >= $2 AND timestamp < $3".to_string(),
+            StatsRepoStatement::RawTopChatters => "SELECT username, COUNT(*) AS count FROM chat_messages WHERE channel = $1 AND timestamp >= $2 AND timestamp < $3 GROUP BY username ORDER BY count DESC, username LIMIT $4".to_string(),
+            StatsRepoStatement::RollupBuckets => format!("{} SELECT date_trunc($6, at AT TIME ZONE 'UTC') AT TIME ZONE 'UTC' AS bucket, SUM(count)::BIGINT AS count FROM counts GROUP BY bucket ORDER BY bucket", ROLLUP_COUNTS),
+            StatsRepoStatement::RollupSummary => format!("{} SELECT COALESCE(SUM(count), 0)::BIGINT AS total, COUNT(DISTINCT username) AS unique_chatters FROM counts", ROLLUP_COUNTS),
+            StatsRepoStatement::RollupTopChatters => format!("{} SELECT username, SUM(count)::BIGINT AS count FROM counts GROUP BY username ORDER BY count DESC, username LIMIT $6", ROLLUP_COUNTS),
+            // Recomputes the hours from the earliest one changed since the
+            // last refresh, see 011_rollup_refresh.sql. Rollups of hours
+            // left without messages are removed.
+            StatsRepoStatement::RefreshRollups => "WITH stale AS (UPDATE channel_sequences s SET rollups_stale_from = NULL FROM (SELECT channel, rollups_stale_from FROM channel_sequences WHERE channel = $1 FOR UPDATE) old WHERE s.channel = old.channel AND old.rollups_stale_from IS NOT NULL RETURNING chat_messages_hour(old.rollups_stale_from) AS since), counts AS (SELECT channel, chat_messages_hour(timestamp) AS bucket_start, username, COUNT(*) AS count FROM chat_messages WHERE channel = $1 AND timestamp >= (SELECT since FROM stale) GROUP BY 1, 2, 3), emptied AS (DELETE FROM chat_message_rollups WHERE channel = $1 AND bucket_start >= (SELECT since FROM stale) AND (bucket_start, username) NOT IN (SELECT bucket_start, username FROM counts)) INSERT INTO chat_message_rollups (channel, bucket_start, username, message_count) SELECT * FROM counts ON CONFLICT (channel, bucket_start, username) DO UPDATE SET message_count = EXCLUDED.message_count".to_string(),
+        }
+    }
+
+    fn get_types(&self) -> Vec<Type> {
+        match self {
+            StatsRepoStatement::RawBuckets => {
+                vec![Type::TEXT, Type::TEXT, Type::TIMESTAMPTZ, Type::TIMESTAMPTZ]
+            }
+            StatsRepoStatement::RawSummary => {
+                vec![Type::TEXT, Type::TIMESTAMPTZ, Type::TIMESTAMPTZ]
+            }
+            StatsRepoStatement::RawTopChatters => {
+                vec![Type::TEXT, Type::TIMESTAMPTZ, Type::TIMESTAMPTZ, Type::INT8]
+            }
+            StatsRepoStatement::RollupBuckets => [ROLLUP_TYPES.as_slice(), &[Type::TEXT]].concat(),
+            StatsRepoStatement::RollupSummary => ROLLUP_TYPES.to_vec(),
+            StatsRepoStatement::RollupTopChatters => {
+                [ROLLUP_TYPES.as_slice(), &[Type::INT8]].concat()
+            }
+            StatsRepoStatement::RefreshRollups => vec![Type::TEXT],
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct StatsRepository {
+    connection_string: ConnectionString,
+    config: StatsConfig,
+    pub client: Option<Client>,
+}
+
+impl StatsRepository {
+    pub fn new(config: &Config) -> Result<Self, Error> {
+        let connection_string = config.db()?;
+        let config = config.stats()?;
+
+        Ok(Self {
+            connection_string,
+            config,
+            client: None,
+        })
+    }
+
+    pub async fn connect(&mut self) -> Result<(), Error> {
+        let client = dal::connect(&self.connection_string).await?;
+        self.client = Some(client);
+
+        Ok(())
+    }
+
+    pub fn config(&self) -> &StatsConfig {
+        &self.config
+    }
+
+    /// Rollups are hourly, so minute buckets, and ranges without a whole
+    /// hour in them, always come from the raw table.
+    fn source_for(&self, channel: &str, bucket: StatsBucket, hours: Option<Hours>) -> StatsSource {
+        if bucket != StatsBucket::Minute
+            && hours.is_some()
+            && self.config.rollup_channels.contains(channel)
+        {
+            StatsSource::Rollup
+        } else {
+            StatsSource::Raw
+        }
+    }
+
+    pub async fn get_channel_stats(
+        &self,
+        channel: &str,
+        from: OffsetDateTime,
+        to: OffsetDateTime,
+        bucket: StatsBucket,
+        top: i64,
+    ) -> Result<ChannelStats, Error> {
+        let client = self.client.as_ref().unwrap();
+        let hours = whole_hours(from, to);
+        let source = self.source_for(channel, bucket, hours);
+
+        let (buckets, summary, top_chatters) = match (source, hours) {
+            (StatsSource::Raw, _) | (StatsSource::Rollup, None) => {
+                let buckets = client
+                    .query(
+                        &StatsRepoStatement::RawBuckets.as_string(),
+                        &[&bucket.as_str(), &channel, &from, &to],
+                    )
+                    .await?;
+                let summary = client
+                    .query_one(
+                        &StatsRepoStatement::RawSummary.as_string(),
+                        &[&channel, &from, &to],
+                    )
+                    .await?;
+                let top_chatters = client
+                    .query(
+                        &StatsRepoStatement::RawTopChatters.as_string(),
+                        &[&channel, &from, &to, &top],
+                    )
+                    .await?;
+                (buckets, summary, top_chatters)
+            }
+            (StatsSource::Rollup, Some((first, last))) => {
+                let range: [&(dyn ToSql + Sync); 5] = [&channel, &from, &first, &last, &to];
+                let buckets = client
+                    .query(
+                        &StatsRepoStatement::RollupBuckets.as_string(),
+                        &[range.as_slice(), &[&bucket.as_str()]].concat(),
+                    )
+                    .await?;
+                let summary = client
+                    .query_one(&StatsRepoStatement::RollupSummary.as_string(), &range)
+                    .await?;
+                let top_chatters = client
+                    .query(
+                        &StatsRepoStatement::RollupTopChatters.as_string(),
+                        &[range.as_slice(), &[&top]].concat(),
+                    )
+                    .await?;
+                (buckets, summary, top_chatters)
+            }
+        };
+
+        let buckets: Vec<BucketCount> = buckets.iter().map(BucketCount::from).collect();
+        let top_chatters = top_chatters.iter().map(ChatterCount::from).collect();
+
+        Ok(ChannelStats {
+            channel: channel.to_string(),
+            from,
+            to,
+            bucket,
+            source,
+            total_messages: summary.get("total"),
+            unique_chatters: summary.get("unique_chatters"),
+            peak: PeakRate::from_buckets(&buckets, bucket),
+            top_chatters,
+            buckets,
+        })
+    }
+
+    /// Brings the rollups of every configured channel up to date.
+    pub async fn refresh_rollups(&self) -> Result<(), Error> {
+        let client = self.client.as_ref().unwrap();
+
+        for channel in self.config.rollup_channels.iter() {
+            client
+                .execute(&StatsRepoStatement::RefreshRollups.as_string(), &[channel])
+                .await?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The start of the first and the end of the last whole hour in
+/// `[from, to)`, if there is one.
+type Hours = (OffsetDateTime, OffsetDateTime);
+
+fn whole_hours(from: OffsetDateTime, to: OffsetDateTime) -> Option<Hours> {
+    let floor = |at: OffsetDateTime| {
+        let at = at.to_offset(UtcOffset::UTC);
+        at.replace_time(Time::from_hms(at.hour(), 0, 0).unwrap())
+    };
+
+    let first = match floor(from) {
+        hour if hour == from => hour,
+        hour => hour + time::Duration::HOUR,
+    };
+    let last = floor(to);
+
+    (first < last).then_some((first, last))
+}
+
+#[cfg(test)]
+mod test {
+    use fake::{Fake, Faker};
+    use test_context::{test_context, AsyncTestContext};
+    use time::Duration;
+    use tokio::test;
+
+    use super::*;
+    use crate::dal::chat_message_repository::ChatMessageRepository;
+    use crate::models::chat_message::ChatMessage;
+
+    struct StatsRepoTestContext {
+        messages: ChatMessageRepository,
+        repo: StatsRepository,
+    }
+
+    #[async_trait::async_trait]
+    impl AsyncTestContext for StatsRepoTestContext {
+        async fn setup() -> StatsRepoTestContext {
+            let config = Config::load("config.json").await.unwrap();
+            let mut messages = ChatMessageRepository::new(&config).unwrap();
+            messages.connect().await.unwrap();
+            let mut repo = StatsRepository::new(&config).unwrap();
+            repo.connect().await.unwrap();
+
+            StatsRepoTestContext { messages, repo }
+        }
+
+        async fn teardown(self) {}
+    }
+
+    #[test_context(StatsRepoTestContext)]
+    #[test]
+    async fn repo_get_channel_stats(ctx: &StatsRepoTestContext) -> Result<(), Error> {
+        let channel: String = Faker.fake();
+        let chatters: Vec<String> = vec![Faker.fake(), Faker.fake()];
+        let start = OffsetDateTime::from_unix_timestamp(1672531200).unwrap();
+
+        let client = ctx.repo.client.as_ref().unwrap();
+        for name in chatters.iter().chain([&channel]) {
+            client
+                .execute(
+                    "INSERT INTO users (name) VALUES ($1) ON CONFLICT DO NOTHING",
+                    &[name],
+                )
+                .await?;
+        }
+
+        for (minutes, chatter) in [(0, 0), (1, 0), (2, 1), (61, 0)] {
+            let username = chatters[chatter].clone();
+            let timestamp = start + Duration::minutes(minutes);
+            let message = ChatMessage::new(Faker.fake(), username, channel.clone(), timestamp);
+            ctx.messages.add_message(&message).await?;
+        }
+
+        let stats = ctx
+            .repo
+            .get_channel_stats(&channel, start, start + Duration::DAY, StatsBucket::Hour, 1)
+            .await?;
+
+        assert_eq!(stats.source, StatsSource::Raw);
+        assert_eq!(stats.total_messages, 4);
+        assert_eq!(stats.unique_chatters, 2);
+        assert_eq!(stats.top_chatters.len(), 1);
+        assert_eq!(stats.top_chatters[0].username, chatters[0]);
+        assert_eq!(stats.buckets.len(), 2);
+        assert_eq!(stats.peak.unwrap().count, 3);
+
+        Ok(())
+    }
+
+    #[test_context(StatsRepoTestContext)]
+    #[test]
+    async fn repo_rollup_stats_include_partial_hours(
+        ctx: &StatsRepoTestContext,
+    ) -> Result<(), Error> {
+        let channel: String = Faker.fake();
+        let chatter: String = Faker.fake();
+        let start = OffsetDateTime::from_unix_timestamp(1672531200).unwrap();
+
+        let client = ctx.repo.client.as_ref().unwrap();
+        for name in [&chatter, &channel] {
+            client
+                .execute(
+                    "INSERT INTO users (name) VALUES ($1) ON CONFLICT DO NOTHING",
+                    &[name],
+                )
+                .await?;
+        }
+
+        for minutes in [5, 30, 61, 125, 170] {
+            let timestamp = start + Duration::minutes(minutes);
+            let message =
+                ChatMessage::new(Faker.fake(), chatter.clone(), channel.clone(), timestamp);
+            ctx.messages.add_message(&message).await?;
+        }
+
+        let mut repo = StatsRepository::new(&Config::load("config.json").await?)?;
+        repo.config.rollup_channels = HashSet::from([channel.clone()]);
+        repo.connect().await?;
+        repo.refresh_rollups().await?;
+
+        // Only the hour from 01:00 is whole, the rest is read from messages.
+        let (from, to) = (
+            start + Duration::minutes(15),
+            start + Duration::minutes(150),
+        );
+        let stats = repo
+            .get_channel_stats(&channel, from, to, StatsBucket::Hour, 10)
+            .await?;
+
+        assert_eq!(stats.source, StatsSource::Rollup);
+        assert_eq!(stats.total_messages, 3);
+        assert_eq!(stats.unique_chatters, 1);
+        assert_eq!(stats.top_chatters[0].count, 3);
+        assert_eq!(stats.buckets.len(), 3);
+
+        Ok(())
+    }
+
+    #[test_context(StatsRepoTestContext)]
+    #[test]
+    async fn repo_rollups_follow_late_and_removed_messages(
+        ctx: &StatsRepoTestContext,
+    ) -> Result<(), Error> {
+        let channel: String = Faker.fake();
+        let chatter: String = Faker.fake();
+        let start = OffsetDateTime::from_unix_timestamp(1672531200).unwrap();
+
+        let client = ctx.repo.client.as_ref().unwrap();
+        for name in [&chatter, &channel] {
+            client
+                .execute(
+                    "INSERT INTO users (name) VALUES ($1) ON CONFLICT DO NOTHING",
+                    &[name],
+                )
+                .await?;
+        }
+
+        let mut repo = StatsRepository::new(&Config::load("config.json").await?)?;
+        repo.config.rollup_channels = HashSet::from([channel.clone()]);
+        repo.connect().await?;
+        // Hours are UTC hours whatever the session's time zone.
+        repo.client
+            .as_ref()
+            .unwrap()
+            .batch_execute("SET TIME ZONE 'Asia/Kolkata'")
+            .await?;
+
+        let (from, to) = (start, start + Duration::hours(3));
+        let total = |stats: ChannelStats| stats.total_messages;
+        let add = |minutes| {
+            let timestamp = start + Duration::minutes(minutes);
+            ChatMessage::new(Faker.fake(), chatter.clone(), channel.clone(), timestamp)
+        };
+
+        ctx.messages.add_message(&add(125)).await?;
+        repo.refresh_rollups().await?;
+
+        // A late message, older than every rolled up hour.
+        let late = ctx.messages.add_message(&add(5)).await?;
+        repo.refresh_rollups().await?;
+        let stats = repo
+            .get_channel_stats(&channel, from, to, StatsBucket::Hour, 10)
+            .await?;
+        assert_eq!(stats.source, StatsSource::Rollup);
+        let starts: Vec<_> = stats.buckets.iter().map(|b| b.start).collect();
+        assert_eq!(starts, [start, start + Duration::hours(2)]);
+        assert_eq!(total(stats), 2);
+
+        client
+            .execute("DELETE FROM chat_messages WHERE id = $1", &[&late.id])
+            .await?;
+        let stats = repo
+            .get_channel_stats(&channel, from, to, StatsBucket::Hour, 10)
+            .await?;
+        assert_eq!(total(stats), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    async fn test_whole_hours() {
+        let start = OffsetDateTime::from_unix_timestamp(1672531200).unwrap();
+        let at = |minutes| start + Duration::minutes(minutes);
+
+        assert_eq!(whole_hours(at(0), at(120)), Some((at(0), at(120))));
+        assert_eq!(whole_hours(at(15), at(150)), Some((at(60), at(120))));
+        assert_eq!(whole_hours(at(15), at(110)), None);
+        assert_eq!(whole_hours(at(0), at(59)), None);
+    }
+}
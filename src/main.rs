@@ -4,10 +4,12 @@
 extern crate log;
 extern crate pretty_env_logger;
 
+use crate::cli::Command;
 use crate::config::Config;
 use crate::error::Error;
 
 mod api;
+mod cli;
 mod config;
 mod dal;
 mod error;
@@ -29,10 +31,16 @@ fn handle_error(err: Error) {
 }
 
 async fn run() -> Result<(), Error> {
+    let command = cli::parse(std::env::args().skip(1))?;
+
     let config = Config::load("config.json").await?;
     logger::setup_logger(&config).expect("Could not initialize logger");
 
-    server::start(&config).await?;
+    match command {
+        Command::Serve => server::start(&config).await?,
+        Command::Export(args) => cli::export(&config, args).await?,
+        Command::Import(args) => cli::import(&config, args).await?,
+    }
 
     Ok(())
 }
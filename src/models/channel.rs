@@ -0,0 +1,81 @@
+use serde::{Deserialize, Deserializer, Serialize};
+use time::OffsetDateTime;
+use tokio_postgres::Row;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Channel {
+    pub name: String,
+    pub display_name: String,
+    pub description: Option<String>,
+    pub archived: bool,
+    /// Only members can read or post in a private channel.
+    pub private: bool,
+    pub created_at: OffsetDateTime,
+}
+
+impl From<&Row> for Channel {
+    fn from(row: &Row) -> Self {
+        Self {
+            name: row.get("name"),
+            display_name: row.get("display_name"),
+            description: row.get("description"),
+            archived: row.get("archived"),
+            private: row.get("private"),
+            created_at: row.get("created_at"),
+        }
+    }
+}
+
+/// The fields a client provides when registering a channel.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct NewChannel {
+    pub name: String,
+    pub display_name: Option<String>,
+    pub description: Option<String>,
+    #[serde(default)]
+    pub private: bool,
+}
+
+/// A partial update; fields left as `None` keep their current value.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct ChannelUpdate {
+    pub display_name: Option<String>,
+    /// An explicit `null` clears the description.
+    #[serde(
+        default,
+        deserialize_with = "present",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub description: Option<Option<String>>,
+    pub archived: Option<bool>,
+    pub private: Option<bool>,
+}
+
+/// Tells a field given as `null` apart from one left out, which `default`
+/// turns into `None`.
+fn present<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+/// A channel along with how active it has been.
+#[derive(Debug, Serialize, PartialEq)]
+pub struct ChannelSummary {
+    #[serde(flatten)]
+    pub channel: Channel,
+    pub message_count: i64,
+    pub last_activity: Option<OffsetDateTime>,
+}
+
+impl From<&Row> for ChannelSummary {
+    fn from(row: &Row) -> Self {
+        Self {
+            channel: Channel::from(row),
+            message_count: row.get("message_count"),
+            last_activity: row.get("last_activity"),
+        }
+    }
+}
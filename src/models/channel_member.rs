@@ -0,0 +1,161 @@
+use std::fmt::Display;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use tokio_postgres::Row;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ChannelRole {
+    /// Manages the channel and its members.
+    Owner,
+    /// Invites and removes members, but not owners or other moderators.
+    Moderator,
+    Member,
+}
+
+impl ChannelRole {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChannelRole::Owner => "owner",
+            ChannelRole::Moderator => "moderator",
+            ChannelRole::Member => "member",
+        }
+    }
+}
+
+impl Display for ChannelRole {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for ChannelRole {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "owner" => Ok(ChannelRole::Owner),
+            "moderator" => Ok(ChannelRole::Moderator),
+            "member" => Ok(ChannelRole::Member),
+            _ => Err(format!("Unknown channel role: {}", s)),
+        }
+    }
+}
+
+/// Reads a role column, which the schema limits to known roles.
+fn role_from_row(row: &Row) -> Option<ChannelRole> {
+    row.get::<_, Option<&str>>("role")
+        .map(|role| role.parse().unwrap())
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ChannelMember {
+    pub channel: String,
+    pub username: String,
+    pub role: ChannelRole,
+    pub added_at: OffsetDateTime,
+}
+
+impl From<&Row> for ChannelMember {
+    fn from(row: &Row) -> Self {
+        Self {
+            channel: row.get("channel"),
+            username: row.get("username"),
+            role: role_from_row(row).unwrap(),
+            added_at: row.get("added_at"),
+        }
+    }
+}
+
+/// What one user, or an anonymous client, may do in a channel.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ChannelAccess {
+    pub private: bool,
+    pub role: Option<ChannelRole>,
+}
+
+impl ChannelAccess {
+    /// Public channels are open to everyone, private ones to their members.
+    pub fn can_read(&self) -> bool {
+        !self.private || self.role.is_some()
+    }
+
+    pub fn is_owner(&self) -> bool {
+        self.role == Some(ChannelRole::Owner)
+    }
+
+    /// Whether they may invite and remove members with the given role.
+    pub fn can_manage(&self, role: ChannelRole) -> bool {
+        match self.role {
+            Some(ChannelRole::Owner) => true,
+            Some(ChannelRole::Moderator) => role == ChannelRole::Member,
+            _ => false,
+        }
+    }
+}
+
+impl From<&Row> for ChannelAccess {
+    fn from(row: &Row) -> Self {
+        Self {
+            private: row.get("private"),
+            role: role_from_row(row),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_channel_access() {
+        assert!(ChannelAccess::default().can_read());
+
+        let outsider = ChannelAccess {
+            private: true,
+            role: None,
+        };
+        assert!(!outsider.can_read());
+
+        let member = ChannelAccess {
+            private: true,
+            role: Some(ChannelRole::Member),
+        };
+        assert!(member.can_read());
+        assert!(!member.is_owner());
+        assert!(!member.can_manage(ChannelRole::Member));
+
+        let moderator = ChannelAccess {
+            private: true,
+            role: Some(ChannelRole::Moderator),
+        };
+        assert!(moderator.can_manage(ChannelRole::Member));
+        assert!(!moderator.can_manage(ChannelRole::Moderator));
+        assert!(!moderator.can_manage(ChannelRole::Owner));
+
+        let owner = ChannelAccess {
+            private: true,
+            role: Some(ChannelRole::Owner),
+        };
+        assert!(owner.can_manage(ChannelRole::Owner));
+    }
+
+    #[test]
+    fn test_role_round_trip() {
+        for role in [
+            ChannelRole::Owner,
+            ChannelRole::Moderator,
+            ChannelRole::Member,
+        ] {
+            assert_eq!(role.as_str().parse::<ChannelRole>(), Ok(role));
+            assert_eq!(
+                serde_json::to_string(&role).unwrap(),
+                format!("\"{}\"", role)
+            );
+        }
+
+        assert!("admin".parse::<ChannelRole>().is_err());
+    }
+}
@@ -0,0 +1,150 @@
+use serde::{Deserialize, Serialize};
+use time::{Duration, OffsetDateTime};
+use tokio_postgres::Row;
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum StatsBucket {
+    Minute,
+    #[default]
+    Hour,
+    Day,
+}
+
+impl StatsBucket {
+    /// The field name understood by Postgres' `date_trunc`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StatsBucket::Minute => "minute",
+            StatsBucket::Hour => "hour",
+            StatsBucket::Day => "day",
+        }
+    }
+
+    pub fn duration(&self) -> Duration {
+        match self {
+            StatsBucket::Minute => Duration::MINUTE,
+            StatsBucket::Hour => Duration::HOUR,
+            StatsBucket::Day => Duration::DAY,
+        }
+    }
+
+    /// The number of buckets needed to cover the given range.
+    pub fn count_in(&self, from: OffsetDateTime, to: OffsetDateTime) -> i64 {
+        let span = (to - from).whole_seconds();
+        let size = self.duration().whole_seconds();
+        (span + size - 1) / size
+    }
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct BucketCount {
+    pub start: OffsetDateTime,
+    pub count: i64,
+}
+
+impl From<&Row> for BucketCount {
+    fn from(row: &Row) -> Self {
+        Self {
+            start: row.get("bucket"),
+            count: row.get("count"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct ChatterCount {
+    pub username: String,
+    pub count: i64,
+}
+
+impl From<&Row> for ChatterCount {
+    fn from(row: &Row) -> Self {
+        Self {
+            username: row.get("username"),
+            count: row.get("count"),
+        }
+    }
+}
+
+/// The busiest bucket of a range, with its rate in messages per minute.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct PeakRate {
+    pub start: OffsetDateTime,
+    pub count: i64,
+    pub per_minute: f64,
+}
+
+impl PeakRate {
+    pub fn from_buckets(buckets: &[BucketCount], bucket: StatsBucket) -> Option<Self> {
+        let minutes = bucket.duration().whole_minutes() as f64;
+
+        buckets.iter().max_by_key(|b| b.count).map(|peak| PeakRate {
+            start: peak.start,
+            count: peak.count,
+            per_minute: peak.count as f64 / minutes,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum StatsSource {
+    Raw,
+    Rollup,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct ChannelStats {
+    pub channel: String,
+    pub from: OffsetDateTime,
+    pub to: OffsetDateTime,
+    pub bucket: StatsBucket,
+    pub source: StatsSource,
+    pub total_messages: i64,
+    pub unique_chatters: i64,
+    pub peak: Option<PeakRate>,
+    pub top_chatters: Vec<ChatterCount>,
+    pub buckets: Vec<BucketCount>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const START: i64 = 1672531200;
+
+    #[test]
+    fn test_bucket_count_in() {
+        let from = OffsetDateTime::from_unix_timestamp(START).unwrap();
+
+        assert_eq!(StatsBucket::Hour.count_in(from, from + Duration::HOUR), 1);
+        assert_eq!(
+            StatsBucket::Hour.count_in(from, from + Duration::minutes(61)),
+            2
+        );
+        assert_eq!(
+            StatsBucket::Minute.count_in(from, from + Duration::DAY),
+            1440
+        );
+        assert_eq!(StatsBucket::Day.count_in(from, from), 0);
+    }
+
+    #[test]
+    fn test_peak_rate() {
+        let start = OffsetDateTime::from_unix_timestamp(START).unwrap();
+        let buckets = vec![
+            BucketCount { start, count: 30 },
+            BucketCount {
+                start: start + Duration::HOUR,
+                count: 120,
+            },
+        ];
+
+        let peak = PeakRate::from_buckets(&buckets, StatsBucket::Hour).unwrap();
+        assert_eq!(peak.start, start + Duration::HOUR);
+        assert_eq!(peak.per_minute, 2.0);
+
+        assert_eq!(PeakRate::from_buckets(&[], StatsBucket::Hour), None);
+    }
+}
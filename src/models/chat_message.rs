@@ -5,23 +5,82 @@ use fake::{Dummy, Fake, Faker};
 use rand::Rng;
 use serde::{Deserialize, Serialize};
 use time::OffsetDateTime;
+use tokio_postgres::types::Json;
 use tokio_postgres::Row;
 
-#[derive(Debug, Serialize, Deserialize, PartialEq)]
+use crate::models::fragment::Fragment;
+use crate::utils::fragments::{self, DEFAULT_PARSERS};
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct ChatMessage {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<i32>,
+    /// The message's position in its channel, counting up from 1 without
+    /// gaps as messages are stored. Imported messages are all numbered 0,
+    /// before everything posted live.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seq: Option<i64>,
     pub text: String,
     pub username: String,
     pub channel: String,
     pub timestamp: OffsetDateTime,
+    /// The id the message had where it came from, such as Twitch's `id`
+    /// tag. Only one message per channel can have a given source id.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_id: Option<String>,
+    /// Taken from the `Idempotency-Key` of the request that posted the
+    /// message, so that retries return the message stored the first time.
+    /// Scoped to the user who posted it, see [`Self::set_idempotency_key`].
+    #[serde(skip)]
+    pub idempotency_key: Option<String>,
+    /// The text split into typed fragments. Parsed on ingest unless given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fragments: Option<Vec<Fragment>>,
+    /// Twitch's `emotes` tag, as received over IRC, which becomes the
+    /// message's emote fragments on ingest. It is not stored itself.
+    #[serde(default, skip_serializing)]
+    pub emotes: Option<String>,
 }
 
 impl ChatMessage {
     pub fn new(text: String, username: String, channel: String, timestamp: OffsetDateTime) -> Self {
         ChatMessage {
+            id: None,
+            seq: None,
             text,
             username,
             channel,
             timestamp,
+            source_id: None,
+            idempotency_key: None,
+            fragments: None,
+            emotes: None,
+        }
+    }
+
+    /// Sets the `Idempotency-Key` the message was posted with, which only
+    /// matches retries by the same `user`. The user is prefixed with its
+    /// length, so that no other user's key can spell the same.
+    pub fn set_idempotency_key(&mut self, user: &str, key: &str) {
+        self.idempotency_key = Some(format!("{}:{}:{}", user.len(), user, key));
+    }
+
+    /// What makes a message a duplicate of one already stored in its
+    /// channel, if anything. A source id takes precedence over an
+    /// idempotency key.
+    pub fn dedupe_key(&self) -> Option<String> {
+        match (&self.source_id, &self.idempotency_key) {
+            (Some(source_id), _) => Some(format!("source:{}", source_id)),
+            (None, Some(key)) => Some(format!("idempotency:{}", key)),
+            (None, None) => None,
+        }
+    }
+
+    /// The message's fragments, parsing its text if it has none yet.
+    pub fn fragments(&self) -> Vec<Fragment> {
+        match &self.fragments {
+            Some(fragments) => fragments.clone(),
+            None => fragments::parse(self, &DEFAULT_PARSERS),
         }
     }
 }
@@ -34,12 +93,27 @@ impl Display for ChatMessage {
 
 impl From<Row> for ChatMessage {
     fn from(row: Row) -> Self {
-        Self {
+        let mut message = Self {
+            id: row.get("id"),
+            seq: row.get("seq"),
             text: row.get("text"),
             channel: row.get("channel"),
             username: row.get("username"),
             timestamp: row.get("timestamp"),
-        }
+            source_id: row.get("source_id"),
+            idempotency_key: None,
+            fragments: None,
+            emotes: None,
+        };
+
+        // Messages stored before fragments existed are parsed when read.
+        let fragments: Option<Json<Vec<Fragment>>> = row.get("fragments");
+        message.fragments = Some(match fragments {
+            Some(Json(fragments)) => fragments,
+            None => message.fragments(),
+        });
+
+        message
     }
 }
 
@@ -48,10 +122,16 @@ impl Dummy<Faker> for ChatMessage {
         let fake_timestamp = Faker.fake_with_rng::<u32, R>(rng).into();
 
         Self {
+            id: None,
+            seq: None,
             text: Faker.fake_with_rng(rng),
             username: Faker.fake_with_rng(rng),
             channel: Faker.fake_with_rng(rng),
             timestamp: OffsetDateTime::from_unix_timestamp(fake_timestamp).unwrap(),
+            source_id: None,
+            idempotency_key: None,
+            fragments: None,
+            emotes: None,
         }
     }
 }
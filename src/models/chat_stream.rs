@@ -1,58 +1,52 @@
 use std::pin::Pin;
+use std::sync::{Arc, Mutex};
 use std::task::{Context, Poll};
 
 use futures::stream::Stream;
 use futures::StreamExt;
-use log::debug;
 use time::OffsetDateTime;
 
 use crate::models::chat_message::ChatMessage;
+use crate::server::pipeline::Pipeline;
+use crate::utils::clock::Clock;
 
-pub(crate) struct ChatStream<'a> {
+pub struct ChatStream<'a> {
     pub(crate) created_at: OffsetDateTime,
-    pub(crate) updated_at: OffsetDateTime,
-    stream: Pin<Box<dyn Stream<Item = ChatMessage> + 'a>>,
+    /// When the source last yielded a message, or the stream was created.
+    /// Shared with the source end of the stream, so that messages dropped
+    /// by a pipeline still count as activity.
+    updated_at: Arc<Mutex<OffsetDateTime>>,
+    stream: Pin<Box<dyn Stream<Item = ChatMessage> + Send + 'a>>,
 }
 
 impl<'a> ChatStream<'a> {
-    pub(crate) fn new<T>(stream: T) -> Self
+    pub(crate) fn new<T>(stream: T, clock: Arc<dyn Clock>) -> Self
     where
-        T: Stream<Item = ChatMessage> + Send + Sync + 'a,
+        T: Stream<Item = ChatMessage> + Send + 'a,
     {
-        let stream = Box::pin(stream);
-        let now = OffsetDateTime::now_local().unwrap();
+        let now = clock.now();
+        let updated_at = Arc::new(Mutex::new(now));
 
-        Self {
-            stream,
-            created_at: now,
-            updated_at: now,
-        }
-    }
+        let activity = updated_at.clone();
+        let stream = stream.inspect(move |_| *activity.lock().unwrap() = clock.now());
 
-    pub(crate) fn with_adapter<T, F>(stream: T, adapter: F) -> Self
-    where
-        T: Stream<Item = ChatMessage> + Send + Sync + 'a,
-        F: FnMut(ChatMessage) -> ChatMessage + Send + Sync + 'a,
-    {
-        let stream = Box::pin(stream.map(adapter));
-        let now = OffsetDateTime::now_local().unwrap();
         Self {
-            stream,
+            stream: Box::pin(stream),
             created_at: now,
-            updated_at: now,
+            updated_at,
         }
     }
 
-    pub(crate) async fn start(&self) {
-        debug!("Starting chat stream");
-    }
-
-    pub(crate) async fn stop(&self) {
-        debug!("Stopping chat stream");
+    pub(crate) fn updated_at(&self) -> OffsetDateTime {
+        *self.updated_at.lock().unwrap()
     }
 
-    pub(crate) async fn await_next(&mut self) -> Option<ChatMessage> {
-        self.stream.next().await
+    /// Runs the stream's messages through the pipeline's stages.
+    pub(crate) fn through(self, pipeline: Pipeline) -> Self {
+        Self {
+            stream: pipeline.apply(self.stream),
+            ..self
+        }
     }
 }
 
@@ -60,8 +54,48 @@ impl<'a> Stream for ChatStream<'a> {
     type Item = ChatMessage;
 
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        let val = self.stream.as_mut().poll_next(cx);
-        self.updated_at = OffsetDateTime::now_local().unwrap();
-        val
+        self.stream.as_mut().poll_next(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fake::{Fake, Faker};
+    use futures::{stream, FutureExt};
+
+    use super::*;
+    use crate::utils::clock::ManualClock;
+
+    #[tokio::test]
+    async fn test_updated_at_follows_clock() {
+        let clock = Arc::new(ManualClock::new(OffsetDateTime::UNIX_EPOCH));
+        let messages: Vec<ChatMessage> = vec![Faker.fake()];
+        let messages = stream::iter(messages).chain(stream::pending());
+        let mut chat_stream = ChatStream::new(messages, clock.clone());
+
+        clock.advance(time::Duration::minutes(1));
+        assert!(chat_stream.next().await.is_some());
+        assert_eq!(chat_stream.created_at, OffsetDateTime::UNIX_EPOCH);
+        let updated_at = OffsetDateTime::UNIX_EPOCH + time::Duration::minutes(1);
+        assert_eq!(chat_stream.updated_at(), updated_at);
+
+        // Waiting for a message is not progress.
+        clock.advance(time::Duration::minutes(1));
+        assert!(chat_stream.next().now_or_never().is_none());
+        assert_eq!(chat_stream.updated_at(), updated_at);
+    }
+
+    #[tokio::test]
+    async fn test_dropped_messages_are_activity() {
+        let clock = Arc::new(ManualClock::new(OffsetDateTime::UNIX_EPOCH));
+        let messages: Vec<ChatMessage> = vec![Faker.fake()];
+        let messages = stream::iter(messages).chain(stream::pending());
+        let mut chat_stream =
+            ChatStream::new(messages, clock.clone()).through(Pipeline::new().filter(|_| false));
+
+        clock.advance(time::Duration::minutes(1));
+        assert!(chat_stream.next().now_or_never().is_none());
+        let updated_at = OffsetDateTime::UNIX_EPOCH + time::Duration::minutes(1);
+        assert_eq!(chat_stream.updated_at(), updated_at);
     }
 }
@@ -0,0 +1,36 @@
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use tokio_postgres::Row;
+
+/// A message between two users, readable only by them.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct DirectMessage {
+    pub id: i32,
+    pub sender: String,
+    pub recipient: String,
+    pub timestamp: OffsetDateTime,
+    pub text: String,
+}
+
+impl DirectMessage {
+    /// The other participant, as seen by `username`.
+    pub fn other_participant(&self, username: &str) -> &str {
+        if self.sender == username {
+            &self.recipient
+        } else {
+            &self.sender
+        }
+    }
+}
+
+impl From<&Row> for DirectMessage {
+    fn from(row: &Row) -> Self {
+        Self {
+            id: row.get("id"),
+            sender: row.get("sender"),
+            recipient: row.get("recipient"),
+            timestamp: row.get("timestamp"),
+            text: row.get("text"),
+        }
+    }
+}
@@ -0,0 +1,50 @@
+use serde::{Deserialize, Serialize};
+
+/// What a part of a message's text stands for.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum FragmentKind {
+    Text,
+    Emote { id: String },
+    Mention { username: String },
+    Link { url: String },
+}
+
+/// A typed part of a message's text. Together, a message's fragments cover
+/// its text in order.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Fragment {
+    #[serde(flatten)]
+    pub kind: FragmentKind,
+    pub text: String,
+    /// Where the fragment starts in the message's text, in characters.
+    pub start: usize,
+    /// Where the fragment ends in the message's text, exclusive.
+    pub end: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_fragment_serialization() {
+        let fragment = Fragment {
+            kind: FragmentKind::Emote {
+                id: "25".to_string(),
+            },
+            text: "Kappa".to_string(),
+            start: 0,
+            end: 5,
+        };
+
+        let json = serde_json::to_value(&fragment).unwrap();
+        assert_eq!(
+            json,
+            json!({ "type": "emote", "id": "25", "text": "Kappa", "start": 0, "end": 5 })
+        );
+        assert_eq!(serde_json::from_value::<Fragment>(json).unwrap(), fragment);
+    }
+}
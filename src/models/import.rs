@@ -0,0 +1,45 @@
+use serde::Serialize;
+use time::OffsetDateTime;
+use tokio_postgres::Row;
+
+/// How far an import of a given source has gotten so far.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct ImportJob {
+    pub source: String,
+    /// The number of input lines whose messages have been committed.
+    pub lines_done: i64,
+    pub imported: i64,
+    /// When the last batch was committed.
+    pub updated_at: OffsetDateTime,
+    pub finished_at: Option<OffsetDateTime>,
+}
+
+impl From<&Row> for ImportJob {
+    fn from(row: &Row) -> Self {
+        Self {
+            source: row.get("source"),
+            lines_done: row.get("lines_done"),
+            imported: row.get("imported"),
+            updated_at: row.get("updated_at"),
+            finished_at: row.get("finished_at"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct LineError {
+    pub line: i64,
+    pub error: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, PartialEq)]
+pub struct ImportReport {
+    pub source: String,
+    /// Lines skipped because an earlier run already committed them.
+    pub resumed_from: i64,
+    pub lines_read: i64,
+    pub imported: i64,
+    pub error_count: i64,
+    /// The first errors encountered; `error_count` has the full tally.
+    pub errors: Vec<LineError>,
+}
@@ -0,0 +1,33 @@
+use serde::{Deserialize, Serialize};
+use tokio_postgres::Row;
+
+use crate::models::chat_message::ChatMessage;
+
+/// A message that mentioned a user, as seen in their inbox.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Mention {
+    pub id: i64,
+    /// Who was mentioned.
+    pub username: String,
+    pub read: bool,
+    pub message: ChatMessage,
+}
+
+impl From<Row> for Mention {
+    fn from(row: Row) -> Self {
+        Self {
+            id: row.get("mention_id"),
+            username: row.get("mentioned"),
+            read: row.get("read"),
+            message: ChatMessage::from(row),
+        }
+    }
+}
+
+/// A page of a user's mentions, newest first.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct MentionPage {
+    pub mentions: Vec<Mention>,
+    /// How many of all the user's mentions are unread.
+    pub unread: i64,
+}
@@ -1,2 +1,13 @@
+pub mod channel;
+pub mod channel_member;
+pub mod channel_stats;
 pub mod chat_message;
 pub mod chat_stream;
+pub mod direct_message;
+pub mod fragment;
+pub mod import;
+pub mod mention;
+pub mod partition;
+pub mod presence;
+pub mod reaction;
+pub mod retention;
@@ -0,0 +1,79 @@
+use serde::Serialize;
+use time::{Date, Month, OffsetDateTime, PrimitiveDateTime, Time};
+use tokio_postgres::Row;
+
+/// One month of `chat_messages`.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct Partition {
+    pub name: String,
+    pub month_start: Date,
+}
+
+impl Partition {
+    /// The first day of the following month, where this partition ends.
+    pub fn month_end(&self) -> Date {
+        next_month(self.month_start)
+    }
+
+    pub fn starts_at(&self) -> OffsetDateTime {
+        midnight_utc(self.month_start)
+    }
+
+    pub fn ends_at(&self) -> OffsetDateTime {
+        midnight_utc(self.month_end())
+    }
+}
+
+impl From<&Row> for Partition {
+    fn from(row: &Row) -> Self {
+        Self {
+            name: row.get("name"),
+            month_start: row.get("month_start"),
+        }
+    }
+}
+
+/// The first day of the month after the one containing `date`.
+pub fn next_month(date: Date) -> Date {
+    let (year, month) = match date.month() {
+        Month::December => (date.year() + 1, Month::January),
+        month => (date.year(), month.next()),
+    };
+
+    Date::from_calendar_date(year, month, 1).unwrap()
+}
+
+fn midnight_utc(date: Date) -> OffsetDateTime {
+    PrimitiveDateTime::new(date, Time::MIDNIGHT).assume_utc()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_next_month() {
+        let date = Date::from_calendar_date(2023, Month::January, 31).unwrap();
+        assert_eq!(
+            next_month(date),
+            Date::from_calendar_date(2023, Month::February, 1).unwrap()
+        );
+
+        let date = Date::from_calendar_date(2023, Month::December, 5).unwrap();
+        assert_eq!(
+            next_month(date),
+            Date::from_calendar_date(2024, Month::January, 1).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_partition_bounds() {
+        let partition = Partition {
+            name: "chat_messages_2023_01".to_string(),
+            month_start: Date::from_calendar_date(2023, Month::January, 1).unwrap(),
+        };
+
+        assert_eq!(partition.starts_at().unix_timestamp(), 1672531200);
+        assert_eq!(partition.ends_at().unix_timestamp(), 1675209600);
+    }
+}
@@ -0,0 +1,18 @@
+use serde::Serialize;
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct Viewer {
+    pub username: String,
+    /// How many open subscriptions the user has to the channel.
+    pub connections: usize,
+}
+
+/// Who is subscribed to a channel on this instance right now.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct ChannelPresence {
+    pub channel: String,
+    pub viewers: Vec<Viewer>,
+    /// Subscriptions from sessions that did not sign in.
+    pub anonymous: usize,
+    pub total: usize,
+}
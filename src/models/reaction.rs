@@ -0,0 +1,44 @@
+use serde::{Deserialize, Serialize};
+use tokio_postgres::Row;
+
+use crate::models::chat_message::ChatMessage;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Reaction {
+    pub message_id: i32,
+    pub username: String,
+    pub emote: String,
+}
+
+impl Reaction {
+    pub fn new(message_id: i32, username: String, emote: String) -> Self {
+        Reaction {
+            message_id,
+            username,
+            emote,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ReactionCount {
+    pub emote: String,
+    pub count: i64,
+}
+
+impl From<&Row> for ReactionCount {
+    fn from(row: &Row) -> Self {
+        Self {
+            emote: row.get("emote"),
+            count: row.get("count"),
+        }
+    }
+}
+
+/// A stored message together with the aggregated reactions it has received.
+#[derive(Debug, Serialize, PartialEq)]
+pub struct ReactedChatMessage {
+    #[serde(flatten)]
+    pub message: ChatMessage,
+    pub reactions: Vec<ReactionCount>,
+}
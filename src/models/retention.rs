@@ -0,0 +1,76 @@
+use serde::{Deserialize, Serialize};
+use time::{Duration, OffsetDateTime};
+
+/// How much of a channel's history to keep.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RetentionPolicy {
+    #[default]
+    Forever,
+    /// Messages older than this many days expire.
+    KeepDays(u32),
+    /// All but this many of the most recent messages expire.
+    KeepMessages(u32),
+}
+
+impl RetentionPolicy {
+    /// The time before which messages expire, for age based policies.
+    pub fn cutoff(&self, now: OffsetDateTime) -> Option<OffsetDateTime> {
+        match self {
+            RetentionPolicy::KeepDays(days) => Some(now - Duration::days(*days as i64)),
+            _ => None,
+        }
+    }
+}
+
+/// What enforcing a policy on a channel would remove.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct RetentionPreview {
+    pub channel: String,
+    pub policy: RetentionPolicy,
+    pub expired_messages: i64,
+    pub oldest_expired: Option<OffsetDateTime>,
+    pub newest_expired: Option<OffsetDateTime>,
+}
+
+/// What enforcing a policy on a channel removed.
+#[derive(Debug, Clone, Default, Serialize, PartialEq)]
+pub struct RetentionRun {
+    pub channel: String,
+    pub deleted: u64,
+    pub archived: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_policy_from_json() {
+        let policy: RetentionPolicy = serde_json::from_value(json!("forever")).unwrap();
+        assert_eq!(policy, RetentionPolicy::Forever);
+
+        let policy: RetentionPolicy = serde_json::from_value(json!({ "keep_days": 30 })).unwrap();
+        assert_eq!(policy, RetentionPolicy::KeepDays(30));
+
+        let policy: RetentionPolicy =
+            serde_json::from_value(json!({ "keep_messages": 1000 })).unwrap();
+        assert_eq!(policy, RetentionPolicy::KeepMessages(1000));
+
+        assert!(serde_json::from_value::<RetentionPolicy>(json!({ "keep_days": -1 })).is_err());
+    }
+
+    #[test]
+    fn test_policy_cutoff() {
+        let now = OffsetDateTime::from_unix_timestamp(1672531200).unwrap();
+
+        assert_eq!(
+            RetentionPolicy::KeepDays(1).cutoff(now),
+            Some(now - Duration::DAY)
+        );
+        assert_eq!(RetentionPolicy::KeepMessages(10).cutoff(now), None);
+        assert_eq!(RetentionPolicy::Forever.cutoff(now), None);
+    }
+}
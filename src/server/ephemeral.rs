@@ -0,0 +1,238 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::config::Config;
+use crate::error::Error;
+use crate::error::Error::Configuration;
+use crate::server::hub::{ChannelEvent, Hub};
+use crate::utils::rate_limit::RateLimit;
+
+const DEFAULT_TYPING_TTL_MS: u64 = 6000;
+const DEFAULT_RATE_LIMIT: RateLimit = RateLimit {
+    burst: 5,
+    per_ms: 1000,
+};
+const ERR_INVALID_RATE_LIMITS: &str =
+    "ephemeral.rate_limits must map event types to {\"burst\": n, \"per_ms\": n}";
+
+/// The rate limit key of typing indicators.
+pub const TYPING: &str = "typing";
+/// The rate limit key that every signal counts against, whatever its name.
+pub const SIGNAL: &str = "signal";
+pub const MAX_SIGNAL_NAME_LENGTH: usize = 32;
+
+/// Settings for events relayed over websockets without being stored.
+#[derive(Debug, Clone)]
+pub struct EphemeralConfig {
+    /// How long someone counts as typing without saying so again.
+    pub typing_ttl: Duration,
+    /// Applies to every event type without a limit of its own.
+    pub default_limit: RateLimit,
+    pub limits: HashMap<String, RateLimit>,
+}
+
+impl EphemeralConfig {
+    pub fn limit_for(&self, event_type: &str) -> RateLimit {
+        self.limits
+            .get(event_type)
+            .copied()
+            .unwrap_or(self.default_limit)
+    }
+
+    /// The rate limits a signal of that name counts against: the one shared
+    /// by all signals, and its own if `rate_limits` has one for the name.
+    pub fn signal_limits(&self, name: &str) -> Vec<(String, RateLimit)> {
+        let mut limits = vec![(SIGNAL.to_string(), self.limit_for(SIGNAL))];
+        if name != SIGNAL && name != TYPING {
+            if let Some(limit) = self.limits.get(name) {
+                limits.push((format!("{}:{}", SIGNAL, name), *limit));
+            }
+        }
+
+        limits
+    }
+}
+
+impl Config {
+    pub(crate) fn ephemeral(&self) -> Result<EphemeralConfig, Error> {
+        let json = self["ephemeral"].clone();
+        let invalid = |_| Configuration(ERR_INVALID_RATE_LIMITS.to_string());
+
+        let typing_ttl = json
+            .get("typing_ttl_ms")
+            .and_then(|x| x.as_u64())
+            .unwrap_or(DEFAULT_TYPING_TTL_MS);
+
+        let mut limits: HashMap<String, RateLimit> = match json.get("rate_limits") {
+            Some(x) => serde_json::from_value(x.clone()).map_err(invalid)?,
+            None => HashMap::new(),
+        };
+        let default_limit = limits.remove("default").unwrap_or(DEFAULT_RATE_LIMIT);
+
+        Ok(EphemeralConfig {
+            typing_ttl: Duration::from_millis(typing_ttl),
+            default_limit,
+            limits,
+        })
+    }
+}
+
+/// Who is typing in each channel on this instance. Typing stops when the
+/// user says so, leaves, or stays quiet for longer than the TTL.
+#[derive(Debug)]
+pub struct Typing {
+    hub: Arc<Hub>,
+    ttl: Duration,
+    /// Each typing user by channel and username, and which refresh of their
+    /// typing state is the latest.
+    typing: Mutex<HashMap<(String, String), u64>>,
+    refreshes: AtomicU64,
+}
+
+impl Typing {
+    pub fn new(hub: Arc<Hub>, ttl: Duration) -> Self {
+        Self {
+            hub,
+            ttl,
+            typing: Mutex::new(HashMap::new()),
+            refreshes: AtomicU64::new(0),
+        }
+    }
+
+    /// Marks the user as typing, announcing it unless they already were.
+    pub fn start(self: &Arc<Self>, channel: &str, username: &str) {
+        let key = (channel.to_string(), username.to_string());
+        let refresh = self.refreshes.fetch_add(1, Ordering::Relaxed);
+        let started = self
+            .typing
+            .lock()
+            .unwrap()
+            .insert(key.clone(), refresh)
+            .is_none();
+
+        if started {
+            let event = ChannelEvent::TypingStarted {
+                channel: channel.to_string(),
+                username: username.to_string(),
+            };
+            self.hub.publish(channel, event);
+        }
+
+        let typing = self.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(typing.ttl).await;
+            typing.expire(key, refresh);
+        });
+    }
+
+    pub fn stop(&self, channel: &str, username: &str) {
+        let key = (channel.to_string(), username.to_string());
+        if self.typing.lock().unwrap().remove(&key).is_some() {
+            self.publish_stopped(key);
+        }
+    }
+
+    fn expire(&self, key: (String, String), refresh: u64) {
+        {
+            let mut typing = self.typing.lock().unwrap();
+            // A later refresh keeps them typing.
+            if typing.get(&key) != Some(&refresh) {
+                return;
+            }
+            typing.remove(&key);
+        }
+
+        self.publish_stopped(key);
+    }
+
+    fn publish_stopped(&self, (channel, username): (String, String)) {
+        let event = ChannelEvent::TypingStopped {
+            channel: channel.clone(),
+            username,
+        };
+        self.hub.publish(&channel, event);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{FutureExt, StreamExt};
+
+    use super::*;
+    use crate::server::hub;
+
+    const TTL: Duration = Duration::from_millis(30);
+
+    fn started(username: &str) -> ChannelEvent {
+        ChannelEvent::TypingStarted {
+            channel: "chan".to_string(),
+            username: username.to_string(),
+        }
+    }
+
+    fn stopped(username: &str) -> ChannelEvent {
+        ChannelEvent::TypingStopped {
+            channel: "chan".to_string(),
+            username: username.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_typing_expires_unless_refreshed() {
+        let hub = Arc::new(Hub::new());
+        let typing = Arc::new(Typing::new(hub.clone(), TTL));
+        let mut events = Box::pin(hub::into_stream(hub.subscribe("chan")));
+
+        typing.start("chan", "alice");
+        typing.start("chan", "bob");
+        assert_eq!(events.next().await, Some(started("alice")));
+        assert_eq!(events.next().await, Some(started("bob")));
+
+        tokio::time::sleep(TTL / 2).await;
+        typing.start("chan", "alice");
+        typing.stop("chan", "bob");
+        assert_eq!(events.next().await, Some(stopped("bob")));
+
+        // The first refresh expiring does not end alice's typing.
+        tokio::time::sleep(TTL * 3 / 4).await;
+        assert!(events.next().now_or_never().is_none());
+
+        assert_eq!(events.next().await, Some(stopped("alice")));
+        assert!(typing.typing.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_ephemeral_config() {
+        let config = Config::load("config.json.default").await.unwrap();
+        let ephemeral = config.ephemeral().unwrap();
+
+        assert_eq!(ephemeral.limit_for(TYPING).burst, 2);
+        assert_eq!(ephemeral.limit_for("wave"), ephemeral.default_limit);
+    }
+
+    #[test]
+    fn test_signal_limits() {
+        let limit = |burst| RateLimit {
+            burst,
+            per_ms: 1000,
+        };
+        let config = EphemeralConfig {
+            typing_ttl: Duration::from_secs(1),
+            default_limit: limit(5),
+            limits: HashMap::from([
+                (TYPING.to_string(), limit(2)),
+                ("wave".to_string(), limit(1)),
+            ]),
+        };
+
+        let shared = (SIGNAL.to_string(), limit(5));
+        assert_eq!(config.signal_limits("a1"), vec![shared.clone()]);
+        assert_eq!(config.signal_limits(TYPING), vec![shared.clone()]);
+        assert_eq!(
+            config.signal_limits("wave"),
+            vec![shared, ("signal:wave".to_string(), limit(1))]
+        );
+    }
+}
@@ -0,0 +1,201 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use futures::stream::{self, Stream};
+use log::warn;
+use serde::Serialize;
+use serde_json::Value as JsonValue;
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::models::chat_message::ChatMessage;
+use crate::models::direct_message::DirectMessage;
+use crate::models::mention::Mention;
+use crate::models::reaction::Reaction;
+
+const CHANNEL_CAPACITY: usize = 256;
+
+/// An event delivered to everyone subscribed to a channel.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ChannelEvent {
+    /// A message has been written and has its id.
+    Message {
+        channel: String,
+        message: ChatMessage,
+    },
+    ReactionAdded {
+        channel: String,
+        reaction: Reaction,
+    },
+    ReactionRemoved {
+        channel: String,
+        reaction: Reaction,
+    },
+    ViewerJoined {
+        channel: String,
+        username: String,
+    },
+    ViewerLeft {
+        channel: String,
+        username: String,
+    },
+    TypingStarted {
+        channel: String,
+        username: String,
+    },
+    TypingStopped {
+        channel: String,
+        username: String,
+    },
+    /// Only delivered to the sessions of the two participants.
+    DirectMessage {
+        message: DirectMessage,
+    },
+    /// Only delivered to the sessions of the user mentioned.
+    Mention {
+        mention: Mention,
+    },
+    /// A client-defined event, relayed as is and never stored.
+    Signal {
+        channel: String,
+        username: String,
+        name: String,
+        data: JsonValue,
+    },
+    /// Who may read the channel may have changed, so subscribers should
+    /// check their access again. Never sent on to clients.
+    AccessChanged {
+        channel: String,
+    },
+}
+
+/// Fans channel events out to the subscribers connected to this instance.
+#[derive(Debug, Default)]
+pub struct Hub {
+    channels: Topics,
+    /// Events only meant for one user's own sessions, kept apart from
+    /// channels so that nobody can subscribe to someone else's.
+    users: Topics,
+}
+
+type Topics = Mutex<HashMap<String, broadcast::Sender<ChannelEvent>>>;
+
+impl Hub {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn subscribe(&self, channel: &str) -> broadcast::Receiver<ChannelEvent> {
+        subscribe(&self.channels, channel)
+    }
+
+    /// Returns the number of subscribers the event was delivered to.
+    pub fn publish(&self, channel: &str, event: ChannelEvent) -> usize {
+        publish(&self.channels, channel, event)
+    }
+
+    pub fn subscribe_user(&self, username: &str) -> broadcast::Receiver<ChannelEvent> {
+        subscribe(&self.users, username)
+    }
+
+    pub fn publish_to_user(&self, username: &str, event: ChannelEvent) -> usize {
+        publish(&self.users, username, event)
+    }
+}
+
+fn subscribe(topics: &Topics, topic: &str) -> broadcast::Receiver<ChannelEvent> {
+    let mut topics = topics.lock().unwrap();
+
+    topics
+        .entry(topic.to_string())
+        .or_insert_with(|| broadcast::channel(CHANNEL_CAPACITY).0)
+        .subscribe()
+}
+
+fn publish(topics: &Topics, topic: &str, event: ChannelEvent) -> usize {
+    let mut topics = topics.lock().unwrap();
+
+    let Some(sender) = topics.get(topic) else {
+        return 0;
+    };
+
+    match sender.send(event) {
+        Ok(count) => count,
+        Err(_) => {
+            // Nobody is listening anymore, so the sender can be dropped.
+            topics.remove(topic);
+            0
+        }
+    }
+}
+
+/// Adapts a receiver into a stream, skipping over events it lagged behind on.
+pub(crate) fn into_stream(
+    receiver: broadcast::Receiver<ChannelEvent>,
+) -> impl Stream<Item = ChannelEvent> {
+    stream::unfold(receiver, |mut receiver| async move {
+        loop {
+            match receiver.recv().await {
+                Ok(event) => return Some((event, receiver)),
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!("Subscriber lagged behind, skipped {} events", skipped);
+                }
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+
+    use super::*;
+
+    fn reaction_added(channel: &str) -> ChannelEvent {
+        ChannelEvent::ReactionAdded {
+            channel: channel.to_string(),
+            reaction: Reaction::new(1, "user".to_string(), "Kappa".to_string()),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_publish_reaches_channel_subscribers() {
+        let hub = Hub::new();
+        let mut stream = Box::pin(into_stream(hub.subscribe("a")));
+        let _other = hub.subscribe("b");
+
+        assert_eq!(hub.publish("a", reaction_added("a")), 1);
+        assert_eq!(stream.next().await, Some(reaction_added("a")));
+    }
+
+    #[tokio::test]
+    async fn test_user_events_are_separate_from_channels() {
+        let hub = Hub::new();
+        let mut user = Box::pin(into_stream(hub.subscribe_user("a")));
+        let _channel = hub.subscribe("a");
+
+        assert_eq!(hub.publish_to_user("a", reaction_added("a")), 1);
+        assert_eq!(hub.publish_to_user("b", reaction_added("a")), 0);
+        assert_eq!(user.next().await, Some(reaction_added("a")));
+    }
+
+    #[test]
+    fn test_publish_without_subscribers() {
+        let hub = Hub::new();
+        assert_eq!(hub.publish("a", reaction_added("a")), 0);
+
+        drop(hub.subscribe("a"));
+        assert_eq!(hub.publish("a", reaction_added("a")), 0);
+        assert!(hub.channels.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_event_serialization() {
+        let json = serde_json::to_value(reaction_added("a")).unwrap();
+        assert_eq!(json["type"], "reaction_added");
+        assert_eq!(json["channel"], "a");
+        assert_eq!(json["reaction"]["emote"], "Kappa");
+    }
+}
@@ -0,0 +1,370 @@
+use std::cmp::{Ordering, Reverse};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BinaryHeap, HashMap, HashSet, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::future;
+use futures::stream::{self, SelectAll};
+use futures::StreamExt;
+use time::OffsetDateTime;
+use tokio::time::Instant;
+
+use crate::config::Config;
+use crate::error::Error;
+use crate::error::Error::Configuration;
+use crate::models::chat_message::ChatMessage;
+use crate::models::chat_stream::ChatStream;
+use crate::server::supervisor::ChatSource;
+use crate::utils::clock::Clock;
+
+const DEFAULT_REORDER_WINDOW_MS: u64 = 500;
+const DEFAULT_DEDUPE_WINDOW_MS: u64 = 5000;
+const DEFAULT_MAX_BUFFERED: u64 = 1000;
+const DEFAULT_MAX_SOURCE_IDS: u64 = 10_000;
+const ERR_INVALID_MERGE: &str = "merge.max_buffered and merge.max_source_ids must be positive";
+
+#[derive(Debug, Clone)]
+pub struct MergeConfig {
+    /// How long a message is held back for earlier messages from other
+    /// streams to catch up...
+    pub reorder_window: Duration,
+    /// ...unless this many are held already.
+    pub max_buffered: usize,
+    /// Messages without an id are duplicates of messages with the same
+    /// content sent this close in time.
+    pub dedupe_window: Duration,
+    /// How many of the latest source ids are remembered.
+    pub max_source_ids: usize,
+}
+
+impl Config {
+    pub(crate) fn merge(&self) -> Result<MergeConfig, Error> {
+        let json = self["merge"].clone();
+        let get =
+            |key: &str, default: u64| json.get(key).and_then(|x| x.as_u64()).unwrap_or(default);
+
+        let max_buffered = get("max_buffered", DEFAULT_MAX_BUFFERED) as usize;
+        let max_source_ids = get("max_source_ids", DEFAULT_MAX_SOURCE_IDS) as usize;
+        if max_buffered == 0 || max_source_ids == 0 {
+            return Err(Configuration(ERR_INVALID_MERGE.to_string()));
+        }
+
+        Ok(MergeConfig {
+            reorder_window: Duration::from_millis(get(
+                "reorder_window_ms",
+                DEFAULT_REORDER_WINDOW_MS,
+            )),
+            max_buffered,
+            dedupe_window: Duration::from_millis(get("dedupe_window_ms", DEFAULT_DEDUPE_WINDOW_MS)),
+            max_source_ids,
+        })
+    }
+}
+
+/// Remembers enough of the messages already let through to tell whether
+/// another is one of them.
+#[derive(Debug)]
+struct Seen {
+    dedupe_window: time::Duration,
+    max_source_ids: usize,
+    /// The latest source ids and idempotency keys, by channel, oldest
+    /// first.
+    keys: HashSet<(String, String)>,
+    key_order: VecDeque<(String, String)>,
+    /// When each recent message without an id was sent, by its content.
+    hashes: HashMap<u64, OffsetDateTime>,
+    hash_order: VecDeque<(OffsetDateTime, u64)>,
+    newest: Option<OffsetDateTime>,
+}
+
+impl Seen {
+    fn new(config: &MergeConfig) -> Self {
+        Self {
+            dedupe_window: time::Duration::try_from(config.dedupe_window).unwrap(),
+            max_source_ids: config.max_source_ids,
+            keys: HashSet::new(),
+            key_order: VecDeque::new(),
+            hashes: HashMap::new(),
+            hash_order: VecDeque::new(),
+            newest: None,
+        }
+    }
+
+    /// Whether the message was seen already. If not, it is from now on.
+    fn check(&mut self, message: &ChatMessage) -> bool {
+        match message.dedupe_key() {
+            Some(key) => self.check_key((message.channel.clone(), key)),
+            None => self.check_content(message),
+        }
+    }
+
+    fn check_key(&mut self, key: (String, String)) -> bool {
+        if !self.keys.insert(key.clone()) {
+            return true;
+        }
+
+        self.key_order.push_back(key);
+        if self.key_order.len() > self.max_source_ids {
+            if let Some(oldest) = self.key_order.pop_front() {
+                self.keys.remove(&oldest);
+            }
+        }
+
+        false
+    }
+
+    fn check_content(&mut self, message: &ChatMessage) -> bool {
+        self.expire(message.timestamp);
+
+        let mut hasher = DefaultHasher::new();
+        (&message.channel, &message.username, &message.text).hash(&mut hasher);
+        let hash = hasher.finish();
+
+        if let Some(sent) = self.hashes.get(&hash) {
+            if (message.timestamp - *sent).abs() <= self.dedupe_window {
+                return true;
+            }
+        }
+
+        self.hashes.insert(hash, message.timestamp);
+        self.hash_order.push_back((message.timestamp, hash));
+
+        false
+    }
+
+    /// Forgets the contents of messages sent a window before the newest.
+    fn expire(&mut self, timestamp: OffsetDateTime) {
+        let newest = self
+            .newest
+            .map_or(timestamp, |newest| newest.max(timestamp));
+        self.newest = Some(newest);
+
+        while let Some(&(sent, hash)) = self.hash_order.front() {
+            if newest - sent <= self.dedupe_window {
+                break;
+            }
+
+            self.hash_order.pop_front();
+            if self.hashes.get(&hash) == Some(&sent) {
+                self.hashes.remove(&hash);
+            }
+        }
+    }
+}
+
+/// A message held back for reordering, earliest sent first, then first
+/// arrived.
+#[derive(Debug)]
+struct Held {
+    message: ChatMessage,
+    arrived: Instant,
+    order: u64,
+}
+
+impl Held {
+    fn key(&self) -> (OffsetDateTime, u64) {
+        (self.message.timestamp, self.order)
+    }
+}
+
+impl PartialEq for Held {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for Held {}
+
+impl PartialOrd for Held {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Held {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key().cmp(&other.key())
+    }
+}
+
+struct Merger {
+    config: MergeConfig,
+    streams: SelectAll<ChatStream<'static>>,
+    seen: Seen,
+    held: BinaryHeap<Reverse<Held>>,
+    arrivals: u64,
+    exhausted: bool,
+}
+
+impl Merger {
+    async fn next(&mut self) -> Option<ChatMessage> {
+        loop {
+            if let Some(message) = self.release() {
+                return Some(message);
+            }
+            if self.exhausted {
+                return self.held.pop().map(|Reverse(held)| held.message);
+            }
+
+            let deadline = self.held.peek().map(|Reverse(held)| held.arrived);
+            let deadline = deadline.unwrap_or_else(Instant::now) + self.config.reorder_window;
+            tokio::select! {
+                message = self.streams.next() => match message {
+                    Some(message) => self.hold(message),
+                    None => self.exhausted = true,
+                },
+                _ = tokio::time::sleep_until(deadline), if !self.held.is_empty() => {}
+            }
+        }
+    }
+
+    fn hold(&mut self, message: ChatMessage) {
+        if self.seen.check(&message) {
+            return;
+        }
+
+        self.arrivals += 1;
+        self.held.push(Reverse(Held {
+            message,
+            arrived: Instant::now(),
+            order: self.arrivals,
+        }));
+    }
+
+    /// The earliest message held, once it has been held long enough or room
+    /// is needed.
+    fn release(&mut self) -> Option<ChatMessage> {
+        let Reverse(earliest) = self.held.peek()?;
+        let due = earliest.arrived + self.config.reorder_window <= Instant::now();
+        if !due && self.held.len() <= self.config.max_buffered {
+            return None;
+        }
+
+        self.held.pop().map(|Reverse(held)| held.message)
+    }
+}
+
+/// Merges streams of the same messages into one, letting only the first
+/// copy of each message through. The survivors come out in the order they
+/// were sent, as far as they arrive within the reorder window of each other.
+/// The merged stream ends once every stream has.
+pub fn merge(
+    streams: Vec<ChatStream<'static>>,
+    config: MergeConfig,
+    clock: Arc<dyn Clock>,
+) -> ChatStream<'static> {
+    let merger = Merger {
+        seen: Seen::new(&config),
+        config,
+        streams: stream::select_all(streams),
+        held: BinaryHeap::new(),
+        arrivals: 0,
+        exhausted: false,
+    };
+
+    let messages = stream::unfold(merger, |mut merger| async move {
+        let message = merger.next().await?;
+        Some((message, merger))
+    });
+
+    ChatStream::new(messages, clock)
+}
+
+/// Redundant sources of the same messages, read as one.
+pub struct MergedSource {
+    sources: Vec<Arc<dyn ChatSource>>,
+    config: MergeConfig,
+}
+
+impl MergedSource {
+    pub fn new(sources: Vec<Arc<dyn ChatSource>>, config: MergeConfig) -> Self {
+        Self { sources, config }
+    }
+}
+
+#[async_trait::async_trait]
+impl ChatSource for MergedSource {
+    async fn open(&self, clock: Arc<dyn Clock>) -> Result<ChatStream<'static>, Error> {
+        let streams = self.sources.iter().map(|source| source.open(clock.clone()));
+        let streams = future::try_join_all(streams).await?;
+
+        Ok(merge(streams, self.config.clone(), clock))
+    }
+
+    fn is_finite(&self) -> bool {
+        self.sources.iter().all(|source| source.is_finite())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fake::{Fake, Faker};
+
+    use super::*;
+    use crate::utils::clock::SystemClock;
+
+    fn config() -> MergeConfig {
+        MergeConfig {
+            reorder_window: Duration::from_millis(50),
+            max_buffered: 100,
+            dedupe_window: Duration::from_secs(5),
+            max_source_ids: 2,
+        }
+    }
+
+    fn message(text: &str, seconds: i64, source_id: Option<&str>) -> ChatMessage {
+        ChatMessage {
+            text: text.to_string(),
+            username: "user".to_string(),
+            channel: "channel".to_string(),
+            timestamp: OffsetDateTime::UNIX_EPOCH + time::Duration::seconds(seconds),
+            source_id: source_id.map(|id| id.to_string()),
+            ..Faker.fake()
+        }
+    }
+
+    #[test]
+    fn test_seen_by_source_id_or_content() {
+        let mut seen = Seen::new(&config());
+
+        assert!(!seen.check(&message("hi", 0, Some("a"))));
+        assert!(seen.check(&message("other text", 9, Some("a"))));
+        assert!(!seen.check(&message("hi", 0, Some("b"))));
+
+        assert!(!seen.check(&message("hi", 0, None)));
+        assert!(seen.check(&message("hi", 4, None)));
+        assert!(!seen.check(&message("hi", 6, None)));
+
+        // Only the latest source ids are remembered.
+        assert!(!seen.check(&message("hi", 0, Some("c"))));
+        assert!(!seen.check(&message("hi", 0, Some("a"))));
+    }
+
+    #[tokio::test]
+    async fn test_merge_drops_duplicates_and_reorders() {
+        let clock: Arc<dyn Clock> = Arc::new(SystemClock);
+        let first = vec![
+            message("one", 1, Some("1")),
+            message("three", 3, Some("3")),
+            message("no id", 4, None),
+        ];
+        let second = vec![
+            message("two", 2, Some("2")),
+            message("three", 3, Some("3")),
+            message("no id", 4, None),
+        ];
+        let streams = vec![
+            ChatStream::new(stream::iter(first), clock.clone()),
+            ChatStream::new(stream::iter(second), clock.clone()),
+        ];
+
+        let texts: Vec<_> = merge(streams, config(), clock)
+            .map(|m| m.text)
+            .collect()
+            .await;
+
+        assert_eq!(texts, vec!["one", "two", "three", "no id"]);
+    }
+}
@@ -1,67 +1,171 @@
 use std::sync::Arc;
 
-use actix::{Actor, StreamHandler};
-use actix_web::web::service;
-use actix_web::{middleware, web, App, HttpRequest, HttpResponse, HttpServer};
-use actix_web_actors::ws;
+use actix_web::{middleware, web, App, HttpServer};
 use log::info;
 
-use crate::api::channel;
+use crate::api::{admin, channel, dm, events, import, mention, message, reaction};
 use crate::config::Config;
+use crate::dal;
+use crate::dal::batch_writer::BatchWriter;
+use crate::dal::channel_repository::ChannelRepository;
 use crate::dal::chat_message_repository::ChatMessageRepository;
+use crate::dal::direct_message_repository::DirectMessageRepository;
+use crate::dal::import_repository::ImportRepository;
+use crate::dal::mention_repository::MentionRepository;
+use crate::dal::partition_repository::PartitionRepository;
+use crate::dal::reaction_repository::ReactionRepository;
+use crate::dal::retention_repository::RetentionRepository;
+use crate::dal::stats_repository::StatsRepository;
 use crate::error::Error;
+use crate::server::ephemeral::Typing;
+use crate::server::hub::Hub;
+use crate::server::presence::{Presence, LEAVE_DEBOUNCE};
+use crate::server::resume::RecentMessages;
 use crate::server::server_state::ServerState;
+use crate::server::supervisor::Supervisor;
+use crate::utils::clock::{Clock, SystemClock};
 
+pub mod ephemeral;
+pub mod hub;
+pub mod merge;
+pub mod pipeline;
+pub mod presence;
+pub mod queue;
+pub mod replay;
+pub mod resume;
 pub mod server_state;
+pub mod source;
+pub mod supervisor;
+pub(crate) mod tasks;
+pub(crate) mod ws;
 
-/// Define HTTP actor
-struct MyWs;
-
-impl Actor for MyWs {
-    type Context = ws::WebsocketContext<Self>;
-}
+pub async fn start(config: &Config) -> Result<(), Error> {
+    info!("Starting server");
 
-/// Handler for ws::Message message
-impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for MyWs {
-    fn handle(&mut self, msg: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
-        match msg {
-            Ok(ws::Message::Ping(msg)) => ctx.pong(&msg),
-            Ok(ws::Message::Text(text)) => ctx.text(text),
-            Ok(ws::Message::Binary(bin)) => ctx.binary(bin),
-            _ => (),
-        }
+    for version in dal::migrations::run(config).await? {
+        info!("Applied migration {}", version);
     }
-}
-
-async fn index(req: HttpRequest, stream: web::Payload) -> Result<HttpResponse, actix_web::Error> {
-    let resp = ws::start(MyWs {}, &req, stream);
-    println!("{:?}", resp);
-    resp
-}
 
-pub async fn start(config: &Config) -> Result<(), Error> {
-    info!("Starting server");
+    let mut partitions = PartitionRepository::new(config)?;
+    partitions.connect().await?;
 
     let mut repo = ChatMessageRepository::new(config).unwrap();
     repo.connect().await?;
 
+    let mut channels = ChannelRepository::new(config)?;
+    channels.connect().await?;
+
+    let mut reactions = ReactionRepository::new(config)?;
+    reactions.connect().await?;
+
+    let mut direct_messages = DirectMessageRepository::new(config)?;
+    direct_messages.connect().await?;
+
+    let mut mentions = MentionRepository::new(config)?;
+    mentions.connect().await?;
+
+    let mut stats = StatsRepository::new(config)?;
+    stats.connect().await?;
+
+    let mut retention = RetentionRepository::new(config)?;
+    retention.connect().await?;
+
+    let mut importer = ImportRepository::new(config)?;
+    importer.connect().await?;
+
     let api_config = &config.api().unwrap();
 
+    let repo = Arc::new(repo);
+    let writer = BatchWriter::spawn(repo.clone(), config.ingest()?);
+
+    let hub = Arc::new(Hub::new());
+    let ephemeral = config.ephemeral()?;
+    let clock: Arc<dyn Clock> = Arc::new(SystemClock);
     let state = ServerState {
-        repo: Arc::new(repo),
+        repo,
+        writer,
+        channels: Arc::new(channels),
+        reactions: Arc::new(reactions),
+        direct_messages: Arc::new(direct_messages),
+        mentions: Arc::new(mentions),
+        stats: Arc::new(stats),
+        retention: Arc::new(retention),
+        importer: Arc::new(importer),
+        presence: Arc::new(Presence::new(hub.clone(), LEAVE_DEBOUNCE)),
+        typing: Arc::new(Typing::new(hub.clone(), ephemeral.typing_ttl)),
+        ephemeral: Arc::new(ephemeral),
+        supervisor: Arc::new(Supervisor::new(config.supervisor()?, clock.clone())),
+        clock,
+        recent: Arc::new(RecentMessages::new(config.resume()?)),
+        hub,
+        tokens: Arc::new(api_config.tokens.clone()),
+        admins: Arc::new(api_config.admins.clone()),
     };
 
+    tasks::spawn_partition_maintenance(partitions, state.clock.clone());
+    tasks::spawn_message_listener(state.repo.clone(), state.hub.clone(), state.recent.clone());
+    tasks::spawn_direct_message_listener(state.direct_messages.clone(), state.hub.clone());
+    tasks::spawn_mention_listener(state.mentions.clone(), state.hub.clone());
+    tasks::spawn_access_listener(state.channels.clone(), state.hub.clone());
+    tasks::spawn_rollup_refresh(state.stats.clone());
+    tasks::spawn_retention(state.retention.clone(), state.clock.clone());
+
+    let merge = config.merge()?;
+    for (name, source) in config.sources()? {
+        let sink = Arc::new(state.writer.clone());
+        let source = source.build(&merge);
+        state.supervisor.supervise(&name, source, sink).await?;
+        info!("Supervising source {}", name);
+    }
+
     HttpServer::new(move || {
         App::new()
             .wrap(middleware::Logger::default())
             .app_data(web::Data::new(state.clone()))
-            .service(web::resource("/ws/").to(index))
+            .service(web::resource("/ws/").to(ws::ws_index))
             .service(
                 web::scope("/channel")
                     .service(channel::channel_index)
+                    .service(channel::channel_post)
+                    .service(channel::channel_info)
+                    .service(channel::channel_patch)
+                    .service(channel::channel_delete)
+                    .service(channel::channel_stats)
+                    .service(channel::channel_presence)
+                    .service(channel::channel_members)
+                    .service(channel::channel_member_put)
+                    .service(channel::channel_member_delete)
+                    .service(channel::channel_export)
+                    .service(events::channel_events)
                     .service(channel::channel_get)
                     .service(channel::channel_get_count),
             )
+            .service(
+                web::scope("/message")
+                    .service(message::message_index)
+                    .service(message::message_post)
+                    .service(message::ingest_stats)
+                    .service(reaction::reaction_post)
+                    .service(reaction::reaction_delete),
+            )
+            .service(web::scope("/dm").service(dm::dm_post).service(dm::dm_get))
+            .service(
+                web::scope("/user")
+                    .service(mention::mentions_get)
+                    .service(mention::mentions_patch),
+            )
+            .service(
+                web::scope("/import")
+                    .service(import::import_post)
+                    .service(import::import_get),
+            )
+            .service(
+                web::scope("/admin")
+                    .service(admin::retention_preview)
+                    .service(admin::streams_index)
+                    .service(admin::replay_post)
+                    .service(admin::replay_delete),
+            )
     })
     .bind(&api_config.address)?
     .run()
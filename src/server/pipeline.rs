@@ -0,0 +1,269 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use futures::future::{self, BoxFuture};
+use futures::stream::{self, BoxStream, Stream};
+use futures::StreamExt;
+use log::warn;
+use serde::Deserialize;
+
+use crate::models::chat_message::ChatMessage;
+use crate::server::supervisor::ChatSink;
+use crate::utils::fragments;
+
+type Enricher = Arc<dyn Fn(ChatMessage) -> BoxFuture<'static, ChatMessage> + Send + Sync>;
+type BatchProcessor =
+    Arc<dyn Fn(Vec<ChatMessage>) -> BoxFuture<'static, Vec<ChatMessage>> + Send + Sync>;
+
+enum Stage {
+    Filter(Box<dyn FnMut(&ChatMessage) -> bool + Send>),
+    Map(Box<dyn FnMut(ChatMessage) -> ChatMessage + Send>),
+    Enrich(Enricher),
+    Batch(usize, BatchProcessor),
+    Tee(Arc<dyn ChatSink>),
+}
+
+/// A built-in stage, as declared in a source's `pipeline` in the config.
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(tag = "stage", rename_all = "snake_case")]
+pub enum StageConfig {
+    RenameChannel { channels: HashMap<String, String> },
+    NormalizeUsername,
+    TrimText,
+}
+
+/// Stages that each message of a stream goes through in order, any of which
+/// can change, drop or copy it.
+#[derive(Default)]
+pub struct Pipeline {
+    stages: Vec<Stage>,
+}
+
+impl Pipeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_config(stages: &[StageConfig]) -> Self {
+        stages
+            .iter()
+            .fold(Self::new(), |pipeline, stage| match stage {
+                StageConfig::RenameChannel { channels } => {
+                    pipeline.rename_channels(channels.clone())
+                }
+                StageConfig::NormalizeUsername => pipeline.normalize_username(),
+                StageConfig::TrimText => pipeline.trim_text(),
+            })
+    }
+
+    /// Drops the messages `f` returns false for.
+    pub fn filter<F>(mut self, f: F) -> Self
+    where
+        F: FnMut(&ChatMessage) -> bool + Send + 'static,
+    {
+        self.stages.push(Stage::Filter(Box::new(f)));
+        self
+    }
+
+    pub fn map<F>(mut self, f: F) -> Self
+    where
+        F: FnMut(ChatMessage) -> ChatMessage + Send + 'static,
+    {
+        self.stages.push(Stage::Map(Box::new(f)));
+        self
+    }
+
+    /// Passes each message through `f`, one at a time and in order.
+    pub fn enrich<F, Fut>(mut self, f: F) -> Self
+    where
+        F: Fn(ChatMessage) -> Fut + Send + Sync + 'static,
+        Fut: std::future::Future<Output = ChatMessage> + Send + 'static,
+    {
+        self.stages
+            .push(Stage::Enrich(Arc::new(move |m| Box::pin(f(m)))));
+        self
+    }
+
+    /// Passes the messages through `f` in batches of up to `size` of those
+    /// already waiting, which can return any number of messages back.
+    pub fn batch<F, Fut>(mut self, size: usize, f: F) -> Self
+    where
+        F: Fn(Vec<ChatMessage>) -> Fut + Send + Sync + 'static,
+        Fut: std::future::Future<Output = Vec<ChatMessage>> + Send + 'static,
+    {
+        self.stages
+            .push(Stage::Batch(size.max(1), Arc::new(move |m| Box::pin(f(m)))));
+        self
+    }
+
+    /// Sends a copy of each message to `sink` as well. A sink that fails only
+    /// misses the message.
+    pub fn tee(mut self, sink: Arc<dyn ChatSink>) -> Self {
+        self.stages.push(Stage::Tee(sink));
+        self
+    }
+
+    /// Moves messages to other channels, by the name of the channel they
+    /// came from.
+    pub fn rename_channels(self, channels: HashMap<String, String>) -> Self {
+        self.map(move |mut message| {
+            if let Some(channel) = channels.get(&message.channel) {
+                message.channel = channel.clone();
+            }
+            message
+        })
+    }
+
+    /// Lowercases usernames, without any surrounding whitespace or leading
+    /// `@`.
+    pub fn normalize_username(self) -> Self {
+        self.map(|mut message| {
+            let username = message.username.trim().trim_start_matches('@');
+            message.username = username.to_lowercase();
+            message
+        })
+    }
+
+    /// Trims whitespace around the text, dropping messages left empty.
+    /// Fragments of trimmed messages are parsed again on ingest, with their
+    /// emotes moved along with the text.
+    pub fn trim_text(self) -> Self {
+        self.map(|mut message| {
+            let text = message.text.trim();
+            if text.len() == message.text.len() {
+                return message;
+            }
+
+            let cut = message.text.chars().count() - message.text.trim_start().chars().count();
+            message.text = text.to_string();
+            message.fragments = None;
+            if let Some(emotes) = &message.emotes {
+                message.emotes = Some(fragments::shift_emotes(emotes, cut));
+            }
+            message
+        })
+        .filter(|message| !message.text.is_empty())
+    }
+
+    pub fn apply<'a, S>(self, messages: S) -> BoxStream<'a, ChatMessage>
+    where
+        S: Stream<Item = ChatMessage> + Send + 'a,
+    {
+        self.stages
+            .into_iter()
+            .fold(messages.boxed(), |messages, stage| match stage {
+                Stage::Filter(mut f) => messages.filter(move |m| future::ready(f(m))).boxed(),
+                Stage::Map(f) => messages.map(f).boxed(),
+                Stage::Enrich(f) => messages.then(move |m| f(m)).boxed(),
+                Stage::Batch(size, f) => messages
+                    .ready_chunks(size)
+                    .then(move |batch| f(batch))
+                    .flat_map(stream::iter)
+                    .boxed(),
+                Stage::Tee(sink) => messages
+                    .then(move |m| {
+                        let sink = sink.clone();
+                        async move {
+                            if let Err(err) = sink.send(m.clone()).await {
+                                warn!("Could not tee message: {}", err);
+                            }
+                            m
+                        }
+                    })
+                    .boxed(),
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use fake::{Fake, Faker};
+    use serde_json::json;
+
+    use super::*;
+    use crate::error::Error;
+
+    fn message(username: &str, channel: &str, text: &str) -> ChatMessage {
+        ChatMessage {
+            username: username.to_string(),
+            channel: channel.to_string(),
+            text: text.to_string(),
+            ..Faker.fake()
+        }
+    }
+
+    #[derive(Default)]
+    struct TestSink(Mutex<Vec<ChatMessage>>);
+
+    #[async_trait::async_trait]
+    impl ChatSink for TestSink {
+        async fn send(&self, message: ChatMessage) -> Result<(), Error> {
+            self.0.lock().unwrap().push(message);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_built_in_stages_from_config() {
+        let stages: Vec<StageConfig> = serde_json::from_value(json!([
+            { "stage": "rename_channel", "channels": { "old": "new" } },
+            { "stage": "normalize_username" },
+            { "stage": "trim_text" }
+        ]))
+        .unwrap();
+
+        let messages = vec![
+            message(" @Alice", "old", "  hi "),
+            message("bob", "other", "   "),
+        ];
+        let messages: Vec<_> = Pipeline::from_config(&stages)
+            .apply(stream::iter(messages))
+            .collect()
+            .await;
+
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].username, "alice");
+        assert_eq!(messages[0].channel, "new");
+        assert_eq!(messages[0].text, "hi");
+    }
+
+    #[tokio::test]
+    async fn test_stages_run_in_order() {
+        let sink = Arc::new(TestSink::default());
+        let pipeline = Pipeline::new()
+            .filter(|m| m.username != "spam")
+            .enrich(|mut m| async move {
+                m.text = format!("{}!", m.text);
+                m
+            })
+            .tee(sink.clone())
+            .batch(10, |batch| async move { batch.into_iter().rev().collect() })
+            .map(|mut m| {
+                m.text = m.text.to_uppercase();
+                m
+            });
+
+        let messages = vec![
+            message("a", "c", "one"),
+            message("spam", "c", "buy"),
+            message("b", "c", "two"),
+        ];
+        let texts: Vec<_> = pipeline
+            .apply(stream::iter(messages))
+            .map(|m| m.text)
+            .collect()
+            .await;
+
+        assert_eq!(texts, vec!["TWO!", "ONE!"]);
+        let teed: Vec<_> = sink
+            .0
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|m| m.text.clone())
+            .collect();
+        assert_eq!(teed, vec!["one!", "two!"]);
+    }
+}
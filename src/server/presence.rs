@@ -0,0 +1,229 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::models::presence::{ChannelPresence, Viewer};
+use crate::server::hub::{ChannelEvent, Hub};
+
+/// How long a user whose last subscription closed still counts as present,
+/// so that a quick reconnect does not announce them leaving and rejoining.
+pub const LEAVE_DEBOUNCE: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Default)]
+struct Subscribers {
+    users: HashMap<String, usize>,
+    anonymous: usize,
+    /// Users whose last subscription closed within the debounce window, and
+    /// which departure that was.
+    leaving: HashMap<String, u64>,
+}
+
+impl Subscribers {
+    fn is_empty(&self) -> bool {
+        self.users.is_empty() && self.anonymous == 0 && self.leaving.is_empty()
+    }
+}
+
+/// Tracks the websocket sessions subscribed to each channel on this
+/// instance, announcing signed-in users joining and leaving.
+#[derive(Debug)]
+pub struct Presence {
+    hub: Arc<Hub>,
+    debounce: Duration,
+    channels: Mutex<HashMap<String, Subscribers>>,
+    departures: AtomicU64,
+}
+
+impl Presence {
+    pub fn new(hub: Arc<Hub>, debounce: Duration) -> Self {
+        Self {
+            hub,
+            debounce,
+            channels: Mutex::new(HashMap::new()),
+            departures: AtomicU64::new(0),
+        }
+    }
+
+    pub fn join(&self, channel: &str, username: Option<&str>) {
+        let mut channels = self.channels.lock().unwrap();
+        let subscribers = channels.entry(channel.to_string()).or_default();
+
+        let Some(username) = username else {
+            subscribers.anonymous += 1;
+            return;
+        };
+
+        let connections = subscribers.users.entry(username.to_string()).or_insert(0);
+        *connections += 1;
+
+        let rejoined = subscribers.leaving.remove(username).is_some();
+        if *connections == 1 && !rejoined {
+            drop(channels);
+            self.publish_joined(channel, username);
+        }
+    }
+
+    /// Announces the user leaving once the debounce window has passed
+    /// without them rejoining.
+    pub fn leave(self: &Arc<Self>, channel: &str, username: Option<&str>) {
+        let mut channels = self.channels.lock().unwrap();
+        let Some(subscribers) = channels.get_mut(channel) else {
+            return;
+        };
+
+        let Some(username) = username else {
+            subscribers.anonymous = subscribers.anonymous.saturating_sub(1);
+            if subscribers.is_empty() {
+                channels.remove(channel);
+            }
+            return;
+        };
+
+        let Some(connections) = subscribers.users.get_mut(username) else {
+            return;
+        };
+        *connections -= 1;
+        if *connections > 0 {
+            return;
+        }
+
+        subscribers.users.remove(username);
+        let departure = self.departures.fetch_add(1, Ordering::Relaxed);
+        subscribers.leaving.insert(username.to_string(), departure);
+
+        let presence = self.clone();
+        let (channel, username) = (channel.to_string(), username.to_string());
+        tokio::spawn(async move {
+            tokio::time::sleep(presence.debounce).await;
+            presence.finish_leave(&channel, &username, departure);
+        });
+    }
+
+    fn finish_leave(&self, channel: &str, username: &str, departure: u64) {
+        let mut channels = self.channels.lock().unwrap();
+        let Some(subscribers) = channels.get_mut(channel) else {
+            return;
+        };
+
+        // A rejoin, possibly followed by another departure, supersedes this one.
+        if subscribers.leaving.get(username) != Some(&departure) {
+            return;
+        }
+
+        subscribers.leaving.remove(username);
+        if subscribers.is_empty() {
+            channels.remove(channel);
+        }
+        drop(channels);
+
+        let event = ChannelEvent::ViewerLeft {
+            channel: channel.to_string(),
+            username: username.to_string(),
+        };
+        self.hub.publish(channel, event);
+    }
+
+    fn publish_joined(&self, channel: &str, username: &str) {
+        let event = ChannelEvent::ViewerJoined {
+            channel: channel.to_string(),
+            username: username.to_string(),
+        };
+        self.hub.publish(channel, event);
+    }
+
+    pub fn get(&self, channel: &str) -> ChannelPresence {
+        let channels = self.channels.lock().unwrap();
+
+        let (mut viewers, anonymous) = match channels.get(channel) {
+            Some(subscribers) => (
+                subscribers
+                    .users
+                    .iter()
+                    .map(|(username, connections)| Viewer {
+                        username: username.clone(),
+                        connections: *connections,
+                    })
+                    .collect::<Vec<_>>(),
+                subscribers.anonymous,
+            ),
+            None => (Vec::new(), 0),
+        };
+        viewers.sort_by(|a, b| a.username.cmp(&b.username));
+
+        ChannelPresence {
+            channel: channel.to_string(),
+            total: viewers.iter().map(|v| v.connections).sum::<usize>() + anonymous,
+            viewers,
+            anonymous,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{FutureExt, StreamExt};
+
+    use super::*;
+    use crate::server::hub;
+
+    const DEBOUNCE: Duration = Duration::from_millis(20);
+
+    fn joined(username: &str) -> ChannelEvent {
+        ChannelEvent::ViewerJoined {
+            channel: "chan".to_string(),
+            username: username.to_string(),
+        }
+    }
+
+    fn left(username: &str) -> ChannelEvent {
+        ChannelEvent::ViewerLeft {
+            channel: "chan".to_string(),
+            username: username.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_join_and_leave_are_announced() {
+        let hub = Arc::new(Hub::new());
+        let presence = Arc::new(Presence::new(hub.clone(), DEBOUNCE));
+        let mut events = Box::pin(hub::into_stream(hub.subscribe("chan")));
+
+        presence.join("chan", Some("alice"));
+        presence.join("chan", Some("alice"));
+        presence.join("chan", None);
+        assert_eq!(events.next().await, Some(joined("alice")));
+
+        let current = presence.get("chan");
+        assert_eq!(current.total, 3);
+        assert_eq!(current.anonymous, 1);
+        assert_eq!(current.viewers[0].connections, 2);
+
+        presence.leave("chan", Some("alice"));
+        presence.leave("chan", Some("alice"));
+        presence.leave("chan", None);
+        assert_eq!(presence.get("chan").total, 0);
+        assert_eq!(events.next().await, Some(left("alice")));
+
+        assert!(presence.channels.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_quick_reconnect_is_not_announced() {
+        let hub = Arc::new(Hub::new());
+        let presence = Arc::new(Presence::new(hub.clone(), DEBOUNCE));
+
+        presence.join("chan", Some("alice"));
+        let mut events = Box::pin(hub::into_stream(hub.subscribe("chan")));
+
+        presence.leave("chan", Some("alice"));
+        presence.join("chan", Some("alice"));
+        presence.join("chan", Some("bob"));
+        tokio::time::sleep(DEBOUNCE * 2).await;
+
+        // Only bob's arrival makes it out.
+        assert_eq!(events.next().await, Some(joined("bob")));
+        assert!(events.next().now_or_never().is_none());
+        assert_eq!(presence.get("chan").viewers.len(), 2);
+    }
+}
@@ -0,0 +1,564 @@
+use std::collections::{HashMap, VecDeque};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use log::{debug, error, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use tokio::fs::{self, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Notify;
+
+use crate::config::Config;
+use crate::error::Error;
+use crate::error::Error::Configuration;
+use crate::models::chat_message::ChatMessage;
+use crate::server::supervisor::ChatSink;
+use crate::utils::archive;
+use crate::utils::export::ExportFormat;
+
+const DEFAULT_CAPACITY: u64 = 1000;
+const DEFAULT_SPILL_DIR: &str = "spill";
+const ERR_INVALID_QUEUE: &str = "queue capacities must be positive";
+
+/// What a source's queue does with a message that arrives while it is full.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OverflowPolicy {
+    /// Wait for room, holding up the source.
+    #[default]
+    Block,
+    DropOldest,
+    DropNewest,
+    /// Write it to disk, to be read back once the queue has drained.
+    Spill,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueueConfig {
+    pub capacity: usize,
+    pub overflow: OverflowPolicy,
+    /// Where spilled messages are kept, in a directory per source.
+    pub spill_dir: PathBuf,
+}
+
+/// The queue of every source, from `queues` with overrides from each
+/// source's `queue` in `sources`.
+#[derive(Debug, Clone)]
+pub struct QueueConfigs {
+    default: QueueConfig,
+    sources: HashMap<String, QueueConfig>,
+}
+
+impl QueueConfigs {
+    pub fn get(&self, source: &str) -> &QueueConfig {
+        self.sources.get(source).unwrap_or(&self.default)
+    }
+}
+
+impl Default for QueueConfigs {
+    fn default() -> Self {
+        Self {
+            default: QueueConfig {
+                capacity: DEFAULT_CAPACITY as usize,
+                overflow: OverflowPolicy::default(),
+                spill_dir: PathBuf::from(DEFAULT_SPILL_DIR),
+            },
+            sources: HashMap::new(),
+        }
+    }
+}
+
+impl Config {
+    pub(crate) fn queues(&self) -> Result<QueueConfigs, Error> {
+        let defaults = QueueConfigs::default().default;
+        let default = queue_config(&self["queues"], &defaults)?;
+
+        let mut sources = HashMap::new();
+        if let Some(configured) = self["sources"].as_object() {
+            for (name, source) in configured {
+                if let Some(queue) = source.get("queue") {
+                    sources.insert(name.clone(), queue_config(queue, &default)?);
+                }
+            }
+        }
+
+        Ok(QueueConfigs { default, sources })
+    }
+}
+
+fn queue_config(json: &JsonValue, defaults: &QueueConfig) -> Result<QueueConfig, Error> {
+    let capacity = json
+        .get("capacity")
+        .and_then(|x| x.as_u64())
+        .map_or(defaults.capacity, |x| x as usize);
+    if capacity == 0 {
+        return Err(Configuration(ERR_INVALID_QUEUE.to_string()));
+    }
+
+    let overflow = match json.get("overflow") {
+        Some(overflow) => serde_json::from_value(overflow.clone())?,
+        None => defaults.overflow,
+    };
+
+    let spill_dir = json
+        .get("spill_dir")
+        .and_then(|x| x.as_str())
+        .map_or_else(|| defaults.spill_dir.clone(), PathBuf::from);
+
+    Ok(QueueConfig {
+        capacity,
+        overflow,
+        spill_dir,
+    })
+}
+
+#[derive(Debug, Default)]
+struct QueueMetrics {
+    delivered: AtomicU64,
+    dropped: AtomicU64,
+    spilled: AtomicU64,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct QueueStats {
+    pub capacity: usize,
+    pub overflow: OverflowPolicy,
+    /// How many messages are waiting in memory...
+    pub queued: usize,
+    /// ...and on disk.
+    pub spilled_pending: usize,
+    pub delivered: u64,
+    /// Messages dropped by the overflow policy, or that could not be
+    /// delivered or spilled.
+    pub dropped: u64,
+    pub spilled: u64,
+}
+
+/// Spilled messages, in NDJSON files of up to a queue's capacity each, read
+/// back a file at a time, oldest first.
+#[derive(Debug)]
+struct Spill {
+    dir: PathBuf,
+    capacity: usize,
+    /// Each file and how many messages it holds.
+    files: tokio::sync::Mutex<VecDeque<(PathBuf, usize)>>,
+    next_file: AtomicU64,
+    pending: AtomicU64,
+}
+
+impl Spill {
+    /// Picks up any messages spilled by a previous run.
+    async fn open(dir: PathBuf, capacity: usize) -> Result<Self, Error> {
+        fs::create_dir_all(&dir).await?;
+
+        let mut paths: Vec<PathBuf> = Vec::new();
+        let mut entries = fs::read_dir(&dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            paths.push(entry.path());
+        }
+        paths.sort();
+
+        let mut files = VecDeque::new();
+        let mut pending = 0;
+        let mut next_file = 0;
+        for path in paths {
+            let Some(number) = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| stem.parse::<u64>().ok())
+            else {
+                continue;
+            };
+
+            let count = fs::read_to_string(&path).await?.lines().count();
+            pending += count as u64;
+            next_file = next_file.max(number + 1);
+            // Never appended to again, so that nothing is written after a
+            // line cut short.
+            files.push_back((path, capacity));
+        }
+
+        Ok(Self {
+            dir,
+            capacity,
+            files: tokio::sync::Mutex::new(files),
+            next_file: AtomicU64::new(next_file),
+            pending: AtomicU64::new(pending),
+        })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pending.load(Ordering::Relaxed) == 0
+    }
+
+    async fn push(&self, message: &ChatMessage) -> Result<(), Error> {
+        // Emotes are not serialized, so they are kept as fragments instead.
+        let mut message = message.clone();
+        message.fragments = Some(message.fragments());
+        let line = ExportFormat::Ndjson.encode(&message)?;
+
+        let mut files = self.files.lock().await;
+        if !matches!(files.back(), Some((_, count)) if *count < self.capacity) {
+            let number = self.next_file.fetch_add(1, Ordering::Relaxed);
+            files.push_back((self.dir.join(format!("{:020}.ndjson", number)), 0));
+        }
+        let (path, count) = files.back_mut().unwrap();
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .await?;
+        file.write_all(line.as_bytes()).await?;
+        *count += 1;
+        self.pending.fetch_add(1, Ordering::Relaxed);
+
+        Ok(())
+    }
+
+    /// Reads back and removes the oldest file of spilled messages.
+    async fn take(&self) -> Result<Vec<ChatMessage>, Error> {
+        let mut files = self.files.lock().await;
+        let Some((path, _)) = files.pop_front() else {
+            return Ok(Vec::new());
+        };
+
+        let text = fs::read_to_string(&path).await?;
+        fs::remove_file(&path).await?;
+
+        let mut messages = Vec::new();
+        for line in text.lines() {
+            self.pending.fetch_sub(1, Ordering::Relaxed);
+            match serde_json::from_str(line) {
+                Ok(message) => messages.push(message),
+                Err(err) => warn!("Skipping spilled message that could not be read: {}", err),
+            }
+        }
+
+        Ok(messages)
+    }
+}
+
+#[derive(Debug)]
+struct Shared {
+    config: QueueConfig,
+    messages: Mutex<VecDeque<ChatMessage>>,
+    spill: Option<Spill>,
+    not_empty: Notify,
+    not_full: Notify,
+    closed: AtomicBool,
+    metrics: QueueMetrics,
+}
+
+/// Buffers a source's messages on their way to a slower sink, such as
+/// storage, holding at most its capacity in memory. What happens to
+/// messages beyond that is up to its overflow policy.
+#[derive(Debug)]
+pub struct SourceQueue {
+    shared: Arc<Shared>,
+}
+
+impl SourceQueue {
+    /// Starts delivering the queue's messages to `sink` in order, until the
+    /// queue is dropped and has drained.
+    pub async fn spawn(
+        source: &str,
+        config: QueueConfig,
+        sink: Arc<dyn ChatSink>,
+    ) -> Result<Self, Error> {
+        let spill = match config.overflow {
+            OverflowPolicy::Spill => {
+                let dir = config.spill_dir.join(archive::file_name(source));
+                Some(Spill::open(dir, config.capacity).await?)
+            }
+            _ => None,
+        };
+
+        let shared = Arc::new(Shared {
+            messages: Mutex::new(VecDeque::with_capacity(config.capacity)),
+            config,
+            spill,
+            not_empty: Notify::new(),
+            not_full: Notify::new(),
+            closed: AtomicBool::new(false),
+            metrics: QueueMetrics::default(),
+        });
+        tokio::spawn(drain(shared.clone(), sink));
+
+        Ok(Self { shared })
+    }
+
+    pub fn stats(&self) -> QueueStats {
+        let shared = &self.shared;
+        let metrics = &shared.metrics;
+
+        QueueStats {
+            capacity: shared.config.capacity,
+            overflow: shared.config.overflow,
+            queued: shared.messages.lock().unwrap().len(),
+            spilled_pending: shared
+                .spill
+                .as_ref()
+                .map_or(0, |spill| spill.pending.load(Ordering::Relaxed) as usize),
+            delivered: metrics.delivered.load(Ordering::Relaxed),
+            dropped: metrics.dropped.load(Ordering::Relaxed),
+            spilled: metrics.spilled.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Queues the message in memory if there is room and nothing is waiting
+    /// on disk, which would have to go first, or drops a message as the
+    /// policy says. Hands the message back otherwise.
+    fn try_push(&self, message: ChatMessage) -> Option<ChatMessage> {
+        let shared = &self.shared;
+        if shared.spill.as_ref().is_some_and(|spill| !spill.is_empty()) {
+            return Some(message);
+        }
+
+        let mut messages = shared.messages.lock().unwrap();
+        if messages.len() < shared.config.capacity {
+            messages.push_back(message);
+            shared.not_empty.notify_one();
+            return None;
+        }
+
+        match shared.config.overflow {
+            OverflowPolicy::DropOldest => {
+                messages.pop_front();
+                messages.push_back(message);
+                shared.metrics.dropped.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+            OverflowPolicy::DropNewest => {
+                shared.metrics.dropped.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+            OverflowPolicy::Block | OverflowPolicy::Spill => Some(message),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl ChatSink for SourceQueue {
+    async fn send(&self, message: ChatMessage) -> Result<(), Error> {
+        let shared = &self.shared;
+        let mut message = message;
+
+        loop {
+            let not_full = shared.not_full.notified();
+            message = match self.try_push(message) {
+                Some(message) => message,
+                None => return Ok(()),
+            };
+
+            if let Some(spill) = &shared.spill {
+                return match spill.push(&message).await {
+                    Ok(()) => {
+                        shared.metrics.spilled.fetch_add(1, Ordering::Relaxed);
+                        shared.not_empty.notify_one();
+                        Ok(())
+                    }
+                    Err(err) => {
+                        shared.metrics.dropped.fetch_add(1, Ordering::Relaxed);
+                        Err(err)
+                    }
+                };
+            }
+
+            not_full.await;
+        }
+    }
+}
+
+impl Drop for SourceQueue {
+    fn drop(&mut self) {
+        self.shared.closed.store(true, Ordering::Relaxed);
+        self.shared.not_empty.notify_one();
+    }
+}
+
+/// Delivers the queue's messages in memory, then those on disk, one file at
+/// a time.
+async fn drain(shared: Arc<Shared>, sink: Arc<dyn ChatSink>) {
+    loop {
+        let not_empty = shared.not_empty.notified();
+        let next = shared.messages.lock().unwrap().pop_front();
+        if let Some(message) = next {
+            shared.not_full.notify_one();
+            deliver(&shared, sink.as_ref(), message).await;
+            continue;
+        }
+
+        if let Some(spill) = shared.spill.as_ref().filter(|spill| !spill.is_empty()) {
+            match spill.take().await {
+                Ok(messages) => {
+                    for message in messages {
+                        deliver(&shared, sink.as_ref(), message).await;
+                    }
+                }
+                Err(err) => error!("Could not read back spilled messages: {}", err),
+            }
+            continue;
+        }
+
+        if shared.closed.load(Ordering::Relaxed) {
+            break;
+        }
+        not_empty.await;
+    }
+
+    debug!("Source queue drained");
+}
+
+async fn deliver(shared: &Shared, sink: &dyn ChatSink, message: ChatMessage) {
+    match sink.send(message).await {
+        Ok(()) => shared.metrics.delivered.fetch_add(1, Ordering::Relaxed),
+        Err(err) => {
+            error!("Could not deliver queued message: {}", err);
+            shared.metrics.dropped.fetch_add(1, Ordering::Relaxed)
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use fake::{Fake, Faker};
+
+    use super::*;
+
+    /// Takes messages only when told to.
+    struct GatedSink {
+        messages: Mutex<Vec<ChatMessage>>,
+        open: tokio::sync::Semaphore,
+    }
+
+    impl GatedSink {
+        fn new() -> Arc<Self> {
+            Arc::new(Self {
+                messages: Mutex::new(Vec::new()),
+                open: tokio::sync::Semaphore::new(0),
+            })
+        }
+
+        fn texts(&self) -> Vec<String> {
+            let messages = self.messages.lock().unwrap();
+            messages.iter().map(|m| m.text.clone()).collect()
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl ChatSink for GatedSink {
+        async fn send(&self, message: ChatMessage) -> Result<(), Error> {
+            self.open.acquire().await.unwrap().forget();
+            self.messages.lock().unwrap().push(message);
+            Ok(())
+        }
+    }
+
+    fn message(text: &str) -> ChatMessage {
+        ChatMessage {
+            text: text.to_string(),
+            ..Faker.fake()
+        }
+    }
+
+    fn config(overflow: OverflowPolicy, spill_dir: PathBuf) -> QueueConfig {
+        QueueConfig {
+            capacity: 2,
+            overflow,
+            spill_dir,
+        }
+    }
+
+    /// Sends one, two, ... while the sink holds on to the first.
+    async fn fill(queue: &SourceQueue, count: usize) {
+        let texts = ["one", "two", "three", "four", "five"];
+        queue.send(message(texts[0])).await.unwrap();
+        while queue.stats().queued > 0 {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        for text in &texts[1..count] {
+            queue.send(message(text)).await.unwrap();
+        }
+    }
+
+    async fn wait_for(sink: &GatedSink, count: usize) {
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while sink.texts().len() < count {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_overflow_drops() {
+        let dir = std::env::temp_dir();
+
+        let sink = GatedSink::new();
+        let settings = config(OverflowPolicy::DropOldest, dir.clone());
+        let queue = SourceQueue::spawn("test", settings, sink.clone())
+            .await
+            .unwrap();
+        fill(&queue, 5).await;
+        sink.open.add_permits(5);
+        wait_for(&sink, 3).await;
+        assert_eq!(sink.texts(), vec!["one", "four", "five"]);
+        assert_eq!(queue.stats().dropped, 2);
+
+        let sink = GatedSink::new();
+        let settings = config(OverflowPolicy::DropNewest, dir);
+        let queue = SourceQueue::spawn("test", settings, sink.clone())
+            .await
+            .unwrap();
+        fill(&queue, 5).await;
+        sink.open.add_permits(5);
+        wait_for(&sink, 3).await;
+        assert_eq!(sink.texts(), vec!["one", "two", "three"]);
+        assert_eq!(queue.stats().dropped, 2);
+    }
+
+    #[tokio::test]
+    async fn test_overflow_spills_in_order() {
+        let dir = std::env::temp_dir().join(format!("spill-test-{}", std::process::id()));
+
+        let sink = GatedSink::new();
+        let settings = config(OverflowPolicy::Spill, dir.clone());
+        let queue = SourceQueue::spawn("test", settings, sink.clone())
+            .await
+            .unwrap();
+        fill(&queue, 5).await;
+        sink.open.add_permits(5);
+        wait_for(&sink, 5).await;
+
+        assert_eq!(sink.texts(), vec!["one", "two", "three", "four", "five"]);
+        let stats = queue.stats();
+        assert_eq!((stats.spilled, stats.dropped, stats.delivered), (2, 0, 5));
+
+        fs::remove_dir_all(dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_overflow_blocks() {
+        let sink = GatedSink::new();
+        let settings = config(OverflowPolicy::Block, std::env::temp_dir());
+        let queue = SourceQueue::spawn("test", settings, sink.clone())
+            .await
+            .unwrap();
+
+        fill(&queue, 3).await;
+        let blocked = tokio::time::timeout(Duration::from_millis(50), queue.send(message("four")));
+        assert!(blocked.await.is_err());
+
+        sink.open.add_permits(4);
+        queue.send(message("five")).await.unwrap();
+        wait_for(&sink, 4).await;
+        assert_eq!(sink.texts(), vec!["one", "two", "three", "five"]);
+        assert_eq!(queue.stats().dropped, 0);
+    }
+}
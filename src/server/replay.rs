@@ -0,0 +1,255 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::stream;
+use futures::StreamExt;
+use log::error;
+use serde::{Deserialize, Deserializer};
+use time::OffsetDateTime;
+use tokio::time::Instant;
+
+use crate::dal::chat_message_repository::ChatMessageRepository;
+use crate::error::Error;
+use crate::models::chat_message::ChatMessage;
+use crate::models::chat_stream::ChatStream;
+use crate::server::hub::{ChannelEvent, Hub};
+use crate::server::supervisor::{ChatSink, ChatSource};
+use crate::utils::clock::Clock;
+
+/// Replays of a channel are delivered on the channel of this name followed
+/// by the channel's.
+pub const REPLAY_PREFIX: &str = "replay:";
+/// Slower replays would wait years between messages a minute apart.
+const MIN_SPEED: f64 = 0.001;
+const ERR_INVALID_SPEED: &str = "speed must be a number of at least 0.001 or \"max\"";
+
+/// Where replays of `channel` are delivered.
+pub fn replay_channel(channel: &str) -> String {
+    format!("{}{}", REPLAY_PREFIX, channel)
+}
+
+/// The channel replayed on `channel`, if it is a replay channel.
+pub fn replayed_channel(channel: &str) -> Option<&str> {
+    channel.strip_prefix(REPLAY_PREFIX)
+}
+
+/// How fast a replay runs compared to the original.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplaySpeed {
+    Factor(f64),
+    /// Without waiting between messages.
+    Max,
+}
+
+impl Default for ReplaySpeed {
+    fn default() -> Self {
+        ReplaySpeed::Factor(1.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for ReplaySpeed {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Speed {
+            Factor(f64),
+            Named(String),
+        }
+
+        match Speed::deserialize(deserializer)? {
+            Speed::Factor(factor) if factor.is_finite() && factor >= MIN_SPEED => {
+                Ok(ReplaySpeed::Factor(factor))
+            }
+            Speed::Named(name) if name == "max" => Ok(ReplaySpeed::Max),
+            _ => Err(serde::de::Error::custom(ERR_INVALID_SPEED)),
+        }
+    }
+}
+
+impl ReplaySpeed {
+    /// How long after the start of the replay a message sent `elapsed` after
+    /// the first one is due, saturating rather than overflowing.
+    fn scale(&self, elapsed: time::Duration) -> Duration {
+        let elapsed = Duration::try_from(elapsed).unwrap_or_default();
+        match self {
+            ReplaySpeed::Factor(factor) => {
+                Duration::try_from_secs_f64(elapsed.as_secs_f64() / factor).unwrap_or(Duration::MAX)
+            }
+            ReplaySpeed::Max => Duration::ZERO,
+        }
+    }
+}
+
+/// Plays back a channel's messages from `[from, to)` in the order they were
+/// sent, keeping the gaps between them scaled by the speed.
+#[derive(Debug)]
+pub struct ReplaySource {
+    repo: Arc<ChatMessageRepository>,
+    channel: String,
+    from: Option<OffsetDateTime>,
+    to: Option<OffsetDateTime>,
+    speed: ReplaySpeed,
+}
+
+impl ReplaySource {
+    pub fn new(
+        repo: Arc<ChatMessageRepository>,
+        channel: String,
+        from: Option<OffsetDateTime>,
+        to: Option<OffsetDateTime>,
+        speed: ReplaySpeed,
+    ) -> Self {
+        Self {
+            repo,
+            channel,
+            from,
+            to,
+            speed,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl ChatSource for ReplaySource {
+    async fn open(&self, clock: Arc<dyn Clock>) -> Result<ChatStream<'static>, Error> {
+        let messages = self
+            .repo
+            .stream_messages_by_timestamp(&self.channel, self.from, self.to)
+            .await?;
+
+        Ok(ChatStream::new(pace(messages, self.speed), clock))
+    }
+
+    fn is_finite(&self) -> bool {
+        true
+    }
+
+    fn is_paced(&self) -> bool {
+        true
+    }
+}
+
+/// Holds each message back until it is due, measured from when the first
+/// one came out, so that waits do not add up. A message that could not be
+/// read ends the stream.
+fn pace<S>(messages: S, speed: ReplaySpeed) -> impl stream::Stream<Item = ChatMessage>
+where
+    S: stream::Stream<Item = Result<ChatMessage, Error>> + Send + 'static,
+{
+    let start: Option<(Instant, OffsetDateTime)> = None;
+
+    stream::unfold(
+        (Box::pin(messages), start),
+        move |(mut messages, start)| async move {
+            let message = match messages.next().await? {
+                Ok(message) => message,
+                Err(err) => {
+                    error!("Could not read message to replay: {}", err);
+                    return None;
+                }
+            };
+
+            let (started, first) = start.unwrap_or((Instant::now(), message.timestamp));
+            tokio::time::sleep_until(started + speed.scale(message.timestamp - first)).await;
+
+            Some((message, (messages, Some((started, first)))))
+        },
+    )
+}
+
+/// Delivers messages to the websocket subscribers of a channel, without
+/// storing them.
+#[derive(Debug)]
+pub struct HubSink {
+    hub: Arc<Hub>,
+    channel: String,
+}
+
+impl HubSink {
+    pub fn new(hub: Arc<Hub>, channel: String) -> Self {
+        Self { hub, channel }
+    }
+}
+
+#[async_trait::async_trait]
+impl ChatSink for HubSink {
+    /// The message is moved to the sink's channel. It loses its sequence
+    /// number, which only means something in the channel it was stored in.
+    async fn send(&self, mut message: ChatMessage) -> Result<(), Error> {
+        message.channel = self.channel.clone();
+        message.seq = None;
+
+        let event = ChannelEvent::Message {
+            channel: self.channel.clone(),
+            message,
+        };
+        self.hub.publish(&self.channel, event);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fake::{Fake, Faker};
+    use serde_json::json;
+
+    use super::*;
+
+    fn message(millis: i64) -> Result<ChatMessage, Error> {
+        Ok(ChatMessage {
+            timestamp: OffsetDateTime::UNIX_EPOCH + time::Duration::milliseconds(millis),
+            ..Faker.fake()
+        })
+    }
+
+    #[test]
+    fn test_replay_channel() {
+        assert_eq!(replay_channel("chan"), "replay:chan");
+        assert_eq!(replayed_channel("replay:chan"), Some("chan"));
+        assert_eq!(replayed_channel("chan"), None);
+    }
+
+    #[test]
+    fn test_parse_speed() {
+        let speed = |json| serde_json::from_value::<ReplaySpeed>(json);
+
+        assert_eq!(speed(json!(10)).unwrap(), ReplaySpeed::Factor(10.0));
+        assert_eq!(speed(json!(0.5)).unwrap(), ReplaySpeed::Factor(0.5));
+        assert_eq!(speed(json!("max")).unwrap(), ReplaySpeed::Max);
+        assert_eq!(speed(json!(0.001)).unwrap(), ReplaySpeed::Factor(0.001));
+        assert!(speed(json!(0)).is_err());
+        assert!(speed(json!(1e-20)).is_err());
+        assert!(speed(json!("fast")).is_err());
+    }
+
+    #[test]
+    fn test_scale_saturates() {
+        let elapsed = time::Duration::seconds(i64::MAX);
+        assert_eq!(ReplaySpeed::Factor(0.001).scale(elapsed), Duration::MAX);
+        assert_eq!(
+            ReplaySpeed::Factor(2.0).scale(time::Duration::seconds(10)),
+            Duration::from_secs(5)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_pace_scales_gaps() {
+        let messages = || stream::iter(vec![message(0), message(100), message(300)]);
+
+        let started = Instant::now();
+        let replayed: Vec<_> = pace(messages(), ReplaySpeed::Factor(10.0)).collect().await;
+        let elapsed = started.elapsed();
+        assert_eq!(replayed.len(), 3);
+        assert!(elapsed >= Duration::from_millis(30), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_millis(300), "{:?}", elapsed);
+
+        let started = Instant::now();
+        let replayed: Vec<_> = pace(messages(), ReplaySpeed::Max).collect().await;
+        assert_eq!(replayed.len(), 3);
+        assert!(started.elapsed() < Duration::from_millis(30));
+    }
+}
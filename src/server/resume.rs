@@ -0,0 +1,150 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+use crate::config::Config;
+use crate::error::Error;
+use crate::models::chat_message::ChatMessage;
+
+const DEFAULT_BUFFER_SIZE: u64 = 200;
+const DEFAULT_MAX_REPLAY: u64 = 1000;
+
+/// Settings for websocket sessions resuming where they left off.
+#[derive(Debug, Clone, Copy)]
+pub struct ResumeConfig {
+    /// How many of each channel's newest messages are kept in memory.
+    pub buffer_size: usize,
+    /// The most missed messages replayed to a resuming session. Past that,
+    /// it is told the gap is too large instead.
+    pub max_replay: usize,
+}
+
+impl Config {
+    pub(crate) fn resume(&self) -> Result<ResumeConfig, Error> {
+        let json = self["resume"].clone();
+
+        let buffer_size = json
+            .get("buffer_size")
+            .and_then(|x| x.as_u64())
+            .unwrap_or(DEFAULT_BUFFER_SIZE);
+
+        let max_replay = json
+            .get("max_replay")
+            .and_then(|x| x.as_u64())
+            .unwrap_or(DEFAULT_MAX_REPLAY);
+
+        Ok(ResumeConfig {
+            buffer_size: buffer_size as usize,
+            max_replay: max_replay as usize,
+        })
+    }
+}
+
+/// The newest messages of each channel seen on this instance, so that most
+/// sessions can resume without reading the database.
+#[derive(Debug)]
+pub struct RecentMessages {
+    config: ResumeConfig,
+    /// Each channel's messages in order, without gaps between them.
+    channels: Mutex<HashMap<String, VecDeque<ChatMessage>>>,
+}
+
+impl RecentMessages {
+    pub fn new(config: ResumeConfig) -> Self {
+        Self {
+            config,
+            channels: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn max_replay(&self) -> usize {
+        self.config.max_replay
+    }
+
+    /// Adds a message delivered live. Messages must arrive in order; after a
+    /// gap the channel's buffer starts over from this message.
+    pub fn push(&self, message: &ChatMessage) {
+        let Some(seq) = message.seq else {
+            return;
+        };
+        if self.config.buffer_size == 0 {
+            return;
+        }
+
+        let mut channels = self.channels.lock().unwrap();
+        let buffer = channels.entry(message.channel.clone()).or_default();
+
+        match buffer.back().and_then(|last| last.seq) {
+            Some(last) if seq <= last => return,
+            Some(last) if seq > last + 1 => buffer.clear(),
+            _ => {}
+        }
+
+        if buffer.len() == self.config.buffer_size {
+            buffer.pop_front();
+        }
+        buffer.push_back(message.clone());
+    }
+
+    /// The channel's messages after `after_seq`, or `None` if the buffer
+    /// does not reach back that far.
+    pub fn after(&self, channel: &str, after_seq: i64) -> Option<Vec<ChatMessage>> {
+        let channels = self.channels.lock().unwrap();
+        let buffer = channels.get(channel)?;
+
+        let first = buffer.front()?.seq?;
+        if after_seq + 1 < first {
+            return None;
+        }
+
+        let missed = buffer
+            .iter()
+            .filter(|message| message.seq > Some(after_seq))
+            .cloned()
+            .collect();
+
+        Some(missed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fake::{Fake, Faker};
+
+    use super::*;
+
+    fn message(seq: i64) -> ChatMessage {
+        let mut message = Faker.fake::<ChatMessage>();
+        message.channel = "chan".to_string();
+        message.seq = Some(seq);
+        message
+    }
+
+    fn seqs(messages: Option<Vec<ChatMessage>>) -> Option<Vec<i64>> {
+        messages.map(|messages| messages.iter().map(|m| m.seq.unwrap()).collect())
+    }
+
+    #[test]
+    fn test_recent_messages_replay_what_they_hold() {
+        let recent = RecentMessages::new(ResumeConfig {
+            buffer_size: 3,
+            max_replay: 10,
+        });
+        assert_eq!(seqs(recent.after("chan", 0)), None);
+
+        for seq in 1..=4 {
+            recent.push(&message(seq));
+        }
+        recent.push(&message(3));
+
+        assert_eq!(seqs(recent.after("chan", 1)), Some(vec![2, 3, 4]));
+        assert_eq!(seqs(recent.after("chan", 3)), Some(vec![4]));
+        assert_eq!(seqs(recent.after("chan", 4)), Some(vec![]));
+        assert_eq!(seqs(recent.after("chan", 0)), None);
+        assert_eq!(seqs(recent.after("other", 0)), None);
+
+        // Nothing between 4 and 7 was seen, so only 7 onwards is known.
+        recent.push(&message(7));
+        assert_eq!(seqs(recent.after("chan", 6)), Some(vec![7]));
+        assert_eq!(seqs(recent.after("chan", 4)), None);
+    }
+}
@@ -1,8 +1,47 @@
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 
+use crate::dal::batch_writer::BatchWriter;
+use crate::dal::channel_repository::ChannelRepository;
 use crate::dal::chat_message_repository::ChatMessageRepository;
+use crate::dal::direct_message_repository::DirectMessageRepository;
+use crate::dal::import_repository::ImportRepository;
+use crate::dal::mention_repository::MentionRepository;
+use crate::dal::reaction_repository::ReactionRepository;
+use crate::dal::retention_repository::RetentionRepository;
+use crate::dal::stats_repository::StatsRepository;
+use crate::server::ephemeral::{EphemeralConfig, Typing};
+use crate::server::hub::Hub;
+use crate::server::presence::Presence;
+use crate::server::resume::RecentMessages;
+use crate::server::supervisor::Supervisor;
+use crate::utils::clock::Clock;
 
 #[derive(Debug, Clone)]
 pub struct ServerState {
     pub repo: Arc<ChatMessageRepository>,
+    /// Batches writes of new messages to `repo`.
+    pub writer: BatchWriter,
+    pub channels: Arc<ChannelRepository>,
+    pub reactions: Arc<ReactionRepository>,
+    pub direct_messages: Arc<DirectMessageRepository>,
+    pub mentions: Arc<MentionRepository>,
+    pub stats: Arc<StatsRepository>,
+    pub retention: Arc<RetentionRepository>,
+    /// Opens a connection of its own for each import.
+    pub importer: Arc<ImportRepository>,
+    pub hub: Arc<Hub>,
+    /// Who is subscribed to each channel over websockets.
+    pub presence: Arc<Presence>,
+    pub typing: Arc<Typing>,
+    pub ephemeral: Arc<EphemeralConfig>,
+    /// Each channel's newest messages, for websocket sessions to resume from.
+    pub recent: Arc<RecentMessages>,
+    /// Keeps the chat sources feeding this instance running.
+    pub supervisor: Arc<Supervisor>,
+    /// Where the API and background tasks get the time from, in UTC.
+    pub clock: Arc<dyn Clock>,
+    /// Bearer tokens and the usernames they authenticate as.
+    pub tokens: Arc<HashMap<String, String>>,
+    pub admins: Arc<HashSet<String>>,
 }
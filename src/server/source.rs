@@ -0,0 +1,373 @@
+use std::io::SeekFrom;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use actix_web::web::BytesMut;
+use futures::stream::{self, StreamExt};
+use log::warn;
+use serde::Deserialize;
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+use crate::config::Config;
+use crate::error::Error;
+use crate::error::Error::Configuration;
+use crate::models::chat_stream::ChatStream;
+use crate::server::merge::{MergeConfig, MergedSource};
+use crate::server::supervisor::ChatSource;
+use crate::utils::clock::Clock;
+use crate::utils::import::{self, ImportFormat};
+
+/// How long a followed file is left alone after reaching its end.
+const FOLLOW_POLL_INTERVAL: Duration = Duration::from_millis(500);
+const READ_CHUNK_SIZE: usize = 64 * 1024;
+
+/// A source opened at startup, as declared in `sources` in the config. Its
+/// `pipeline` and `queue` are read along with it by the supervisor.
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SourceConfig {
+    /// A log file in one of the import formats.
+    File {
+        path: PathBuf,
+        /// Guessed from the file's extension if not given.
+        format: Option<ImportFormat>,
+        /// The channel of messages that don't name one themselves.
+        channel: Option<String>,
+        /// Whether to keep reading the lines appended to the file from its
+        /// end on, rather than reading it once.
+        #[serde(default)]
+        follow: bool,
+    },
+    /// Redundant sources of the same messages, read as one with duplicates
+    /// dropped, as configured in `merge`.
+    Merged { sources: Vec<SourceConfig> },
+}
+
+impl SourceConfig {
+    pub fn build(&self, merge: &MergeConfig) -> Arc<dyn ChatSource> {
+        match self {
+            SourceConfig::File {
+                path,
+                format,
+                channel,
+                follow,
+            } => {
+                let format =
+                    format.unwrap_or_else(|| ImportFormat::from_path(&path.to_string_lossy()));
+                Arc::new(FileSource::new(
+                    path.clone(),
+                    format,
+                    channel.clone(),
+                    *follow,
+                ))
+            }
+            SourceConfig::Merged { sources } => {
+                let sources = sources.iter().map(|source| source.build(merge)).collect();
+                Arc::new(MergedSource::new(sources, merge.clone()))
+            }
+        }
+    }
+}
+
+impl Config {
+    /// Each source in `sources` by name, such as
+    /// `{ "sources": { "backup": { "kind": "file", "path": "chat.log" } } }`.
+    pub(crate) fn sources(&self) -> Result<Vec<(String, SourceConfig)>, Error> {
+        let Some(sources) = self["sources"].as_object() else {
+            return Ok(vec![]);
+        };
+
+        sources
+            .iter()
+            .map(|(name, source)| {
+                let source = SourceConfig::deserialize(source)
+                    .map_err(|err| Configuration(format!("sources.{}: {}", name, err)))?;
+                Ok((name.clone(), source))
+            })
+            .collect()
+    }
+}
+
+/// Reads messages from a log file, going on from the last complete line
+/// read when it is reopened. Lines that cannot be parsed are skipped.
+#[derive(Debug)]
+pub struct FileSource {
+    path: PathBuf,
+    format: ImportFormat,
+    channel: Option<String>,
+    follow: bool,
+    /// How far into the file the lines read so far go, once it was opened.
+    offset: Arc<Mutex<Option<u64>>>,
+}
+
+impl FileSource {
+    pub fn new(path: PathBuf, format: ImportFormat, channel: Option<String>, follow: bool) -> Self {
+        Self {
+            path,
+            format,
+            channel,
+            follow,
+            offset: Arc::new(Mutex::new(None)),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl ChatSource for FileSource {
+    async fn open(&self, clock: Arc<dyn Clock>) -> Result<ChatStream<'static>, Error> {
+        let mut file = File::open(&self.path).await?;
+        let len = file.metadata().await?.len();
+
+        let start = {
+            let mut offset = self.offset.lock().unwrap();
+            let start = match *offset {
+                Some(offset) if offset <= len => offset,
+                // The file was truncated or replaced since.
+                Some(_) => 0,
+                None if self.follow => len,
+                None => 0,
+            };
+            *offset = Some(start);
+            start
+        };
+        file.seek(SeekFrom::Start(start)).await?;
+
+        let reader = LineReader {
+            file,
+            buffer: BytesMut::new(),
+            offset: self.offset.clone(),
+            follow: self.follow,
+        };
+        let lines = stream::unfold(reader, |mut reader| async move {
+            let line = reader.next_line().await?;
+            Some((line, reader))
+        });
+
+        let (format, channel) = (self.format, self.channel.clone());
+        let path = self.path.display().to_string();
+        let messages = lines.filter_map(move |line| {
+            let message = match import::parse_line(format, &line, channel.as_deref()) {
+                Ok(message) => message,
+                Err(err) => {
+                    warn!("Skipping a line of {}: {}", path, err);
+                    None
+                }
+            };
+            futures::future::ready(message)
+        });
+
+        Ok(ChatStream::new(messages, clock))
+    }
+
+    fn is_finite(&self) -> bool {
+        !self.follow
+    }
+}
+
+struct LineReader {
+    file: File,
+    buffer: BytesMut,
+    offset: Arc<Mutex<Option<u64>>>,
+    follow: bool,
+}
+
+impl LineReader {
+    /// The next line, waiting for more to be written if following. Ends at
+    /// the end of the file otherwise, or if it cannot be read.
+    async fn next_line(&mut self) -> Option<String> {
+        loop {
+            if let Some(end) = self.buffer.iter().position(|b| *b == b'\n') {
+                return Some(self.take(end + 1));
+            }
+
+            self.buffer.reserve(READ_CHUNK_SIZE);
+            match self.file.read_buf(&mut self.buffer).await {
+                // A partial line is left for when the rest is written.
+                Ok(0) if self.follow => tokio::time::sleep(FOLLOW_POLL_INTERVAL).await,
+                Ok(0) if self.buffer.is_empty() => return None,
+                Ok(0) => return Some(self.take(self.buffer.len())),
+                Ok(_) => {}
+                Err(err) => {
+                    warn!("Could not read a source file: {}", err);
+                    return None;
+                }
+            }
+        }
+    }
+
+    fn take(&mut self, len: usize) -> String {
+        let line = self.buffer.split_to(len);
+        if let Some(offset) = self.offset.lock().unwrap().as_mut() {
+            *offset += len as u64;
+        }
+
+        import::decode_line(&line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::FutureExt;
+    use serde_json::json;
+    use tokio::io::AsyncWriteExt;
+
+    use super::*;
+    use crate::utils::clock::SystemClock;
+
+    const LINE: &str = "[2023-01-01 00:00:00] user: hi\n";
+
+    fn path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("source-test-{}-{}.log", name, std::process::id()))
+    }
+
+    async fn append(path: &PathBuf, text: &str) {
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await
+            .unwrap();
+        file.write_all(text.as_bytes()).await.unwrap();
+    }
+
+    #[test]
+    fn test_parse_source_config() {
+        let source: SourceConfig =
+            serde_json::from_value(json!({ "kind": "file", "path": "chat.log", "pipeline": [] }))
+                .unwrap();
+        assert_eq!(
+            source,
+            SourceConfig::File {
+                path: PathBuf::from("chat.log"),
+                format: None,
+                channel: None,
+                follow: false,
+            }
+        );
+
+        assert!(serde_json::from_value::<SourceConfig>(json!({ "path": "chat.log" })).is_err());
+
+        let source: SourceConfig = serde_json::from_value(json!({
+            "kind": "merged",
+            "sources": [{ "kind": "file", "path": "a.log", "follow": true }]
+        }))
+        .unwrap();
+        let SourceConfig::Merged { sources } = source else {
+            panic!("not a merged source");
+        };
+        assert_eq!(sources.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_merged_sources_drop_duplicates() {
+        let path = path("merged");
+        let _ = tokio::fs::remove_file(&path).await;
+        append(&path, &format!("{}{}", LINE, LINE.replace("hi", "bye"))).await;
+
+        let file = SourceConfig::File {
+            path: path.clone(),
+            format: None,
+            channel: Some("chan".to_string()),
+            follow: false,
+        };
+        let merge = MergeConfig {
+            reorder_window: Duration::from_millis(10),
+            max_buffered: 100,
+            dedupe_window: Duration::from_secs(5),
+            max_source_ids: 100,
+        };
+        let source = SourceConfig::Merged {
+            sources: vec![file.clone(), file],
+        }
+        .build(&merge);
+        assert!(source.is_finite());
+
+        let messages: Vec<_> = source
+            .open(Arc::new(SystemClock))
+            .await
+            .unwrap()
+            .collect()
+            .await;
+        let texts: Vec<_> = messages
+            .iter()
+            .map(|message| message.text.as_str())
+            .collect();
+        assert_eq!(texts, ["hi", "bye"]);
+
+        tokio::fs::remove_file(&path).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_file_source_reads_once() {
+        let path = path("once");
+        let _ = tokio::fs::remove_file(&path).await;
+        append(
+            &path,
+            &format!("{}not a message\n{}", LINE, LINE.trim_end()),
+        )
+        .await;
+
+        let source = FileSource::new(
+            path.clone(),
+            ImportFormat::Txt,
+            Some("chan".to_string()),
+            false,
+        );
+        assert!(source.is_finite());
+
+        let messages: Vec<_> = source
+            .open(Arc::new(SystemClock))
+            .await
+            .unwrap()
+            .collect()
+            .await;
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].channel, "chan");
+
+        // Reopening goes on from where the last stream stopped.
+        let mut stream = source.open(Arc::new(SystemClock)).await.unwrap();
+        assert!(stream.next().await.is_none());
+
+        tokio::fs::remove_file(&path).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_file_source_follows_appended_lines() {
+        let path = path("follow");
+        let _ = tokio::fs::remove_file(&path).await;
+        append(&path, LINE).await;
+
+        let source = FileSource::new(
+            path.clone(),
+            ImportFormat::Txt,
+            Some("chan".to_string()),
+            true,
+        );
+        assert!(!source.is_finite());
+
+        // What was there before the file was first opened is skipped.
+        let mut stream = source.open(Arc::new(SystemClock)).await.unwrap();
+        assert!(stream.next().now_or_never().is_none());
+
+        append(&path, "[2023-01-01 00:00:01] user: ").await;
+        append(&path, "again\n").await;
+        let message = tokio::time::timeout(Duration::from_secs(5), stream.next())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(message.text, "again");
+
+        drop(stream);
+        append(&path, LINE).await;
+        let mut stream = source.open(Arc::new(SystemClock)).await.unwrap();
+        let message = tokio::time::timeout(Duration::from_secs(5), stream.next())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(message.text, "hi");
+
+        tokio::fs::remove_file(&path).await.unwrap();
+    }
+}
@@ -0,0 +1,553 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures::StreamExt;
+use log::{error, info, warn};
+use serde::Serialize;
+use time::OffsetDateTime;
+use tokio::sync::Notify;
+
+use crate::config::Config;
+use crate::dal::batch_writer::BatchWriter;
+use crate::error::Error;
+use crate::error::Error::Configuration;
+use crate::models::chat_message::ChatMessage;
+use crate::models::chat_stream::ChatStream;
+use crate::server::pipeline::{Pipeline, StageConfig};
+use crate::server::queue::{QueueConfigs, QueueStats, SourceQueue};
+use crate::utils::clock::Clock;
+
+const DEFAULT_STALL_AFTER_SECS: u64 = 60;
+const DEFAULT_CHECK_INTERVAL_SECS: u64 = 5;
+const DEFAULT_MIN_BACKOFF_MS: u64 = 1000;
+const DEFAULT_MAX_BACKOFF_MS: u64 = 60_000;
+const ERR_INVALID_SUPERVISOR: &str =
+    "supervisor durations must be positive, and min_backoff_ms at most max_backoff_ms";
+
+#[derive(Debug, Clone)]
+pub struct SupervisorConfig {
+    /// A stream that has not yielded a message for this long is stalled.
+    pub stall_after: Duration,
+    /// How often streams are checked for stalls.
+    pub check_interval: Duration,
+    /// How long to wait before the first restart of a source...
+    pub min_backoff: Duration,
+    /// ...doubling with every restart that follows without a message in
+    /// between, up to this.
+    pub max_backoff: Duration,
+    /// The stages each source's messages go through, by source name.
+    pub pipelines: HashMap<String, Vec<StageConfig>>,
+    /// The queue between each source and its sink.
+    pub queues: QueueConfigs,
+}
+
+impl Config {
+    pub(crate) fn supervisor(&self) -> Result<SupervisorConfig, Error> {
+        let json = self["supervisor"].clone();
+        let get =
+            |key: &str, default: u64| json.get(key).and_then(|x| x.as_u64()).unwrap_or(default);
+
+        let config = SupervisorConfig {
+            stall_after: Duration::from_secs(get("stall_after_secs", DEFAULT_STALL_AFTER_SECS)),
+            check_interval: Duration::from_secs(get(
+                "check_interval_secs",
+                DEFAULT_CHECK_INTERVAL_SECS,
+            )),
+            min_backoff: Duration::from_millis(get("min_backoff_ms", DEFAULT_MIN_BACKOFF_MS)),
+            max_backoff: Duration::from_millis(get("max_backoff_ms", DEFAULT_MAX_BACKOFF_MS)),
+            pipelines: self.pipelines()?,
+            queues: self.queues()?,
+        };
+
+        let durations = [
+            config.stall_after,
+            config.check_interval,
+            config.min_backoff,
+        ];
+        if durations.iter().any(Duration::is_zero) || config.min_backoff > config.max_backoff {
+            return Err(Configuration(ERR_INVALID_SUPERVISOR.to_string()));
+        }
+
+        Ok(config)
+    }
+
+    /// The `pipeline` of each source in `sources`, such as
+    /// `{ "sources": { "twitch": { "pipeline": [{ "stage": "trim_text" }] } } }`.
+    fn pipelines(&self) -> Result<HashMap<String, Vec<StageConfig>>, Error> {
+        let Some(sources) = self["sources"].as_object() else {
+            return Ok(HashMap::new());
+        };
+
+        let mut pipelines = HashMap::new();
+        for (name, source) in sources {
+            if let Some(stages) = source.get("pipeline") {
+                let stages = serde_json::from_value(stages.clone())?;
+                pipelines.insert(name.clone(), stages);
+            }
+        }
+
+        Ok(pipelines)
+    }
+}
+
+/// Somewhere chat messages come from, which can be reopened when its stream
+/// fails.
+#[async_trait::async_trait]
+pub trait ChatSource: Send + Sync {
+    async fn open(&self, clock: Arc<dyn Clock>) -> Result<ChatStream<'static>, Error>;
+
+    /// Whether the source runs out of messages by itself. A finite source
+    /// whose stream ends is stopped rather than restarted.
+    fn is_finite(&self) -> bool {
+        false
+    }
+
+    /// Whether the source waits between messages on purpose, in which case
+    /// it is never considered stalled.
+    fn is_paced(&self) -> bool {
+        false
+    }
+}
+
+/// Where a supervised stream's messages go.
+#[async_trait::async_trait]
+pub trait ChatSink: Send + Sync {
+    async fn send(&self, message: ChatMessage) -> Result<(), Error>;
+}
+
+#[async_trait::async_trait]
+impl ChatSink for BatchWriter {
+    /// Queues the message for storage without waiting for it to be written.
+    async fn send(&self, message: ChatMessage) -> Result<(), Error> {
+        self.submit(message).await.map(|_| ())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StreamState {
+    Running,
+    /// Waiting to restart after the stream went quiet for too long.
+    Stalled,
+    /// Waiting to restart after the stream failed, or reopening it.
+    Restarting,
+    Stopped,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct StreamStatus {
+    pub name: String,
+    pub state: StreamState,
+    /// When the current stream was opened.
+    pub created_at: Option<OffsetDateTime>,
+    /// When the current stream last yielded a message.
+    pub updated_at: Option<OffsetDateTime>,
+    pub restarts: u32,
+    pub last_error: Option<String>,
+    pub queue: Option<QueueStats>,
+}
+
+impl StreamStatus {
+    fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            state: StreamState::Restarting,
+            created_at: None,
+            updated_at: None,
+            restarts: 0,
+            last_error: None,
+            queue: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Supervised {
+    status: StreamStatus,
+    stop: Arc<Notify>,
+    /// Missing while the queue is being set up.
+    queue: Option<Arc<SourceQueue>>,
+}
+
+impl Supervised {
+    fn status(&self) -> StreamStatus {
+        StreamStatus {
+            queue: self.queue.as_ref().map(|queue| queue.stats()),
+            ..self.status.clone()
+        }
+    }
+}
+
+/// Why a supervised stream was given up on.
+enum Outcome {
+    Stopped,
+    Ended,
+    Stalled,
+    Failed(Error),
+}
+
+/// Keeps every chat source running: each source's stream is watched for
+/// stalls, and reopened with exponential backoff when it stalls or fails.
+#[derive(Debug)]
+pub struct Supervisor {
+    config: SupervisorConfig,
+    clock: Arc<dyn Clock>,
+    streams: Mutex<HashMap<String, Supervised>>,
+}
+
+impl Supervisor {
+    pub fn new(config: SupervisorConfig, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            config,
+            clock,
+            streams: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Starts feeding the source's messages into the sink through the
+    /// source's queue, under `name`. Fails if a source of that name is
+    /// already supervised and not stopped.
+    pub async fn supervise(
+        self: &Arc<Self>,
+        name: &str,
+        source: Arc<dyn ChatSource>,
+        sink: Arc<dyn ChatSink>,
+    ) -> Result<(), Error> {
+        // The name is taken before the queue is set up, so that nobody else
+        // can supervise a source of the same name meanwhile.
+        let stop = Arc::new(Notify::new());
+        {
+            let mut streams = self.streams.lock().unwrap();
+            if let Some(existing) = streams.get(name) {
+                if existing.status.state != StreamState::Stopped {
+                    return Err(Error::Server(format!(
+                        "Stream {} is already supervised",
+                        name
+                    )));
+                }
+            }
+
+            let supervised = Supervised {
+                status: StreamStatus::new(name),
+                stop: stop.clone(),
+                queue: None,
+            };
+            streams.insert(name.to_string(), supervised);
+        }
+
+        let queue = SourceQueue::spawn(name, self.config.queues.get(name).clone(), sink).await;
+        let queue = match queue {
+            Ok(queue) => Arc::new(queue),
+            Err(err) => {
+                self.streams.lock().unwrap().remove(name);
+                return Err(err);
+            }
+        };
+
+        if let Some(supervised) = self.streams.lock().unwrap().get_mut(name) {
+            supervised.queue = Some(queue.clone());
+        }
+
+        tokio::spawn(self.clone().run(name.to_string(), source, queue, stop));
+
+        Ok(())
+    }
+
+    /// Stops the stream of that name for good. Returns whether it was
+    /// running.
+    pub fn stop(&self, name: &str) -> bool {
+        let streams = self.streams.lock().unwrap();
+        match streams.get(name) {
+            Some(supervised) if supervised.status.state != StreamState::Stopped => {
+                supervised.stop.notify_one();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Every supervised stream, stopped ones included, by name.
+    pub fn statuses(&self) -> Vec<StreamStatus> {
+        let streams = self.streams.lock().unwrap();
+        let mut statuses: Vec<_> = streams.values().map(Supervised::status).collect();
+        statuses.sort_by(|a, b| a.name.cmp(&b.name));
+
+        statuses
+    }
+
+    pub fn status(&self, name: &str) -> Option<StreamStatus> {
+        let streams = self.streams.lock().unwrap();
+        streams.get(name).map(Supervised::status)
+    }
+
+    fn update(&self, name: &str, f: impl FnOnce(&mut StreamStatus)) {
+        let mut streams = self.streams.lock().unwrap();
+        if let Some(supervised) = streams.get_mut(name) {
+            f(&mut supervised.status);
+        }
+    }
+
+    async fn run(
+        self: Arc<Self>,
+        name: String,
+        source: Arc<dyn ChatSource>,
+        sink: Arc<SourceQueue>,
+        stop: Arc<Notify>,
+    ) {
+        let mut backoff = self.config.min_backoff;
+        loop {
+            let outcome = tokio::select! {
+                _ = stop.notified() => Outcome::Stopped,
+                stream = source.open(self.clock.clone()) => match stream {
+                    Ok(stream) => {
+                        let stream = match self.config.pipelines.get(&name) {
+                            Some(stages) => stream.through(Pipeline::from_config(stages)),
+                            None => stream,
+                        };
+                        info!("Stream {} is running", name);
+                        self.update(&name, |status| {
+                            status.state = StreamState::Running;
+                            status.created_at = Some(stream.created_at);
+                            status.updated_at = Some(stream.updated_at());
+                        });
+                        let paced = source.is_paced();
+                        self.drive(&name, stream, sink.as_ref(), &stop, paced, &mut backoff)
+                            .await
+                    }
+                    Err(err) => Outcome::Failed(err),
+                },
+            };
+
+            let state = match outcome {
+                Outcome::Stopped => break,
+                Outcome::Ended if source.is_finite() => break,
+                Outcome::Ended => {
+                    warn!("Stream {} ended", name);
+                    self.update(&name, |status| {
+                        status.last_error = Some("The stream ended".to_string())
+                    });
+                    StreamState::Restarting
+                }
+                Outcome::Stalled => {
+                    warn!(
+                        "Stream {} stalled, with no message for {:?}",
+                        name, self.config.stall_after
+                    );
+                    StreamState::Stalled
+                }
+                Outcome::Failed(err) => {
+                    error!("Stream {} failed: {}", name, err);
+                    self.update(&name, |status| status.last_error = Some(err.to_string()));
+                    StreamState::Restarting
+                }
+            };
+
+            info!("Restarting stream {} in {:?}", name, backoff);
+            self.update(&name, |status| status.state = state);
+            tokio::select! {
+                _ = stop.notified() => break,
+                _ = tokio::time::sleep(backoff) => {}
+            }
+
+            backoff = (backoff * 2).min(self.config.max_backoff);
+            self.update(&name, |status| {
+                status.state = StreamState::Restarting;
+                status.restarts += 1;
+            });
+        }
+
+        info!("Stream {} stopped", name);
+        self.update(&name, |status| status.state = StreamState::Stopped);
+    }
+
+    /// Feeds the stream into the sink until it ends, stalls or fails, or the
+    /// source is stopped. Paced streams are not checked for stalls. The
+    /// backoff is reset once the stream has yielded a message.
+    async fn drive(
+        &self,
+        name: &str,
+        mut stream: ChatStream<'static>,
+        sink: &dyn ChatSink,
+        stop: &Notify,
+        paced: bool,
+        backoff: &mut Duration,
+    ) -> Outcome {
+        let stall_after = time::Duration::try_from(self.config.stall_after).unwrap();
+        let mut check = tokio::time::interval(self.config.check_interval);
+
+        loop {
+            tokio::select! {
+                _ = stop.notified() => return Outcome::Stopped,
+                message = stream.next() => {
+                    let Some(message) = message else {
+                        return Outcome::Ended;
+                    };
+                    if let Err(err) = sink.send(message).await {
+                        return Outcome::Failed(err);
+                    }
+
+                    *backoff = self.config.min_backoff;
+                    self.update(name, |status| status.updated_at = Some(stream.updated_at()));
+                }
+                _ = check.tick() => {
+                    // Messages a pipeline dropped are activity all the same.
+                    let updated_at = stream.updated_at();
+                    self.update(name, |status| status.updated_at = Some(updated_at));
+                    if !paced && self.clock.now() - updated_at > stall_after {
+                        return Outcome::Stalled;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fake::{Fake, Faker};
+    use futures::stream;
+
+    use super::*;
+    use crate::utils::clock::SystemClock;
+
+    const WAIT: Duration = Duration::from_secs(5);
+
+    /// Yields `count` messages each time it is opened, then either ends or
+    /// goes quiet.
+    struct TestSource {
+        count: usize,
+        finite: bool,
+    }
+
+    #[async_trait::async_trait]
+    impl ChatSource for TestSource {
+        async fn open(&self, clock: Arc<dyn Clock>) -> Result<ChatStream<'static>, Error> {
+            let messages: Vec<ChatMessage> = (0..self.count).map(|_| Faker.fake()).collect();
+            let messages = stream::iter(messages);
+
+            Ok(match self.finite {
+                true => ChatStream::new(messages, clock),
+                false => ChatStream::new(messages.chain(stream::pending()), clock),
+            })
+        }
+
+        fn is_finite(&self) -> bool {
+            self.finite
+        }
+    }
+
+    #[derive(Default)]
+    struct TestSink(Mutex<Vec<ChatMessage>>);
+
+    #[async_trait::async_trait]
+    impl ChatSink for TestSink {
+        async fn send(&self, message: ChatMessage) -> Result<(), Error> {
+            self.0.lock().unwrap().push(message);
+            Ok(())
+        }
+    }
+
+    fn supervisor() -> Arc<Supervisor> {
+        let config = SupervisorConfig {
+            stall_after: Duration::from_millis(50),
+            check_interval: Duration::from_millis(10),
+            min_backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_millis(40),
+            pipelines: HashMap::new(),
+            queues: QueueConfigs::default(),
+        };
+
+        Arc::new(Supervisor::new(config, Arc::new(SystemClock)))
+    }
+
+    async fn wait_for(supervisor: &Supervisor, f: impl Fn(&StreamStatus) -> bool) {
+        tokio::time::timeout(WAIT, async {
+            while !supervisor.status("test").is_some_and(|status| f(&status)) {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .expect("the stream never reached the expected state");
+    }
+
+    #[tokio::test]
+    async fn test_stalled_streams_are_restarted() {
+        let supervisor = supervisor();
+        let sink = Arc::new(TestSink::default());
+        let source = Arc::new(TestSource {
+            count: 2,
+            finite: false,
+        });
+
+        supervisor
+            .supervise("test", source, sink.clone())
+            .await
+            .unwrap();
+        assert!(supervisor
+            .supervise(
+                "test",
+                Arc::new(TestSource {
+                    count: 0,
+                    finite: true
+                }),
+                sink.clone()
+            )
+            .await
+            .is_err());
+
+        wait_for(&supervisor, |status| {
+            status.restarts >= 2 && status.queue.as_ref().unwrap().delivered >= 4
+        })
+        .await;
+        assert!(sink.0.lock().unwrap().len() >= 4);
+
+        assert!(supervisor.stop("test"));
+        wait_for(&supervisor, |status| status.state == StreamState::Stopped).await;
+        assert!(!supervisor.stop("test"));
+    }
+
+    #[tokio::test]
+    async fn test_supervise_reserves_the_name() {
+        let supervisor = supervisor();
+        let sink = Arc::new(TestSink::default());
+        let source = || {
+            Arc::new(TestSource {
+                count: 0,
+                finite: false,
+            })
+        };
+
+        let (first, second) = tokio::join!(
+            supervisor.supervise("test", source(), sink.clone()),
+            supervisor.supervise("test", source(), sink.clone()),
+        );
+        assert!(first.is_ok() != second.is_ok());
+
+        assert!(supervisor.stop("test"));
+        wait_for(&supervisor, |status| status.state == StreamState::Stopped).await;
+    }
+
+    #[tokio::test]
+    async fn test_finite_streams_stop_when_they_end() {
+        let supervisor = supervisor();
+        let sink = Arc::new(TestSink::default());
+        let source = Arc::new(TestSource {
+            count: 3,
+            finite: true,
+        });
+
+        supervisor
+            .supervise("test", source, sink.clone())
+            .await
+            .unwrap();
+
+        wait_for(&supervisor, |status| {
+            status.state == StreamState::Stopped && status.queue.as_ref().unwrap().delivered == 3
+        })
+        .await;
+        let status = supervisor.status("test").unwrap();
+        assert_eq!(status.restarts, 0);
+        assert_eq!(sink.0.lock().unwrap().len(), 3);
+    }
+}
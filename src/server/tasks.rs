@@ -0,0 +1,208 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::StreamExt;
+use log::{debug, error, info, warn};
+
+use crate::dal::channel_repository::ChannelRepository;
+use crate::dal::chat_message_repository::ChatMessageRepository;
+use crate::dal::direct_message_repository::DirectMessageRepository;
+use crate::dal::mention_repository::MentionRepository;
+use crate::dal::partition_repository::PartitionRepository;
+use crate::dal::retention_repository::RetentionRepository;
+use crate::dal::stats_repository::StatsRepository;
+use crate::server::hub::{ChannelEvent, Hub};
+use crate::server::replay;
+use crate::server::resume::RecentMessages;
+use crate::utils::clock::Clock;
+
+const LISTENER_MIN_BACKOFF: Duration = Duration::from_secs(1);
+const LISTENER_MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Keeps partitions of `chat_messages` ready ahead of time, starting with
+/// one pass right away.
+pub(crate) fn spawn_partition_maintenance(partitions: PartitionRepository, clock: Arc<dyn Clock>) {
+    let period = partitions.config().interval;
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+
+            match partitions.maintain(clock.now()).await {
+                Ok(()) => debug!("Maintained message partitions"),
+                Err(err) => error!("Could not maintain message partitions: {}", err),
+            }
+        }
+    });
+}
+
+/// Periodically refreshes the stats rollups of the configured channels.
+pub(crate) fn spawn_rollup_refresh(stats: Arc<StatsRepository>) {
+    if stats.config().rollup_channels.is_empty() {
+        return;
+    }
+
+    let period = stats.config().rollup_interval;
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+
+            match stats.refresh_rollups().await {
+                Ok(()) => debug!("Refreshed stats rollups"),
+                Err(err) => error!("Could not refresh stats rollups: {}", err),
+            }
+        }
+    });
+}
+
+/// Periodically removes messages that have outlived their channel's
+/// retention policy.
+pub(crate) fn spawn_retention(retention: Arc<RetentionRepository>, clock: Arc<dyn Clock>) {
+    if !retention.enforces_anything() {
+        return;
+    }
+
+    let period = retention.config().interval;
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+
+            match retention.enforce_all(clock.now()).await {
+                Ok(runs) => debug!("Enforced retention on {} channels", runs.len()),
+                Err(err) => error!("Could not enforce retention: {}", err),
+            }
+        }
+    });
+}
+
+/// Feeds every message stored by any instance into the local hub and the
+/// recent messages, over a dedicated `LISTEN` connection that is reopened
+/// whenever it drops.
+pub(crate) fn spawn_message_listener(
+    repo: Arc<ChatMessageRepository>,
+    hub: Arc<Hub>,
+    recent: Arc<RecentMessages>,
+) {
+    tokio::spawn(async move {
+        let mut backoff = LISTENER_MIN_BACKOFF;
+        loop {
+            match repo.listen().await {
+                Ok(messages) => {
+                    info!("Listening for new messages");
+                    backoff = LISTENER_MIN_BACKOFF;
+
+                    let mut messages = Box::pin(messages);
+                    while let Some(message) = messages.next().await {
+                        recent.push(&message);
+                        let channel = message.channel.clone();
+                        let event = ChannelEvent::Message {
+                            channel: channel.clone(),
+                            message,
+                        };
+                        hub.publish(&channel, event);
+                    }
+
+                    warn!("Lost the connection listening for new messages");
+                }
+                Err(err) => error!("Could not listen for new messages: {}", err),
+            }
+
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(LISTENER_MAX_BACKOFF);
+        }
+    });
+}
+
+/// Delivers every direct message stored by any instance to the local
+/// sessions of both participants, reconnecting like the message listener.
+pub(crate) fn spawn_direct_message_listener(repo: Arc<DirectMessageRepository>, hub: Arc<Hub>) {
+    tokio::spawn(async move {
+        let mut backoff = LISTENER_MIN_BACKOFF;
+        loop {
+            match repo.listen().await {
+                Ok(messages) => {
+                    info!("Listening for direct messages");
+                    backoff = LISTENER_MIN_BACKOFF;
+
+                    let mut messages = Box::pin(messages);
+                    while let Some(message) = messages.next().await {
+                        let (sender, recipient) =
+                            (message.sender.clone(), message.recipient.clone());
+                        let event = ChannelEvent::DirectMessage { message };
+                        hub.publish_to_user(&recipient, event.clone());
+                        hub.publish_to_user(&sender, event);
+                    }
+
+                    warn!("Lost the connection listening for direct messages");
+                }
+                Err(err) => error!("Could not listen for direct messages: {}", err),
+            }
+
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(LISTENER_MAX_BACKOFF);
+        }
+    });
+}
+
+/// Delivers every announced mention to the local sessions of the user
+/// mentioned, reconnecting like the message listener.
+pub(crate) fn spawn_mention_listener(repo: Arc<MentionRepository>, hub: Arc<Hub>) {
+    tokio::spawn(async move {
+        let mut backoff = LISTENER_MIN_BACKOFF;
+        loop {
+            match repo.listen().await {
+                Ok(mentions) => {
+                    info!("Listening for mentions");
+                    backoff = LISTENER_MIN_BACKOFF;
+
+                    let mut mentions = Box::pin(mentions);
+                    while let Some(mention) = mentions.next().await {
+                        let username = mention.username.clone();
+                        hub.publish_to_user(&username, ChannelEvent::Mention { mention });
+                    }
+
+                    warn!("Lost the connection listening for mentions");
+                }
+                Err(err) => error!("Could not listen for mentions: {}", err),
+            }
+
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(LISTENER_MAX_BACKOFF);
+        }
+    });
+}
+
+/// Tells the local subscribers of every channel, and of its replay, whose
+/// readers changed on any instance to check their access again,
+/// reconnecting like the message listener.
+pub(crate) fn spawn_access_listener(channels: Arc<ChannelRepository>, hub: Arc<Hub>) {
+    tokio::spawn(async move {
+        let mut backoff = LISTENER_MIN_BACKOFF;
+        loop {
+            match channels.listen().await {
+                Ok(changed) => {
+                    info!("Listening for channel access changes");
+                    backoff = LISTENER_MIN_BACKOFF;
+
+                    let mut changed = Box::pin(changed);
+                    while let Some(channel) = changed.next().await {
+                        for topic in [replay::replay_channel(&channel), channel] {
+                            let event = ChannelEvent::AccessChanged {
+                                channel: topic.clone(),
+                            };
+                            hub.publish(&topic, event);
+                        }
+                    }
+
+                    warn!("Lost the connection listening for channel access changes");
+                }
+                Err(err) => error!("Could not listen for channel access changes: {}", err),
+            }
+
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(LISTENER_MAX_BACKOFF);
+        }
+    });
+}
@@ -0,0 +1,472 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Instant;
+
+use actix::{
+    Actor, ActorContext, ActorFutureExt, AsyncContext, SpawnHandle, StreamHandler, WrapFuture,
+};
+use actix_web::{web, HttpRequest, HttpResponse};
+use actix_web_actors::ws;
+use log::debug;
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use tokio::sync::broadcast;
+
+use crate::api::auth::AuthenticatedUser;
+use crate::dal::channel_repository::ChannelRepository;
+use crate::dal::chat_message_repository::ChatMessageRepository;
+use crate::models::chat_message::ChatMessage;
+use crate::server::ephemeral::{EphemeralConfig, Typing, MAX_SIGNAL_NAME_LENGTH, TYPING};
+use crate::server::hub::{self, ChannelEvent, Hub};
+use crate::server::presence::Presence;
+use crate::server::replay;
+use crate::server::resume::RecentMessages;
+use crate::server::server_state::ServerState;
+use crate::utils::rate_limit::{RateLimit, RateLimiter};
+
+/// A command sent by a client over the socket.
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ClientCommand {
+    /// Given the sequence number of the last message received before
+    /// reconnecting, the messages missed since are sent first.
+    Subscribe {
+        channel: String,
+        #[serde(default)]
+        last_seq: Option<i64>,
+    },
+    Unsubscribe {
+        channel: String,
+    },
+    Typing {
+        channel: String,
+    },
+    StopTyping {
+        channel: String,
+    },
+    /// Relays `data` to the channel's subscribers without storing it.
+    Signal {
+        channel: String,
+        name: String,
+        #[serde(default)]
+        data: JsonValue,
+    },
+}
+
+/// A reply to a [`ClientCommand`], as opposed to a [`ChannelEvent`].
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum SessionReply {
+    Subscribed {
+        channel: String,
+    },
+    Unsubscribed {
+        channel: String,
+    },
+    /// Too much was missed since `last_seq` to replay, so the client should
+    /// reload the channel's history. Live messages follow regardless.
+    GapTooLarge {
+        channel: String,
+        last_seq: i64,
+    },
+    Error {
+        message: String,
+    },
+}
+
+/// A websocket connection that relays events of the channels it subscribed
+/// to, and lets signed-in users send ephemeral events to them.
+pub(crate) struct WsSession {
+    hub: Arc<Hub>,
+    repo: Arc<ChatMessageRepository>,
+    recent: Arc<RecentMessages>,
+    channels: Arc<ChannelRepository>,
+    presence: Arc<Presence>,
+    typing: Arc<Typing>,
+    ephemeral: Arc<EphemeralConfig>,
+    /// The signed-in user, if the connection presented a bearer token.
+    user: Option<String>,
+    subscriptions: HashMap<String, SpawnHandle>,
+    /// The sequence number of the last message sent from each channel, so
+    /// nothing replayed is sent again when it also arrives live.
+    delivered: HashMap<String, i64>,
+    limiter: RateLimiter,
+}
+
+impl WsSession {
+    pub(crate) fn new(state: &ServerState, user: Option<String>) -> Self {
+        Self {
+            hub: state.hub.clone(),
+            repo: state.repo.clone(),
+            recent: state.recent.clone(),
+            channels: state.channels.clone(),
+            presence: state.presence.clone(),
+            typing: state.typing.clone(),
+            ephemeral: state.ephemeral.clone(),
+            user,
+            subscriptions: HashMap::new(),
+            delivered: HashMap::new(),
+            limiter: RateLimiter::new(),
+        }
+    }
+
+    /// Who may send an ephemeral event to `channel`, if anyone, taking a
+    /// token from each of the event's rate limits.
+    fn sender(
+        &mut self,
+        channel: &str,
+        event_type: &str,
+        limits: &[(String, RateLimit)],
+    ) -> Result<String, String> {
+        let Some(user) = self.user.clone() else {
+            return Err("Sign in to send events".to_string());
+        };
+
+        if !self.subscriptions.contains_key(channel) {
+            return Err(format!("Not subscribed to {}", channel));
+        }
+
+        let now = Instant::now();
+        for (key, limit) in limits {
+            if !self.limiter.check(key, *limit, now) {
+                return Err(format!("Too many {} events, slow down", event_type));
+            }
+        }
+
+        Ok(user)
+    }
+
+    fn subscribe(
+        &mut self,
+        channel: String,
+        last_seq: Option<i64>,
+        ctx: &mut ws::WebsocketContext<Self>,
+    ) {
+        // Subscribing before replaying means nothing posted in between is
+        // lost; the receiver holds it until the replay is done.
+        let receiver = self.hub.subscribe(&channel);
+        self.presence.join(&channel, self.user.as_deref());
+        self.reply(
+            SessionReply::Subscribed {
+                channel: channel.clone(),
+            },
+            ctx,
+        );
+
+        let Some(after) = last_seq else {
+            self.go_live(channel, receiver, ctx);
+            return;
+        };
+
+        if let Some(missed) = self.recent.after(&channel, after) {
+            self.replay(&channel, after, missed, ctx);
+            self.go_live(channel, receiver, ctx);
+            return;
+        }
+
+        // One more than can be replayed tells whether the gap is too large.
+        let (repo, name) = (self.repo.clone(), channel.clone());
+        let limit = self.recent.max_replay() as i64 + 1;
+        let missed = async move { repo.get_messages_after(&name, after, limit).await };
+
+        ctx.wait(missed.into_actor(self).map(move |missed, session, ctx| {
+            match missed {
+                Ok(missed) => session.replay(&channel, after, missed, ctx),
+                Err(err) => {
+                    let message = format!("Could not replay {}: {}", channel, err);
+                    session.reply(SessionReply::Error { message }, ctx);
+                }
+            }
+            session.go_live(channel, receiver, ctx);
+        }));
+    }
+
+    fn replay(
+        &mut self,
+        channel: &str,
+        after: i64,
+        missed: Vec<ChatMessage>,
+        ctx: &mut ws::WebsocketContext<Self>,
+    ) {
+        if missed.len() > self.recent.max_replay() {
+            let channel = channel.to_string();
+            self.reply(
+                SessionReply::GapTooLarge {
+                    channel,
+                    last_seq: after,
+                },
+                ctx,
+            );
+            return;
+        }
+
+        self.delivered.insert(channel.to_string(), after);
+        for message in missed {
+            let channel = channel.to_string();
+            self.deliver(ChannelEvent::Message { channel, message }, ctx);
+        }
+    }
+
+    fn go_live(
+        &mut self,
+        channel: String,
+        receiver: broadcast::Receiver<ChannelEvent>,
+        ctx: &mut ws::WebsocketContext<Self>,
+    ) {
+        let handle = ctx.add_stream(hub::into_stream(receiver));
+        self.subscriptions.insert(channel, handle);
+    }
+
+    fn unsubscribe(&mut self, channel: &str, ctx: &mut ws::WebsocketContext<Self>) {
+        if let Some(handle) = self.subscriptions.remove(channel) {
+            ctx.cancel_future(handle);
+            self.delivered.remove(channel);
+            self.presence.leave(channel, self.user.as_deref());
+            if let Some(user) = &self.user {
+                self.typing.stop(channel, user);
+            }
+        }
+    }
+
+    /// Drops the subscription to `channel` if the session may no longer read
+    /// it, after someone was removed from it or it became private.
+    fn check_access(&mut self, channel: String, ctx: &mut ws::WebsocketContext<Self>) {
+        let channels = self.channels.clone();
+        let name = replay::replayed_channel(&channel).unwrap_or(&channel);
+        let (name, user) = (name.to_string(), self.user.clone());
+        let access = async move { channels.get_access(&name, user.as_deref()).await };
+
+        // Waiting holds back further events until access is settled.
+        ctx.wait(
+            access
+                .into_actor(self)
+                .map(move |access, session, ctx| match access {
+                    Ok(access) if access.can_read() => {}
+                    Ok(_) => {
+                        session.unsubscribe(&channel, ctx);
+                        let message = format!("No longer allowed to read {}", channel);
+                        session.reply(SessionReply::Error { message }, ctx);
+                        session.reply(SessionReply::Unsubscribed { channel }, ctx);
+                    }
+                    Err(err) => {
+                        // Failing closed is safer than leaking a private
+                        // channel; the client can subscribe again.
+                        session.unsubscribe(&channel, ctx);
+                        let message = format!("Could not check access to {}: {}", channel, err);
+                        session.reply(SessionReply::Error { message }, ctx);
+                        session.reply(SessionReply::Unsubscribed { channel }, ctx);
+                    }
+                }),
+        );
+    }
+
+    fn deliver(&mut self, event: ChannelEvent, ctx: &mut ws::WebsocketContext<Self>) {
+        if let ChannelEvent::Message { channel, message } = &event {
+            if let Some(seq) = message.seq {
+                let last = self.delivered.entry(channel.clone()).or_insert(0);
+                if seq <= *last {
+                    return;
+                }
+                *last = seq;
+            }
+        }
+
+        ctx.text(serde_json::to_string(&event).unwrap());
+    }
+
+    fn reply(&self, reply: SessionReply, ctx: &mut ws::WebsocketContext<Self>) {
+        ctx.text(serde_json::to_string(&reply).unwrap());
+    }
+
+    fn handle_command(&mut self, command: ClientCommand, ctx: &mut ws::WebsocketContext<Self>) {
+        match command {
+            ClientCommand::Subscribe { channel, last_seq } => {
+                if self.subscriptions.contains_key(&channel) {
+                    self.reply(SessionReply::Subscribed { channel }, ctx);
+                    return;
+                }
+
+                // Replays are as private as the channel replayed.
+                let channels = self.channels.clone();
+                let name = replay::replayed_channel(&channel).unwrap_or(&channel);
+                let (name, user) = (name.to_string(), self.user.clone());
+                let access = async move { channels.get_access(&name, user.as_deref()).await };
+
+                // Waiting keeps later commands from running ahead of the
+                // subscription.
+                ctx.wait(
+                    access
+                        .into_actor(self)
+                        .map(move |access, session, ctx| match access {
+                            Ok(access) if access.can_read() => {
+                                session.subscribe(channel, last_seq, ctx)
+                            }
+                            Ok(_) => {
+                                let message = format!("Not allowed to read {}", channel);
+                                session.reply(SessionReply::Error { message }, ctx);
+                            }
+                            Err(err) => {
+                                let message =
+                                    format!("Could not subscribe to {}: {}", channel, err);
+                                session.reply(SessionReply::Error { message }, ctx);
+                            }
+                        }),
+                );
+            }
+            ClientCommand::Unsubscribe { channel } => {
+                self.unsubscribe(&channel, ctx);
+                self.reply(SessionReply::Unsubscribed { channel }, ctx);
+            }
+            ClientCommand::Typing { channel } => {
+                let limits = [(TYPING.to_string(), self.ephemeral.limit_for(TYPING))];
+                match self.sender(&channel, TYPING, &limits) {
+                    Ok(user) => self.typing.start(&channel, &user),
+                    Err(message) => self.reply(SessionReply::Error { message }, ctx),
+                }
+            }
+            ClientCommand::StopTyping { channel } => {
+                if let Some(user) = &self.user {
+                    self.typing.stop(&channel, user);
+                }
+            }
+            ClientCommand::Signal {
+                channel,
+                name,
+                data,
+            } => {
+                if name.is_empty() || name.chars().count() > MAX_SIGNAL_NAME_LENGTH {
+                    let message = format!(
+                        "Signal names must be 1 to {} characters",
+                        MAX_SIGNAL_NAME_LENGTH
+                    );
+                    self.reply(SessionReply::Error { message }, ctx);
+                    return;
+                }
+
+                let limits = self.ephemeral.signal_limits(&name);
+                match self.sender(&channel, &name, &limits) {
+                    Ok(username) => {
+                        let event = ChannelEvent::Signal {
+                            channel: channel.clone(),
+                            username,
+                            name,
+                            data,
+                        };
+                        self.hub.publish(&channel, event);
+                    }
+                    Err(message) => self.reply(SessionReply::Error { message }, ctx),
+                }
+            }
+        }
+    }
+}
+
+impl Actor for WsSession {
+    type Context = ws::WebsocketContext<Self>;
+
+    /// Signed-in sessions receive their direct messages without asking.
+    fn started(&mut self, ctx: &mut Self::Context) {
+        if let Some(user) = &self.user {
+            ctx.add_stream(hub::into_stream(self.hub.subscribe_user(user)));
+        }
+    }
+
+    fn stopped(&mut self, _ctx: &mut Self::Context) {
+        for channel in self.subscriptions.keys() {
+            self.presence.leave(channel, self.user.as_deref());
+            if let Some(user) = &self.user {
+                self.typing.stop(channel, user);
+            }
+        }
+    }
+}
+
+impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WsSession {
+    fn handle(&mut self, msg: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
+        match msg {
+            Ok(ws::Message::Ping(msg)) => ctx.pong(&msg),
+            Ok(ws::Message::Text(text)) => match serde_json::from_str::<ClientCommand>(&text) {
+                Ok(command) => self.handle_command(command, ctx),
+                Err(err) => {
+                    let message = format!("Invalid command: {}", err);
+                    self.reply(SessionReply::Error { message }, ctx);
+                }
+            },
+            Ok(ws::Message::Close(reason)) => {
+                ctx.close(reason);
+                ctx.stop();
+            }
+            _ => (),
+        }
+    }
+}
+
+impl StreamHandler<ChannelEvent> for WsSession {
+    fn handle(&mut self, event: ChannelEvent, ctx: &mut Self::Context) {
+        match event {
+            ChannelEvent::AccessChanged { channel } => {
+                if self.subscriptions.contains_key(&channel) {
+                    self.check_access(channel, ctx);
+                }
+            }
+            event => self.deliver(event, ctx),
+        }
+    }
+
+    /// A closed subscription must not end the whole session.
+    fn finished(&mut self, _ctx: &mut Self::Context) {}
+}
+
+pub(crate) async fn ws_index(
+    req: HttpRequest,
+    stream: web::Payload,
+    data: web::Data<ServerState>,
+    user: Option<AuthenticatedUser>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let session = WsSession::new(&data, user.map(|user| user.0));
+    let resp = ws::start(session, &req, stream);
+    debug!("{:?}", resp);
+    resp
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_client_command() {
+        let command: ClientCommand =
+            serde_json::from_str(r#"{"type": "subscribe", "channel": "a"}"#).unwrap();
+        assert_eq!(
+            command,
+            ClientCommand::Subscribe {
+                channel: "a".to_string(),
+                last_seq: None,
+            }
+        );
+
+        let command: ClientCommand =
+            serde_json::from_str(r#"{"type": "subscribe", "channel": "a", "last_seq": 41}"#)
+                .unwrap();
+        assert_eq!(
+            command,
+            ClientCommand::Subscribe {
+                channel: "a".to_string(),
+                last_seq: Some(41),
+            }
+        );
+
+        let command: ClientCommand =
+            serde_json::from_str(r#"{"type": "signal", "channel": "a", "name": "wave"}"#).unwrap();
+        assert_eq!(
+            command,
+            ClientCommand::Signal {
+                channel: "a".to_string(),
+                name: "wave".to_string(),
+                data: JsonValue::Null,
+            }
+        );
+
+        assert!(serde_json::from_str::<ClientCommand>(r#"{"type": "shout"}"#).is_err());
+    }
+}
@@ -0,0 +1,153 @@
+use std::io::Write;
+use std::path::Path;
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use time::{Date, UtcOffset};
+use tokio::fs::{self, OpenOptions};
+use tokio::io::AsyncWriteExt;
+
+use crate::error::Error;
+use crate::models::chat_message::ChatMessage;
+use crate::utils::export::ExportFormat;
+
+/// Appends messages to a channel's archive, one gzipped NDJSON file per UTC
+/// day under `<dir>/<channel>/`. Every call appends a complete gzip member,
+/// so a file is readable even if a later append is cut short.
+pub(crate) async fn append(
+    dir: &Path,
+    channel: &str,
+    messages: &[ChatMessage],
+) -> Result<(), Error> {
+    let channel_dir = dir.join(file_name(channel));
+    fs::create_dir_all(&channel_dir).await?;
+
+    for day in chunk_by_day(messages) {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        for message in day {
+            encoder.write_all(ExportFormat::Ndjson.encode(message)?.as_bytes())?;
+        }
+
+        let path = channel_dir.join(format!("{}.ndjson.gz", utc_date(&day[0])));
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await?;
+        file.write_all(&encoder.finish()?).await?;
+        file.sync_data().await?;
+    }
+
+    Ok(())
+}
+
+/// Reads back every message archived in a channel's directory, oldest file
+/// first.
+#[cfg(test)]
+pub(crate) async fn read_all(channel_dir: &Path) -> Result<Vec<ChatMessage>, Error> {
+    use flate2::read::MultiGzDecoder;
+    use std::io::Read;
+
+    let mut paths: Vec<std::path::PathBuf> = Vec::new();
+    let mut entries = fs::read_dir(channel_dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        paths.push(entry.path());
+    }
+    paths.sort();
+
+    let mut messages = Vec::new();
+    for path in paths {
+        let compressed = fs::read(&path).await?;
+        let mut text = String::new();
+        MultiGzDecoder::new(compressed.as_slice()).read_to_string(&mut text)?;
+
+        for line in text.lines() {
+            messages.push(serde_json::from_str(line)?);
+        }
+    }
+
+    Ok(messages)
+}
+
+/// Keeps channel names from escaping the archive directory.
+pub(crate) fn file_name(channel: &str) -> String {
+    channel
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '_' | '-' => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+fn utc_date(message: &ChatMessage) -> Date {
+    message.timestamp.to_offset(UtcOffset::UTC).date()
+}
+
+/// Splits ordered messages into runs that share a UTC date.
+fn chunk_by_day(messages: &[ChatMessage]) -> Vec<&[ChatMessage]> {
+    let mut chunks = Vec::new();
+    let mut rest = messages;
+
+    while let Some(first) = rest.first() {
+        let date = utc_date(first);
+        let len = rest
+            .iter()
+            .position(|m| utc_date(m) != date)
+            .unwrap_or(rest.len());
+        let (chunk, tail) = rest.split_at(len);
+        chunks.push(chunk);
+        rest = tail;
+    }
+
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use time::{Duration, OffsetDateTime};
+
+    use super::*;
+
+    fn message(timestamp: OffsetDateTime) -> ChatMessage {
+        ChatMessage::new(
+            "hi".to_string(),
+            "user".to_string(),
+            "chan".to_string(),
+            timestamp,
+        )
+    }
+
+    #[test]
+    fn test_file_name() {
+        assert_eq!(file_name("some_chan"), "some_chan");
+        assert_eq!(file_name("../etc"), "___etc");
+    }
+
+    #[tokio::test]
+    async fn test_append_and_read_back() {
+        let start = OffsetDateTime::from_unix_timestamp(1672531200).unwrap();
+        let dir = std::env::temp_dir().join(format!("archive-test-{}", std::process::id()));
+
+        let first = [message(start), message(start + Duration::DAY)];
+        let second = [message(start + Duration::HOUR)];
+        append(&dir, "chan", &first).await.unwrap();
+        append(&dir, "chan", &second).await.unwrap();
+
+        let mut files: Vec<String> = std::fs::read_dir(dir.join("chan"))
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect();
+        files.sort();
+        assert_eq!(files, ["2023-01-01.ndjson.gz", "2023-01-02.ndjson.gz"]);
+
+        let messages = read_all(&dir.join("chan")).await.unwrap();
+        let timestamps: Vec<OffsetDateTime> = messages.iter().map(|m| m.timestamp).collect();
+        assert_eq!(
+            timestamps,
+            [start, start + Duration::HOUR, start + Duration::DAY]
+        );
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
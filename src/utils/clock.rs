@@ -0,0 +1,43 @@
+use std::fmt::Debug;
+#[cfg(test)]
+use std::sync::Mutex;
+
+use time::OffsetDateTime;
+
+/// Tells the time, always in UTC. Passed around rather than read directly,
+/// so that tests can control it.
+pub trait Clock: Debug + Send + Sync {
+    fn now(&self) -> OffsetDateTime;
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> OffsetDateTime {
+        OffsetDateTime::now_utc()
+    }
+}
+
+/// Stands still until it is moved.
+#[cfg(test)]
+#[derive(Debug)]
+pub(crate) struct ManualClock(Mutex<OffsetDateTime>);
+
+#[cfg(test)]
+impl ManualClock {
+    pub(crate) fn new(now: OffsetDateTime) -> Self {
+        Self(Mutex::new(now))
+    }
+
+    pub(crate) fn advance(&self, by: time::Duration) {
+        *self.0.lock().unwrap() += by;
+    }
+}
+
+#[cfg(test)]
+impl Clock for ManualClock {
+    fn now(&self) -> OffsetDateTime {
+        *self.0.lock().unwrap()
+    }
+}
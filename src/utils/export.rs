@@ -0,0 +1,182 @@
+use std::str::FromStr;
+
+use actix_web::web::Bytes;
+use futures::stream::{self, Stream};
+use futures::StreamExt;
+use serde::Deserialize;
+use time::format_description::well_known::Rfc3339;
+
+use crate::error::Error;
+use crate::models::chat_message::ChatMessage;
+
+/// The most messages encoded into a single chunk of output.
+const EXPORT_CHUNK_SIZE: usize = 500;
+const CSV_HEADER: &str = "id,timestamp,channel,username,text\n";
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Ndjson,
+    Csv,
+    Txt,
+}
+
+impl ExportFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Ndjson => "application/x-ndjson",
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+            ExportFormat::Txt => "text/plain; charset=utf-8",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Ndjson => "ndjson",
+            ExportFormat::Csv => "csv",
+            ExportFormat::Txt => "txt",
+        }
+    }
+
+    fn header(&self) -> Option<&'static str> {
+        match self {
+            ExportFormat::Csv => Some(CSV_HEADER),
+            _ => None,
+        }
+    }
+
+    /// Encodes a single message as one line, including its line terminator.
+    pub fn encode(&self, message: &ChatMessage) -> Result<String, Error> {
+        let line = match self {
+            ExportFormat::Ndjson => serde_json::to_string(message)?,
+            ExportFormat::Csv => {
+                let timestamp = message
+                    .timestamp
+                    .format(&Rfc3339)
+                    .map_err(|e| Error::Unspecified(e.to_string()))?;
+                let id = message.id.map(|id| id.to_string()).unwrap_or_default();
+
+                [
+                    id,
+                    timestamp,
+                    csv_field(&message.channel),
+                    csv_field(&message.username),
+                    csv_field(&message.text),
+                ]
+                .join(",")
+            }
+            ExportFormat::Txt => message.to_string(),
+        };
+
+        Ok(line + "\n")
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ndjson" => Ok(ExportFormat::Ndjson),
+            "csv" => Ok(ExportFormat::Csv),
+            "txt" => Ok(ExportFormat::Txt),
+            _ => Err(Error::Unspecified(format!("Unknown export format: {}", s))),
+        }
+    }
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Encodes a stream of messages into chunks of bytes in the given format.
+pub(crate) fn encode_stream<S>(
+    messages: S,
+    format: ExportFormat,
+) -> impl Stream<Item = Result<Bytes, Error>>
+where
+    S: Stream<Item = Result<ChatMessage, Error>>,
+{
+    let header = stream::iter(
+        format
+            .header()
+            .map(|h| Ok(Bytes::from_static(h.as_bytes()))),
+    );
+
+    let body = messages
+        .map(move |message| message.and_then(|m| format.encode(&m)))
+        .ready_chunks(EXPORT_CHUNK_SIZE)
+        .map(|lines| {
+            let chunk = lines.into_iter().collect::<Result<String, Error>>()?;
+            Ok(Bytes::from(chunk))
+        });
+
+    header.chain(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use time::OffsetDateTime;
+
+    use super::*;
+
+    fn message(text: &str) -> ChatMessage {
+        let timestamp = OffsetDateTime::from_unix_timestamp(1672531200).unwrap();
+        let mut message = ChatMessage::new(
+            text.to_string(),
+            "user".to_string(),
+            "chan".to_string(),
+            timestamp,
+        );
+        message.id = Some(7);
+        message
+    }
+
+    #[test]
+    fn test_encode_csv() {
+        let line = ExportFormat::Csv.encode(&message("hi, \"you\"")).unwrap();
+        assert_eq!(
+            line,
+            "7,2023-01-01T00:00:00Z,chan,user,\"hi, \"\"you\"\"\"\n"
+        );
+    }
+
+    #[test]
+    fn test_encode_txt_matches_display() {
+        let message = message("hello");
+        let line = ExportFormat::Txt.encode(&message).unwrap();
+        assert_eq!(line, format!("{}\n", message));
+    }
+
+    #[test]
+    fn test_encode_ndjson_round_trips() {
+        let line = ExportFormat::Ndjson.encode(&message("hello")).unwrap();
+        let decoded: ChatMessage = serde_json::from_str(line.trim_end()).unwrap();
+        assert_eq!(decoded, message("hello"));
+    }
+
+    #[tokio::test]
+    async fn test_encode_stream_writes_header_first() {
+        let messages = stream::iter(vec![Ok(message("a")), Ok(message("b"))]);
+        let chunks: Vec<Bytes> = encode_stream(messages, ExportFormat::Csv)
+            .map(|chunk| chunk.unwrap())
+            .collect()
+            .await;
+
+        let output: Vec<u8> = chunks.concat();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.starts_with(CSV_HEADER));
+        assert_eq!(output.lines().count(), 3);
+    }
+
+    #[test]
+    fn test_parse_format() {
+        assert_eq!("csv".parse::<ExportFormat>().unwrap(), ExportFormat::Csv);
+        assert!("xml".parse::<ExportFormat>().is_err());
+    }
+}
@@ -0,0 +1,294 @@
+use crate::models::chat_message::ChatMessage;
+use crate::models::fragment::{Fragment, FragmentKind};
+
+const MAX_NAME_LENGTH: usize = 25;
+const LINK_SCHEMES: [&str; 2] = ["http://", "https://"];
+/// Punctuation after a link that more likely ends the sentence than the link.
+const LINK_TRAILERS: &[char] = &['.', ',', '!', '?', ';', ':', ')', '"', '\''];
+
+/// The parsers messages are split into fragments with, unless they arrive
+/// with fragments of their own.
+pub(crate) const DEFAULT_PARSERS: [&dyn FragmentParser; 3] = [&TwitchEmotes, &Mentions, &Links];
+
+/// A part of a message's text recognized by a parser, by character offsets.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Span {
+    pub(crate) start: usize,
+    pub(crate) end: usize,
+    pub(crate) kind: FragmentKind,
+}
+
+/// Recognizes the parts of a message's text it knows about.
+pub(crate) trait FragmentParser: Send + Sync {
+    fn spans(&self, message: &ChatMessage) -> Vec<Span>;
+}
+
+/// Splits a message's text into fragments. Parsers run in order, and spans
+/// overlapping one found earlier are dropped, as are spans out of bounds.
+/// Whatever no parser recognized is left as text.
+pub(crate) fn parse(message: &ChatMessage, parsers: &[&dyn FragmentParser]) -> Vec<Fragment> {
+    let chars: Vec<char> = message.text.chars().collect();
+
+    let mut spans: Vec<Span> = Vec::new();
+    for parser in parsers {
+        for span in parser.spans(message) {
+            let valid = span.start < span.end && span.end <= chars.len();
+            let overlaps = spans
+                .iter()
+                .any(|other| span.start < other.end && other.start < span.end);
+            if valid && !overlaps {
+                spans.push(span);
+            }
+        }
+    }
+    spans.sort_by_key(|span| span.start);
+
+    let mut fragments = Vec::with_capacity(spans.len() * 2 + 1);
+    let mut position = 0;
+    for span in spans {
+        if position < span.start {
+            fragments.push(fragment(&chars, position, span.start, FragmentKind::Text));
+        }
+        position = span.end;
+        fragments.push(fragment(&chars, span.start, span.end, span.kind));
+    }
+    if position < chars.len() {
+        fragments.push(fragment(&chars, position, chars.len(), FragmentKind::Text));
+    }
+
+    fragments
+}
+
+fn fragment(chars: &[char], start: usize, end: usize, kind: FragmentKind) -> Fragment {
+    Fragment {
+        kind,
+        text: chars[start..end].iter().collect(),
+        start,
+        end,
+    }
+}
+
+/// The character ranges of the whitespace-separated words of `chars`.
+fn words(chars: &[char]) -> Vec<(usize, usize)> {
+    let mut words = Vec::new();
+    let mut start = None;
+
+    for (i, c) in chars.iter().enumerate() {
+        match (c.is_whitespace(), start) {
+            (false, None) => start = Some(i),
+            (true, Some(s)) => {
+                words.push((s, i));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(s) = start {
+        words.push((s, chars.len()));
+    }
+
+    words
+}
+
+/// Emotes as given by Twitch's `emotes` IRC tag, such as
+/// `25:0-4,12-16/1902:6-10`, whose ranges include their last character.
+#[derive(Debug)]
+pub(crate) struct TwitchEmotes;
+
+impl FragmentParser for TwitchEmotes {
+    fn spans(&self, message: &ChatMessage) -> Vec<Span> {
+        let Some(tag) = message.emotes.as_deref() else {
+            return Vec::new();
+        };
+
+        tag.split('/')
+            .filter_map(|emote| emote.split_once(':'))
+            .flat_map(|(id, ranges)| {
+                ranges.split(',').filter_map(move |range| {
+                    let (start, last) = range.split_once('-')?;
+                    let start = start.parse().ok()?;
+                    let end = last.parse::<usize>().ok()?.checked_add(1)?;
+                    let kind = FragmentKind::Emote { id: id.to_string() };
+                    Some(Span { start, end, kind })
+                })
+            })
+            .collect()
+    }
+}
+
+/// Moves the ranges of an `emotes` tag `by` characters to the left, as when
+/// that many characters are cut from the start of the text. Ranges that
+/// were cut into are dropped.
+pub(crate) fn shift_emotes(tag: &str, by: usize) -> String {
+    let emotes: Vec<String> = tag
+        .split('/')
+        .filter_map(|emote| emote.split_once(':'))
+        .filter_map(|(id, ranges)| {
+            let ranges: Vec<String> = ranges
+                .split(',')
+                .filter_map(|range| {
+                    let (start, last) = range.split_once('-')?;
+                    let start = start.parse::<usize>().ok()?.checked_sub(by)?;
+                    let last = last.parse::<usize>().ok()?.checked_sub(by)?;
+                    Some(format!("{}-{}", start, last))
+                })
+                .collect();
+
+            (!ranges.is_empty()).then(|| format!("{}:{}", id, ranges.join(",")))
+        })
+        .collect();
+
+    emotes.join("/")
+}
+
+/// `@username` mentions.
+#[derive(Debug)]
+pub(crate) struct Mentions;
+
+impl FragmentParser for Mentions {
+    fn spans(&self, message: &ChatMessage) -> Vec<Span> {
+        let chars: Vec<char> = message.text.chars().collect();
+
+        words(&chars)
+            .into_iter()
+            .filter(|&(start, _)| chars[start] == '@')
+            .filter_map(|(start, end)| {
+                let name: String = chars[start + 1..end]
+                    .iter()
+                    .take_while(|c| c.is_ascii_alphanumeric() || **c == '_')
+                    .collect();
+                if name.is_empty() || name.len() > MAX_NAME_LENGTH {
+                    return None;
+                }
+
+                let end = start + 1 + name.len();
+                let kind = FragmentKind::Mention { username: name };
+                Some(Span { start, end, kind })
+            })
+            .collect()
+    }
+}
+
+/// `http` and `https` URLs.
+#[derive(Debug)]
+pub(crate) struct Links;
+
+impl FragmentParser for Links {
+    fn spans(&self, message: &ChatMessage) -> Vec<Span> {
+        let chars: Vec<char> = message.text.chars().collect();
+
+        words(&chars)
+            .into_iter()
+            .filter_map(|(start, end)| {
+                let word: String = chars[start..end].iter().collect();
+                let url = word.trim_end_matches(LINK_TRAILERS);
+                let scheme = LINK_SCHEMES
+                    .iter()
+                    .find(|scheme| url.to_ascii_lowercase().starts_with(*scheme))?;
+                if url.len() == scheme.len() {
+                    return None;
+                }
+
+                let end = start + url.chars().count();
+                let kind = FragmentKind::Link {
+                    url: url.to_string(),
+                };
+                Some(Span { start, end, kind })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fake::{Fake, Faker};
+
+    use super::*;
+
+    fn message(text: &str, emotes: Option<&str>) -> ChatMessage {
+        let mut message = Faker.fake::<ChatMessage>();
+        message.text = text.to_string();
+        message.emotes = emotes.map(|emotes| emotes.to_string());
+        message
+    }
+
+    fn kinds(fragments: &[Fragment]) -> Vec<(&str, &FragmentKind)> {
+        fragments
+            .iter()
+            .map(|fragment| (fragment.text.as_str(), &fragment.kind))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_fragments() {
+        let message = message(
+            "Kappa hi @some_one, see https://example.com/a?b=1. Kappa",
+            Some("25:0-4,51-55"),
+        );
+        let fragments = parse(&message, &DEFAULT_PARSERS);
+
+        let emote = FragmentKind::Emote {
+            id: "25".to_string(),
+        };
+        let mention = FragmentKind::Mention {
+            username: "some_one".to_string(),
+        };
+        let link = FragmentKind::Link {
+            url: "https://example.com/a?b=1".to_string(),
+        };
+        assert_eq!(
+            kinds(&fragments),
+            vec![
+                ("Kappa", &emote),
+                (" hi ", &FragmentKind::Text),
+                ("@some_one", &mention),
+                (", see ", &FragmentKind::Text),
+                ("https://example.com/a?b=1", &link),
+                (". ", &FragmentKind::Text),
+                ("Kappa", &emote),
+            ]
+        );
+        assert_eq!((fragments[2].start, fragments[2].end), (9, 18));
+    }
+
+    #[test]
+    fn test_parse_counts_characters() {
+        let message = message("ça va @toi", None);
+        let fragments = parse(&message, &DEFAULT_PARSERS);
+
+        assert_eq!(fragments.len(), 2);
+        assert_eq!((fragments[1].start, fragments[1].end), (6, 10));
+    }
+
+    #[test]
+    fn test_parse_ignores_bad_emote_ranges() {
+        // Out of bounds, overlapping and malformed ranges are all dropped.
+        let message = message("@Kappa", Some("25:0-9/1:1-3/2:x-1,3-2"));
+        let fragments = parse(&message, &DEFAULT_PARSERS);
+
+        assert_eq!(
+            kinds(&fragments),
+            vec![
+                ("@", &FragmentKind::Text),
+                (
+                    "Kap",
+                    &FragmentKind::Emote {
+                        id: "1".to_string()
+                    }
+                ),
+                ("pa", &FragmentKind::Text),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_shift_emotes() {
+        assert_eq!(shift_emotes("25:2-6,10-14/1902:0-1", 2), "25:0-4,8-12");
+        assert_eq!(shift_emotes("25:0-4", 0), "25:0-4");
+    }
+
+    #[test]
+    fn test_parse_empty_text() {
+        assert!(parse(&message("", None), &DEFAULT_PARSERS).is_empty());
+    }
+}
@@ -0,0 +1,404 @@
+use std::fmt::Display;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use actix_web::web::{Bytes, BytesMut};
+use futures::stream::{self, Stream};
+use futures::{pin_mut, StreamExt};
+use log::info;
+use serde::Deserialize;
+use time::format_description::well_known::Rfc3339;
+use time::format_description::FormatItem;
+use time::{OffsetDateTime, PrimitiveDateTime};
+
+use crate::dal::import_repository::ImportRepository;
+use crate::error::Error;
+use crate::models::chat_message::ChatMessage;
+use crate::models::import::{ImportReport, LineError};
+
+const IMPORT_BATCH_SIZE: usize = 1000;
+const MAX_REPORTED_ERRORS: usize = 100;
+const MAX_NAME_LENGTH: usize = 25;
+const MAX_TEXT_LENGTH: usize = 500;
+const MAX_SOURCE_ID_LENGTH: usize = 64;
+
+/// How `ChatMessage`'s `Display` impl renders its timestamp.
+const DISPLAY_TIMESTAMP: &str = "[year]-[month]-[day] [hour padding:none]:[minute]:[second].[subsecond] [offset_hour sign:mandatory]:[offset_minute]:[offset_second]";
+/// A plain timestamp, assumed to be in UTC.
+const PLAIN_TIMESTAMP: &str = "[year]-[month]-[day] [hour]:[minute]:[second]";
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportFormat {
+    /// One JSON message per line, as written by the NDJSON export.
+    #[default]
+    Ndjson,
+    /// IRC-style `[timestamp] user: text` or `[timestamp] <user> text` lines.
+    Txt,
+}
+
+impl ImportFormat {
+    pub fn from_path(path: &str) -> Self {
+        match path.rsplit_once('.').map(|(_, ext)| ext) {
+            Some("txt" | "log") => ImportFormat::Txt,
+            _ => ImportFormat::Ndjson,
+        }
+    }
+}
+
+impl FromStr for ImportFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ndjson" => Ok(ImportFormat::Ndjson),
+            "txt" => Ok(ImportFormat::Txt),
+            _ => Err(Error::Unspecified(format!("Unknown import format: {}", s))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct ImportOptions {
+    /// Identifies the input, so that an interrupted import can be resumed.
+    pub(crate) source: String,
+    pub(crate) format: ImportFormat,
+    /// The channel of messages that don't name one themselves.
+    pub(crate) channel: Option<String>,
+}
+
+/// A message from another tool, with an RFC 3339 timestamp.
+#[derive(Debug, Deserialize)]
+struct ForeignMessage {
+    text: String,
+    username: String,
+    channel: Option<String>,
+    #[serde(with = "time::serde::rfc3339")]
+    timestamp: OffsetDateTime,
+    source_id: Option<String>,
+}
+
+/// Parses one line of input. Blank lines yield `None`.
+pub(crate) fn parse_line(
+    format: ImportFormat,
+    line: &str,
+    channel: Option<&str>,
+) -> Result<Option<ChatMessage>, String> {
+    if line.trim().is_empty() {
+        return Ok(None);
+    }
+
+    let message = match format {
+        ImportFormat::Ndjson => parse_ndjson(line, channel)?,
+        ImportFormat::Txt => parse_txt(line, channel)?,
+    };
+
+    validate(&message)?;
+    Ok(Some(message))
+}
+
+fn parse_ndjson(line: &str, channel: Option<&str>) -> Result<ChatMessage, String> {
+    if let Ok(mut message) = serde_json::from_str::<ChatMessage>(line) {
+        message.id = None;
+        return Ok(message);
+    }
+
+    let message: ForeignMessage = serde_json::from_str(line).map_err(|e| e.to_string())?;
+    let channel = message
+        .channel
+        .or(channel.map(|c| c.to_string()))
+        .ok_or("Message has no channel and no default channel was given")?;
+
+    Ok(ChatMessage {
+        source_id: message.source_id,
+        ..ChatMessage::new(message.text, message.username, channel, message.timestamp)
+    })
+}
+
+fn parse_txt(line: &str, channel: Option<&str>) -> Result<ChatMessage, String> {
+    let channel = channel.ok_or("Text logs need a channel to import into")?;
+
+    let (timestamp, rest) = line
+        .strip_prefix('[')
+        .and_then(|line| line.split_once(']'))
+        .ok_or("Line does not start with a [timestamp]")?;
+
+    let rest = rest.trim_start();
+    let (username, text) = match rest.strip_prefix('<') {
+        Some(rest) => rest.split_once('>'),
+        None => rest.split_once(':'),
+    }
+    .ok_or("Line has no username")?;
+
+    Ok(ChatMessage::new(
+        text.strip_prefix(' ').unwrap_or(text).to_string(),
+        username.trim().to_string(),
+        channel.to_string(),
+        parse_timestamp(timestamp.trim())?,
+    ))
+}
+
+fn parse_timestamp(value: &str) -> Result<OffsetDateTime, String> {
+    static DISPLAY: OnceLock<Vec<FormatItem<'static>>> = OnceLock::new();
+    static PLAIN: OnceLock<Vec<FormatItem<'static>>> = OnceLock::new();
+
+    let display =
+        DISPLAY.get_or_init(|| time::format_description::parse(DISPLAY_TIMESTAMP).unwrap());
+    let plain = PLAIN.get_or_init(|| time::format_description::parse(PLAIN_TIMESTAMP).unwrap());
+
+    OffsetDateTime::parse(value, &Rfc3339)
+        .or_else(|_| OffsetDateTime::parse(value, display))
+        .or_else(|_| PrimitiveDateTime::parse(value, plain).map(|t| t.assume_utc()))
+        .map_err(|_| format!("Unrecognized timestamp: {}", value))
+}
+
+fn validate(message: &ChatMessage) -> Result<(), String> {
+    for (field, value) in [
+        ("username", &message.username),
+        ("channel", &message.channel),
+    ] {
+        if value.is_empty() || value.chars().count() > MAX_NAME_LENGTH {
+            return Err(format!("Invalid {}: {:?}", field, value));
+        }
+    }
+
+    if let Some(source_id) = &message.source_id {
+        if source_id.is_empty() || source_id.chars().count() > MAX_SOURCE_ID_LENGTH {
+            return Err(format!("Invalid source_id: {:?}", source_id));
+        }
+    }
+
+    if message.text.chars().count() > MAX_TEXT_LENGTH {
+        return Err(format!(
+            "Text is longer than {} characters",
+            MAX_TEXT_LENGTH
+        ));
+    }
+
+    Ok(())
+}
+
+/// Splits a stream of byte chunks into lines, without their terminators.
+pub(crate) fn lines_from_chunks<S, E>(chunks: S) -> impl Stream<Item = Result<String, Error>>
+where
+    S: Stream<Item = Result<Bytes, E>>,
+    E: Display,
+{
+    let state = (Box::pin(chunks), BytesMut::new(), false);
+
+    stream::unfold(state, |(mut chunks, mut buffer, mut done)| async move {
+        loop {
+            if let Some(end) = buffer.iter().position(|b| *b == b'\n') {
+                let line = buffer.split_to(end + 1);
+                return Some((Ok(decode_line(&line)), (chunks, buffer, done)));
+            }
+
+            if done {
+                if buffer.is_empty() {
+                    return None;
+                }
+
+                let line = buffer.split();
+                return Some((Ok(decode_line(&line)), (chunks, buffer, done)));
+            }
+
+            match chunks.next().await {
+                Some(Ok(chunk)) => buffer.extend_from_slice(&chunk),
+                Some(Err(err)) => {
+                    let err = Error::Server(format!("Could not read input: {}", err));
+                    return Some((Err(err), (chunks, BytesMut::new(), true)));
+                }
+                None => done = true,
+            }
+        }
+    })
+}
+
+pub(crate) fn decode_line(line: &[u8]) -> String {
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    String::from_utf8_lossy(line).into_owned()
+}
+
+/// Imports every line of the input in batches. Lines that were committed by
+/// an earlier run for the same source are skipped, and lines that cannot be
+/// parsed are reported rather than failing the whole import.
+pub(crate) async fn import_lines<S>(
+    repo: &mut ImportRepository,
+    lines: S,
+    options: &ImportOptions,
+) -> Result<ImportReport, Error>
+where
+    S: Stream<Item = Result<String, Error>>,
+{
+    let job = repo.start_job(&options.source).await?;
+    if job.lines_done > 0 {
+        info!(
+            "Resuming import of {} after line {}",
+            job.source, job.lines_done
+        );
+    }
+
+    let mut report = ImportReport {
+        source: options.source.clone(),
+        resumed_from: job.lines_done,
+        ..Default::default()
+    };
+
+    let mut batch = Vec::with_capacity(IMPORT_BATCH_SIZE);
+    let mut line_number = 0;
+    pin_mut!(lines);
+
+    while let Some(line) = lines.next().await {
+        let line = line?;
+        line_number += 1;
+        if line_number <= job.lines_done {
+            continue;
+        }
+
+        report.lines_read += 1;
+        match parse_line(options.format, &line, options.channel.as_deref()) {
+            Ok(Some(message)) => batch.push(message),
+            Ok(None) => (),
+            Err(error) => {
+                report.error_count += 1;
+                if report.errors.len() < MAX_REPORTED_ERRORS {
+                    report.errors.push(LineError {
+                        line: line_number,
+                        error,
+                    });
+                }
+            }
+        }
+
+        if batch.len() >= IMPORT_BATCH_SIZE {
+            load_batch(repo, &mut batch, line_number, &mut report).await?;
+        }
+    }
+
+    if report.lines_read > 0 {
+        load_batch(repo, &mut batch, line_number, &mut report).await?;
+    }
+
+    repo.finish_job(&options.source).await?;
+    Ok(report)
+}
+
+async fn load_batch(
+    repo: &mut ImportRepository,
+    batch: &mut Vec<ChatMessage>,
+    lines_done: i64,
+    report: &mut ImportReport,
+) -> Result<(), Error> {
+    let copied = repo.load_batch(&report.source, batch, lines_done).await?;
+    batch.clear();
+    report.imported += copied as i64;
+
+    info!(
+        "Imported {} messages from {} ({} lines, {} errors)",
+        report.imported, report.source, lines_done, report.error_count
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_txt_line() {
+        let message = parse_line(
+            ImportFormat::Txt,
+            "[2023-01-01T12:00:00Z] someone: hello: world",
+            Some("chan"),
+        )
+        .unwrap()
+        .unwrap();
+
+        assert_eq!(message.username, "someone");
+        assert_eq!(message.text, "hello: world");
+        assert_eq!(message.channel, "chan");
+        assert_eq!(message.timestamp.unix_timestamp(), 1672574400);
+
+        let message = parse_line(
+            ImportFormat::Txt,
+            "[2023-01-01 12:00:00] <someone> hi there",
+            Some("chan"),
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(message.username, "someone");
+        assert_eq!(message.text, "hi there");
+        assert_eq!(message.timestamp.unix_timestamp(), 1672574400);
+    }
+
+    #[test]
+    fn test_parse_txt_round_trips_display() {
+        let timestamp = OffsetDateTime::from_unix_timestamp(1672574400).unwrap();
+        let message = ChatMessage::new(
+            "hello".to_string(),
+            "someone".to_string(),
+            "chan".to_string(),
+            timestamp,
+        );
+
+        let line = message.to_string();
+        let parsed = parse_line(ImportFormat::Txt, &line, Some("chan")).unwrap();
+        assert_eq!(parsed, Some(message));
+    }
+
+    #[test]
+    fn test_parse_invalid_lines() {
+        assert_eq!(parse_line(ImportFormat::Txt, "  ", Some("chan")), Ok(None));
+        assert!(parse_line(ImportFormat::Txt, "no timestamp", Some("chan")).is_err());
+        assert!(parse_line(ImportFormat::Txt, "[2023-01-01 12:00:00] hi", Some("c")).is_err());
+        assert!(parse_line(ImportFormat::Txt, "[yesterday] a: hi", Some("chan")).is_err());
+        assert!(parse_line(ImportFormat::Txt, "[2023-01-01 12:00:00] a: hi", None).is_err());
+        assert!(parse_line(ImportFormat::Ndjson, "{", None).is_err());
+    }
+
+    #[test]
+    fn test_parse_ndjson_line() {
+        let line = r#"{"text": "hi", "username": "someone", "timestamp": "2023-01-01T12:00:00Z"}"#;
+
+        let message = parse_line(ImportFormat::Ndjson, line, Some("chan"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(message.channel, "chan");
+        assert!(parse_line(ImportFormat::Ndjson, line, None).is_err());
+
+        let mut exported = message;
+        exported.id = Some(3);
+        let line = serde_json::to_string(&exported).unwrap();
+        let message = parse_line(ImportFormat::Ndjson, &line, None)
+            .unwrap()
+            .unwrap();
+        assert_eq!(message.id, None);
+        assert_eq!(message.text, "hi");
+    }
+
+    #[tokio::test]
+    async fn test_lines_from_chunks() {
+        let chunks = stream::iter(vec![
+            Ok::<_, io::Error>(Bytes::from("first\r\nsec")),
+            Ok(Bytes::from("ond\n")),
+            Ok(Bytes::from("third")),
+        ]);
+
+        let lines: Vec<String> = lines_from_chunks(chunks)
+            .map(|line| line.unwrap())
+            .collect()
+            .await;
+
+        assert_eq!(lines, vec!["first", "second", "third"]);
+    }
+
+    #[test]
+    fn test_format_from_path() {
+        assert_eq!(ImportFormat::from_path("logs/chan.txt"), ImportFormat::Txt);
+        assert_eq!(ImportFormat::from_path("chan.ndjson"), ImportFormat::Ndjson);
+    }
+}
@@ -1,2 +1,9 @@
+pub(crate) mod archive;
+pub(crate) mod clock;
 pub(crate) mod connection_string;
+pub(crate) mod export;
+pub(crate) mod fragments;
+pub(crate) mod import;
+pub(crate) mod rate_limit;
 pub(crate) mod repo_statement;
+pub(crate) mod tz;
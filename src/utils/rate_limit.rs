@@ -0,0 +1,94 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+
+/// Allows `burst` events at once, refilling one every `per_ms`.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub struct RateLimit {
+    pub burst: u32,
+    pub per_ms: u64,
+}
+
+impl RateLimit {
+    fn refill_interval(&self) -> Duration {
+        Duration::from_millis(self.per_ms)
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: u32,
+    refilled_at: Instant,
+}
+
+/// Token buckets for one client, one per kind of event.
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    buckets: HashMap<String, Bucket>,
+}
+
+impl RateLimiter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Takes a token for `key` if one is left, returning whether the event is
+    /// allowed.
+    pub fn check(&mut self, key: &str, limit: RateLimit, now: Instant) -> bool {
+        let bucket = self.buckets.entry(key.to_string()).or_insert(Bucket {
+            tokens: limit.burst,
+            refilled_at: now,
+        });
+
+        let interval = limit.refill_interval();
+        if interval.is_zero() {
+            return true;
+        }
+
+        let elapsed = now.saturating_duration_since(bucket.refilled_at);
+        let refills = (elapsed.as_millis() / interval.as_millis()) as u32;
+        if refills > 0 {
+            bucket.tokens = bucket.tokens.saturating_add(refills).min(limit.burst);
+            bucket.refilled_at += interval * refills;
+        }
+
+        if bucket.tokens == 0 {
+            return false;
+        }
+
+        bucket.tokens -= 1;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_limiter_refills() {
+        let limit = RateLimit {
+            burst: 2,
+            per_ms: 100,
+        };
+        let mut limiter = RateLimiter::new();
+        let start = Instant::now();
+
+        assert!(limiter.check("typing", limit, start));
+        assert!(limiter.check("typing", limit, start));
+        assert!(!limiter.check("typing", limit, start));
+
+        // Each kind of event has a bucket of its own.
+        assert!(limiter.check("wave", limit, start));
+
+        let later = start + Duration::from_millis(150);
+        assert!(limiter.check("typing", limit, later));
+        assert!(!limiter.check("typing", limit, later));
+
+        let much_later = start + Duration::from_secs(10);
+        assert!(limiter.check("typing", limit, much_later));
+        assert!(limiter.check("typing", limit, much_later));
+        assert!(!limiter.check("typing", limit, much_later));
+    }
+}
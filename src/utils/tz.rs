@@ -0,0 +1,131 @@
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer};
+use time::{OffsetDateTime, UtcOffset};
+use time_tz::{timezones, OffsetDateTimeExt, Tz};
+
+const ERR_INVALID_TZ: &str =
+    "tz must be UTC, an offset such as +02:00 or -0530, or a zone such as Europe/Paris";
+
+/// The time zone timestamps are rendered in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum TimeZone {
+    Offset(UtcOffset),
+    /// A zone from the tz database, whose offset depends on the date.
+    Named(&'static Tz),
+}
+
+impl TimeZone {
+    /// The same instant as `at`, at this zone's offset at that time.
+    pub(crate) fn convert(&self, at: OffsetDateTime) -> OffsetDateTime {
+        match self {
+            TimeZone::Offset(offset) => at.to_offset(*offset),
+            TimeZone::Named(tz) => at.to_timezone(*tz),
+        }
+    }
+}
+
+impl Default for TimeZone {
+    fn default() -> Self {
+        TimeZone::Offset(UtcOffset::UTC)
+    }
+}
+
+/// Parses the time zone timestamps are rendered in, as a fixed offset or an
+/// IANA zone name.
+pub(crate) fn parse(tz: &str) -> Result<TimeZone, &'static str> {
+    let tz = tz.trim();
+    if tz.eq_ignore_ascii_case("utc") || tz.eq_ignore_ascii_case("z") {
+        return Ok(TimeZone::default());
+    }
+
+    let (sign, rest) = match (tz.strip_prefix('+'), tz.strip_prefix('-')) {
+        (Some(rest), _) => (1, rest),
+        (_, Some(rest)) => (-1, rest),
+        _ => {
+            return timezones::get_by_name(tz)
+                .map(TimeZone::Named)
+                .ok_or(ERR_INVALID_TZ)
+        }
+    };
+
+    let digits: String = rest.chars().filter(|c| *c != ':').collect();
+    if !digits.chars().all(|c| c.is_ascii_digit()) || rest.matches(':').count() > 1 {
+        return Err(ERR_INVALID_TZ);
+    }
+
+    let (hours, minutes) = match digits.len() {
+        1 | 2 => (digits.as_str(), "0"),
+        4 => digits.split_at(2),
+        _ => return Err(ERR_INVALID_TZ),
+    };
+    let hours: i8 = hours.parse().map_err(|_| ERR_INVALID_TZ)?;
+    let minutes: i8 = minutes.parse().map_err(|_| ERR_INVALID_TZ)?;
+    if minutes >= 60 {
+        return Err(ERR_INVALID_TZ);
+    }
+
+    UtcOffset::from_hms(sign * hours, sign * minutes, 0)
+        .map(TimeZone::Offset)
+        .map_err(|_| ERR_INVALID_TZ)
+}
+
+/// Deserializes an optional `tz` query parameter with [`parse`].
+pub(crate) fn deserialize<'de, D>(deserializer: D) -> Result<Option<TimeZone>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Option::<String>::deserialize(deserializer)? {
+        Some(tz) => parse(&tz).map(Some).map_err(D::Error::custom),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn offset(hours: i8, minutes: i8) -> Result<TimeZone, &'static str> {
+        Ok(TimeZone::Offset(
+            UtcOffset::from_hms(hours, minutes, 0).unwrap(),
+        ))
+    }
+
+    #[test]
+    fn test_parse_tz() {
+        assert_eq!(parse("UTC"), Ok(TimeZone::default()));
+        assert_eq!(parse("z"), Ok(TimeZone::default()));
+        assert_eq!(parse("+02:00"), offset(2, 0));
+        assert_eq!(parse("-0530"), offset(-5, -30));
+        assert_eq!(parse("+9"), offset(9, 0));
+
+        for tz in [
+            "Europe/Nowhere",
+            "+2:30",
+            "+02:60",
+            "+0:2:00",
+            "02:00",
+            "+99",
+            "",
+        ] {
+            assert!(parse(tz).is_err(), "{} should not parse", tz);
+        }
+    }
+
+    /// The instant `timestamp` seconds after the unix epoch.
+    fn at(timestamp: i64) -> OffsetDateTime {
+        OffsetDateTime::from_unix_timestamp(timestamp).unwrap()
+    }
+
+    #[test]
+    fn test_named_tz_follows_daylight_saving() {
+        let paris = parse("Europe/Paris").unwrap();
+
+        let winter = paris.convert(at(1673784000));
+        assert_eq!(winter.offset(), UtcOffset::from_hms(1, 0, 0).unwrap());
+        assert_eq!(winter.hour(), 13);
+
+        let summer = paris.convert(at(1689422400));
+        assert_eq!(summer.offset(), UtcOffset::from_hms(2, 0, 0).unwrap());
+        assert_eq!(summer.hour(), 14);
+    }
+}